@@ -0,0 +1,107 @@
+use hbb_common::compress::{compress, compress_block, decompress, sample_entropy};
+use std::time::Instant;
+
+// cargo run --package hbb_common --example compress_benchmark --release
+//
+// Compares compressing every file transfer block with the per-block decision
+// of `compress_block`, on synthetic corpora.
+
+const BLOCK_SIZE: usize = 128 * 1024;
+const CORPUS_SIZE: usize = 64 * 1024 * 1024;
+
+fn random(len: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed.max(1);
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect()
+}
+
+fn text(len: usize) -> Vec<u8> {
+    let words = [
+        "remote", "desktop", "file", "transfer", "block", "the", "a", "of", "compress", "peer",
+        "session", "\n", "connection", "and", "to", "server",
+    ];
+    let rnd = random(len, 7);
+    let mut out = Vec::with_capacity(len + 16);
+    let mut i = 0;
+    while out.len() < len {
+        out.extend_from_slice(words[rnd[i % rnd.len()] as usize % words.len()].as_bytes());
+        out.push(b' ');
+        i += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+// Like a video or archive: random data with some container structure
+fn media(len: usize) -> Vec<u8> {
+    let mut out = random(len, 3);
+    for chunk in out.chunks_mut(4096) {
+        chunk[..16].copy_from_slice(b"\0\0\0\x18ftypmp42\0\0\0\0");
+    }
+    out
+}
+
+// Like a disk image or database: alternating sparse and dense regions
+fn mixed(len: usize) -> Vec<u8> {
+    let mut out = vec![0u8; len];
+    let noise = random(len, 5);
+    for (i, chunk) in out.chunks_mut(BLOCK_SIZE / 2).enumerate() {
+        if i % 3 == 0 {
+            chunk.copy_from_slice(&noise[..chunk.len()]);
+        }
+    }
+    out
+}
+
+fn run(name: &str, corpus: &[u8], f: impl Fn(&[u8]) -> (Vec<u8>, bool)) {
+    let start = Instant::now();
+    let mut wire = 0;
+    let mut compressed_blocks = 0;
+    for block in corpus.chunks(BLOCK_SIZE) {
+        let (out, compressed) = f(block);
+        if compressed {
+            compressed_blocks += 1;
+            debug_assert_eq!(decompress(&out), block);
+        }
+        wire += out.len();
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "  {:<8} {:>8.1} MB/s  ratio {:.3}  compressed blocks {}/{}",
+        name,
+        corpus.len() as f64 / secs / 1024. / 1024.,
+        wire as f64 / corpus.len() as f64,
+        compressed_blocks,
+        corpus.len().div_ceil(BLOCK_SIZE),
+    );
+}
+
+fn main() {
+    let corpora = [
+        ("text", text(CORPUS_SIZE)),
+        ("media", media(CORPUS_SIZE)),
+        ("mixed", mixed(CORPUS_SIZE)),
+        ("zeros", vec![0u8; CORPUS_SIZE]),
+    ];
+    for (name, corpus) in corpora.iter() {
+        println!("{} (entropy of first block {:.2})", name, sample_entropy(&corpus[..BLOCK_SIZE]));
+        run("always", corpus, |b| {
+            let out = compress(b);
+            if out.len() < b.len() {
+                (out, true)
+            } else {
+                (b.to_vec(), false)
+            }
+        });
+        run("adaptive", corpus, |b| match compress_block(b) {
+            Some(out) => (out, true),
+            None => (b.to_vec(), false),
+        });
+    }
+}
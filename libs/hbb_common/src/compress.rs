@@ -32,3 +32,90 @@ pub fn compress(data: &[u8]) -> Vec<u8> {
 pub fn decompress(data: &[u8]) -> Vec<u8> {
     zstd::decode_all(data).unwrap_or_default()
 }
+
+// Blocks smaller than this are sent raw
+const MIN_BLOCK_LEN: usize = 256;
+// Sampled slices, spread over a block
+const SAMPLE_COUNT: usize = 4;
+const SAMPLE_LEN: usize = 1024;
+// Bits per byte above which a sample is treated as already compressed or random
+const MAX_ENTROPY: f64 = 7.5;
+// Compression must save at least this share of a block, in percent
+const MIN_SAVING_PERCENT: usize = 3;
+
+/// Shannon entropy in bits per byte of a few slices sampled from `data`.
+pub fn sample_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0u32; 256];
+    let mut total = 0u32;
+    let step = (data.len() / SAMPLE_COUNT).max(1);
+    for start in (0..data.len()).step_by(step).take(SAMPLE_COUNT) {
+        for b in &data[start..(start + SAMPLE_LEN).min(data.len())] {
+            counts[*b as usize] += 1;
+            total += 1;
+        }
+    }
+    if total == 0 {
+        return 0.;
+    }
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Compress a file transfer block, `None` if it's better sent raw: too small,
+/// high entropy (media, archives, office documents...), or barely smaller
+/// once compressed.
+pub fn compress_block(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < MIN_BLOCK_LEN || sample_entropy(data) > MAX_ENTROPY {
+        return None;
+    }
+    let out = compress(data);
+    if out.is_empty() || out.len() * 100 > data.len() * (100 - MIN_SAVING_PERCENT) {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random(len: usize) -> Vec<u8> {
+        let mut x: u32 = 1;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compress_block() {
+        let text = "the quick brown fox jumps over the lazy dog. "
+            .repeat(3000)
+            .into_bytes();
+        let compressed = compress_block(&text).unwrap();
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(decompress(&compressed), text);
+
+        let noise = random(128 * 1024);
+        assert!(sample_entropy(&noise) > MAX_ENTROPY);
+        assert!(compress_block(&noise).is_none());
+        assert!(compress_block(&text[..100]).is_none());
+
+        // Low entropy in the samples, but only half of the block compresses
+        let mut mixed = vec![0u8; 64 * 1024];
+        mixed.extend(random(64 * 1024));
+        assert!(sample_entropy(&mixed) < MAX_ENTROPY);
+        let compressed = compress_block(&mixed).unwrap();
+        assert_eq!(decompress(&compressed), mixed);
+    }
+}
@@ -1719,6 +1719,12 @@ pub struct LocalConfig {
     // Various data for flutter ui
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     ui_flutter: HashMap<String, String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_vec_portforwardprofile",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    port_forward_profiles: Vec<PortForwardProfile>,
 }
 
 impl LocalConfig {
@@ -1854,6 +1860,115 @@ impl LocalConfig {
             config.store();
         }
     }
+
+    pub fn get_port_forward_profiles() -> Vec<PortForwardProfile> {
+        Self::decrypt_profiles(LOCAL_CONFIG.read().unwrap().port_forward_profiles.clone())
+    }
+
+    // For the port forward service, the profiles may be edited by other processes.
+    pub fn get_port_forward_profiles_from_file() -> Vec<PortForwardProfile> {
+        Self::decrypt_profiles(Self::load().port_forward_profiles)
+    }
+
+    pub fn set_port_forward_profiles(profiles: Vec<PortForwardProfile>) {
+        let profiles: Vec<PortForwardProfile> = profiles
+            .into_iter()
+            .map(|mut p| {
+                p.password =
+                    encrypt_str_or_original(&p.password, PASSWORD_ENC_VERSION, ENCRYPT_MAX_LEN);
+                p
+            })
+            .collect();
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if config.port_forward_profiles != profiles {
+            config.port_forward_profiles = profiles;
+            config.store();
+        }
+    }
+
+    fn decrypt_profiles(profiles: Vec<PortForwardProfile>) -> Vec<PortForwardProfile> {
+        profiles
+            .into_iter()
+            .map(|mut p| {
+                p.password = decrypt_str_or_original(&p.password, PASSWORD_ENC_VERSION).0;
+                p
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PortForwardMode {
+    /// A local port forwarded to `remote_host:remote_port` of the peer side
+    #[default]
+    Local,
+    /// A local SOCKS5 proxy to the destinations the peer allows
+    Dynamic,
+    /// `remote_port` of the peer forwarded to `local_host:local_port`
+    Reverse,
+}
+
+/// A named port forward kept up by the port forward service.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PortForwardProfile {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub peer_id: String,
+    #[serde(default, deserialize_with = "deserialize_portforwardmode")]
+    pub mode: PortForwardMode,
+    // The bind address of local and dynamic forwards, empty for the default
+    // one, or the destination of reverse forwards
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub local_host: String,
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub local_port: i32,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub remote_host: String,
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub remote_port: i32,
+    #[serde(
+        default = "PortForwardProfile::default_auto_reconnect",
+        deserialize_with = "PortForwardProfile::deserialize_auto_reconnect"
+    )]
+    pub auto_reconnect: bool,
+    // Encrypted when stored, the remembered password of the peer is used if empty
+    #[serde(
+        default,
+        deserialize_with = "deserialize_string",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub password: String,
+}
+
+impl Default for PortForwardProfile {
+    fn default() -> Self {
+        Self {
+            name: Default::default(),
+            peer_id: Default::default(),
+            mode: Default::default(),
+            local_host: Default::default(),
+            local_port: Default::default(),
+            remote_host: Default::default(),
+            remote_port: Default::default(),
+            auto_reconnect: Self::default_auto_reconnect(),
+            password: Default::default(),
+        }
+    }
+}
+
+impl PortForwardProfile {
+    fn default_auto_reconnect() -> bool {
+        true
+    }
+
+    fn deserialize_auto_reconnect<'de, D>(deserializer: D) -> Result<bool, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        Ok(de::Deserialize::deserialize(deserializer).unwrap_or(Self::default_auto_reconnect()))
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -2306,6 +2421,8 @@ deserialize_default!(deserialize_vec_abentry, Vec<AbEntry>);
 deserialize_default!(deserialize_vec_groupuser, Vec<GroupUser>);
 deserialize_default!(deserialize_vec_grouppeer, Vec<GroupPeer>);
 deserialize_default!(deserialize_vec_devicegroup, Vec<DeviceGroup>);
+deserialize_default!(deserialize_vec_portforwardprofile, Vec<PortForwardProfile>);
+deserialize_default!(deserialize_portforwardmode, PortForwardMode);
 deserialize_default!(deserialize_keypair, KeyPair);
 deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
@@ -2467,12 +2584,32 @@ pub mod keys {
     pub const OPTION_ENABLE_CAMERA: &str = "enable-camera";
     pub const OPTION_ENABLE_TERMINAL: &str = "enable-terminal";
     pub const OPTION_TERMINAL_PERSISTENT: &str = "terminal-persistent";
+    // All terminals run the command dispatcher of the allowlist instead of a shell
+    pub const OPTION_TERMINAL_RESTRICTED: &str = "terminal-restricted";
+    // `;` or line separated, e.g. `ipconfig; ping *; systemctl status ...`
+    pub const OPTION_TERMINAL_ALLOWLIST: &str = "terminal-allowlist";
     pub const OPTION_ENABLE_AUDIO: &str = "enable-audio";
     pub const OPTION_ENABLE_TUNNEL: &str = "enable-tunnel";
+    // `;` or line separated destinations of port forwarding, e.g. `192.168.1.0/24:80,443; nas.lan`,
+    // empty for no restriction
+    pub const OPTION_PORT_FORWARD_ALLOWLIST: &str = "port-forward-allowlist";
+    // The controlling side may let this side listen on a loopback port and forward it back
+    pub const OPTION_ALLOW_REVERSE_TUNNEL: &str = "allow-reverse-tunnel";
     pub const OPTION_ENABLE_REMOTE_RESTART: &str = "enable-remote-restart";
     pub const OPTION_ENABLE_RECORD_SESSION: &str = "enable-record-session";
     pub const OPTION_ENABLE_BLOCK_INPUT: &str = "enable-block-input";
     pub const OPTION_ALLOW_REMOTE_CONFIG_MODIFICATION: &str = "allow-remote-config-modification";
+    // File transfer sandbox: `;` separated root directories, empty for no restriction
+    pub const OPTION_FILE_TRANSFER_ALLOWED_ROOTS: &str = "file-transfer-allowed-roots";
+    pub const OPTION_FILE_TRANSFER_READ_ONLY: &str = "file-transfer-read-only";
+    // In MiB, 0 or empty for no limit
+    pub const OPTION_FILE_TRANSFER_MAX_SIZE: &str = "file-transfer-max-size";
+    // Comma separated, without dot
+    pub const OPTION_FILE_TRANSFER_ALLOWED_EXTENSIONS: &str = "file-transfer-allowed-extensions";
+    pub const OPTION_FILE_TRANSFER_BLOCKED_EXTENSIONS: &str = "file-transfer-blocked-extensions";
+    // KiB/s of all file transfers, and the default of each job, empty for no limit
+    pub const OPTION_FILE_TRANSFER_RATE_LIMIT: &str = "file-transfer-rate-limit";
+    pub const OPTION_FILE_TRANSFER_JOB_RATE_LIMIT: &str = "file-transfer-job-rate-limit";
     pub const OPTION_ALLOW_NUMERNIC_ONE_TIME_PASSWORD: &str = "allow-numeric-one-time-password";
     pub const OPTION_ENABLE_LAN_DISCOVERY: &str = "enable-lan-discovery";
     pub const OPTION_DIRECT_SERVER: &str = "direct-server";
@@ -2484,6 +2621,15 @@ pub mod keys {
     pub const OPTION_ALLOW_AUTO_RECORD_INCOMING: &str = "allow-auto-record-incoming";
     pub const OPTION_ALLOW_AUTO_RECORD_OUTGOING: &str = "allow-auto-record-outgoing";
     pub const OPTION_VIDEO_SAVE_DIRECTORY: &str = "video-save-directory";
+    // Base64 public key, recordings are encrypted for it if set
+    pub const OPTION_RECORD_ENCRYPTION_KEY: &str = "record-encryption-key";
+    // Recording retention, 0 or empty for no limit
+    pub const OPTION_RECORD_MAX_AGE_DAYS: &str = "record-max-age-days";
+    // In MiB
+    pub const OPTION_RECORD_MAX_TOTAL_SIZE: &str = "record-max-total-size";
+    pub const OPTION_RECORD_KEEP_LAST_PER_PEER: &str = "record-keep-last-per-peer";
+    // In MiB, recording pauses below it, empty for the default
+    pub const OPTION_RECORD_MIN_FREE_SPACE: &str = "record-min-free-space";
     pub const OPTION_ENABLE_ABR: &str = "enable-abr";
     pub const OPTION_ALLOW_REMOVE_WALLPAPER: &str = "allow-remove-wallpaper";
     pub const OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER: &str = "allow-always-software-render";
@@ -2505,6 +2651,16 @@ pub mod keys {
     pub const OPTION_PRESET_DEVICE_NAME: &str = "preset-device-name";
     pub const OPTION_PRESET_NOTE: &str = "preset-note";
     pub const OPTION_ENABLE_DIRECTX_CAPTURE: &str = "enable-directx-capture";
+    // Synthetic frames instead of the displays, e.g. "size=1280x720;fps=30;damage=0.5",
+    // see `scrap::synthetic::SyntheticConfig::parse`. Empty for the displays
+    pub const OPTION_SYNTHETIC_CAPTURE: &str = "synthetic-capture";
+    // Shares the first X11 window whose class or title contains it, case insensitive,
+    // instead of the displays. Empty for the displays
+    pub const OPTION_SHARE_WINDOW: &str = "share-window";
+    // Screen regions blacked out or pixelated before the frames are encoded, e.g.
+    // "rect=0,0,400,300;window=keepass;style=pixelate", see
+    // `scrap::redact::RedactConfig::parse`. Empty for none
+    pub const OPTION_REDACT_REGIONS: &str = "redact-regions";
     pub const OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE: &str =
         "enable-android-software-encoding-half-scale";
     pub const OPTION_ENABLE_TRUSTED_DEVICES: &str = "enable-trusted-devices";
@@ -2516,6 +2672,8 @@ pub mod keys {
     // joystick is the virtual mouse.
     // So `OPTION_SHOW_VIRTUAL_MOUSE` should also be set if `OPTION_SHOW_VIRTUAL_JOYSTICK` is set.
     pub const OPTION_SHOW_VIRTUAL_JOYSTICK: &str = "show-virtual-joystick";
+    // Address local port forwards listen on, loopback if empty
+    pub const OPTION_PORT_FORWARD_BIND_ADDRESS: &str = "port-forward-bind-address";
 
     // built-in options
     pub const OPTION_DISPLAY_NAME: &str = "display-name";
@@ -2650,6 +2808,7 @@ pub mod keys {
         OPTION_TOUCH_MODE,
         OPTION_SHOW_VIRTUAL_MOUSE,
         OPTION_SHOW_VIRTUAL_JOYSTICK,
+        OPTION_PORT_FORWARD_BIND_ADDRESS,
     ];
     // DEFAULT_SETTINGS, OVERWRITE_SETTINGS
     pub const KEYS_SETTINGS: &[&str] = &[
@@ -2659,13 +2818,24 @@ pub mod keys {
         OPTION_ENABLE_FILE_TRANSFER,
         OPTION_ENABLE_CAMERA,
         OPTION_ENABLE_TERMINAL,
+        OPTION_TERMINAL_RESTRICTED,
+        OPTION_TERMINAL_ALLOWLIST,
         OPTION_ENABLE_REMOTE_PRINTER,
         OPTION_ENABLE_AUDIO,
         OPTION_ENABLE_TUNNEL,
+        OPTION_PORT_FORWARD_ALLOWLIST,
+        OPTION_ALLOW_REVERSE_TUNNEL,
         OPTION_ENABLE_REMOTE_RESTART,
         OPTION_ENABLE_RECORD_SESSION,
         OPTION_ENABLE_BLOCK_INPUT,
         OPTION_ALLOW_REMOTE_CONFIG_MODIFICATION,
+        OPTION_FILE_TRANSFER_ALLOWED_ROOTS,
+        OPTION_FILE_TRANSFER_READ_ONLY,
+        OPTION_FILE_TRANSFER_MAX_SIZE,
+        OPTION_FILE_TRANSFER_ALLOWED_EXTENSIONS,
+        OPTION_FILE_TRANSFER_BLOCKED_EXTENSIONS,
+        OPTION_FILE_TRANSFER_RATE_LIMIT,
+        OPTION_FILE_TRANSFER_JOB_RATE_LIMIT,
         OPTION_ALLOW_NUMERNIC_ONE_TIME_PASSWORD,
         OPTION_ENABLE_LAN_DISCOVERY,
         OPTION_DIRECT_SERVER,
@@ -2675,6 +2845,11 @@ pub mod keys {
         OPTION_AUTO_DISCONNECT_TIMEOUT,
         OPTION_ALLOW_ONLY_CONN_WINDOW_OPEN,
         OPTION_ALLOW_AUTO_RECORD_INCOMING,
+        OPTION_RECORD_ENCRYPTION_KEY,
+        OPTION_RECORD_MAX_AGE_DAYS,
+        OPTION_RECORD_MAX_TOTAL_SIZE,
+        OPTION_RECORD_KEEP_LAST_PER_PEER,
+        OPTION_RECORD_MIN_FREE_SPACE,
         OPTION_ENABLE_ABR,
         OPTION_ALLOW_REMOVE_WALLPAPER,
         OPTION_ALLOW_ALWAYS_SOFTWARE_RENDER,
@@ -2702,6 +2877,9 @@ pub mod keys {
         OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE,
         OPTION_ENABLE_TRUSTED_DEVICES,
         OPTION_RELAY_SERVER,
+        OPTION_SYNTHETIC_CAPTURE,
+        OPTION_SHARE_WINDOW,
+        OPTION_REDACT_REGIONS,
     ];
 
     // BUILDIN_SETTINGS
@@ -3027,6 +3205,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_port_forward_profile_deserialize() {
+        let profile = toml::from_str::<PortForwardProfile>(
+            r#"
+            name = "rdp"
+            peer_id = "123456789"
+            mode = "reverse"
+            local_port = 3389
+            remote_port = "13389"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            profile,
+            PortForwardProfile {
+                name: "rdp".to_string(),
+                peer_id: "123456789".to_string(),
+                mode: PortForwardMode::Reverse,
+                local_port: 3389,
+                auto_reconnect: true,
+                ..Default::default()
+            }
+        );
+        let profile = toml::from_str::<PortForwardProfile>(
+            r#"
+            mode = "unknown"
+            auto_reconnect = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(profile.mode, PortForwardMode::Local);
+        assert!(!profile.auto_reconnect);
+    }
+
     #[test]
     fn test_store_load() {
         let peerconfig_id = "123456789";
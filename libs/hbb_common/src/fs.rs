@@ -5,7 +5,7 @@ use std::{
     io::Cursor,
     path::{Path, PathBuf},
     sync::atomic::{AtomicI32, Ordering},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use serde_derive::{Deserialize, Serialize};
@@ -18,8 +18,10 @@ use tokio::{
 use crate::{anyhow::anyhow, bail, get_version_number, message_proto::*, ResultType, Stream};
 // https://doc.rust-lang.org/std/os/windows/fs/trait.MetadataExt.html
 use crate::{
-    compress::{compress, decompress},
+    compress::{compress_block, decompress},
     config::Config,
+    fs_delta::{self, ContentHasher, DeltaEncoder},
+    fs_rate::{self, RateLimiter},
 };
 
 static NEXT_JOB_ID: AtomicI32 = AtomicI32::new(1);
@@ -140,12 +142,25 @@ pub fn get_home_as_string() -> String {
     get_string(&Config::get_home())
 }
 
+// Only the requested path itself may be a link. Links below it are skipped,
+// following them could loop or lead out of the shared directory.
+#[inline]
+fn is_link_below(path: &Path, prefix: &Path) -> bool {
+    !prefix.as_os_str().is_empty()
+        && std::fs::symlink_metadata(path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(true)
+}
+
 fn read_dir_recursive(
     path: &Path,
     prefix: &Path,
     include_hidden: bool,
 ) -> ResultType<Vec<FileEntry>> {
     let mut files = Vec::new();
+    if is_link_below(path, prefix) {
+        return Ok(files);
+    }
     if path.is_dir() {
         // to-do: symbol link handling, cp the link rather than the content
         // to-do: file mode, for unix
@@ -209,6 +224,9 @@ fn read_empty_dirs_recursive(
     include_hidden: bool,
 ) -> ResultType<Vec<FileDirectory>> {
     let mut dirs = Vec::new();
+    if is_link_below(path, prefix) {
+        return Ok(dirs);
+    }
     if path.is_dir() {
         // to-do: symbol link handling, cp the link rather than the content
         // to-do: file mode, for unix
@@ -383,6 +401,8 @@ impl DataStream {
 pub struct FileDigest {
     pub size: u64,
     pub modified: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hash: Vec<u8>,
 }
 
 #[derive(Default, Serialize, Debug)]
@@ -414,6 +434,38 @@ pub struct TransferJob {
     default_overwrite_strategy: Option<bool>,
     #[serde(skip_serializing)]
     digest: FileDigest,
+    // Sending side: signatures of the receiver's version of a file, by file number
+    #[serde(skip_serializing)]
+    delta_signatures: Option<(i32, FileDeltaSignatures)>,
+    #[serde(skip_serializing)]
+    delta_encoder: Option<DeltaEncoder>,
+    // Hash of the current file. Receiving side: checked against `digest.hash`,
+    // sending side: sent with the block ending the file.
+    #[serde(skip_serializing)]
+    hasher: Option<ContentHasher>,
+    // Sending side: the receiver asked for the content hash in the digest
+    #[serde(skip_serializing)]
+    send_content_hash: bool,
+    #[serde(skip_serializing)]
+    hash_mismatch: bool,
+    #[serde(skip_serializing)]
+    verify_error: Option<String>,
+    // Stats, see `TransferStats`
+    #[serde(skip_serializing)]
+    started: Option<(Instant, u64)>,
+    #[serde(skip_serializing)]
+    payload_size: u64,
+    // Part of `transferred` skipped on resume, not sent on the wire
+    #[serde(skip_serializing)]
+    resumed_size: u64,
+    #[serde(skip_serializing)]
+    compressed_blocks: u64,
+    #[serde(skip_serializing)]
+    raw_blocks: u64,
+    // Sending side: no blocks are read while paused
+    paused: bool,
+    #[serde(skip_serializing)]
+    rate_limiter: RateLimiter,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -442,19 +494,16 @@ pub struct RemoveJobMeta {
     pub no_confirm: bool,
 }
 
-#[inline]
-fn get_ext(name: &str) -> &str {
-    if let Some(i) = name.rfind('.') {
-        return &name[i + 1..];
-    }
-    ""
-}
-
-#[inline]
-fn is_compressed_file(name: &str) -> bool {
-    let compressed_exts = ["xz", "gz", "zip", "7z", "rar", "bz2", "tgz", "png", "jpg"];
-    let ext = get_ext(name);
-    compressed_exts.contains(&ext)
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferStats {
+    pub elapsed_ms: u64,
+    // File bytes per second since the first block
+    pub throughput: u64,
+    // Bytes of block data on the wire per byte before compression
+    pub ratio: f64,
+    pub compressed_blocks: u64,
+    pub raw_blocks: u64,
 }
 
 impl TransferJob {
@@ -518,6 +567,7 @@ impl TransferJob {
             files,
             total_size,
             enable_overwrite_detection,
+            rate_limiter: RateLimiter::new(fs_rate::default_job_rate()),
             ..Default::default()
         })
     }
@@ -546,6 +596,33 @@ impl TransferJob {
     pub fn set_digest(&mut self, size: u64, modified: u64) {
         self.digest.size = size;
         self.digest.modified = modified;
+        self.digest.hash.clear();
+    }
+
+    /// Content hash the peer announced for the file of the last `set_digest`,
+    /// verified when the file is complete.
+    #[inline]
+    pub fn set_content_hash(&mut self, hash: Vec<u8>) {
+        self.digest.hash = hash;
+    }
+
+    #[inline]
+    pub fn set_delta_signatures(
+        &mut self,
+        file_num: i32,
+        signatures: protobuf::MessageField<FileDeltaSignatures>,
+    ) {
+        self.delta_signatures = signatures.into_option().map(|s| (file_num, s));
+    }
+
+    pub fn take_delta_signatures(
+        &mut self,
+        file_num: i32,
+    ) -> protobuf::MessageField<FileDeltaSignatures> {
+        match self.delta_signatures.take() {
+            Some((n, s)) if n == file_num => protobuf::MessageField::some(s),
+            _ => protobuf::MessageField::none(),
+        }
     }
 
     #[inline]
@@ -573,6 +650,44 @@ impl TransferJob {
         self.file_num
     }
 
+    pub fn stats(&self) -> TransferStats {
+        let mut stats = TransferStats {
+            compressed_blocks: self.compressed_blocks,
+            raw_blocks: self.raw_blocks,
+            ..Default::default()
+        };
+        if let Some((started, finished_size)) = self.started {
+            let elapsed = started.elapsed();
+            stats.elapsed_ms = elapsed.as_millis() as _;
+            let secs = elapsed.as_secs_f64();
+            if secs > 0. {
+                stats.throughput =
+                    (self.finished_size.saturating_sub(finished_size) as f64 / secs) as _;
+            }
+        }
+        if self.payload_size > 0 {
+            stats.ratio = self.transferred.saturating_sub(self.resumed_size) as f64
+                / self.payload_size as f64;
+        }
+        stats
+    }
+
+    // `raw_len`: length of the block data before compression
+    fn update_stats(&mut self, raw_len: usize, compressed: bool) {
+        if raw_len == 0 {
+            return;
+        }
+        if self.started.is_none() {
+            self.started = Some((Instant::now(), self.finished_size));
+        }
+        self.payload_size += raw_len as u64;
+        if compressed {
+            self.compressed_blocks += 1;
+        } else {
+            self.raw_blocks += 1;
+        }
+    }
+
     pub fn modify_time(&self) {
         if self.r#type == JobType::Printer {
             return;
@@ -585,6 +700,11 @@ impl TransferJob {
                 let download_path = format!("{}.download", get_string(&path));
                 let digest_path = format!("{}.digest", get_string(&path));
                 std::fs::remove_file(digest_path).ok();
+                if self.hash_mismatch {
+                    // Keep the existing file, the received one is corrupted
+                    std::fs::remove_file(download_path).ok();
+                    return;
+                }
                 std::fs::rename(download_path, &path).ok();
                 filetime::set_file_mtime(
                     &path,
@@ -641,6 +761,8 @@ impl TransferJob {
                         file.sync_all().await?;
                     }
                     self.file_num = block.file_num;
+                    self.hasher = Some(ContentHasher::default());
+                    self.hash_mismatch = false;
                     let entry = &self.files[file_num];
                     let (path, digest_path) = if self.r#type == JobType::Printer {
                         (p.to_string_lossy().to_string(), None)
@@ -672,26 +794,91 @@ impl TransferJob {
                 }
             }
         }
-        if block.compressed {
-            let tmp = decompress(&block.data);
-            self.data_stream
-                .as_mut()
-                .ok_or(anyhow!("data stream is None"))?
-                .write_all(&tmp)
-                .await?;
-            self.finished_size += tmp.len() as u64;
+        let tmp;
+        let data: &[u8] = if block.compressed {
+            tmp = decompress(&block.data);
+            &tmp
         } else {
-            self.data_stream
-                .as_mut()
-                .ok_or(anyhow!("file is None"))?
-                .write_all(&block.data)
-                .await?;
-            self.finished_size += block.data.len() as u64;
+            &block.data
+        };
+        self.data_stream
+            .as_mut()
+            .ok_or(anyhow!("data stream is None"))?
+            .write_all(data)
+            .await?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(data);
+        }
+        self.update_stats(data.len(), block.compressed);
+        self.finished_size += data.len() as u64;
+        if !block.delta_copy_blocks.is_empty() {
+            self.write_delta_copies(&block).await?;
         }
         self.transferred += block.data.len() as u64;
+        // The sender ends every file with an empty block
+        if block.data.is_empty() && block.delta_copy_blocks.is_empty() {
+            if !block.content_hash.is_empty() {
+                self.digest.hash = block.content_hash.to_vec();
+            }
+            self.verify_content_hash();
+        }
+        Ok(())
+    }
+
+    // Copy the blocks the sender referenced from our existing version of the file.
+    async fn write_delta_copies(&mut self, block: &FileTransferBlock) -> ResultType<()> {
+        let basis_path = match &self.data_source {
+            DataSource::FilePath(p) if self.r#type == JobType::Generic => {
+                Self::join(p, &self.files[block.file_num as usize].name)
+            }
+            _ => bail!("Delta blocks are only supported for file jobs"),
+        };
+        let block_size = block.delta_block_size as u64;
+        if block_size == 0 {
+            bail!("Invalid delta block size");
+        }
+        let mut basis = File::open(&basis_path).await?;
+        let basis_size = basis.metadata().await?.len();
+        let mut buf = vec![0u8; block_size as usize];
+        let stream = self
+            .data_stream
+            .as_mut()
+            .ok_or(anyhow!("data stream is None"))?;
+        for index in block.delta_copy_blocks.iter() {
+            let offset = *index as u64 * block_size;
+            if offset >= basis_size {
+                bail!("Delta block {} out of range", index);
+            }
+            let len = block_size.min(basis_size - offset) as usize;
+            basis.seek(std::io::SeekFrom::Start(offset)).await?;
+            basis.read_exact(&mut buf[..len]).await?;
+            stream.write_all(&buf[..len]).await?;
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf[..len]);
+            }
+            self.finished_size += len as u64;
+        }
         Ok(())
     }
 
+    fn verify_content_hash(&mut self) {
+        if let Some(hasher) = self.hasher.take() {
+            if self.digest.hash.is_empty() {
+                return;
+            }
+            if hasher.finish() != self.digest.hash {
+                let name = self
+                    .files
+                    .get(self.file_num as usize)
+                    .map(|f| f.name.clone())
+                    .unwrap_or_default();
+                log::error!("id: {}, content hash mismatch of {}", self.id, name);
+                self.hash_mismatch = true;
+                self.verify_error = Some(format!("Content hash mismatch: {}", name));
+            }
+        }
+    }
+
     #[inline]
     pub fn join(p: &PathBuf, name: &str) -> PathBuf {
         if name.is_empty() {
@@ -703,18 +890,18 @@ impl TransferJob {
 
     pub async fn read(&mut self, stream: &mut Stream) -> ResultType<Option<FileTransferBlock>> {
         let file_num = self.file_num as usize;
-        let name: &str;
         match &mut self.data_source {
             DataSource::FilePath(p) => {
                 if file_num >= self.files.len() {
                     self.data_stream.take();
                     return Ok(None);
                 };
-                name = &self.files[file_num].name;
                 if self.data_stream.is_none() {
+                    let name = &self.files[file_num].name;
                     match File::open(Self::join(p, name)).await {
                         Ok(file) => {
                             self.data_stream = Some(DataStream::FileStream(file));
+                            self.hasher = Some(ContentHasher::default());
                             self.file_confirmed = false;
                             self.file_is_waiting = false;
                         }
@@ -728,7 +915,6 @@ impl TransferJob {
                 }
             }
             DataSource::MemoryCursor(c) => {
-                name = "";
                 if self.data_stream.is_none() {
                     let mut t = std::io::Cursor::new(Vec::new());
                     std::mem::swap(&mut t, c);
@@ -745,6 +931,9 @@ impl TransferJob {
                 return Ok(None);
             }
         }
+        if self.delta_encoder.is_some() {
+            return self.read_delta(file_num).await;
+        }
         const BUF_SIZE: usize = 128 * 1024;
         let mut buf: Vec<u8> = vec![0; BUF_SIZE];
         let mut compressed = false;
@@ -773,24 +962,29 @@ impl TransferJob {
             }
         }
         unsafe { buf.set_len(offset) };
+        let mut content_hash = Vec::new();
         if offset == 0 {
             if matches!(self.data_source, DataSource::MemoryCursor(_)) {
                 self.data_stream.take();
                 return Ok(None);
             }
+            content_hash = self.hasher.take().map(|h| h.finish()).unwrap_or_default();
             self.file_num += 1;
             self.data_stream = None;
             self.file_confirmed = false;
             self.file_is_waiting = false;
         } else {
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf);
+            }
             self.finished_size += offset as u64;
-            if matches!(self.data_source, DataSource::FilePath(_)) && !is_compressed_file(name) {
-                let tmp = compress(&buf);
-                if tmp.len() < buf.len() {
+            if matches!(self.data_source, DataSource::FilePath(_)) {
+                if let Some(tmp) = compress_block(&buf) {
                     buf = tmp;
                     compressed = true;
                 }
             }
+            self.update_stats(offset, compressed);
             self.transferred += buf.len() as u64;
         }
         Ok(Some(FileTransferBlock {
@@ -798,10 +992,90 @@ impl TransferJob {
             file_num: file_num as _,
             data: buf.into(),
             compressed,
+            content_hash: content_hash.into(),
             ..Default::default()
         }))
     }
 
+    // Like `read`, but sends literal data and references to the blocks the
+    // receiver already has.
+    async fn read_delta(&mut self, file_num: usize) -> ResultType<Option<FileTransferBlock>> {
+        const BUF_SIZE: usize = 128 * 1024;
+        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        let chunk = loop {
+            let encoder = self
+                .delta_encoder
+                .as_mut()
+                .ok_or(anyhow!("delta encoder is None"))?;
+            if let Some(chunk) = encoder.next_chunk() {
+                break Some(chunk);
+            }
+            if encoder.is_done() {
+                break None;
+            }
+            match self
+                .data_stream
+                .as_mut()
+                .ok_or(anyhow!("data stream is None"))?
+                .read(&mut buf)
+                .await
+            {
+                Err(err) => {
+                    self.file_num += 1;
+                    self.data_stream = None;
+                    self.delta_encoder = None;
+                    self.file_confirmed = false;
+                    self.file_is_waiting = false;
+                    return Err(err.into());
+                }
+                Ok(0) => encoder.finish(),
+                Ok(n) => {
+                    if let Some(hasher) = self.hasher.as_mut() {
+                        hasher.update(&buf[..n]);
+                    }
+                    encoder.feed(&buf[..n])
+                }
+            }
+        };
+        let mut block = FileTransferBlock {
+            id: self.id,
+            file_num: file_num as _,
+            ..Default::default()
+        };
+        match (chunk, self.delta_encoder.as_ref()) {
+            (Some(chunk), Some(encoder)) => {
+                self.finished_size +=
+                    chunk.literal.len() as u64 + encoder.copied_len(&chunk.copies);
+                block.delta_block_size = encoder.block_size();
+                let mut data = chunk.literal;
+                let raw_len = data.len();
+                if let Some(tmp) = compress_block(&data) {
+                    data = tmp;
+                    block.compressed = true;
+                }
+                self.update_stats(raw_len, block.compressed);
+                self.transferred += data.len() as u64;
+                block.data = data.into();
+                block.delta_copy_blocks = chunk.copies;
+            }
+            _ => {
+                // End of file, an empty block like `read` sends
+                block.content_hash = self
+                    .hasher
+                    .take()
+                    .map(|h| h.finish())
+                    .unwrap_or_default()
+                    .into();
+                self.file_num += 1;
+                self.data_stream = None;
+                self.delta_encoder = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+            }
+        }
+        Ok(Some(block))
+    }
+
     // Only for generic job and file stream
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
@@ -814,14 +1088,30 @@ impl TransferJob {
             .modified()?
             .duration_since(SystemTime::UNIX_EPOCH)?
             .as_secs();
-        resp.set_digest(FileTransferDigest {
+        let mut digest = FileTransferDigest {
             id: self.id,
             file_num: self.file_num,
             last_modified,
             file_size: meta.len(),
             is_resume: self.is_resume,
+            // Announce the content hash, it's only sent when the receiver asks
+            hash_algorithm: FileHashAlgorithm::Sha256.into(),
             ..Default::default()
-        });
+        };
+        if let DataSource::FilePath(p) = &self.data_source {
+            if std::mem::take(&mut self.send_content_hash) {
+                let path = Self::join(p, &self.files[self.file_num as usize].name);
+                match crate::tokio::task::spawn_blocking(move || fs_delta::hash_file(&path)).await?
+                {
+                    Ok(hash) => digest.content_hash = hash.into(),
+                    Err(e) => {
+                        log::warn!("Failed to hash file {}: {}", self.file_num, e);
+                        digest.hash_algorithm = FileHashAlgorithm::NoHash.into();
+                    }
+                }
+            }
+        }
+        resp.set_digest(digest);
         msg.set_file_response(resp);
         stream.send(&msg).await?;
         log::info!(
@@ -891,12 +1181,35 @@ impl TransferJob {
         if self.job_skipped() {
             return Some("skipped".to_string());
         }
-        None
+        self.verify_error.clone()
+    }
+
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            log::info!("job {} paused: {}", self.id, paused);
+            self.paused = paused;
+        }
+    }
+
+    /// Bytes/s, 0 for no limit
+    #[inline]
+    pub fn rate_limit(&self) -> u64 {
+        self.rate_limiter.rate()
+    }
+
+    pub fn set_rate_limit(&mut self, rate: u64) {
+        self.rate_limiter.set_rate(rate);
     }
 
     pub fn set_file_skipped(&mut self) -> bool {
         log::debug!("skip file {} in job {}", self.file_num, self.id);
         self.data_stream.take();
+        self.delta_encoder = None;
         self.set_file_confirmed(false);
         self.set_file_is_waiting(false);
         self.file_num += 1;
@@ -912,6 +1225,7 @@ impl TransferJob {
             let download_path = format!("{}.download", &file_path);
             let digest_path = format!("{}.digest", &file_path);
 
+            let mut hasher = None;
             let mut f = if Path::new(&download_path).exists() && Path::new(&digest_path).exists() {
                 // If both download and digest files exist, seek (writer) to the offset
                 match OpenOptions::new()
@@ -920,7 +1234,11 @@ impl TransferJob {
                     .open(&download_path)
                     .await
                 {
-                    Ok(f) => f,
+                    Ok(f) => {
+                        // Received data so far is part of the content hash
+                        hasher = hash_prefix(&download_path, offset).await.ok();
+                        f
+                    }
                     Err(e) => {
                         log::warn!("Failed to open file {}: {}", download_path, e);
                         return;
@@ -929,7 +1247,11 @@ impl TransferJob {
             } else if Path::new(&file_path).exists() {
                 // If `file_path` exists, seek (reader) to the offset
                 match File::open(&file_path).await {
-                    Ok(f) => f,
+                    Ok(f) => {
+                        // The skipped data is part of the content hash
+                        hasher = hash_prefix(&file_path, offset).await.ok();
+                        f
+                    }
                     Err(e) => {
                         log::warn!("Failed to open file {}: {}", file_path, e);
                         return;
@@ -945,7 +1267,10 @@ impl TransferJob {
             };
             if f.seek(std::io::SeekFrom::Start(offset)).await.is_ok() {
                 self.data_stream = Some(DataStream::FileStream(f));
+                self.hasher = hasher;
+                self.hash_mismatch = false;
                 self.transferred += offset;
+                self.resumed_size += offset;
                 self.finished_size += offset;
             }
         }
@@ -954,6 +1279,10 @@ impl TransferJob {
     pub async fn confirm(&mut self, r: &FileTransferSendConfirmRequest) -> bool {
         if self.file_num() != r.file_num {
             log::info!("file num truncated, ignoring");
+        } else if r.need_content_hash && r.union.is_none() {
+            // Sending side: the digest is sent again, with the content hash
+            self.send_content_hash = true;
+            self.set_file_is_waiting(false);
         } else {
             match r.union {
                 Some(file_transfer_send_confirm_request::Union::Skip(s)) => {
@@ -964,11 +1293,25 @@ impl TransferJob {
                     }
                 }
                 Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset)) => {
+                    // Only the sending side waits for a confirmation
+                    let is_sender = self.file_is_waiting;
                     self.set_file_confirmed(true);
                     // If offset is greater than 0, we need to seek to the offset
                     if offset > 0 {
                         self.set_stream_offset(r.file_num as usize, offset as u64)
                             .await;
+                    } else if is_sender {
+                        // Signatures come with the request, or with the digest
+                        // replied by the receiver before the user confirmed
+                        let signatures = if r.delta_signatures.is_some() {
+                            r.delta_signatures.clone()
+                        } else {
+                            self.take_delta_signatures(r.file_num)
+                        };
+                        self.delta_encoder = signatures.as_ref().and_then(DeltaEncoder::new);
+                        if self.delta_encoder.is_some() {
+                            log::info!("id: {}, file_num: {}, delta transfer", self.id, r.file_num);
+                        }
                     }
                 }
                 _ => {}
@@ -1119,9 +1462,13 @@ pub async fn handle_read_jobs(
     let mut job_log = Default::default();
     let mut finished = Vec::new();
     for job in jobs.iter_mut() {
-        if job.is_last_job {
+        if job.is_last_job || job.paused {
             continue;
         }
+        // Out of tokens, try again on the next tick
+        if !job.rate_limiter.ready() || !fs_rate::global_ready() {
+            break;
+        }
         match job.read(stream).await {
             Err(err) => {
                 stream
@@ -1129,6 +1476,8 @@ pub async fn handle_read_jobs(
                     .await?;
             }
             Ok(Some(block)) => {
+                job.rate_limiter.consume(block.data.len());
+                fs_rate::global_consume(block.data.len());
                 stream.send(&new_block(block)).await?;
             }
             Ok(None) => {
@@ -1209,14 +1558,62 @@ pub fn transform_windows_path(entries: &mut Vec<FileEntry>) {
     }
 }
 
+async fn hash_prefix(path: &str, len: u64) -> std::io::Result<ContentHasher> {
+    let mut reader = File::open(path).await?.take(len);
+    let mut hasher = ContentHasher::default();
+    let mut buf = vec![0u8; 128 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher)
+}
+
+#[inline]
+pub fn supports_content_hash(digest: &FileTransferDigest) -> bool {
+    digest.hash_algorithm.enum_value() == Ok(FileHashAlgorithm::Sha256)
+}
+
+#[inline]
+fn has_content_hash(digest: &FileTransferDigest) -> bool {
+    supports_content_hash(digest) && !digest.content_hash.is_empty()
+}
+
+/// The content hash of a digest, empty if the peer sent none we understand.
+#[inline]
+pub fn digest_content_hash(digest: &FileTransferDigest) -> Vec<u8> {
+    if has_content_hash(digest) {
+        digest.content_hash.to_vec()
+    } else {
+        Vec::new()
+    }
+}
+
 pub enum DigestCheckResult {
     IsSame,
     NeedConfirm(FileTransferDigest),
     NoSuchFile,
+    // A file of the same size exists, ask the sender for the content hash
+    NeedContentHash,
 }
 
-#[inline]
-pub fn is_write_need_confirmation(
+/// Compare the digest of the sender with the local file. It may hash the file,
+/// so it runs on a blocking thread.
+pub async fn is_write_need_confirmation(
+    is_resume: bool,
+    file_path: &str,
+    digest: &FileTransferDigest,
+) -> ResultType<DigestCheckResult> {
+    let file_path = file_path.to_owned();
+    let digest = digest.clone();
+    crate::tokio::task::spawn_blocking(move || check_write_digest(is_resume, &file_path, &digest))
+        .await?
+}
+
+fn check_write_digest(
     is_resume: bool,
     file_path: &str,
     digest: &FileTransferDigest,
@@ -1230,7 +1627,10 @@ pub fn is_write_need_confirmation(
         if let Ok(content) = std::fs::read_to_string(digest_file) {
             if let Ok(local_digest) = serde_json::from_str::<FileDigest>(&content) {
                 let is_identical = local_digest.modified == digest.last_modified
-                    && local_digest.size == digest.file_size;
+                    && local_digest.size == digest.file_size
+                    && (local_digest.hash.is_empty()
+                        || !has_content_hash(digest)
+                        || local_digest.hash[..] == digest.content_hash[..]);
                 if is_identical {
                     if let Ok(download_metadata) = std::fs::metadata(download_file) {
                         // Get the file size of the local file
@@ -1261,16 +1661,37 @@ pub fn is_write_need_confirmation(
         // [Note]
         // We decide to give the decision whether to override the existing file to users,
         // which obey the behavior of the file manager in our system.
+        //
+        // Peers sending a content hash are compared by content, and get the
+        // signatures of our version for a delta transfer if it differs.
         let mut is_identical = false;
-        if remote_mt == local_mt && digest.file_size == metadata.len() {
-            is_identical = true;
+        if digest.file_size == metadata.len() {
+            if supports_content_hash(digest) && !has_content_hash(digest) {
+                return Ok(DigestCheckResult::NeedContentHash);
+            }
+            is_identical = if has_content_hash(digest) {
+                fs_delta::hash_file(path)
+                    .map(|h| h[..] == digest.content_hash[..])
+                    .unwrap_or(false)
+            } else {
+                remote_mt == local_mt
+            };
         }
+        let delta_signatures = if supports_content_hash(digest)
+            && !is_identical
+            && metadata.len() >= fs_delta::DELTA_MIN_SIZE
+        {
+            fs_delta::file_signatures(path).ok()
+        } else {
+            None
+        };
         Ok(DigestCheckResult::NeedConfirm(FileTransferDigest {
             id: digest.id,
             file_num: digest.file_num,
             last_modified: local_mt.as_secs(),
             file_size: metadata.len(),
             is_identical,
+            delta_signatures: delta_signatures.into(),
             ..Default::default()
         }))
     } else {
@@ -1293,5 +1714,37 @@ pub fn serialize_transfer_job(job: &TransferJob, done: bool, cancel: bool, error
     value["done"] = json!(done);
     value["cancel"] = json!(cancel);
     value["error"] = json!(error);
+    value["stats"] = json!(job.stats());
+    value["rateLimit"] = json!(job.rate_limit());
     serde_json::to_string(&value).unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recursive_files_skip_links() {
+        let tmp = std::env::temp_dir().join(format!("hbb_fs_links_{}", std::process::id()));
+        let outside = tmp.join("outside");
+        let root = tmp.join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("sub").join("a.txt"), "a").unwrap();
+        std::fs::write(outside.join("secret.txt"), "s").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("sub").join("loop")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("secret.txt")).unwrap();
+
+        let files = get_recursive_files(&get_string(&root), true).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.name.clone()).collect();
+        assert_eq!(names, vec![get_string(&Path::new("sub").join("a.txt"))]);
+        let dirs = get_empty_dirs_recursive(&get_string(&root), true).unwrap();
+        assert!(dirs.is_empty());
+        // The requested path itself may be a link
+        let files = get_recursive_files(&get_string(&root.join("out")), true).unwrap();
+        assert_eq!(files.len(), 1);
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -0,0 +1,369 @@
+// Content hashes and rsync-style delta encoding for file transfer.
+//
+// The receiver of a file it already has an older version of sends the
+// signatures of that version's blocks (a rolling checksum plus a truncated
+// sha256 each). The sender scans its file with the rolling checksum and
+// sends, per `FileTransferBlock`, literal data followed by the indexes of
+// the receiver's blocks to copy. The whole-file `content_hash` in the
+// digest lets the receiver verify the result.
+use crate::message_proto::{FileBlockSignature, FileDeltaSignatures};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Smaller files are always sent whole.
+pub const DELTA_MIN_SIZE: u64 = 1024 * 1024;
+const MIN_BLOCK_SIZE: u32 = 2 * 1024;
+const MAX_BLOCK_SIZE: u32 = 128 * 1024;
+const STRONG_LEN: usize = 16;
+// Limits of one delta block message
+const MAX_LITERAL_LEN: usize = 128 * 1024;
+const MAX_COPIES: usize = 4096;
+
+#[derive(Default)]
+pub struct ContentHasher(Sha256);
+
+impl Debug for ContentHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ContentHasher")
+    }
+}
+
+impl ContentHasher {
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    #[inline]
+    pub fn finish(self) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+pub fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = ContentHasher::default();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// About the square root of the size, as rsync does: fewer signatures for
+/// big files, finer granularity for small ones.
+pub fn block_size_for(size: u64) -> u32 {
+    let size = ((size as f64).sqrt() as u32) & !63;
+    size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+fn strong_hash(data: &[u8]) -> Vec<u8> {
+    let mut h = Sha256::digest(data).to_vec();
+    h.truncate(STRONG_LEN);
+    h
+}
+
+// rsync's rolling checksum: a = sum(x[i]), b = sum((n - i) * x[i]), mod 2^16
+#[derive(Debug, Clone)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let len = data.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, x) in data.iter().enumerate() {
+            a = a.wrapping_add(*x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*x as u32));
+        }
+        Self { a, b, len }
+    }
+
+    #[inline]
+    fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+
+    #[inline]
+    fn roll(&mut self, out: u8, inp: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inp as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+}
+
+/// Signatures of `basis_size` bytes read from `reader`.
+pub fn signatures<R: Read>(mut reader: R, basis_size: u64) -> io::Result<FileDeltaSignatures> {
+    let block_size = block_size_for(basis_size);
+    let mut blocks = Vec::new();
+    let mut buf = vec![0u8; block_size as usize];
+    loop {
+        let mut n = 0;
+        while n < buf.len() {
+            let m = reader.read(&mut buf[n..])?;
+            if m == 0 {
+                break;
+            }
+            n += m;
+        }
+        if n == 0 {
+            break;
+        }
+        blocks.push(FileBlockSignature {
+            weak: Rolling::new(&buf[..n]).value(),
+            strong: strong_hash(&buf[..n]).into(),
+            ..Default::default()
+        });
+        if n < buf.len() {
+            break;
+        }
+    }
+    Ok(FileDeltaSignatures {
+        block_size,
+        basis_size,
+        blocks,
+        ..Default::default()
+    })
+}
+
+pub fn file_signatures(path: &Path) -> io::Result<FileDeltaSignatures> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    signatures(io::BufReader::new(file), size)
+}
+
+/// Literal data followed by blocks of the receiver's file.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeltaChunk {
+    pub literal: Vec<u8>,
+    pub copies: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct DeltaEncoder {
+    block_size: usize,
+    basis_size: u64,
+    // weak checksum -> (block index, strong hash)
+    blocks: HashMap<u32, Vec<(u32, Vec<u8>)>>,
+    buf: Vec<u8>,
+    pos: usize,
+    rolling: Option<Rolling>,
+    chunk: DeltaChunk,
+    eof: bool,
+}
+
+impl DeltaEncoder {
+    pub fn new(sigs: &FileDeltaSignatures) -> Option<Self> {
+        if sigs.block_size == 0 || sigs.blocks.is_empty() {
+            return None;
+        }
+        let mut blocks: HashMap<u32, Vec<(u32, Vec<u8>)>> = HashMap::new();
+        for (i, b) in sigs.blocks.iter().enumerate() {
+            blocks
+                .entry(b.weak)
+                .or_default()
+                .push((i as u32, b.strong.to_vec()));
+        }
+        Some(Self {
+            block_size: sigs.block_size as usize,
+            basis_size: sigs.basis_size,
+            blocks,
+            buf: Vec::new(),
+            pos: 0,
+            rolling: None,
+            chunk: DeltaChunk::default(),
+            eof: false,
+        })
+    }
+
+    #[inline]
+    pub fn block_size(&self) -> u32 {
+        self.block_size as _
+    }
+
+    #[inline]
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// No more data will be fed.
+    #[inline]
+    pub fn finish(&mut self) {
+        self.eof = true;
+    }
+
+    /// All data fed so far has been returned in chunks.
+    pub fn is_done(&self) -> bool {
+        self.eof
+            && self.pos == self.buf.len()
+            && self.chunk.literal.is_empty()
+            && self.chunk.copies.is_empty()
+    }
+
+    /// Number of bytes of the receiver's file the copies stand for.
+    pub fn copied_len(&self, copies: &[u32]) -> u64 {
+        copies.iter().map(|i| self.block_len(*i) as u64).sum()
+    }
+
+    fn block_len(&self, index: u32) -> usize {
+        let offset = index as u64 * self.block_size as u64;
+        (self.basis_size.saturating_sub(offset) as usize).min(self.block_size)
+    }
+
+    fn find(&self, weak: u32, window: &[u8]) -> Option<u32> {
+        let candidates = self.blocks.get(&weak)?;
+        let strong = strong_hash(window);
+        candidates
+            .iter()
+            .find(|(i, s)| *s == strong && self.block_len(*i) == window.len())
+            .map(|(i, _)| *i)
+    }
+
+    fn take_chunk(&mut self) -> Option<DeltaChunk> {
+        Some(std::mem::take(&mut self.chunk))
+    }
+
+    /// The next chunk, or `None` if more data has to be fed first (or,
+    /// after `finish`, if everything has been returned).
+    pub fn next_chunk(&mut self) -> Option<DeltaChunk> {
+        let bs = self.block_size;
+        loop {
+            let avail = self.buf.len() - self.pos;
+            if avail == 0 || (avail < bs && !self.eof) {
+                break;
+            }
+            let n = avail.min(bs);
+            let weak = match &self.rolling {
+                Some(r) if n == bs => r.value(),
+                _ => {
+                    let r = Rolling::new(&self.buf[self.pos..self.pos + n]);
+                    let weak = r.value();
+                    self.rolling = if n == bs { Some(r) } else { None };
+                    weak
+                }
+            };
+            if let Some(index) = self.find(weak, &self.buf[self.pos..self.pos + n]) {
+                self.chunk.copies.push(index);
+                self.pos += n;
+                self.rolling = None;
+                if self.chunk.copies.len() >= MAX_COPIES {
+                    return self.take_chunk();
+                }
+            } else {
+                // Literal data goes before the copies of a chunk
+                if !self.chunk.copies.is_empty() {
+                    return self.take_chunk();
+                }
+                let out = self.buf[self.pos];
+                self.chunk.literal.push(out);
+                let next = self.pos + bs;
+                match self.rolling.as_mut() {
+                    Some(r) if n == bs && next < self.buf.len() => r.roll(out, self.buf[next]),
+                    _ => self.rolling = None,
+                }
+                self.pos += 1;
+                if self.chunk.literal.len() >= MAX_LITERAL_LEN {
+                    return self.take_chunk();
+                }
+            }
+        }
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        if self.eof && (!self.chunk.literal.is_empty() || !self.chunk.copies.is_empty()) {
+            return self.take_chunk();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn apply(basis: &[u8], block_size: usize, chunk: &DeltaChunk, out: &mut Vec<u8>) {
+        out.extend_from_slice(&chunk.literal);
+        for i in chunk.copies.iter() {
+            let start = *i as usize * block_size;
+            out.extend_from_slice(&basis[start..(start + block_size).min(basis.len())]);
+        }
+    }
+
+    #[test]
+    fn test_rolling() {
+        let d = data(100, 1);
+        let mut r = Rolling::new(&d[0..32]);
+        for i in 0..60 {
+            r.roll(d[i], d[i + 32]);
+            assert_eq!(r.value(), Rolling::new(&d[i + 1..i + 33]).value());
+        }
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let basis = data(300_000, 7);
+        let mut target = basis.clone();
+        // Changed in place, inserted and removed data
+        target[1000..1100].copy_from_slice(&data(100, 8));
+        target.splice(150_000..150_000, data(777, 9));
+        target.drain(250_000..251_234);
+        target.extend_from_slice(&data(50, 10));
+
+        let sigs = signatures(&basis[..], basis.len() as u64).unwrap();
+        let mut encoder = DeltaEncoder::new(&sigs).unwrap();
+        let mut out = Vec::new();
+        let mut literal = 0;
+        for part in target.chunks(65_537) {
+            encoder.feed(part);
+            while let Some(chunk) = encoder.next_chunk() {
+                literal += chunk.literal.len();
+                apply(&basis, sigs.block_size as _, &chunk, &mut out);
+            }
+        }
+        encoder.finish();
+        while let Some(chunk) = encoder.next_chunk() {
+            literal += chunk.literal.len();
+            apply(&basis, sigs.block_size as _, &chunk, &mut out);
+        }
+        assert!(encoder.is_done());
+        assert_eq!(out, target);
+        assert!(literal < 10 * sigs.block_size as usize, "{}", literal);
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let path = std::env::temp_dir().join("hbb_common_test_hash_file");
+        let d = data(3 * 1024 * 1024 + 5, 3);
+        std::fs::write(&path, &d).unwrap();
+        let mut hasher = ContentHasher::default();
+        hasher.update(&d);
+        assert_eq!(hash_file(&path).unwrap(), hasher.finish());
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,210 @@
+// Rate limiting of file transfer blocks.
+//
+// Every block read by `handle_read_jobs` takes tokens from the job's bucket
+// and from a process wide one. The global rate comes from
+// `OPTION_FILE_TRANSFER_RATE_LIMIT` and is lowered while the video stream
+// suffers from high network delay, so that file traffic doesn't starve it.
+use crate::config::{keys, Config};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Tokens that may pile up while idle, in seconds of the rate
+const BURST_SECS: f64 = 0.25;
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Global rate while deprioritised if no limit is configured, in bytes/s.
+pub const DEPRIORITIZED_RATE: u64 = 128 * 1024;
+
+/// Token bucket, 0 bytes/s for no limit.
+///
+/// A block may take more tokens than are available, the debt then delays
+/// the following blocks. So block sizes don't need to fit into the burst.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            tokens: 0.,
+            last: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: u64) {
+        if rate != self.rate {
+            self.refill();
+            self.rate = rate;
+            self.tokens = self.tokens.min(self.burst());
+        }
+    }
+
+    #[inline]
+    fn burst(&self) -> f64 {
+        self.rate as f64 * BURST_SECS
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst());
+    }
+
+    /// Whether the next block may be sent.
+    pub fn ready(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        self.refill();
+        self.tokens >= 0.
+    }
+
+    pub fn consume(&mut self, n: usize) {
+        if self.rate > 0 {
+            self.refill();
+            self.tokens -= n as f64;
+        }
+    }
+}
+
+struct GlobalLimiter {
+    limiter: RateLimiter,
+    configured: u64,
+    deprioritized: bool,
+    checked: Option<Instant>,
+}
+
+impl GlobalLimiter {
+    fn update_rate(&mut self) {
+        let rate = if self.deprioritized {
+            if self.configured == 0 {
+                DEPRIORITIZED_RATE
+            } else {
+                (self.configured / 4).min(DEPRIORITIZED_RATE)
+            }
+        } else {
+            self.configured
+        };
+        self.limiter.set_rate(rate);
+    }
+
+    fn check_config(&mut self) {
+        if self
+            .checked
+            .map(|t| t.elapsed() < CONFIG_CHECK_INTERVAL)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.checked = Some(Instant::now());
+        self.configured = option_rate(keys::OPTION_FILE_TRANSFER_RATE_LIMIT);
+        self.update_rate();
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref GLOBAL: Mutex<GlobalLimiter> = Mutex::new(GlobalLimiter {
+        limiter: RateLimiter::default(),
+        configured: 0,
+        deprioritized: false,
+        checked: None,
+    });
+}
+
+/// Option values are in KiB/s.
+fn option_rate(key: &str) -> u64 {
+    Config::get_option(key).trim().parse::<u64>().unwrap_or(0) * 1024
+}
+
+/// Limit of new jobs, in bytes/s.
+pub fn default_job_rate() -> u64 {
+    option_rate(keys::OPTION_FILE_TRANSFER_JOB_RATE_LIMIT)
+}
+
+/// Lower the global rate, e.g. while the video stream has high delay.
+pub fn set_deprioritized(v: bool) {
+    let mut global = GLOBAL.lock().unwrap();
+    if global.deprioritized != v {
+        log::info!("file transfer deprioritized: {}", v);
+        global.deprioritized = v;
+        global.update_rate();
+    }
+}
+
+pub fn is_deprioritized() -> bool {
+    GLOBAL.lock().unwrap().deprioritized
+}
+
+pub fn global_ready() -> bool {
+    let mut global = GLOBAL.lock().unwrap();
+    global.check_config();
+    global.limiter.ready()
+}
+
+pub fn global_consume(n: usize) {
+    GLOBAL.lock().unwrap().limiter.consume(n);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..100 {
+            assert!(limiter.ready());
+            limiter.consume(1024 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_rate() {
+        let rate = 1024 * 1024;
+        let mut limiter = RateLimiter::new(rate);
+        let start = Instant::now();
+        let mut sent = 0;
+        while start.elapsed() < Duration::from_millis(500) {
+            if limiter.ready() {
+                limiter.consume(64 * 1024);
+                sent += 64 * 1024;
+            } else {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let expected = rate as f64 * start.elapsed().as_secs_f64();
+        // One block of debt plus the burst at most
+        assert!(sent as f64 <= expected + 64. * 1024. + rate as f64 * BURST_SECS);
+        assert!(sent as f64 >= expected * 0.8, "{} {}", sent, expected);
+    }
+
+    #[test]
+    fn test_debt() {
+        let mut limiter = RateLimiter::new(100 * 1024);
+        assert!(limiter.ready());
+        limiter.consume(50 * 1024);
+        assert!(!limiter.ready());
+        // Lowering the rate keeps the debt
+        limiter.set_rate(10 * 1024);
+        assert!(!limiter.ready());
+        limiter.set_rate(0);
+        assert!(limiter.ready());
+    }
+}
@@ -198,12 +198,14 @@ impl Encoder {
             decodings.len() > 0 && decodings.iter().all(|(_, s)| s.ability_h264 > 0);
         let _all_support_h265_decoding =
             decodings.len() > 0 && decodings.iter().all(|(_, s)| s.ability_h265 > 0);
+        // Encrypted recordings are WebM only, see `Recorder`
+        let _hw_recordable = !encrypted_record_incoming();
         #[allow(unused_mut)]
         let mut h264vram_encoding = false;
         #[allow(unused_mut)]
         let mut h265vram_encoding = false;
         #[cfg(feature = "vram")]
-        if enable_vram_option(true) {
+        if enable_vram_option(true) && _hw_recordable {
             if _all_support_h264_decoding {
                 if VRamEncoder::available(CodecFormat::H264).len() > 0 {
                     h264vram_encoding = true;
@@ -220,7 +222,7 @@ impl Encoder {
         #[allow(unused_mut)]
         let mut h265hw_encoding: Option<String> = None;
         #[cfg(feature = "hwcodec")]
-        if enable_hwcodec_option() {
+        if enable_hwcodec_option() && _hw_recordable {
             if _all_support_h264_decoding {
                 h264hw_encoding =
                     HwRamEncoder::try_get(CodecFormat::H264).map_or(None, |c| Some(c.name));
@@ -1030,6 +1032,18 @@ pub fn codec_thread_num(limit: usize) -> usize {
     res
 }
 
+// Incoming sessions are recorded for an encryption key
+fn encrypted_record_incoming() -> bool {
+    use hbb_common::config::keys::{
+        OPTION_ALLOW_AUTO_RECORD_INCOMING, OPTION_RECORD_ENCRYPTION_KEY,
+    };
+
+    option2bool(
+        OPTION_ALLOW_AUTO_RECORD_INCOMING,
+        &Config::get_option(OPTION_ALLOW_AUTO_RECORD_INCOMING),
+    ) && !Config::get_option(OPTION_RECORD_ENCRYPTION_KEY).is_empty()
+}
+
 fn disable_av1() -> bool {
     // aom is very slow for x86 sciter version on windows x64
     // disable it for all 32 bit platforms
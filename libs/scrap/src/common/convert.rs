@@ -14,8 +14,8 @@ use hbb_common::{bail, log, ResultType};
 generate_call_macro!(call_yuv, false);
 
 #[cfg(not(target_os = "ios"))]
-pub fn convert_to_yuv(
-    captured: &PixelBuffer,
+pub fn convert_to_yuv<P: TraitPixelBuffer>(
+    captured: &P,
     dst_fmt: EncodeYuvFormat,
     dst: &mut Vec<u8>,
     mid_data: &mut Vec<u8>,
@@ -18,7 +18,7 @@ cfg_if! {
                 mod x11;
                 pub use self::linux::*;
                 pub use self::wayland::set_map_err;
-                pub use self::x11::PixelBuffer;
+                pub use self::x11::{PixelBuffer, WindowCapturer};
             } else {
                 mod x11;
                 pub use self::x11::*;
@@ -51,7 +51,15 @@ pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 pub mod aom;
 #[cfg(not(any(target_os = "ios")))]
 pub mod camera;
+#[cfg(not(any(target_os = "ios")))]
+pub mod synthetic;
+pub mod redact;
 pub mod record;
+pub mod record_cast;
+pub mod record_crypt;
+pub mod record_event;
+pub mod record_retention;
+pub mod record_transcode;
 mod vpx;
 
 #[repr(usize)]
@@ -195,7 +203,7 @@ impl Frame<'_> {
     ) -> ResultType<EncodeInput<'a>> {
         match self {
             Frame::PixelBuffer(pixelbuffer) => {
-                convert_to_yuv(&pixelbuffer, yuvfmt, yuv, mid_data)?;
+                convert_to_yuv(pixelbuffer, yuvfmt, yuv, mid_data)?;
                 Ok(EncodeInput::YUV(yuv))
             }
             Frame::Texture(texture) => Ok(EncodeInput::Texture(*texture)),
@@ -1,24 +1,40 @@
-use crate::CodecFormat;
-#[cfg(feature = "hwcodec")]
+use crate::{
+    record_crypt::{encrypted_filename, parse_public_key, RecordFile},
+    record_event::{EventLog, RecordEvent, RecordEventType},
+    record_retention::{self, ActiveFiles},
+    CodecFormat,
+};
+#[cfg(any(feature = "hwcodec", test))]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono, log,
     message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    sodiumoxide::crypto::box_::PublicKey,
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
+#[cfg(any(feature = "hwcodec", test))]
+use std::io::Write;
 use std::{
     fs::{File, OpenOptions},
     io,
     ops::{Deref, DerefMut},
     path::PathBuf,
     sync::mpsc::Sender,
-    time::Instant,
+    time::{Duration, Instant},
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+// Opus is always decoded at 48 kHz in containers
+const OPUS_SAMPLE_RATE: u64 = 48000;
+// Audio later than its running timestamp by more than this is restamped,
+// i.e. after the peer stopped sending audio for a while
+const AUDIO_RESYNC_NS: u64 = 60_000_000;
+// Events kept until the first file is created
+const MAX_PENDING_EVENTS: usize = 256;
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -28,6 +44,8 @@ pub struct RecorderContext {
     pub display_idx: usize,
     pub camera: bool,
     pub tx: Option<Sender<RecordState>>,
+    /// Base64 public key the files are encrypted for, empty for plain files.
+    pub encryption_key: String,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +54,8 @@ pub struct RecorderContext2 {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    pub audio_channels: u32,
+    pub public_key: Option<PublicKey>,
 }
 
 impl RecorderContext2 {
@@ -61,10 +81,11 @@ impl RecorderContext2 {
             } else {
                 ".mp4"
             };
-        self.filename = PathBuf::from(&ctx.dir)
+        let filename = PathBuf::from(&ctx.dir)
             .join(file)
             .to_string_lossy()
             .to_string();
+        self.filename = encrypted_filename(&filename, self.public_key.is_some());
         Ok(())
     }
 }
@@ -77,6 +98,8 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    // `ns`: on the clock of the video pts
+    fn write_audio(&mut self, data: &[u8], ns: u64) -> bool;
 }
 
 #[derive(Debug)]
@@ -89,10 +112,23 @@ pub enum RecordState {
 
 pub struct Recorder {
     pub inner: Option<Box<dyn RecorderApi>>,
+    // After `inner`, so that it's dropped after the video is removed
+    events: Option<EventLog>,
+    // Logins of the connections still open, repeated in every file
+    logins: Vec<RecordEvent>,
+    pending_events: Vec<RecordEvent>,
     ctx: RecorderContext,
     ctx2: Option<RecorderContext2>,
     pts: Option<i64>,
     check_failed: bool,
+    // Time and pts of the first video frame of the file
+    clock: Option<(Instant, i64)>,
+    audio_next_ns: Option<u64>,
+    audio_channels: u32,
+    public_key: Option<PublicKey>,
+    disk_low: bool,
+    disk_checked: Option<Instant>,
+    disk_notice: Option<bool>,
 }
 
 impl Deref for Recorder {
@@ -111,15 +147,75 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(ctx: RecorderContext) -> ResultType<Self> {
+        // Never fall back to plain files
+        let public_key = parse_public_key(&ctx.encryption_key)?;
         Ok(Self {
             inner: None,
+            events: None,
+            logins: vec![],
+            pending_events: vec![],
             ctx,
             ctx2: None,
             pts: None,
             check_failed: false,
+            clock: None,
+            audio_next_ns: None,
+            audio_channels: 2,
+            public_key,
+            disk_low: false,
+            disk_checked: None,
+            disk_notice: None,
         })
     }
 
+    /// Channels of the Opus stream of `write_audio`. The audio track of a
+    /// file can't change, so a change goes on in a new file.
+    pub fn set_audio_channels(&mut self, channels: u32) {
+        let channels = channels.clamp(1, 2);
+        if channels == self.audio_channels {
+            return;
+        }
+        self.audio_channels = channels;
+        if self.inner.is_some() {
+            log::info!("Audio channels changed to {channels}, starting a new file");
+            self.inner = None;
+            self.ctx2 = None;
+            self.events = None;
+        }
+    }
+
+    /// `Some(true)` if recording paused for low disk space since the last
+    /// call, `Some(false)` if it resumed.
+    pub fn take_disk_notice(&mut self) -> Option<bool> {
+        self.disk_notice.take()
+    }
+
+    // The file is finished while the disk is low on space, a new one is
+    // started once there is space again
+    fn check_disk(&mut self) -> bool {
+        if self
+            .disk_checked
+            .map_or(false, |t| t.elapsed() < DISK_CHECK_INTERVAL)
+        {
+            return !self.disk_low;
+        }
+        self.disk_checked = Some(Instant::now());
+        let low = record_retention::is_disk_low(&self.ctx.dir);
+        if low != self.disk_low {
+            self.disk_low = low;
+            self.disk_notice = Some(low);
+            if low {
+                log::warn!("Low disk space in {}, recording paused", self.ctx.dir);
+                self.inner = None;
+                self.ctx2 = None;
+                self.events = None;
+            } else {
+                log::info!("Recording resumed");
+            }
+        }
+        !low
+    }
+
     fn check(&mut self, w: usize, h: usize, format: CodecFormat) -> ResultType<()> {
         match self.ctx2 {
             Some(ref ctx2) => {
@@ -129,6 +225,8 @@ impl Recorder {
                         height: h,
                         format,
                         filename: Default::default(),
+                        audio_channels: self.audio_channels,
+                        public_key: self.public_key.clone(),
                     };
                     ctx2.set_filename(&self.ctx)?;
                     self.ctx2 = Some(ctx2);
@@ -141,6 +239,8 @@ impl Recorder {
                     height: h,
                     format,
                     filename: Default::default(),
+                    audio_channels: self.audio_channels,
+                    public_key: self.public_key.clone(),
                 };
                 ctx2.set_filename(&self.ctx)?;
                 self.ctx2 = Some(ctx2);
@@ -155,6 +255,11 @@ impl Recorder {
                 CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => Some(Box::new(
                     WebmRecorder::new(self.ctx.clone(), (*ctx2).clone())?,
                 )),
+                // The hardware muxer writes by path, so there would be a plain
+                // file on disk until it's finished
+                _ if ctx2.public_key.is_some() => {
+                    bail!("Encrypted recordings need VP8, VP9 or AV1, not {format:?}")
+                }
                 #[cfg(feature = "hwcodec")]
                 _ => Some(Box::new(HwRecorder::new(
                     self.ctx.clone(),
@@ -165,11 +270,75 @@ impl Recorder {
             };
             // pts is None when new inner is created
             self.pts = None;
-            self.send_state(RecordState::NewFile(ctx2.filename.clone()));
+            self.clock = None;
+            self.audio_next_ns = None;
+            self.events = None;
+            let filename = ctx2.filename.clone();
+            self.open_events();
+            self.send_state(RecordState::NewFile(filename));
         }
         Ok(())
     }
 
+    // Start the sidecar of a new file with the current logins
+    fn open_events(&mut self) {
+        let mut events = self.logins.clone();
+        for e in self.pending_events.drain(..) {
+            if !events.contains(&e) {
+                events.push(e);
+            }
+        }
+        if events.is_empty() {
+            return;
+        }
+        events.sort_by_key(|e| e.time);
+        for mut e in events {
+            e.offset = 0;
+            self.write_event_to_file(e);
+        }
+    }
+
+    fn write_event_to_file(&mut self, event: RecordEvent) {
+        if self.events.is_none() {
+            let Some(ctx2) = &self.ctx2 else {
+                return;
+            };
+            match EventLog::new(&ctx2.filename, ctx2.public_key.as_ref()) {
+                Ok(log) => self.events = Some(log),
+                Err(e) => {
+                    log::error!("Failed to create record event file: {e}");
+                    return;
+                }
+            }
+        }
+        if let Some(log) = self.events.as_mut() {
+            log.write(&event);
+        }
+    }
+
+    /// Write a session event to the sidecar of the current file.
+    ///
+    /// Events before the first video frame go to the first file, logins of
+    /// connections that are still open are repeated in every new file.
+    pub fn write_event(&mut self, mut event: RecordEvent) {
+        match event.event {
+            RecordEventType::Login => self.logins.push(event.clone()),
+            RecordEventType::Close => self.logins.retain(|e| e.conn_id != event.conn_id),
+            _ => {}
+        }
+        if self.inner.is_none() {
+            if self.pending_events.len() < MAX_PENDING_EVENTS {
+                self.pending_events.push(event);
+            }
+            return;
+        }
+        event.offset = self
+            .clock
+            .map(|(instant, _)| instant.elapsed().as_millis() as _)
+            .unwrap_or_default();
+        self.write_event_to_file(event);
+    }
+
     pub fn write_message(&mut self, msg: &Message, w: usize, h: usize) {
         if let Some(message::Union::VideoFrame(vf)) = &msg.union {
             if let Some(frame) = &vf.union {
@@ -178,6 +347,31 @@ impl Recorder {
         }
     }
 
+    /// Write an Opus packet of an `AudioFrame`.
+    ///
+    /// Packets are stamped with the time since the first video frame on the
+    /// video pts clock, which goes on while the screen is static, so audio
+    /// and video stay in sync across pauses of either. Packets arriving in a
+    /// burst follow each other by their duration.
+    pub fn write_audio(&mut self, data: &[u8]) {
+        if self.check_failed {
+            return;
+        }
+        let Some((instant, pts)) = self.clock else {
+            return;
+        };
+        let Some(samples) = opus_packet_samples(data) else {
+            return;
+        };
+        let now = pts.max(0) as u64 * 1_000_000 + instant.elapsed().as_nanos() as u64;
+        let ns = match self.audio_next_ns {
+            Some(next) if now < next + AUDIO_RESYNC_NS => next,
+            _ => now,
+        };
+        self.audio_next_ns = Some(ns + samples as u64 * 1_000_000_000 / OPUS_SAMPLE_RATE);
+        self.as_mut().map(|x| x.write_audio(data, ns));
+    }
+
     pub fn write_frame(
         &mut self,
         frame: &video_frame::Union,
@@ -191,6 +385,9 @@ impl Recorder {
         if format == CodecFormat::Unknown {
             bail!("unsupported frame type");
         }
+        if !self.check_disk() {
+            return Ok(());
+        }
         let res = self.check(w, h, format);
         if res.is_err() {
             self.check_failed = true;
@@ -262,6 +459,9 @@ impl Recorder {
             }
             self.pts = Some(pts);
         }
+        if self.clock.is_none() {
+            self.clock = Some((Instant::now(), pts));
+        }
         Ok(())
     }
 
@@ -270,14 +470,95 @@ impl Recorder {
     }
 }
 
+/// Samples at 48 kHz in an Opus packet, RFC 6716 3.1
+fn opus_packet_samples(data: &[u8]) -> Option<u32> {
+    let toc = *data.first()?;
+    let config = toc >> 3;
+    // 2.5, 5, 10, 20, 40 and 60 ms
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        12..=15 => [480, 960][(config % 2) as usize],
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    };
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*data.get(1)? & 0x3f) as u32,
+    };
+    Some(frame * frames)
+}
+
+/// Identification header of Ogg Opus, also the CodecPrivate of WebM
+fn opus_head(channels: u32) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(channels as u8);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&(OPUS_SAMPLE_RATE as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+// A segment with a video track, and an Opus track if `audio_channels` is set
+pub(crate) fn webm_segment<W: io::Write + io::Seek>(
+    out: W,
+    width: usize,
+    height: usize,
+    format: CodecFormat,
+    audio_channels: Option<u32>,
+) -> ResultType<(Segment<Writer<W>>, VideoTrack, Option<AudioTrack>)> {
+    let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
+        Some(v) => v,
+        None => bail!("Failed to create webm mux"),
+    };
+    let vt = webm.add_video_track(
+        width as _,
+        height as _,
+        None,
+        if format == CodecFormat::VP9 {
+            mux::VideoCodecId::VP9
+        } else if format == CodecFormat::VP8 {
+            mux::VideoCodecId::VP8
+        } else {
+            mux::VideoCodecId::AV1
+        },
+    );
+    if format == CodecFormat::AV1 {
+        // [129, 8, 12, 0] in 3.6.0, but zero works
+        let codec_private = vec![0, 0, 0, 0];
+        if !webm.set_codec_private(vt.track_number(), &codec_private) {
+            bail!("Failed to set codec private");
+        }
+    }
+    let at = match audio_channels {
+        Some(channels) => {
+            let at = webm.add_audio_track(
+                OPUS_SAMPLE_RATE as _,
+                channels as _,
+                None,
+                mux::AudioCodecId::Opus,
+            );
+            if !webm.set_codec_private(at.track_number(), &opus_head(channels)) {
+                bail!("Failed to set codec private");
+            }
+            Some(at)
+        }
+        None => None,
+    };
+    Ok((webm, vt, at))
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
-    webm: Option<Segment<Writer<File>>>,
+    at: AudioTrack,
+    webm: Option<Segment<Writer<RecordFile>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     key: bool,
     written: bool,
     start: Instant,
+    _active: ActiveFiles,
 }
 
 impl RecorderApi for WebmRecorder {
@@ -292,37 +573,27 @@ impl RecorderApi for WebmRecorder {
             Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx2.filename)?,
             Err(e) => return Err(e.into()),
         };
-        let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
-            Some(v) => v,
-            None => bail!("Failed to create webm mux"),
+        let out = RecordFile::new(out, ctx2.public_key.as_ref())?;
+        let (webm, vt, at) = webm_segment(
+            out,
+            ctx2.width,
+            ctx2.height,
+            ctx2.format,
+            Some(ctx2.audio_channels),
+        )?;
+        let Some(at) = at else {
+            bail!("Failed to add audio track");
         };
-        let vt = webm.add_video_track(
-            ctx2.width as _,
-            ctx2.height as _,
-            None,
-            if ctx2.format == CodecFormat::VP9 {
-                mux::VideoCodecId::VP9
-            } else if ctx2.format == CodecFormat::VP8 {
-                mux::VideoCodecId::VP8
-            } else {
-                mux::VideoCodecId::AV1
-            },
-        );
-        if ctx2.format == CodecFormat::AV1 {
-            // [129, 8, 12, 0] in 3.6.0, but zero works
-            let codec_private = vec![0, 0, 0, 0];
-            if !webm.set_codec_private(vt.track_number(), &codec_private) {
-                bail!("Failed to set codec private");
-            }
-        }
         Ok(WebmRecorder {
             vt,
+            at,
             webm: Some(webm),
             ctx,
-            ctx2,
             key: false,
             written: false,
             start: Instant::now(),
+            _active: ActiveFiles::new(vec![ctx2.filename.clone()]),
+            ctx2,
         })
     }
 
@@ -342,6 +613,11 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], ns: u64) -> bool {
+        // Clusters start with a video key frame
+        self.key && self.at.add_frame(data, ns, true)
+    }
 }
 
 impl Drop for WebmRecorder {
@@ -359,11 +635,14 @@ impl Drop for WebmRecorder {
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Option<Muxer>,
+    // The hardware muxer has no audio, which goes to `<filename>.opus`
+    audio: Option<OggOpusWriter<RecordFile>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     written: bool,
     key: bool,
     start: Instant,
+    _active: ActiveFiles,
 }
 
 #[cfg(feature = "hwcodec")]
@@ -379,6 +658,8 @@ impl RecorderApi for HwRecorder {
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
         Ok(HwRecorder {
             muxer: Some(muxer),
+            audio: None,
+            _active: ActiveFiles::new(vec![ctx2.filename.clone()]),
             ctx,
             ctx2,
             written: false,
@@ -405,19 +686,264 @@ impl RecorderApi for HwRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], ns: u64) -> bool {
+        if !self.key {
+            return false;
+        }
+        if self.audio.is_none() {
+            let audio = RecordFile::create(&self.audio_filename(), self.ctx2.public_key.as_ref())
+                .map_err(|e| e.into())
+                .and_then(|f| OggOpusWriter::new(f, self.ctx2.audio_channels));
+            match audio {
+                Ok(audio) => self.audio = Some(audio),
+                Err(e) => {
+                    log::error!("Failed to create audio file: {e}");
+                    return false;
+                }
+            }
+        }
+        self.audio
+            .as_mut()
+            .map(|a| a.write(data, ns).is_ok())
+            .unwrap_or_default()
+    }
 }
 
 #[cfg(feature = "hwcodec")]
 impl Drop for HwRecorder {
     fn drop(&mut self) {
         self.muxer.as_mut().map(|m| m.write_tail().ok());
+        let has_audio = self.audio.take().map(|a| a.finish().is_ok()).is_some();
         let mut state = RecordState::WriteTail;
         if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
             // The process cannot access the file because it is being used by another process
             self.muxer = None;
             std::fs::remove_file(&self.ctx2.filename).ok();
+            if has_audio {
+                std::fs::remove_file(self.audio_filename()).ok();
+            }
             state = RecordState::RemoveFile;
         }
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+#[cfg(feature = "hwcodec")]
+impl HwRecorder {
+    fn audio_filename(&self) -> String {
+        encrypted_filename(
+            &format!("{}.opus", self.ctx2.filename),
+            self.ctx2.public_key.is_some(),
+        )
+    }
+}
+
+// Ogg pages are flushed when they hold this much audio
+#[cfg(any(feature = "hwcodec", test))]
+const OGG_PAGE_NS: u64 = 1_000_000_000;
+// Below the 255 lacing values of a page, with room for large packets
+#[cfg(any(feature = "hwcodec", test))]
+const OGG_PAGE_MAX_SEGMENTS: usize = 200;
+// A 20 ms CELT frame of silence
+#[cfg(any(feature = "hwcodec", test))]
+const OPUS_SILENCE: [u8; 3] = [0xf8, 0xff, 0xfe];
+#[cfg(any(feature = "hwcodec", test))]
+const OPUS_SILENCE_NS: u64 = 20_000_000;
+
+/// Ogg Opus stream, RFC 7845.
+///
+/// Gaps before a packet are filled with silence, so that the stream starts
+/// with the video and players keep both in sync.
+#[cfg(any(feature = "hwcodec", test))]
+struct OggOpusWriter<W: Write> {
+    out: W,
+    serial: u32,
+    sequence: u32,
+    // Granule position at the end of the written packets
+    granule: u64,
+    segments: Vec<u8>,
+    body: Vec<u8>,
+    page_start: u64,
+}
+
+#[cfg(any(feature = "hwcodec", test))]
+impl<W: Write> OggOpusWriter<W> {
+    fn new(out: W, channels: u32) -> ResultType<Self> {
+        let mut writer = Self {
+            out,
+            serial: hbb_common::rand::random(),
+            sequence: 0,
+            granule: 0,
+            segments: vec![],
+            body: vec![],
+            page_start: 0,
+        };
+        writer.push(&opus_head(channels));
+        writer.flush_page(0x02)?;
+        let vendor = b"RustDesk";
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer.push(&tags);
+        writer.flush_page(0)?;
+        Ok(writer)
+    }
+
+    fn granule_ns(&self) -> u64 {
+        self.granule * 1_000_000_000 / OPUS_SAMPLE_RATE
+    }
+
+    fn write(&mut self, data: &[u8], ns: u64) -> ResultType<()> {
+        let samples = opus_packet_samples(data).ok_or(anyhow!("Invalid opus packet"))?;
+        while self.granule_ns() + OPUS_SILENCE_NS <= ns {
+            self.write_packet(&OPUS_SILENCE, 960)?;
+        }
+        self.write_packet(data, samples)
+    }
+
+    fn write_packet(&mut self, data: &[u8], samples: u32) -> ResultType<()> {
+        if self.segments.len() + data.len() / 255 + 1 > OGG_PAGE_MAX_SEGMENTS {
+            self.flush_page(0)?;
+        }
+        self.push(data);
+        self.granule += samples as u64;
+        if self.granule_ns() >= self.page_start + OGG_PAGE_NS {
+            self.flush_page(0)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let mut len = data.len();
+        while len >= 255 {
+            self.segments.push(255);
+            len -= 255;
+        }
+        self.segments.push(len as u8);
+        self.body.extend_from_slice(data);
+    }
+
+    fn flush_page(&mut self, header_type: u8) -> ResultType<()> {
+        if self.segments.is_empty() && header_type & 0x04 == 0 {
+            return Ok(());
+        }
+        let mut page = b"OggS".to_vec();
+        page.push(0); // version
+        page.push(header_type);
+        page.extend_from_slice(&self.granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes()); // crc
+        page.push(self.segments.len() as u8);
+        page.append(&mut self.segments);
+        page.append(&mut self.body);
+        let crc = ogg_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.out.write_all(&page)?;
+        self.sequence += 1;
+        self.page_start = self.granule_ns();
+        Ok(())
+    }
+
+    fn finish(mut self) -> ResultType<()> {
+        self.flush_page(0x04)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "hwcodec", test))]
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for b in data {
+        crc ^= (*b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_opus_packet_samples() {
+        // SILK 20 ms, one frame
+        assert_eq!(opus_packet_samples(&[1 << 3]), Some(960));
+        // CELT FB 20 ms, two frames
+        assert_eq!(opus_packet_samples(&[(31 << 3) | 1]), Some(1920));
+        // CELT 2.5 ms, code 3 with 4 frames
+        assert_eq!(opus_packet_samples(&[(16 << 3) | 3, 4]), Some(480));
+        // Hybrid 10 ms
+        assert_eq!(opus_packet_samples(&[12 << 3]), Some(480));
+        assert_eq!(opus_packet_samples(&OPUS_SILENCE), Some(960));
+        assert_eq!(opus_packet_samples(&[]), None);
+    }
+
+    #[test]
+    fn test_ogg_crc() {
+        // CRC-32/CKSUM without the final xor
+        assert_eq!(ogg_crc(b"123456789"), 0x765e_7680 ^ 0xffff_ffff);
+    }
+
+    fn pages(data: &[u8]) -> Vec<(u8, u64, Vec<u8>)> {
+        let mut pages = vec![];
+        let mut pos = 0;
+        while pos < data.len() {
+            assert_eq!(&data[pos..pos + 4], b"OggS");
+            let header_type = data[pos + 5];
+            let granule = u64::from_le_bytes(data[pos + 6..pos + 14].try_into().unwrap());
+            let n = data[pos + 26] as usize;
+            let body_len: usize = data[pos + 27..pos + 27 + n]
+                .iter()
+                .map(|x| *x as usize)
+                .sum();
+            let end = pos + 27 + n + body_len;
+            let mut page = data[pos..end].to_vec();
+            let crc = u32::from_le_bytes(page[22..26].try_into().unwrap());
+            page[22..26].copy_from_slice(&[0; 4]);
+            assert_eq!(ogg_crc(&page), crc);
+            pages.push((header_type, granule, data[pos + 27 + n..end].to_vec()));
+            pos = end;
+        }
+        pages
+    }
+
+    #[test]
+    fn test_ogg_opus_writer() {
+        let mut out = vec![];
+        let mut writer = OggOpusWriter::new(&mut out, 2).unwrap();
+        let packet = [(31 << 3) as u8, 1, 2, 3];
+        // Starts 100 ms after the video, then 2 s of 20 ms packets
+        for i in 0..100 {
+            writer
+                .write(&packet, 100_000_000 + i * OPUS_SILENCE_NS)
+                .unwrap();
+        }
+        // A pause of one second
+        writer.write(&packet, 3_100_000_000).unwrap();
+        writer.finish().unwrap();
+
+        let pages = pages(&out);
+        assert_eq!(pages[0].0, 0x02);
+        assert_eq!(&pages[0].2[..8], b"OpusHead");
+        assert_eq!(pages[0].2[9], 2);
+        assert_eq!(&pages[1].2[..8], b"OpusTags");
+        let last = pages.last().unwrap();
+        assert_eq!(last.0, 0x04);
+        // 5 + 100 + 50 packets of silence and audio, plus the last one
+        assert_eq!(last.1, (5 + 100 + 50 + 1) * 960);
+        let granules: Vec<u64> = pages.iter().map(|p| p.1).collect();
+        assert!(granules.windows(2).all(|w| w[0] <= w[1]));
+        assert!(pages.len() > 4);
+    }
+}
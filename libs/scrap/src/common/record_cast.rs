@@ -0,0 +1,258 @@
+// Terminal recordings in asciicast v2.
+//
+// A `.cast` file is a JSON header line followed by one `[time, code, data]`
+// line per event: "o" output, "i" input and "r" a resize to "COLSxROWS".
+// The time is in seconds since the start. The files are named, encrypted,
+// swept and uploaded like the video recordings of incoming sessions.
+use crate::{
+    record::RecordState,
+    record_crypt::{encrypted_filename, parse_public_key, RecordFile},
+    record_retention::{self, ActiveFiles},
+};
+use hbb_common::{
+    bail, chrono, log,
+    serde_json::{json, Value},
+    ResultType,
+};
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+/// Extension of terminal recordings, before that of encrypted files.
+pub const CAST_EXT: &str = ".cast";
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct CastContext {
+    pub id: String,
+    pub dir: String,
+    pub terminal_id: i32,
+    pub cols: u16,
+    pub rows: u16,
+    /// For the header, empty if unknown.
+    pub shell: String,
+    pub tx: Option<Sender<RecordState>>,
+    /// Base64 public key the file is encrypted for, empty for a plain file.
+    pub encryption_key: String,
+}
+
+/// Records a terminal of the controlled side, finished on drop.
+pub struct CastRecorder {
+    file: Option<RecordFile>,
+    path: String,
+    dir: String,
+    start: Instant,
+    // Incomplete UTF-8 at the end of the last output and input
+    output_tail: Vec<u8>,
+    input_tail: Vec<u8>,
+    tx: Option<Sender<RecordState>>,
+    disk_checked: Instant,
+    _active: ActiveFiles,
+}
+
+impl CastRecorder {
+    pub fn new(ctx: CastContext) -> ResultType<Self> {
+        std::fs::create_dir_all(&ctx.dir)?;
+        if record_retention::is_disk_low(&ctx.dir) {
+            bail!("Low disk space in {}", ctx.dir);
+        }
+        let pk = parse_public_key(&ctx.encryption_key)?;
+        let now = chrono::Local::now();
+        // Parsed like the names of video recordings
+        let name = format!(
+            "incoming_{}{}terminal{}_asciicast{}",
+            ctx.id,
+            now.format("_%Y%m%d%H%M%S%3f_"),
+            ctx.terminal_id,
+            CAST_EXT
+        );
+        let path = PathBuf::from(&ctx.dir)
+            .join(name)
+            .to_string_lossy()
+            .to_string();
+        let path = encrypted_filename(&path, pk.is_some());
+        let mut file = RecordFile::create(&path, pk.as_ref())?;
+        let mut header = json!({
+            "version": 2,
+            "width": ctx.cols,
+            "height": ctx.rows,
+            "timestamp": now.timestamp(),
+            "env": { "TERM": "xterm-256color" },
+        });
+        if !ctx.shell.is_empty() {
+            header["env"]["SHELL"] = Value::from(ctx.shell.clone());
+        }
+        writeln!(file, "{}", header)?;
+        log::info!("Start terminal recording: {}", path);
+        if let Some(tx) = &ctx.tx {
+            tx.send(RecordState::NewFile(path.clone())).ok();
+        }
+        Ok(Self {
+            file: Some(file),
+            _active: ActiveFiles::new(vec![path.clone()]),
+            path,
+            dir: ctx.dir,
+            start: Instant::now(),
+            output_tail: vec![],
+            input_tail: vec![],
+            tx: ctx.tx,
+            disk_checked: Instant::now(),
+        })
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        let text = take_utf8(&mut self.output_tail, data);
+        self.write_event("o", &text);
+    }
+
+    pub fn input(&mut self, data: &[u8]) {
+        let text = take_utf8(&mut self.input_tail, data);
+        self.write_event("i", &text);
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.write_event("r", &format!("{}x{}", cols, rows));
+    }
+
+    fn write_event(&mut self, code: &str, data: &str) {
+        if data.is_empty() {
+            return;
+        }
+        if self.disk_checked.elapsed() >= DISK_CHECK_INTERVAL {
+            self.disk_checked = Instant::now();
+            if record_retention::is_disk_low(&self.dir) {
+                log::warn!("Low disk space, stop terminal recording: {}", self.path);
+                self.finish();
+            }
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let time = (self.start.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        if let Err(e) = writeln!(file, "{}", json!([time, code, data])) {
+            log::error!("Failed to write terminal recording {}: {}", self.path, e);
+            self.finish();
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            tx.send(RecordState::NewFrame).ok();
+        }
+    }
+
+    fn finish(&mut self) {
+        // Closed before the upload reads the end
+        if self.file.take().is_some() {
+            log::info!("Finish terminal recording: {}", self.path);
+            if let Some(tx) = &self.tx {
+                tx.send(RecordState::WriteTail).ok();
+            }
+        }
+    }
+}
+
+impl Drop for CastRecorder {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// `tail` and `data` as text, an incomplete sequence at the end is kept in `tail`
+fn take_utf8(tail: &mut Vec<u8>, data: &[u8]) -> String {
+    tail.extend_from_slice(data);
+    let keep = incomplete_utf8_len(tail);
+    let text = String::from_utf8_lossy(&tail[..tail.len() - keep]).to_string();
+    tail.drain(..tail.len() - keep);
+    text
+}
+
+fn incomplete_utf8_len(data: &[u8]) -> usize {
+    for i in 1..=data.len().min(3) {
+        let b = data[data.len() - i];
+        // Continuation byte
+        if b & 0xC0 == 0x80 {
+            continue;
+        }
+        let len = match b {
+            0xF0..=0xFF => 4,
+            0xE0..=0xEF => 3,
+            0xC0..=0xDF => 2,
+            _ => 1,
+        };
+        return if len > i { i } else { 0 };
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::serde_json;
+
+    #[test]
+    fn test_take_utf8() {
+        let mut tail = vec![];
+        let e = "é".as_bytes();
+        assert_eq!(take_utf8(&mut tail, &[b'a', e[0]]), "a");
+        assert_eq!(take_utf8(&mut tail, &[e[1], b'b']), "éb");
+        let cjk = "中".as_bytes();
+        assert_eq!(take_utf8(&mut tail, &cjk[..1]), "");
+        assert_eq!(take_utf8(&mut tail, &cjk[1..2]), "");
+        assert_eq!(take_utf8(&mut tail, &cjk[2..]), "中");
+        assert_eq!(take_utf8(&mut tail, &[0xFF, b'c']), "\u{FFFD}c");
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn test_cast_recorder() {
+        let dir = std::env::temp_dir()
+            .join(format!("record_cast_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut recorder = CastRecorder::new(CastContext {
+            id: "123".to_owned(),
+            dir: dir.clone(),
+            terminal_id: 2,
+            cols: 80,
+            rows: 24,
+            shell: "/bin/bash".to_owned(),
+            tx: None,
+            encryption_key: "".to_owned(),
+        })
+        .unwrap();
+        let path = recorder.path.clone();
+        assert!(path.ends_with("_terminal2_asciicast.cast"));
+        recorder.input(b"ls\r");
+        recorder.output("a.txt\r\n".as_bytes());
+        recorder.resize(100, 30);
+        drop(recorder);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(
+            (lines[0]["width"].clone(), lines[0]["height"].clone()),
+            (json!(80), json!(24))
+        );
+        assert_eq!(lines[0]["env"]["SHELL"], "/bin/bash");
+        let events: Vec<_> = lines[1..]
+            .iter()
+            .map(|e| (e[1].as_str().unwrap(), e[2].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![("i", "ls\r"), ("o", "a.txt\r\n"), ("r", "100x30")]
+        );
+        assert!(lines[1][0].as_f64().unwrap() >= 0.);
+
+        let recordings = record_retention::list_recordings(&dir).unwrap();
+        assert_eq!(recordings.len(), 1);
+        assert_eq!(recordings[0].display, "terminal2");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
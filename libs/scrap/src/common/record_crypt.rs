@@ -0,0 +1,393 @@
+// Encryption of recordings at rest.
+//
+// Recordings are encrypted for a public key, so the recording device can't
+// read them back. Each file has its own stream key, sealed for the public
+// key. The content is a `secretstream` of records, each holding a write of
+// the muxer and the offset it was written at. Records are only appended:
+// when the muxer seeks back to patch a header, the patch becomes a new
+// record. So a file cut off by a crash decrypts up to its last complete
+// record, and uploads can follow the file as it grows.
+//
+// Layout: MAGIC | sealed stream key | stream header | (u32 length | record)*
+// The last record of a finished file is tagged `Final`.
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    base64::{engine::general_purpose::STANDARD as BASE64, Engine},
+    sodiumoxide::crypto::{
+        box_::{self, PublicKey, SecretKey},
+        sealedbox,
+        secretstream::{self, Header, Key, Pull, Push, Stream, Tag},
+    },
+    ResultType,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    time::{Duration, Instant},
+};
+
+/// Appended to the names of encrypted files.
+pub const ENC_EXT: &str = ".enc";
+const MAGIC: &[u8; 8] = b"RDREC\x00\x00\x01";
+// Plaintext of a record
+const RECORD_SIZE: usize = 64 * 1024;
+// Bounds the data lost on a crash
+const SEAL_INTERVAL: Duration = Duration::from_secs(1);
+const OFFSET_LEN: usize = 8;
+// A sealed record, with its offset and tag
+const MAX_SEALED_LEN: usize = OFFSET_LEN + RECORD_SIZE + secretstream::ABYTES;
+
+/// Base64 public key, empty for no encryption.
+pub fn parse_public_key(key: &str) -> ResultType<Option<PublicKey>> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Ok(None);
+    }
+    let bytes = BASE64.decode(key)?;
+    match PublicKey::from_slice(&bytes) {
+        Some(pk) => Ok(Some(pk)),
+        None => bail!("Invalid recording public key"),
+    }
+}
+
+pub fn parse_secret_key(key: &str) -> ResultType<SecretKey> {
+    let bytes = BASE64.decode(key.trim())?;
+    SecretKey::from_slice(&bytes).ok_or(anyhow!("Invalid recording secret key"))
+}
+
+/// Base64 public and secret key.
+pub fn gen_keypair() -> (String, String) {
+    let (pk, sk) = box_::gen_keypair();
+    (BASE64.encode(pk), BASE64.encode(sk))
+}
+
+#[inline]
+pub fn encrypted_filename(path: &str, encrypted: bool) -> String {
+    if encrypted {
+        format!("{path}{ENC_EXT}")
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Writes the encrypted form of what is written and seeked, see the module.
+pub struct EncryptWriter<W: Write> {
+    out: W,
+    stream: Stream<Push>,
+    pos: u64,
+    end: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+    last_seal: Instant,
+    finished: bool,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(mut out: W, pk: &PublicKey) -> io::Result<Self> {
+        let key = secretstream::gen_key();
+        let (stream, header) =
+            Stream::init_push(&key).map_err(|_| io::Error::other("Failed to init stream"))?;
+        out.write_all(MAGIC)?;
+        out.write_all(&sealedbox::seal(key.as_ref(), pk))?;
+        out.write_all(header.as_ref())?;
+        Ok(Self {
+            out,
+            stream,
+            pos: 0,
+            end: 0,
+            buf: Vec::with_capacity(RECORD_SIZE),
+            buf_start: 0,
+            last_seal: Instant::now(),
+            finished: false,
+        })
+    }
+
+    fn seal(&mut self, tag: Tag) -> io::Result<()> {
+        if self.buf.is_empty() && tag != Tag::Final {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(OFFSET_LEN + self.buf.len());
+        record.extend_from_slice(&self.buf_start.to_le_bytes());
+        record.extend_from_slice(&self.buf);
+        let c = self
+            .stream
+            .push(&record, None, tag)
+            .map_err(|_| io::Error::other("Failed to encrypt"))?;
+        self.out.write_all(&(c.len() as u32).to_le_bytes())?;
+        self.out.write_all(&c)?;
+        self.buf_start += self.buf.len() as u64;
+        self.buf.clear();
+        self.last_seal = Instant::now();
+        // Complete records reach the disk, and uploads
+        self.out.flush()
+    }
+
+    /// Mark the file complete, also done on drop.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.seal(Tag::Final)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("Write after finish"));
+        }
+        // Patches of the pending record are applied in place
+        if self.pos < self.buf_start || self.pos > self.buf_start + self.buf.len() as u64 {
+            self.seal(Tag::Message)?;
+            self.buf_start = self.pos;
+        }
+        // Records never hold more than RECORD_SIZE, see `decrypt`
+        let mut rest = data;
+        while !rest.is_empty() {
+            let off = (self.pos - self.buf_start) as usize;
+            let (chunk, next) = rest.split_at(rest.len().min(RECORD_SIZE - off));
+            let overlap = (self.buf.len() - off).min(chunk.len());
+            self.buf[off..off + overlap].copy_from_slice(&chunk[..overlap]);
+            self.buf.extend_from_slice(&chunk[overlap..]);
+            self.pos += chunk.len() as u64;
+            if self.buf.len() >= RECORD_SIZE {
+                self.seal(Tag::Message)?;
+            }
+            rest = next;
+        }
+        self.end = self.end.max(self.pos);
+        if self.last_seal.elapsed() >= SEAL_INTERVAL {
+            self.seal(Tag::Message)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal(Tag::Message)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Seek for EncryptWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.end.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+}
+
+impl<W: Write> Drop for EncryptWriter<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+/// A recording file, encrypted if there is a public key.
+pub enum RecordFile {
+    Plain(File),
+    Encrypted(EncryptWriter<BufWriter<File>>),
+}
+
+impl RecordFile {
+    pub fn new(file: File, pk: Option<&PublicKey>) -> io::Result<Self> {
+        Ok(match pk {
+            Some(pk) => Self::Encrypted(EncryptWriter::new(BufWriter::new(file), pk)?),
+            None => Self::Plain(file),
+        })
+    }
+
+    pub fn create(path: &str, pk: Option<&PublicKey>) -> io::Result<Self> {
+        Self::new(File::create(path)?, pk)
+    }
+}
+
+impl Write for RecordFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(f) => f.write(data),
+            Self::Encrypted(f) => f.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(f) => f.flush(),
+            Self::Encrypted(f) => f.flush(),
+        }
+    }
+}
+
+impl Seek for RecordFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(f) => f.seek(pos),
+            Self::Encrypted(f) => f.seek(pos),
+        }
+    }
+}
+
+/// Returns whether the file was complete, or was cut off and is decrypted
+/// up to its last complete record.
+pub fn decrypt<R: Read, W: Write + Seek>(
+    input: R,
+    sk: &SecretKey,
+    out: &mut W,
+) -> ResultType<bool> {
+    let mut input = BufReader::new(input);
+    let mut magic = [0u8; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("Not an encrypted recording");
+    }
+    let mut sealed = vec![0u8; secretstream::KEYBYTES + sealedbox::SEALBYTES];
+    input.read_exact(&mut sealed)?;
+    let key = sealedbox::open(&sealed, &sk.public_key(), sk)
+        .map_err(|_| anyhow!("The recording is encrypted for another key"))?;
+    let key = Key::from_slice(&key).ok_or(anyhow!("Invalid stream key"))?;
+    let mut header = [0u8; secretstream::HEADERBYTES];
+    input.read_exact(&mut header)?;
+    let header = Header::from_slice(&header).ok_or(anyhow!("Invalid stream header"))?;
+    let mut stream: Stream<Pull> =
+        Stream::init_pull(&header, &key).map_err(|_| anyhow!("Invalid stream header"))?;
+    loop {
+        let mut len = [0u8; 4];
+        if input.read_exact(&mut len).is_err() {
+            return Ok(false);
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_SEALED_LEN {
+            bail!("The recording is corrupted");
+        }
+        let mut c = vec![0u8; len];
+        if input.read_exact(&mut c).is_err() {
+            return Ok(false);
+        }
+        let (record, tag) = stream
+            .pull(&c, None)
+            .map_err(|_| anyhow!("The recording is corrupted"))?;
+        if record.len() < OFFSET_LEN {
+            bail!("The recording is corrupted");
+        }
+        let mut offset = [0u8; OFFSET_LEN];
+        offset.copy_from_slice(&record[..OFFSET_LEN]);
+        out.seek(SeekFrom::Start(u64::from_le_bytes(offset)))?;
+        out.write_all(&record[OFFSET_LEN..])?;
+        if tag == Tag::Final {
+            return Ok(true);
+        }
+    }
+}
+
+/// Decrypt `src` to `dst`, see `decrypt`.
+pub fn decrypt_file(src: &str, dst: &str, sk: &SecretKey) -> ResultType<bool> {
+    let mut out = File::create(dst)?;
+    let complete = decrypt(File::open(src)?, sk, &mut out)?;
+    out.flush()?;
+    Ok(complete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn keys() -> (PublicKey, SecretKey) {
+        let (pk, sk) = gen_keypair();
+        (
+            parse_public_key(&pk).unwrap().unwrap(),
+            parse_secret_key(&sk).unwrap(),
+        )
+    }
+
+    // Writes like a muxer: appends and patches of earlier positions
+    fn write_all<W: Write + Seek>(w: &mut W) -> Vec<u8> {
+        let mut expected = vec![0u8; 300_000];
+        for (i, x) in expected.iter_mut().enumerate() {
+            *x = (i % 251) as u8;
+        }
+        for chunk in expected.chunks(1000) {
+            w.write_all(chunk).unwrap();
+        }
+        // A patch in a sealed record, and one in the pending record
+        expected[10..14].copy_from_slice(&[1, 2, 3, 4]);
+        w.seek(SeekFrom::Start(10)).unwrap();
+        w.write_all(&[1, 2, 3, 4]).unwrap();
+        expected[299_990..299_992].copy_from_slice(&[5, 6]);
+        w.seek(SeekFrom::End(-10)).unwrap();
+        w.write_all(&[5, 6]).unwrap();
+        // Appends after the end
+        w.seek(SeekFrom::End(0)).unwrap();
+        w.write_all(&[7; 100]).unwrap();
+        expected.extend_from_slice(&[7; 100]);
+        expected
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (pk, sk) = keys();
+        let mut w = EncryptWriter::new(vec![], &pk).unwrap();
+        let expected = write_all(&mut w);
+        w.finish().unwrap();
+        let encrypted = std::mem::take(&mut w.out);
+        assert!(!encrypted.windows(100).any(|x| x == &expected[1000..1100]));
+
+        let mut out = Cursor::new(vec![]);
+        assert!(decrypt(&encrypted[..], &sk, &mut out).unwrap());
+        assert_eq!(out.into_inner(), expected);
+
+        // Another key
+        let (_, sk2) = keys();
+        assert!(decrypt(&encrypted[..], &sk2, &mut Cursor::new(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_large_write() {
+        let (pk, sk) = keys();
+        let mut w = EncryptWriter::new(vec![], &pk).unwrap();
+        let expected = vec![9u8; RECORD_SIZE * 3 + 10];
+        w.write_all(&expected).unwrap();
+        w.finish().unwrap();
+        let encrypted = std::mem::take(&mut w.out);
+
+        let mut out = Cursor::new(vec![]);
+        assert!(decrypt(&encrypted[..], &sk, &mut out).unwrap());
+        assert_eq!(out.into_inner(), expected);
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let (pk, sk) = keys();
+        let mut w = EncryptWriter::new(vec![], &pk).unwrap();
+        w.write_all(&[1; 100]).unwrap();
+        w.finish().unwrap();
+        let mut encrypted = std::mem::take(&mut w.out);
+        let at =
+            MAGIC.len() + secretstream::KEYBYTES + sealedbox::SEALBYTES + secretstream::HEADERBYTES;
+        encrypted[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decrypt(&encrypted[..], &sk, &mut Cursor::new(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let (pk, sk) = keys();
+        let mut w = EncryptWriter::new(vec![], &pk).unwrap();
+        let expected = write_all(&mut w);
+        w.finish().unwrap();
+        let encrypted = std::mem::take(&mut w.out);
+
+        let mut out = Cursor::new(vec![]);
+        let cut = encrypted.len() * 2 / 3;
+        assert!(!decrypt(&encrypted[..cut], &sk, &mut out).unwrap());
+        let out = out.into_inner();
+        assert!(out.len() >= RECORD_SIZE);
+        assert!(out.len() < expected.len());
+        assert_eq!(out[20..], expected[20..out.len()]);
+    }
+}
@@ -0,0 +1,383 @@
+// Session events of recordings.
+//
+// Every recording `<name>.webm` or `<name>.mp4` gets a sidecar
+// `<name>.events.jsonl` with one `RecordEvent` per line, so that the log of
+// a file cut off by a crash is readable up to its last line. Recordings are
+// found again by the peers and events of their sidecars with
+// `query_recordings`.
+use crate::{
+    record_cast::CAST_EXT,
+    record_crypt::{encrypted_filename, RecordFile, ENC_EXT},
+};
+use hbb_common::{
+    chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone},
+    log,
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{self, Value},
+    sodiumoxide::crypto::box_::PublicKey,
+    ResultType,
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+const EVENTS_EXT: &str = "events.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordEventType {
+    Login,
+    Close,
+    Permission,
+    Chat,
+    File,
+    Clipboard,
+    Display,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordEvent {
+    /// Unix time in milliseconds.
+    pub time: i64,
+    /// Milliseconds since the first video frame of the file, 0 before it.
+    #[serde(default)]
+    pub offset: i64,
+    pub event: RecordEventType,
+    #[serde(default)]
+    pub conn_id: i32,
+    #[serde(default)]
+    pub peer_id: String,
+    #[serde(default)]
+    pub peer_name: String,
+    /// Depends on `event`, e.g. `{"name": "keyboard", "enabled": false}`.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub detail: Value,
+}
+
+impl RecordEvent {
+    pub fn new(event: RecordEventType, detail: Value) -> Self {
+        Self {
+            time: Local::now().timestamp_millis(),
+            offset: 0,
+            event,
+            conn_id: 0,
+            peer_id: Default::default(),
+            peer_name: Default::default(),
+            detail,
+        }
+    }
+}
+
+/// The sidecar of a recording, `video` without the extension of encrypted files.
+pub fn events_filename(video: &str) -> String {
+    format!("{video}.{EVENTS_EXT}")
+}
+
+/// The sidecar of a recording, removed with the recording.
+pub(crate) struct EventLog {
+    file: Option<RecordFile>,
+    path: String,
+    video: String,
+}
+
+impl EventLog {
+    /// Encrypted like the video if `pk` is set.
+    pub(crate) fn new(video: &str, pk: Option<&PublicKey>) -> ResultType<Self> {
+        let plain = video.strip_suffix(ENC_EXT).unwrap_or(video);
+        let path = encrypted_filename(&events_filename(plain), pk.is_some());
+        Ok(Self {
+            file: Some(RecordFile::create(&path, pk)?),
+            path,
+            video: video.to_owned(),
+        })
+    }
+
+    pub(crate) fn write(&mut self, event: &RecordEvent) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        // Events are rare, each is flushed to a record of its own
+        let res = serde_json::to_string(event)
+            .map_err(|e| e.into())
+            .and_then(|line| writeln!(file, "{line}"))
+            .and_then(|_| file.flush());
+        if let Err(e) = res {
+            log::error!("Failed to write record event: {e}");
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        // Closed first for Windows
+        self.file = None;
+        // Dropped after the recorder, which removes files that are too short
+        if !Path::new(&self.video).exists() {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Conditions of `query_recordings`, `None` matches all.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingQuery {
+    /// Part of the id or name of a peer of the recording, case insensitive.
+    pub peer: Option<String>,
+    /// First day of the recording start, local time.
+    pub from: Option<NaiveDate>,
+    /// Last day of the recording start, local time.
+    pub to: Option<NaiveDate>,
+    /// The recording has an event of this type.
+    pub event: Option<RecordEventType>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingInfo {
+    pub path: String,
+    /// Incoming for the recordings of the controlled side.
+    pub incoming: bool,
+    /// Local id when incoming, else the peer id.
+    pub id: String,
+    pub display: String,
+    /// Unix time in milliseconds.
+    pub start: i64,
+    pub size: u64,
+    /// Events of encrypted recordings can't be read.
+    pub encrypted: bool,
+    /// All events, or those of `RecordingQuery::event`.
+    pub events: Vec<RecordEvent>,
+}
+
+/// Recordings of `dir`, oldest first.
+///
+/// Recordings without readable events, e.g. encrypted ones or those of
+/// older versions, only match queries without `peer` and `event`.
+pub fn query_recordings(dir: &str, query: &RecordingQuery) -> ResultType<Vec<RecordingInfo>> {
+    let peer = query.peer.as_ref().map(|p| p.to_lowercase());
+    let mut v = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(mut info) = parse_recording(&path) else {
+            continue;
+        };
+        let date = match Local.timestamp_millis_opt(info.start).single() {
+            Some(t) => t.date_naive(),
+            None => continue,
+        };
+        if query.from.map_or(false, |from| date < from) || query.to.map_or(false, |to| date > to) {
+            continue;
+        }
+        let events = if info.encrypted {
+            vec![]
+        } else {
+            read_events(&events_filename(&info.path))
+        };
+        if let Some(peer) = &peer {
+            let matched = (!info.incoming && info.id.to_lowercase().contains(peer))
+                || events.iter().any(|e| {
+                    e.peer_id.to_lowercase().contains(peer)
+                        || e.peer_name.to_lowercase().contains(peer)
+                });
+            if !matched {
+                continue;
+            }
+        }
+        info.events = match query.event {
+            Some(t) => {
+                let events: Vec<_> = events.into_iter().filter(|e| e.event == t).collect();
+                if events.is_empty() {
+                    continue;
+                }
+                events
+            }
+            None => events,
+        };
+        v.push(info);
+    }
+    v.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.path.cmp(&b.path)));
+    Ok(v)
+}
+
+/// Lines that can't be parsed, e.g. the last one of a crashed session, are skipped.
+pub fn read_events(path: &str) -> Vec<RecordEvent> {
+    let Ok(file) = File::open(path) else {
+        return vec![];
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect()
+}
+
+// `{incoming|outgoing}_<id>_<%Y%m%d%H%M%S%3f>_{display|camera}<index>_<codec>.{webm|mp4}[.enc]`,
+// or `incoming_<id>_<%Y%m%d%H%M%S%3f>_terminal<id>_asciicast.cast[.enc]`
+pub(crate) fn parse_recording(path: &PathBuf) -> Option<RecordingInfo> {
+    let name = path.file_name()?.to_str()?;
+    let (encrypted, name) = match name.strip_suffix(ENC_EXT) {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+    let stem = name
+        .strip_suffix(".webm")
+        .or_else(|| name.strip_suffix(".mp4"))
+        .or_else(|| name.strip_suffix(CAST_EXT))?;
+    let (incoming, rest) = if let Some(rest) = stem.strip_prefix("incoming_") {
+        (true, rest)
+    } else {
+        (false, stem.strip_prefix("outgoing_")?)
+    };
+    // The id may contain '_'
+    let mut parts = rest.rsplitn(4, '_');
+    let _codec = parts.next()?;
+    let display = parts.next()?;
+    let time = parts.next()?;
+    let id = parts.next()?;
+    let time = NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S%3f").ok()?;
+    let start: DateTime<Local> = Local.from_local_datetime(&time).earliest()?;
+    Some(RecordingInfo {
+        path: path.to_string_lossy().to_string(),
+        incoming,
+        id: id.to_owned(),
+        display: display.to_owned(),
+        start: start.timestamp_millis(),
+        size: std::fs::metadata(path).map(|m| m.len()).unwrap_or_default(),
+        encrypted,
+        events: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::serde_json::json;
+
+    fn event(event: RecordEventType, peer_id: &str, peer_name: &str) -> RecordEvent {
+        RecordEvent {
+            peer_id: peer_id.to_owned(),
+            peer_name: peer_name.to_owned(),
+            ..RecordEvent::new(event, json!({}))
+        }
+    }
+
+    fn write(dir: &Path, name: &str, events: &[RecordEvent]) {
+        let video = dir.join(name).to_string_lossy().to_string();
+        std::fs::write(&video, b"video").unwrap();
+        let mut log = EventLog::new(&video, None).unwrap();
+        for e in events {
+            log.write(e);
+        }
+    }
+
+    #[test]
+    fn test_parse_recording() {
+        let info = parse_recording(&PathBuf::from(
+            "/r/incoming_my_id_20240102030405678_display1_vp9.webm",
+        ))
+        .unwrap();
+        assert!(info.incoming);
+        assert_eq!(info.id, "my_id");
+        assert_eq!(info.display, "display1");
+        let start = Local.timestamp_millis_opt(info.start).unwrap();
+        assert_eq!(
+            start.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            "2024-01-02 03:04:05.678"
+        );
+        assert!(parse_recording(&PathBuf::from("/r/outgoing_1_2_display0_h264.mp4")).is_none());
+        assert!(parse_recording(&PathBuf::from(
+            "/r/incoming_1_20240102030405678_display0_vp9.webm.events.jsonl"
+        ))
+        .is_none());
+        let info = parse_recording(&PathBuf::from(
+            "/r/incoming_1_20240102030405678_terminal3_asciicast.cast.enc",
+        ))
+        .unwrap();
+        assert!(info.encrypted);
+        assert_eq!(info.display, "terminal3");
+    }
+
+    #[test]
+    fn test_query_recordings() {
+        let dir = std::env::temp_dir().join(format!("record_event_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write(
+            &dir,
+            "incoming_123_20240102030405678_display0_vp9.webm",
+            &[
+                event(RecordEventType::Login, "456", "Alice"),
+                event(RecordEventType::Chat, "456", "Alice"),
+            ],
+        );
+        write(
+            &dir,
+            "incoming_123_20240305030405678_display0_vp9.webm",
+            &[event(RecordEventType::Login, "789", "Bob")],
+        );
+        // Without events
+        std::fs::write(
+            dir.join("outgoing_456_20240103030405678_display0_h264.mp4"),
+            b"video",
+        )
+        .unwrap();
+        let dir_str = dir.to_string_lossy().to_string();
+        let query = |q: RecordingQuery| query_recordings(&dir_str, &q).unwrap().len();
+
+        assert_eq!(query(Default::default()), 3);
+        assert_eq!(
+            query(RecordingQuery {
+                peer: Some("alice".to_owned()),
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            query(RecordingQuery {
+                peer: Some("456".to_owned()),
+                ..Default::default()
+            }),
+            2
+        );
+        assert_eq!(
+            query(RecordingQuery {
+                event: Some(RecordEventType::Chat),
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            query(RecordingQuery {
+                from: NaiveDate::from_ymd_opt(2024, 1, 3),
+                to: NaiveDate::from_ymd_opt(2024, 3, 5),
+                ..Default::default()
+            }),
+            2
+        );
+        let chats = query_recordings(
+            &dir_str,
+            &RecordingQuery {
+                event: Some(RecordEventType::Chat),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(chats[0].events.len(), 1);
+        assert_eq!(chats[0].events[0].peer_name, "Alice");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_event_log_removed_with_video() {
+        let dir = std::env::temp_dir();
+        let video = dir
+            .join(format!("record_event_removed_{}.webm", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut log = EventLog::new(&video, None).unwrap();
+        log.write(&event(RecordEventType::Login, "1", ""));
+        assert!(Path::new(&events_filename(&video)).exists());
+        drop(log);
+        assert!(!Path::new(&events_filename(&video)).exists());
+    }
+}
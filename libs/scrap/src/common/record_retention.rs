@@ -0,0 +1,445 @@
+// Retention and disk space of recordings.
+//
+// A sweeper thread removes the recordings of the recording directory that
+// are older than `record-max-age-days`, beyond the `record-keep-last-per-peer`
+// newest ones of a peer, and the oldest ones while all take more than
+// `record-max-total-size`. Recordings are removed with their sidecars, files
+// still being written are never removed, neither by the sweeper nor by
+// `delete_recording`. Recorders pause while the free space of the directory
+// is below `record-min-free-space`.
+use crate::record_crypt::ENC_EXT;
+use crate::record_event::{
+    events_filename, parse_recording, query_recordings, RecordEventType, RecordingInfo,
+};
+use hbb_common::{
+    bail,
+    config::{keys, Config},
+    log, ResultType,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+// Recordings modified since are not removed, they may be written by another
+// process, e.g. the server and the client record to the same directory
+const ACTIVE_GRACE: Duration = Duration::from_secs(600);
+const DEFAULT_MIN_FREE_SPACE_MB: u64 = 512;
+const DAY_MS: i64 = 24 * 3600 * 1000;
+
+lazy_static::lazy_static! {
+    // Files being written by the recorders of this process
+    static ref ACTIVE: Mutex<HashSet<String>> = Default::default();
+    // Directories with a sweeper
+    static ref SWEEPERS: Mutex<HashSet<String>> = Default::default();
+}
+
+/// Marks files as being written until dropped.
+pub(crate) struct ActiveFiles(Vec<String>);
+
+impl ActiveFiles {
+    pub(crate) fn new(files: Vec<String>) -> Self {
+        let mut active = ACTIVE.lock().unwrap();
+        for f in files.iter() {
+            active.insert(f.clone());
+        }
+        Self(files)
+    }
+}
+
+impl Drop for ActiveFiles {
+    fn drop(&mut self) {
+        let mut active = ACTIVE.lock().unwrap();
+        for f in self.0.iter() {
+            active.remove(f);
+        }
+    }
+}
+
+// If one of `files` of a recording is written by this process, or was
+// modified lately as it may be written by another one
+fn is_active(files: &[String], now: SystemTime) -> bool {
+    let active = ACTIVE.lock().unwrap();
+    files.iter().any(|f| {
+        active.contains(f)
+            || std::fs::metadata(f)
+                .and_then(|m| m.modified())
+                .is_ok_and(|t| now.duration_since(t).unwrap_or_default() < ACTIVE_GRACE)
+    })
+}
+
+/// Limits of the recordings, 0 for no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age_days: u64,
+    /// In bytes, with the sidecars.
+    pub max_total_size: u64,
+    pub keep_last_per_peer: usize,
+}
+
+impl RetentionPolicy {
+    pub fn load() -> Self {
+        let option = |key: &str| -> u64 { Config::get_option(key).trim().parse().unwrap_or(0) };
+        Self {
+            max_age_days: option(keys::OPTION_RECORD_MAX_AGE_DAYS),
+            max_total_size: option(keys::OPTION_RECORD_MAX_TOTAL_SIZE).saturating_mul(1024 * 1024),
+            keep_last_per_peer: option(keys::OPTION_RECORD_KEEP_LAST_PER_PEER) as _,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Free space below which recording pauses, in bytes, 0 to never pause.
+pub fn min_free_space() -> u64 {
+    let v = Config::get_option(keys::OPTION_RECORD_MIN_FREE_SPACE);
+    let mb = if v.trim().is_empty() {
+        DEFAULT_MIN_FREE_SPACE_MB
+    } else {
+        v.trim().parse().unwrap_or(DEFAULT_MIN_FREE_SPACE_MB)
+    };
+    mb.saturating_mul(1024 * 1024)
+}
+
+/// Space available to the current user on the disk of `dir`.
+#[cfg(not(windows))]
+pub fn free_space(dir: &str) -> Option<u64> {
+    use hbb_common::libc;
+    let path = std::ffi::CString::new(dir).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Space available to the current user on the disk of `dir`.
+#[cfg(windows)]
+pub fn free_space(dir: &str) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::{fileapi::GetDiskFreeSpaceExW, winnt::ULARGE_INTEGER};
+    let path: Vec<u16> = std::ffi::OsStr::new(dir)
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+    Some(unsafe { *available.QuadPart() })
+}
+
+/// Unknown free space, e.g. of a directory not created yet, is not low.
+pub fn is_disk_low(dir: &str) -> bool {
+    let min = min_free_space();
+    min > 0 && free_space(dir).map_or(false, |free| free < min)
+}
+
+/// The recording and its sidecars that exist.
+pub fn recording_files(video: &str) -> Vec<String> {
+    let plain = video.strip_suffix(ENC_EXT).unwrap_or(video);
+    let encrypted = plain != video;
+    let mut files = vec![video.to_owned()];
+    for sidecar in [events_filename(plain), format!("{plain}.opus")] {
+        if encrypted {
+            files.push(format!("{sidecar}{ENC_EXT}"));
+        }
+        files.push(sidecar);
+    }
+    files.retain(|f| Path::new(f).exists());
+    files
+}
+
+fn files_size(files: &[String]) -> u64 {
+    files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Recordings of `dir`, oldest first.
+pub fn list_recordings(dir: &str) -> ResultType<Vec<RecordingInfo>> {
+    query_recordings(dir, &Default::default())
+}
+
+/// Remove a recording of `dir` with its sidecars.
+pub fn delete_recording(dir: &str, path: &str) -> ResultType<()> {
+    let path_buf = PathBuf::from(path);
+    let parent = path_buf.parent().and_then(|p| p.canonicalize().ok());
+    if parent.is_none() || parent != Path::new(dir).canonicalize().ok() {
+        bail!("{} is not in the recording directory", path);
+    }
+    if parse_recording(&path_buf).is_none() {
+        bail!("{} is not a recording", path);
+    }
+    let files = recording_files(path);
+    if is_active(&files, SystemTime::now()) {
+        bail!(
+            "{} is being recorded, or was modified less than {} minutes ago",
+            path,
+            ACTIVE_GRACE.as_secs() / 60
+        );
+    }
+    for f in files {
+        std::fs::remove_file(&f)?;
+    }
+    Ok(())
+}
+
+// Outgoing recordings are of their id, incoming ones of the first login
+fn peer_of(info: &RecordingInfo) -> Option<String> {
+    if !info.incoming {
+        return Some(info.id.clone());
+    }
+    info.events
+        .iter()
+        .find(|e| e.event == RecordEventType::Login && !e.peer_id.is_empty())
+        .map(|e| e.peer_id.clone())
+}
+
+/// Indexes of the recordings to remove, oldest first.
+///
+/// `recordings` are sorted oldest first and `sizes` are of their files.
+/// Recordings without a known peer, e.g. incoming encrypted ones, only
+/// count for age and size. Those in `protected`, e.g. being written, are
+/// kept but count for the total size.
+fn select_expired(
+    recordings: &[RecordingInfo],
+    sizes: &[u64],
+    protected: &[bool],
+    policy: &RetentionPolicy,
+    now_ms: i64,
+) -> Vec<usize> {
+    let mut expired = vec![false; recordings.len()];
+    if policy.max_age_days > 0 {
+        let min_start = now_ms - policy.max_age_days as i64 * DAY_MS;
+        for (i, r) in recordings.iter().enumerate() {
+            if r.start < min_start {
+                expired[i] = true;
+            }
+        }
+    }
+    if policy.keep_last_per_peer > 0 {
+        let mut peers: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, r) in recordings.iter().enumerate() {
+            if let Some(peer) = peer_of(r) {
+                peers.entry(peer).or_default().push(i);
+            }
+        }
+        for v in peers.values() {
+            let n = v.len().saturating_sub(policy.keep_last_per_peer);
+            for i in &v[..n] {
+                expired[*i] = true;
+            }
+        }
+    }
+    for i in 0..recordings.len() {
+        if protected[i] {
+            expired[i] = false;
+        }
+    }
+    if policy.max_total_size > 0 {
+        let mut total: u64 = (0..recordings.len())
+            .filter(|i| !expired[*i])
+            .map(|i| sizes[i])
+            .sum();
+        for i in 0..recordings.len() {
+            if total <= policy.max_total_size {
+                break;
+            }
+            if !expired[i] && !protected[i] {
+                expired[i] = true;
+                total -= sizes[i];
+            }
+        }
+    }
+    (0..recordings.len()).filter(|i| expired[*i]).collect()
+}
+
+/// Apply `policy` to the recordings of `dir`, returns the removed ones.
+pub fn sweep(dir: &str, policy: &RetentionPolicy) -> ResultType<Vec<String>> {
+    if policy.is_unlimited() || !Path::new(dir).exists() {
+        return Ok(vec![]);
+    }
+    let recordings = list_recordings(dir)?;
+    let files: Vec<Vec<String>> = recordings
+        .iter()
+        .map(|r| recording_files(&r.path))
+        .collect();
+    let sizes: Vec<u64> = files.iter().map(|f| files_size(f)).collect();
+    let now = SystemTime::now();
+    let protected: Vec<bool> = files.iter().map(|f| is_active(f, now)).collect();
+    let now_ms = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default();
+    let mut removed = vec![];
+    for i in select_expired(&recordings, &sizes, &protected, policy, now_ms) {
+        for f in files[i].iter() {
+            if let Err(e) = std::fs::remove_file(f) {
+                log::error!("Failed to remove recording {}: {}", f, e);
+            }
+        }
+        removed.push(recordings[i].path.clone());
+    }
+    if !removed.is_empty() {
+        log::info!("Removed {} expired recordings of {}", removed.len(), dir);
+    }
+    Ok(removed)
+}
+
+/// Start the sweeper of `dir` once, the policy is reloaded on every sweep.
+pub fn start_sweeper(dir: String) {
+    if !SWEEPERS.lock().unwrap().insert(dir.clone()) {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        if let Err(e) = sweep(&dir, &RetentionPolicy::load()) {
+            log::error!("Failed to sweep recordings of {}: {}", dir, e);
+        }
+        std::thread::sleep(SWEEP_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_event::RecordEvent;
+    use hbb_common::serde_json::json;
+
+    fn recording(incoming: bool, id: &str, start: i64, login: Option<&str>) -> RecordingInfo {
+        RecordingInfo {
+            path: format!("{id}_{start}"),
+            incoming,
+            id: id.to_owned(),
+            display: "display0".to_owned(),
+            start,
+            size: 0,
+            encrypted: false,
+            events: login
+                .map(|peer| {
+                    vec![RecordEvent {
+                        peer_id: peer.to_owned(),
+                        ..RecordEvent::new(RecordEventType::Login, json!({}))
+                    }]
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn test_select_expired() {
+        let now = 100 * DAY_MS;
+        let recordings = vec![
+            recording(false, "a", now - 10 * DAY_MS, None),
+            recording(true, "me", now - 9 * DAY_MS, Some("b")),
+            recording(false, "a", now - 8 * DAY_MS, None),
+            recording(true, "me", now - 7 * DAY_MS, None),
+            recording(false, "a", now - 6 * DAY_MS, None),
+            recording(true, "me", now - 5 * DAY_MS, Some("b")),
+        ];
+        let sizes = vec![10; recordings.len()];
+        let none = vec![false; recordings.len()];
+        let select = |policy: RetentionPolicy, protected: &[bool]| {
+            select_expired(&recordings, &sizes, protected, &policy, now)
+        };
+
+        assert!(select(Default::default(), &none).is_empty());
+        assert_eq!(
+            select(
+                RetentionPolicy {
+                    max_age_days: 8,
+                    ..Default::default()
+                },
+                &none
+            ),
+            vec![0, 1]
+        );
+        // The incoming one without a login has no peer
+        assert_eq!(
+            select(
+                RetentionPolicy {
+                    keep_last_per_peer: 1,
+                    ..Default::default()
+                },
+                &none
+            ),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            select(
+                RetentionPolicy {
+                    max_total_size: 35,
+                    ..Default::default()
+                },
+                &none
+            ),
+            vec![0, 1, 2]
+        );
+        let mut protected = none.clone();
+        protected[0] = true;
+        assert_eq!(
+            select(
+                RetentionPolicy {
+                    max_age_days: 8,
+                    max_total_size: 35,
+                    ..Default::default()
+                },
+                &protected
+            ),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_sweep_and_delete() {
+        let dir = std::env::temp_dir().join(format!("record_retention_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_string_lossy().to_string();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let old = path("outgoing_1_20200102030405678_display0_vp9.webm");
+        let new = path("outgoing_1_20200103030405678_display0_vp9.webm");
+        std::fs::write(&old, b"video").unwrap();
+        std::fs::write(events_filename(&old), b"{}").unwrap();
+        std::fs::write(&new, b"video").unwrap();
+        std::fs::write(path("other.webm"), b"video").unwrap();
+
+        // Both were just written
+        let policy = RetentionPolicy {
+            keep_last_per_peer: 1,
+            ..Default::default()
+        };
+        assert!(sweep(&dir_str, &policy).unwrap().is_empty());
+
+        assert_eq!(recording_files(&old).len(), 2);
+        // Maybe written by another process
+        assert!(delete_recording(&dir_str, &old).is_err());
+        let before = SystemTime::now() - ACTIVE_GRACE * 2;
+        for f in recording_files(&old) {
+            let file = std::fs::File::options().write(true).open(f).unwrap();
+            file.set_modified(before).unwrap();
+        }
+        let active = ActiveFiles::new(vec![old.clone()]);
+        assert!(delete_recording(&dir_str, &old).is_err());
+        drop(active);
+        assert!(delete_recording(&dir_str, &path("other.webm")).is_err());
+        delete_recording(&dir_str, &old).unwrap();
+        assert!(recording_files(&old).is_empty());
+        assert_eq!(list_recordings(&dir_str).unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,1042 @@
+// Offline processing of recordings: re-encode, trim, join and thumbnails.
+//
+// `.webm` recordings are read by a small Matroska demuxer. The video is
+// copied when its codec, size and bitrate are kept, else it is decoded and
+// encoded again with the codecs of the sessions. Opus audio is always
+// copied. Encrypted recordings must be decrypted first, the `.mp4`
+// recordings of the hardware muxer aren't supported.
+use crate::{
+    aom::AomEncoderConfig,
+    codec::{base_bitrate, Decoder, Encoder, EncoderApi, EncoderCfg, Quality},
+    convert_to_yuv,
+    record::webm_segment,
+    record_crypt::ENC_EXT,
+    vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
+    CodecFormat, EncodeInput, ImageFormat, ImageRgb, ImageTexture, Pixfmt, TraitPixelBuffer,
+};
+use hbb_common::{
+    bail, log,
+    message_proto::{video_frame, EncodedVideoFrame, EncodedVideoFrames},
+    ResultType,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+use webm::mux::Track;
+
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_INFO: u32 = 0x1549A966;
+const ID_TIMECODE_SCALE: u32 = 0x2AD7B1;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_VIDEO: u32 = 0xE0;
+const ID_PIXEL_WIDTH: u32 = 0xB0;
+const ID_PIXEL_HEIGHT: u32 = 0xBA;
+const ID_AUDIO: u32 = 0xE1;
+const ID_CHANNELS: u32 = 0x9F;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_TIMECODE: u32 = 0xE7;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_REFERENCE_BLOCK: u32 = 0xFB;
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+// Larger elements are rejected instead of allocated
+const MAX_ELEMENT_SIZE: u64 = 256 * 1024 * 1024;
+// Between joined recordings, a frame at 30 fps
+const JOIN_GAP_NS: u64 = 1_000_000_000 / 30;
+// Copied video starts at the key frame before the start if it is this close
+const KEYFRAME_SNAP_NS: u64 = 1_000_000_000;
+// In frames, so that the output can be seeked
+const KEYFRAME_INTERVAL: usize = 150;
+
+/// A video or audio frame of a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub video: bool,
+    /// Nanoseconds, as written by the recorder.
+    pub ns: u64,
+    pub key: bool,
+    pub data: Vec<u8>,
+}
+
+/// Demuxer of the `.webm` files written by the recorder.
+///
+/// A file cut off by a crash is read up to its last complete block.
+pub struct WebmReader<R: Read + Seek> {
+    r: R,
+    timecode_scale: u64,
+    cluster_time: u64,
+    video_track: u64,
+    audio_track: Option<u64>,
+    pub format: CodecFormat,
+    pub width: usize,
+    pub height: usize,
+    /// Channels of the Opus track, if any.
+    pub audio_channels: Option<u32>,
+}
+
+impl<R: Read + Seek> WebmReader<R> {
+    pub fn new(r: R) -> ResultType<Self> {
+        let mut reader = Self {
+            r,
+            timecode_scale: 1_000_000,
+            cluster_time: 0,
+            video_track: 0,
+            audio_track: None,
+            format: CodecFormat::Unknown,
+            width: 0,
+            height: 0,
+            audio_channels: None,
+        };
+        let (id, size) = read_header(&mut reader.r)?;
+        if id != ID_EBML {
+            bail!("not a webm file");
+        }
+        reader.skip(size)?;
+        let (id, _) = read_header(&mut reader.r)?;
+        if id != ID_SEGMENT {
+            bail!("no segment in the webm file");
+        }
+        // The first cluster follows the tracks
+        loop {
+            let (id, size) = read_header(&mut reader.r)?;
+            match id {
+                ID_INFO => {
+                    let data = reader.read_body(size)?;
+                    for (id, v) in children(&data)? {
+                        if id == ID_TIMECODE_SCALE {
+                            reader.timecode_scale = read_uint(v).max(1);
+                        }
+                    }
+                }
+                ID_TRACKS => {
+                    let data = reader.read_body(size)?;
+                    reader.read_tracks(&data)?;
+                }
+                ID_CLUSTER => break,
+                _ => reader.skip(size)?,
+            }
+        }
+        if reader.video_track == 0 {
+            bail!("no video track in the webm file");
+        }
+        Ok(reader)
+    }
+
+    fn read_tracks(&mut self, data: &[u8]) -> ResultType<()> {
+        for (id, entry) in children(data)? {
+            if id != ID_TRACK_ENTRY {
+                continue;
+            }
+            let (mut number, mut track_type, mut codec) = (0, 0, String::new());
+            let (mut width, mut height, mut channels) = (0, 0, 1);
+            for (id, v) in children(entry)? {
+                match id {
+                    ID_TRACK_NUMBER => number = read_uint(v),
+                    ID_TRACK_TYPE => track_type = read_uint(v),
+                    ID_CODEC_ID => codec = String::from_utf8_lossy(v).to_string(),
+                    ID_VIDEO => {
+                        for (id, v) in children(v)? {
+                            match id {
+                                ID_PIXEL_WIDTH => width = read_uint(v) as usize,
+                                ID_PIXEL_HEIGHT => height = read_uint(v) as usize,
+                                _ => {}
+                            }
+                        }
+                    }
+                    ID_AUDIO => {
+                        for (id, v) in children(v)? {
+                            if id == ID_CHANNELS {
+                                channels = read_uint(v) as u32;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            match track_type {
+                TRACK_TYPE_VIDEO if self.video_track == 0 => {
+                    self.format = match codec.as_str() {
+                        "V_VP8" => CodecFormat::VP8,
+                        "V_VP9" => CodecFormat::VP9,
+                        "V_AV1" => CodecFormat::AV1,
+                        _ => bail!("unsupported video codec {}", codec),
+                    };
+                    self.video_track = number;
+                    self.width = width;
+                    self.height = height;
+                }
+                TRACK_TYPE_AUDIO if self.audio_track.is_none() && codec == "A_OPUS" => {
+                    self.audio_track = Some(number);
+                    self.audio_channels = Some(channels);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The next frame in file order, `None` at the end.
+    pub fn next_packet(&mut self) -> ResultType<Option<Packet>> {
+        loop {
+            // Cluster children are read as if they followed the cluster
+            let (id, size) = match read_header(&mut self.r) {
+                Ok(v) => v,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let data = match id {
+                ID_CLUSTER => {
+                    self.cluster_time = 0;
+                    continue;
+                }
+                ID_TIMECODE | ID_SIMPLE_BLOCK | ID_BLOCK_GROUP => match self.read_body(size) {
+                    Ok(data) => data,
+                    Err(e) if is_eof(&e) => return Ok(None),
+                    Err(e) => return Err(e),
+                },
+                _ => {
+                    self.skip(size)?;
+                    continue;
+                }
+            };
+            let packet = match id {
+                ID_TIMECODE => {
+                    self.cluster_time = read_uint(&data);
+                    None
+                }
+                ID_SIMPLE_BLOCK => self.parse_block(&data, None),
+                _ => {
+                    let children = children(&data)?;
+                    // Key frames don't reference others
+                    let key = !children.iter().any(|(id, _)| *id == ID_REFERENCE_BLOCK);
+                    children
+                        .iter()
+                        .find(|(id, _)| *id == ID_BLOCK)
+                        .and_then(|(_, block)| self.parse_block(block, Some(key)))
+                }
+            };
+            if packet.is_some() {
+                return Ok(packet);
+            }
+        }
+    }
+
+    // `key` of blocks of block groups
+    fn parse_block(&self, data: &[u8], key: Option<bool>) -> Option<Packet> {
+        let mut r = data;
+        let (track, _) = read_vint(&mut r).ok()?;
+        if r.len() < 3 {
+            return None;
+        }
+        let relative = i16::from_be_bytes([r[0], r[1]]) as i64;
+        let flags = r[2];
+        if flags & 0x06 != 0 {
+            log::warn!("Laced webm blocks are not supported");
+            return None;
+        }
+        let video = if track == self.video_track {
+            true
+        } else if Some(track) == self.audio_track {
+            false
+        } else {
+            return None;
+        };
+        let time = (self.cluster_time as i64 + relative).max(0) as u64;
+        Some(Packet {
+            video,
+            ns: time * self.timecode_scale,
+            key: key.unwrap_or(flags & 0x80 != 0),
+            data: r[3..].to_vec(),
+        })
+    }
+
+    fn read_body(&mut self, size: Option<u64>) -> ResultType<Vec<u8>> {
+        let Some(size) = size else {
+            bail!("webm element of unknown size");
+        };
+        if size > MAX_ELEMENT_SIZE {
+            bail!("webm element of {} bytes", size);
+        }
+        let mut data = vec![0; size as usize];
+        self.r.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: Option<u64>) -> ResultType<()> {
+        let Some(size) = size else {
+            bail!("webm element of unknown size");
+        };
+        self.r.seek(SeekFrom::Current(size as i64))?;
+        Ok(())
+    }
+}
+
+fn is_eof(e: &hbb_common::anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .map_or(false, |e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+// Returns the value without the length marker and whether all its bits are set
+fn read_vint<R: Read>(r: &mut R) -> io::Result<(u64, bool)> {
+    let first = read_u8(r)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid vint"));
+    }
+    let mask = (1u64 << (8 - len)) - 1;
+    let mut value = first as u64 & mask;
+    let mut all_ones = value == mask;
+    for _ in 1..len {
+        let b = read_u8(r)?;
+        all_ones &= b == 0xFF;
+        value = (value << 8) | b as u64;
+    }
+    Ok((value, all_ones))
+}
+
+// Returns the id with its length marker, and the size, `None` if unknown
+fn read_header<R: Read>(r: &mut R) -> io::Result<(u32, Option<u64>)> {
+    let first = read_u8(r)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid id"));
+    }
+    let mut id = first as u32;
+    for _ in 1..len {
+        id = (id << 8) | read_u8(r)? as u32;
+    }
+    let (size, unknown) = read_vint(r)?;
+    Ok((id, if unknown { None } else { Some(size) }))
+}
+
+fn children(mut data: &[u8]) -> io::Result<Vec<(u32, &[u8])>> {
+    let mut v = vec![];
+    while !data.is_empty() {
+        let (id, size) = read_header(&mut data)?;
+        let size = size.unwrap_or(u64::MAX);
+        if size > data.len() as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (body, rest) = data.split_at(size as usize);
+        v.push((id, body));
+        data = rest;
+    }
+    Ok(v)
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |v, b| (v << 8) | *b as u64)
+}
+
+fn open_webm(path: &str) -> ResultType<WebmReader<BufReader<File>>> {
+    if path.ends_with(ENC_EXT) {
+        bail!("{} is encrypted, decrypt it first", path);
+    }
+    if !path.ends_with(".webm") {
+        bail!("{} is not a .webm recording", path);
+    }
+    WebmReader::new(BufReader::new(File::open(path)?))
+}
+
+/// Summary of a recording.
+#[derive(Debug, Clone)]
+pub struct RecordingProbe {
+    pub format: CodecFormat,
+    pub width: usize,
+    pub height: usize,
+    pub audio_channels: Option<u32>,
+    /// Time of the first frame, the times below are since it.
+    pub first_ns: u64,
+    /// Time of the last frame.
+    pub duration_ns: u64,
+    /// Times of the video key frames.
+    pub keyframes: Vec<u64>,
+    pub video_frames: usize,
+    pub audio_frames: usize,
+}
+
+pub fn probe(path: &str) -> ResultType<RecordingProbe> {
+    let mut reader = open_webm(path)?;
+    let mut packets = vec![];
+    while let Some(p) = reader.next_packet()? {
+        packets.push((p.video, p.ns, p.key));
+    }
+    let first_ns = packets.iter().map(|p| p.1).min().unwrap_or_default();
+    Ok(RecordingProbe {
+        format: reader.format,
+        width: reader.width,
+        height: reader.height,
+        audio_channels: reader.audio_channels,
+        first_ns,
+        duration_ns: packets
+            .iter()
+            .map(|p| p.1 - first_ns)
+            .max()
+            .unwrap_or_default(),
+        keyframes: packets
+            .iter()
+            .filter(|p| p.0 && p.2)
+            .map(|p| p.1 - first_ns)
+            .collect(),
+        video_frames: packets.iter().filter(|p| p.0).count(),
+        audio_frames: packets.iter().filter(|p| !p.0).count(),
+    })
+}
+
+/// Options of `transcode` and `concat`.
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    /// VP8, VP9 or AV1, `None` keeps the codec of the first input.
+    pub format: Option<CodecFormat>,
+    /// Video bitrate in kbps, `None` copies the video if possible.
+    pub bitrate: Option<u32>,
+    /// Start of the output, in milliseconds since the first frame.
+    pub start_ms: Option<u64>,
+    /// End of the output, in milliseconds since the first frame.
+    pub end_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeResult {
+    pub reencoded: bool,
+    pub video_frames: usize,
+    pub audio_frames: usize,
+    pub duration_ms: u64,
+}
+
+/// Re-encode or trim a recording.
+///
+/// Copied video starts at the key frame before `start_ms` if it is at most
+/// a second earlier, else the video is encoded again to start exactly.
+pub fn transcode(
+    input: &str,
+    output: &str,
+    opts: &TranscodeOptions,
+) -> ResultType<TranscodeResult> {
+    concat(&[input], output, opts)
+}
+
+/// Join recordings in the given order, e.g. those the recorder split on
+/// resolution changes, each starts right after the previous one.
+///
+/// Video of different codecs or sizes is encoded again, centered on the
+/// largest size. Audio of another channel count than the first is dropped.
+/// The range of `opts` is of the joined recordings.
+pub fn concat(
+    inputs: &[&str],
+    output: &str,
+    opts: &TranscodeOptions,
+) -> ResultType<TranscodeResult> {
+    if inputs.is_empty() {
+        bail!("no input");
+    }
+    if inputs.iter().any(|i| Path::new(i) == Path::new(output)) {
+        bail!("{} is an input", output);
+    }
+    let probes = inputs
+        .iter()
+        .map(|i| probe(i))
+        .collect::<ResultType<Vec<_>>>()?;
+    // Start of each input in the output before trimming
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for p in probes.iter() {
+        offsets.push(offset);
+        offset += p.duration_ns + JOIN_GAP_NS;
+    }
+    let format = opts.format.unwrap_or(probes[0].format);
+    let width = probes.iter().map(|p| p.width).max().unwrap_or_default();
+    let height = probes.iter().map(|p| p.height).max().unwrap_or_default();
+    let start = opts.start_ms.unwrap_or(0) * 1_000_000;
+    let end = opts.end_ms.map_or(u64::MAX, |ms| ms * 1_000_000);
+    if start >= end {
+        bail!("the start is not before the end");
+    }
+    let keyframe = probes
+        .iter()
+        .zip(offsets.iter())
+        .flat_map(|(p, offset)| p.keyframes.iter().map(move |k| k + offset))
+        .filter(|k| *k <= start)
+        .max();
+    let copy = opts.bitrate.is_none()
+        && probes
+            .iter()
+            .all(|p| p.format == format && p.width == width && p.height == height)
+        && keyframe.map_or(false, |k| start - k <= KEYFRAME_SNAP_NS);
+    // Frames before the key frame aren't needed to decode those after the start
+    let decode_from = keyframe.unwrap_or(0);
+    let start = if copy { decode_from } else { start };
+    let audio_channels = probes[0].audio_channels;
+
+    let (mut webm, mut vt, mut at) =
+        webm_segment(File::create(output)?, width, height, format, audio_channels)?;
+    let mut reencoder = if copy {
+        None
+    } else {
+        Some(Reencoder::new(format, width, height, opts.bitrate)?)
+    };
+    let mut res = TranscodeResult {
+        reencoded: !copy,
+        ..Default::default()
+    };
+    'inputs: for ((input, probe), offset) in inputs.iter().zip(probes.iter()).zip(offsets) {
+        let audio = probe.audio_channels.is_some() && probe.audio_channels == audio_channels;
+        if probe.audio_channels.is_some() && !audio {
+            log::warn!("Audio of {} is dropped, its channels differ", input);
+        }
+        let mut reader = open_webm(input)?;
+        while let Some(p) = reader.next_packet()? {
+            let t = p.ns.saturating_sub(probe.first_ns) + offset;
+            if t > end {
+                break 'inputs;
+            }
+            if !p.video {
+                if audio && t >= start {
+                    if let Some(at) = at.as_mut() {
+                        if at.add_frame(&p.data, t - start, true) {
+                            res.audio_frames += 1;
+                        }
+                    }
+                }
+                continue;
+            }
+            if t < decode_from {
+                continue;
+            }
+            match reencoder.as_mut() {
+                None => {
+                    if vt.add_frame(&p.data, t - start, p.key) {
+                        res.video_frames += 1;
+                    }
+                }
+                Some(re) => {
+                    let ms = (t.saturating_sub(start) / 1_000_000) as i64;
+                    match re.decode(reader.format, p.data, p.key, ms) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(e) => {
+                            log::warn!("Failed to decode a frame of {}: {}", input, e);
+                            continue;
+                        }
+                    }
+                    if t < start {
+                        continue;
+                    }
+                    for f in re.encode(ms)? {
+                        if vt.add_frame(&f.data, f.pts as u64 * 1_000_000, f.key) {
+                            res.video_frames += 1;
+                        }
+                    }
+                }
+            }
+            res.duration_ms = res.duration_ms.max(t.saturating_sub(start) / 1_000_000);
+        }
+    }
+    let finalized = webm.finalize(None);
+    if res.video_frames == 0 {
+        std::fs::remove_file(output).ok();
+        bail!("no video frames in the range");
+    }
+    if !finalized {
+        bail!("Failed to finalize {}", output);
+    }
+    Ok(res)
+}
+
+fn video_frame_union(
+    format: CodecFormat,
+    data: Vec<u8>,
+    key: bool,
+    ms: i64,
+) -> ResultType<video_frame::Union> {
+    let frames = EncodedVideoFrames {
+        frames: vec![EncodedVideoFrame {
+            data: data.into(),
+            key,
+            pts: ms,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    Ok(match format {
+        CodecFormat::VP8 => video_frame::Union::Vp8s(frames),
+        CodecFormat::VP9 => video_frame::Union::Vp9s(frames),
+        CodecFormat::AV1 => video_frame::Union::Av1s(frames),
+        _ => bail!("unsupported codec {:?}", format),
+    })
+}
+
+struct RgbaBuffer<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl TraitPixelBuffer for RgbaBuffer<'_> {
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn stride(&self) -> Vec<usize> {
+        vec![self.width * 4]
+    }
+
+    fn pixfmt(&self) -> Pixfmt {
+        Pixfmt::RGBA
+    }
+}
+
+// Decodes frames and encodes them again
+struct Reencoder {
+    decoder: Option<(CodecFormat, Decoder)>,
+    encoder: Encoder,
+    width: usize,
+    height: usize,
+    rgb: ImageRgb,
+    canvas: Vec<u8>,
+    yuv: Vec<u8>,
+    mid: Vec<u8>,
+    last_ms: i64,
+}
+
+impl Reencoder {
+    fn new(
+        format: CodecFormat,
+        width: usize,
+        height: usize,
+        bitrate: Option<u32>,
+    ) -> ResultType<Self> {
+        let quality = match bitrate {
+            Some(kbps) => kbps as f32 / base_bitrate(width as _, height as _).max(1) as f32,
+            None => Quality::Balanced.ratio(),
+        };
+        let cfg = match format {
+            CodecFormat::VP8 | CodecFormat::VP9 => EncoderCfg::VPX(VpxEncoderConfig {
+                width: width as _,
+                height: height as _,
+                quality,
+                codec: if format == CodecFormat::VP8 {
+                    VpxVideoCodecId::VP8
+                } else {
+                    VpxVideoCodecId::VP9
+                },
+                keyframe_interval: Some(KEYFRAME_INTERVAL),
+            }),
+            CodecFormat::AV1 => EncoderCfg::AOM(AomEncoderConfig {
+                width: width as _,
+                height: height as _,
+                quality,
+                keyframe_interval: Some(KEYFRAME_INTERVAL),
+            }),
+            _ => bail!("unsupported codec {:?}, use VP8, VP9 or AV1", format),
+        };
+        Ok(Self {
+            decoder: None,
+            encoder: Encoder::new(cfg, false)?,
+            width,
+            height,
+            rgb: ImageRgb::new(ImageFormat::ABGR, 1),
+            canvas: vec![],
+            yuv: vec![],
+            mid: vec![],
+            last_ms: -1,
+        })
+    }
+
+    // Returns whether a frame was decoded
+    fn decode(
+        &mut self,
+        format: CodecFormat,
+        data: Vec<u8>,
+        key: bool,
+        ms: i64,
+    ) -> ResultType<bool> {
+        if self.decoder.as_ref().map_or(true, |(f, _)| *f != format) {
+            self.decoder = Some((format, Decoder::new(format, None)));
+        }
+        let Some((_, decoder)) = self.decoder.as_mut() else {
+            bail!("no decoder");
+        };
+        decoder.handle_video_frame(
+            &video_frame_union(format, data, key, ms)?,
+            &mut self.rgb,
+            &mut ImageTexture::default(),
+            &mut true,
+            &mut None,
+        )
+    }
+
+    // Encode the last decoded frame
+    fn encode(&mut self, ms: i64) -> ResultType<Vec<EncodedVideoFrame>> {
+        let ms = ms.max(self.last_ms + 1);
+        self.last_ms = ms;
+        let src = if self.rgb.w == self.width && self.rgb.h == self.height {
+            &self.rgb.raw
+        } else {
+            center(&self.rgb, self.width, self.height, &mut self.canvas);
+            &self.canvas
+        };
+        let buffer = RgbaBuffer {
+            data: src,
+            width: self.width,
+            height: self.height,
+        };
+        convert_to_yuv(&buffer, self.encoder.yuvfmt(), &mut self.yuv, &mut self.mid)?;
+        // Nothing is returned while the encoder buffers
+        let Ok(vf) = self
+            .encoder
+            .encode_to_message(EncodeInput::YUV(&self.yuv), ms)
+        else {
+            return Ok(vec![]);
+        };
+        Ok(match vf.union {
+            Some(video_frame::Union::Vp8s(f))
+            | Some(video_frame::Union::Vp9s(f))
+            | Some(video_frame::Union::Av1s(f)) => f.frames.into_iter().collect(),
+            _ => vec![],
+        })
+    }
+}
+
+// Center `rgb` on a black canvas, cropped if larger
+fn center(rgb: &ImageRgb, width: usize, height: usize, canvas: &mut Vec<u8>) {
+    canvas.clear();
+    canvas.resize(width * height * 4, 0);
+    for px in canvas.chunks_exact_mut(4) {
+        px[3] = 255;
+    }
+    if rgb.h == 0 {
+        return;
+    }
+    let stride = rgb.raw.len() / rgb.h;
+    let (w, h) = (rgb.w.min(width), rgb.h.min(height));
+    let (dx, dy) = ((width - w) / 2, (height - h) / 2);
+    let (sx, sy) = ((rgb.w - w) / 2, (rgb.h - h) / 2);
+    for y in 0..h {
+        let src = (sy + y) * stride + sx * 4;
+        let dst = ((dy + y) * width + dx) * 4;
+        canvas[dst..dst + w * 4].copy_from_slice(&rgb.raw[src..src + w * 4]);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn save_png(&self, path: &str) -> ResultType<()> {
+        repng::encode(
+            File::create(path)?,
+            self.width as _,
+            self.height as _,
+            &self.data,
+        )?;
+        Ok(())
+    }
+}
+
+// Box filter to `height`, never scaled up, so no box is empty
+fn scale(rgb: &ImageRgb, height: usize) -> RgbaImage {
+    let (sw, sh) = (rgb.w, rgb.h);
+    let stride = if sh > 0 { rgb.raw.len() / sh } else { 0 };
+    let height = height.clamp(1, sh.max(1));
+    let width = ((sw * height + sh / 2) / sh.max(1)).max(1);
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let (y0, y1) = (y * sh / height, (y + 1) * sh / height);
+        for x in 0..width {
+            let (x0, x1) = (x * sw / width, (x + 1) * sw / width);
+            let mut sum = [0usize; 4];
+            let mut n = 0;
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = sy * stride + sx * 4;
+                    for c in 0..4 {
+                        sum[c] += rgb.raw[i + c] as usize;
+                    }
+                    n += 1;
+                }
+            }
+            for c in 0..4 {
+                data.push((sum[c] / n.max(1)) as u8);
+            }
+        }
+    }
+    RgbaImage {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Images side by side, top aligned on black.
+pub fn strip(images: &[RgbaImage]) -> RgbaImage {
+    let width = images.iter().map(|i| i.width).sum();
+    let height = images.iter().map(|i| i.height).max().unwrap_or_default();
+    let mut data = vec![0; width * height * 4];
+    for px in data.chunks_exact_mut(4) {
+        px[3] = 255;
+    }
+    let mut x = 0;
+    for image in images {
+        for y in 0..image.height {
+            let src = &image.data[y * image.width * 4..(y + 1) * image.width * 4];
+            let dst = (y * width + x) * 4;
+            data[dst..dst + src.len()].copy_from_slice(src);
+        }
+        x += image.width;
+    }
+    RgbaImage {
+        width,
+        height,
+        data,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThumbnailOptions {
+    /// At least this long between thumbnails.
+    pub interval_ms: u64,
+    pub height: usize,
+    pub max_count: usize,
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            interval_ms: 10_000,
+            height: 90,
+            max_count: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    /// Since the first frame.
+    pub ms: u64,
+    pub image: RgbaImage,
+}
+
+/// Thumbnails of key frames, which are decoded alone.
+pub fn thumbnails(input: &str, opts: &ThumbnailOptions) -> ResultType<Vec<Thumbnail>> {
+    let mut reader = open_webm(input)?;
+    let mut decoder = Decoder::new(reader.format, None);
+    let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+    let mut first_ns = None;
+    let mut next_ns = 0;
+    let mut v = vec![];
+    while let Some(p) = reader.next_packet()? {
+        let first_ns = *first_ns.get_or_insert(p.ns);
+        let ns = p.ns.saturating_sub(first_ns);
+        if !p.video || !p.key || ns < next_ns {
+            continue;
+        }
+        let ms = ns / 1_000_000;
+        let frame = video_frame_union(reader.format, p.data, true, ms as _)?;
+        match decoder.handle_video_frame(
+            &frame,
+            &mut rgb,
+            &mut ImageTexture::default(),
+            &mut true,
+            &mut None,
+        ) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                log::warn!("Failed to decode a key frame of {}: {}", input, e);
+                continue;
+            }
+        }
+        v.push(Thumbnail {
+            ms,
+            image: scale(&rgb, opts.height),
+        });
+        if v.len() >= opts.max_count {
+            break;
+        }
+        next_ns = ns + opts.interval_ms * 1_000_000;
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn rgb(w: usize, h: usize, f: impl Fn(usize, usize) -> [u8; 4]) -> ImageRgb {
+        let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+        rgb.w = w;
+        rgb.h = h;
+        for y in 0..h {
+            for x in 0..w {
+                rgb.raw.extend_from_slice(&f(x, y));
+            }
+        }
+        rgb
+    }
+
+    #[test]
+    fn test_read_header() {
+        let mut r: &[u8] = &[0x1A, 0x45, 0xDF, 0xA3, 0x84];
+        assert_eq!(read_header(&mut r).unwrap(), (ID_EBML, Some(4)));
+        let mut r: &[u8] = &[
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        assert_eq!(read_header(&mut r).unwrap(), (ID_SEGMENT, None));
+        let mut r: &[u8] = &[0xA3, 0x40, 0x02];
+        assert_eq!(read_header(&mut r).unwrap(), (ID_SIMPLE_BLOCK, Some(2)));
+        let mut r: &[u8] = &[0x00];
+        assert!(read_header(&mut r).is_err());
+        assert_eq!(read_uint(&[0x0F, 0x42, 0x40]), 1_000_000);
+    }
+
+    fn write_webm(path: &str, keys: &[bool], audio: bool) {
+        let (mut webm, mut vt, mut at) = webm_segment(
+            File::create(path).unwrap(),
+            64,
+            48,
+            CodecFormat::VP9,
+            if audio { Some(2) } else { None },
+        )
+        .unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            let ns = 1_000_000_000 + i as u64 * 100_000_000;
+            assert!(vt.add_frame(&[i as u8; 10], ns, *key));
+            if let Some(at) = at.as_mut() {
+                assert!(at.add_frame(&[0xF8, i as u8], ns + 10_000_000, true));
+            }
+        }
+        assert!(webm.finalize(None));
+    }
+
+    #[test]
+    fn test_webm_reader() {
+        let path = std::env::temp_dir()
+            .join(format!("record_transcode_{}.webm", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        write_webm(&path, &[true, false, false], true);
+        let mut reader = WebmReader::new(Cursor::new(std::fs::read(&path).unwrap())).unwrap();
+        assert_eq!(reader.format, CodecFormat::VP9);
+        assert_eq!((reader.width, reader.height), (64, 48));
+        assert_eq!(reader.audio_channels, Some(2));
+        let mut packets = vec![];
+        while let Some(p) = reader.next_packet().unwrap() {
+            packets.push(p);
+        }
+        let video: Vec<_> = packets.iter().filter(|p| p.video).collect();
+        assert_eq!(video.len(), 3);
+        assert_eq!(video[1].ns, 1_100_000_000);
+        assert_eq!(video[1].data, vec![1; 10]);
+        assert_eq!(
+            video.iter().map(|p| p.key).collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+        assert_eq!(packets.iter().filter(|p| !p.video).count(), 3);
+
+        // Cut off by a crash
+        let data = std::fs::read(&path).unwrap();
+        let mut reader = WebmReader::new(Cursor::new(data[..data.len() - 40].to_vec())).unwrap();
+        while reader.next_packet().unwrap().is_some() {}
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_concat_and_trim() {
+        let dir = std::env::temp_dir().join(format!("record_transcode_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let (a, b, out) = (path("a.webm"), path("b.webm"), path("out.webm"));
+        write_webm(&a, &[true, false, false, false, true, false], true);
+        write_webm(&b, &[true, false], false);
+
+        let res = concat(&[&a, &b], &out, &Default::default()).unwrap();
+        assert!(!res.reencoded);
+        assert_eq!((res.video_frames, res.audio_frames), (8, 6));
+        let p = probe(&out).unwrap();
+        assert_eq!(p.video_frames, 8);
+        // b starts a frame after the last audio of a
+        assert_eq!(p.keyframes, vec![0, 400_000_000, 510_000_000 + JOIN_GAP_NS]);
+
+        // Starts at the key frame before
+        let res = transcode(
+            &a,
+            &out,
+            &TranscodeOptions {
+                start_ms: Some(450),
+                end_ms: Some(500),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!res.reencoded);
+        assert_eq!(res.video_frames, 2);
+        assert_eq!(probe(&out).unwrap().keyframes, vec![0]);
+
+        assert!(transcode(&a, &a, &Default::default()).is_err());
+        assert!(transcode(
+            &a,
+            &out,
+            &TranscodeOptions {
+                start_ms: Some(10_000),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert!(!Path::new(&out).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_center() {
+        let mut canvas = vec![];
+        center(&rgb(2, 2, |_, _| [9, 9, 9, 9]), 4, 2, &mut canvas);
+        let row: Vec<u8> = [[0, 0, 0, 255], [9; 4], [9; 4], [0, 0, 0, 255]].concat();
+        assert_eq!(canvas, [row.clone(), row].concat());
+        // Cropped
+        center(&rgb(4, 1, |x, _| [x as u8; 4]), 2, 1, &mut canvas);
+        assert_eq!(canvas, [[1; 4], [2; 4]].concat());
+    }
+
+    #[test]
+    fn test_scale_and_strip() {
+        let image = scale(&rgb(4, 4, |x, _| [if x < 2 { 0 } else { 200 }; 4]), 2);
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.data, [[0; 4], [200; 4], [0; 4], [200; 4]].concat());
+        // Never scaled up
+        assert_eq!(scale(&rgb(3, 1, |_, _| [1; 4]), 10).height, 1);
+
+        let strip = strip(&[image.clone(), scale(&rgb(1, 1, |_, _| [7; 4]), 1)]);
+        assert_eq!((strip.width, strip.height), (3, 2));
+        assert_eq!(&strip.data[8..12], &[7; 4]);
+        assert_eq!(&strip.data[20..24], &[0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,432 @@
+//! Redaction of screen regions in captured frames, before they are encoded,
+//! so the regions are never sent or recorded: fixed rectangles, or windows
+//! matched by title or class where they can be listed (X11).
+//!
+//! The regions are blacked out, or pixelated in blocks of `PIXELATE_BLOCK`
+//! pixels, on the YUV frame given to the encoder or on the RGBA screenshot.
+use crate::common::{bail, ResultType};
+use crate::{EncodeYuvFormat, Pixfmt};
+
+/// The size of the blocks of a pixelated region, in frame pixels
+pub const PIXELATE_BLOCK: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedactStyle {
+    #[default]
+    Black,
+    Pixelate,
+}
+
+/// A region of the screen, or of the frame once moved by the frame origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedactRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl RedactRect {
+    pub fn offset(&self, dx: i32, dy: i32) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..*self
+        }
+    }
+
+    // (x0, y0, x1, y1) inside a frame of `width` x `height`
+    fn clip(&self, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+        let x0 = self.x.max(0) as i64;
+        let y0 = self.y.max(0) as i64;
+        let x1 = (self.x as i64 + self.w as i64).min(width as i64);
+        let y1 = (self.y as i64 + self.h as i64).min(height as i64);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        Some((x0 as _, y0 as _, x1 as _, y1 as _))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RedactConfig {
+    pub rects: Vec<RedactRect>,
+    /// Matched case insensitively against the title or the class of a window
+    pub windows: Vec<String>,
+    pub style: RedactStyle,
+}
+
+impl RedactConfig {
+    /// Parses rules separated by ";", e.g.
+    /// "rect=0,0,400,300;window=keepass;window=bank;style=pixelate".
+    /// `rect` is x,y,width,height in screen pixels, `style` is black (the
+    /// default) or pixelate.
+    pub fn parse(s: &str) -> ResultType<Self> {
+        let mut cfg = Self::default();
+        for pair in s.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = pair.split_once('=') else {
+                bail!("Invalid redaction rule {:?}", pair);
+            };
+            let value = value.trim();
+            match key.trim() {
+                "rect" => {
+                    let v: Vec<i32> = value
+                        .split(',')
+                        .filter_map(|n| n.trim().parse().ok())
+                        .collect();
+                    match v[..] {
+                        [x, y, w, h] if w > 0 && h > 0 => cfg.rects.push(RedactRect { x, y, w, h }),
+                        _ => bail!("Invalid redaction rect {:?}", value),
+                    }
+                }
+                "window" if !value.is_empty() => cfg.windows.push(value.to_owned()),
+                "window" => bail!("Empty redaction window"),
+                "style" => match value {
+                    "black" => cfg.style = RedactStyle::Black,
+                    "pixelate" => cfg.style = RedactStyle::Pixelate,
+                    _ => bail!("Invalid redaction style {:?}", value),
+                },
+                key => bail!("Unknown redaction rule {:?}", key),
+            }
+        }
+        Ok(cfg)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty() && self.windows.is_empty()
+    }
+}
+
+// A plane of `fill.len()` interleaved samples a pixel, `sub` times smaller
+// than the frame both ways
+struct Plane<'a> {
+    offset: usize,
+    stride: usize,
+    sub: usize,
+    fill: &'a [u8],
+}
+
+/// Redacts `rects` of the frame in `yuv`, laid out as `fmt`.
+pub fn redact_yuv(
+    yuv: &mut [u8],
+    fmt: &EncodeYuvFormat,
+    rects: &[RedactRect],
+    style: RedactStyle,
+) -> ResultType<()> {
+    // Black in the limited range of the encoders
+    const Y: &[u8] = &[16];
+    const UV: &[u8] = &[128];
+    let planes = match fmt.pixfmt {
+        Pixfmt::I420 | Pixfmt::I444 => {
+            let sub = if fmt.pixfmt == Pixfmt::I420 { 2 } else { 1 };
+            let [y, u, v, ..] = fmt.stride[..] else {
+                bail!("Invalid {:?} strides {:?}", fmt.pixfmt, fmt.stride);
+            };
+            vec![
+                Plane {
+                    offset: 0,
+                    stride: y,
+                    sub: 1,
+                    fill: Y,
+                },
+                Plane {
+                    offset: fmt.u,
+                    stride: u,
+                    sub,
+                    fill: UV,
+                },
+                Plane {
+                    offset: fmt.v,
+                    stride: v,
+                    sub,
+                    fill: UV,
+                },
+            ]
+        }
+        Pixfmt::NV12 => {
+            let [y, uv, ..] = fmt.stride[..] else {
+                bail!("Invalid NV12 strides {:?}", fmt.stride);
+            };
+            vec![
+                Plane {
+                    offset: 0,
+                    stride: y,
+                    sub: 1,
+                    fill: Y,
+                },
+                Plane {
+                    offset: fmt.u,
+                    stride: uv,
+                    sub: 2,
+                    fill: &[128, 128],
+                },
+            ]
+        }
+        pixfmt => bail!("Can't redact {:?}", pixfmt),
+    };
+    for rect in rects {
+        if let Some(r) = rect.clip(fmt.w, fmt.h) {
+            for plane in planes.iter() {
+                redact_plane(yuv, plane, r, style);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Redacts `rects` of a RGBA or BGRA frame, the alpha kept opaque.
+pub fn redact_rgba(
+    data: &mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    rects: &[RedactRect],
+    style: RedactStyle,
+) {
+    let plane = Plane {
+        offset: 0,
+        stride,
+        sub: 1,
+        fill: &[0, 0, 0, 255],
+    };
+    for rect in rects {
+        if let Some(r) = rect.clip(width, height) {
+            redact_plane(data, &plane, r, style);
+        }
+    }
+}
+
+fn redact_plane(
+    data: &mut [u8],
+    plane: &Plane,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+    style: RedactStyle,
+) {
+    let n = plane.fill.len();
+    // Rounded out, a subsampled sample is redacted if any of its pixels is
+    let (x0, y0) = (x0 / plane.sub, y0 / plane.sub);
+    let (x1, y1) = (x1.div_ceil(plane.sub), y1.div_ceil(plane.sub));
+    // Never past the buffer, whatever the format says
+    let len = data.len();
+    let row = |y: usize| {
+        let start = plane.offset + y * plane.stride;
+        Some(start + x0 * n..start + x1 * n).filter(|r| r.end <= len)
+    };
+    match style {
+        RedactStyle::Black => {
+            for y in y0..y1 {
+                if let Some(r) = row(y) {
+                    for px in data[r].chunks_exact_mut(n) {
+                        px.copy_from_slice(plane.fill);
+                    }
+                }
+            }
+        }
+        RedactStyle::Pixelate => {
+            let block = (PIXELATE_BLOCK / plane.sub).max(1);
+            for by in (y0..y1).step_by(block) {
+                let rows = by..(by + block).min(y1);
+                for bx in (x0..x1).step_by(block) {
+                    let cols = bx * n..(bx + block).min(x1) * n;
+                    let mut sum = [0usize; 4];
+                    let mut count = 0;
+                    for y in rows.clone() {
+                        let start = plane.offset + y * plane.stride;
+                        let Some(px) = data.get(start + cols.start..start + cols.end) else {
+                            continue;
+                        };
+                        for px in px.chunks_exact(n) {
+                            for (s, v) in sum.iter_mut().zip(px) {
+                                *s += *v as usize;
+                            }
+                            count += 1;
+                        }
+                    }
+                    if count == 0 {
+                        continue;
+                    }
+                    let mut avg = [0u8; 4];
+                    for (a, s) in avg.iter_mut().zip(sum) {
+                        *a = (s / count) as u8;
+                    }
+                    for y in rows.clone() {
+                        let start = plane.offset + y * plane.stride;
+                        if let Some(px) = data.get_mut(start + cols.start..start + cols.end) {
+                            for px in px.chunks_exact_mut(n) {
+                                px.copy_from_slice(&avg[..n]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> RedactRect {
+        RedactRect { x, y, w, h }
+    }
+
+    // An I420 frame of `w` x `h` with distinct samples, as laid out for the
+    // encoders
+    fn i420(w: usize, h: usize) -> (Vec<u8>, EncodeYuvFormat) {
+        let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+        let fmt = EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w,
+            h,
+            stride: vec![w, cw, cw],
+            u: w * h,
+            v: w * h + cw * ch,
+        };
+        let yuv = (0..w * h + cw * ch * 2).map(|i| (i % 100) as u8).collect();
+        (yuv, fmt)
+    }
+
+    #[test]
+    fn test_parse() {
+        let cfg = RedactConfig::parse(
+            " rect=10,20,300,200 ; window=KeePassXC;style=pixelate;window=bank",
+        )
+        .unwrap();
+        assert_eq!(cfg.rects, vec![rect(10, 20, 300, 200)]);
+        assert_eq!(cfg.windows, vec!["KeePassXC", "bank"]);
+        assert_eq!(cfg.style, RedactStyle::Pixelate);
+        assert!(RedactConfig::parse("").unwrap().is_empty());
+        assert_eq!(RedactConfig::parse("rect=-5,0,1,1").unwrap().rects[0].x, -5);
+        for s in [
+            "rect=1,2,3",
+            "rect=0,0,0,10",
+            "rect=a,b,c,d",
+            "window=",
+            "style=blur",
+            "region=1",
+            "bank",
+        ] {
+            assert!(RedactConfig::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_black_i420() {
+        let (mut yuv, fmt) = i420(8, 6);
+        let orig = yuv.clone();
+        // Odd edges, the chroma samples they touch are redacted too
+        redact_yuv(&mut yuv, &fmt, &[rect(1, 1, 4, 2)], RedactStyle::Black).unwrap();
+        for y in 0..6 {
+            for x in 0..8 {
+                let i = y * 8 + x;
+                let inside = (1..5).contains(&x) && (1..3).contains(&y);
+                assert_eq!(yuv[i], if inside { 16 } else { orig[i] }, "{} {}", x, y);
+            }
+        }
+        for plane in [fmt.u, fmt.v] {
+            for y in 0..3 {
+                for x in 0..4 {
+                    let i = plane + y * 4 + x;
+                    let inside = x <= 2 && y <= 1;
+                    assert_eq!(yuv[i], if inside { 128 } else { orig[i] }, "{} {}", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_black_nv12_and_i444() {
+        let fmt = EncodeYuvFormat {
+            pixfmt: Pixfmt::NV12,
+            w: 4,
+            h: 4,
+            stride: vec![4, 4],
+            u: 16,
+            v: 17,
+        };
+        let mut yuv = vec![1; 16 + 8];
+        redact_yuv(&mut yuv, &fmt, &[rect(2, 0, 2, 2)], RedactStyle::Black).unwrap();
+        assert_eq!(&yuv[..8], &[1, 1, 16, 16, 1, 1, 16, 16]);
+        assert_eq!(&yuv[16..], &[1, 1, 128, 128, 1, 1, 1, 1]);
+
+        let fmt = EncodeYuvFormat {
+            pixfmt: Pixfmt::I444,
+            w: 2,
+            h: 2,
+            stride: vec![2, 2, 2],
+            u: 4,
+            v: 8,
+        };
+        let mut yuv = vec![1; 12];
+        redact_yuv(&mut yuv, &fmt, &[rect(0, 1, 1, 1)], RedactStyle::Black).unwrap();
+        assert_eq!(yuv, [1, 1, 16, 1, 1, 1, 128, 1, 1, 1, 128, 1]);
+    }
+
+    #[test]
+    fn test_clip() {
+        let (mut yuv, fmt) = i420(8, 6);
+        let orig = yuv.clone();
+        // Off the frame
+        let rects = [rect(-10, -10, 5, 5), rect(8, 0, 10, 10), rect(0, 6, 8, 1)];
+        redact_yuv(&mut yuv, &fmt, &rects, RedactStyle::Black).unwrap();
+        assert_eq!(yuv, orig);
+        // Across the whole frame, and a buffer shorter than the format says
+        redact_yuv(
+            &mut yuv,
+            &fmt,
+            &[rect(-4, -4, 100, 100)],
+            RedactStyle::Black,
+        )
+        .unwrap();
+        assert!(yuv[..48].iter().all(|v| *v == 16));
+        assert!(yuv[48..].iter().all(|v| *v == 128));
+        let mut short = orig[..50].to_vec();
+        redact_yuv(&mut short, &fmt, &[rect(0, 0, 8, 6)], RedactStyle::Pixelate).unwrap();
+    }
+
+    #[test]
+    fn test_pixelate_rgba() {
+        // Two blocks wide and one high, plus a column left alone
+        let (w, h) = (PIXELATE_BLOCK * 2 + 1, PIXELATE_BLOCK);
+        let stride = w * 4 + 8;
+        let mut data = vec![0u8; stride * h];
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * stride + x * 4;
+                data[i..i + 4].copy_from_slice(&[x as u8, y as u8, 200, 255]);
+            }
+        }
+        let orig = data.clone();
+        let r = rect(0, 0, PIXELATE_BLOCK as i32 * 2, PIXELATE_BLOCK as i32);
+        redact_rgba(&mut data, w, h, stride, &[r], RedactStyle::Pixelate);
+        let px = |data: &[u8], x: usize, y: usize| data[y * stride + x * 4..][..4].to_vec();
+        // Each block is its average
+        let mid = (PIXELATE_BLOCK - 1) / 2;
+        for y in 0..h {
+            for x in 0..PIXELATE_BLOCK {
+                assert_eq!(px(&data, x, y), [mid as u8, mid as u8, 200, 255]);
+                let x2 = x + PIXELATE_BLOCK;
+                assert_eq!(
+                    px(&data, x2, y),
+                    [(PIXELATE_BLOCK + mid) as u8, mid as u8, 200, 255]
+                );
+            }
+        }
+        let last = w - 1;
+        for y in 0..h {
+            let i = y * stride + last * 4;
+            assert_eq!(data[i..i + 4], orig[i..i + 4]);
+            // The padding
+            assert_eq!(data[i + 4..(y + 1) * stride], orig[i + 4..(y + 1) * stride]);
+        }
+
+        redact_rgba(&mut data, w, h, stride, &[r], RedactStyle::Black);
+        assert_eq!(px(&data, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(px(&data, last, 0), px(&orig, last, 0));
+    }
+}
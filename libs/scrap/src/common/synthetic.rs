@@ -0,0 +1,467 @@
+//! A capturer of generated frames, to run the video pipeline without a
+//! display, e.g. in tests and demos: a moving test pattern, or raw I420
+//! frames or PNG images played in a loop.
+//!
+//! The content advances `fps` times a second, `damage` of those steps change
+//! the image. A frame that is the same as the last one is `WouldBlock`, as
+//! for a static screen.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::common::{bail, ResultType};
+#[cfg(feature = "vram")]
+use crate::AdapterDevice;
+use crate::{Frame, Pixfmt, TraitCapturer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntheticSource {
+    Pattern,
+    /// A file of I420 frames of the configured size
+    Yuv(PathBuf),
+    /// A PNG image, or a directory of them played in name order
+    Png(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticConfig {
+    pub width: usize,
+    pub height: usize,
+    pub fps: u32,
+    /// The part of the steps that change the image, 0 to 1
+    pub damage: f32,
+    pub source: SyntheticSource,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fps: 30,
+            damage: 1.0,
+            source: SyntheticSource::Pattern,
+        }
+    }
+}
+
+impl SyntheticConfig {
+    /// Parses `key=value` pairs separated by `;`: `size=WxH`, `fps=N`,
+    /// `damage=0..1`, and `yuv=FILE` or `png=FILE_OR_DIR`. PNG images keep
+    /// their own size. Unset keys keep the defaults.
+    pub fn parse(s: &str) -> ResultType<Self> {
+        let mut cfg = Self::default();
+        for pair in s.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = pair.split_once('=') else {
+                bail!("Invalid synthetic capture option {:?}", pair);
+            };
+            let value = value.trim();
+            match key.trim() {
+                "size" => {
+                    let size = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                    match size {
+                        Some((w, h)) if w >= 2 && h >= 2 => (cfg.width, cfg.height) = (w, h),
+                        _ => bail!("Invalid synthetic capture size {:?}", value),
+                    }
+                }
+                "fps" => match value.parse() {
+                    Ok(fps) if fps > 0 => cfg.fps = fps,
+                    _ => bail!("Invalid synthetic capture fps {:?}", value),
+                },
+                "damage" => match value.parse::<f32>() {
+                    Ok(damage) if (0.0..=1.0).contains(&damage) => cfg.damage = damage,
+                    _ => bail!("Invalid synthetic capture damage {:?}", value),
+                },
+                "yuv" => cfg.source = SyntheticSource::Yuv(value.into()),
+                "png" => cfg.source = SyntheticSource::Png(value.into()),
+                key => bail!("Unknown synthetic capture option {:?}", key),
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+enum Frames {
+    Pattern,
+    Yuv {
+        file: File,
+        count: u64,
+        buf: Vec<u8>,
+    },
+    // RGBA
+    Png(Vec<Vec<u8>>),
+}
+
+pub struct SyntheticCapturer {
+    cfg: SyntheticConfig,
+    frames: Frames,
+    start: Instant,
+    // Step of the image of the last frame
+    last: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl SyntheticCapturer {
+    pub fn new(mut cfg: SyntheticConfig) -> ResultType<Self> {
+        let frames = match &cfg.source {
+            SyntheticSource::Pattern => Frames::Pattern,
+            SyntheticSource::Yuv(path) => {
+                let file = File::open(path)?;
+                let len = file.metadata()?.len();
+                let count = len / i420_len(cfg.width, cfg.height) as u64;
+                if count == 0 {
+                    bail!(
+                        "{} has no {}x{} I420 frame",
+                        path.display(),
+                        cfg.width,
+                        cfg.height
+                    );
+                }
+                Frames::Yuv {
+                    file,
+                    count,
+                    buf: vec![],
+                }
+            }
+            SyntheticSource::Png(path) => {
+                let mut images = vec![];
+                for path in png_files(path)? {
+                    let (w, h, rgba) = decode_png(&path)?;
+                    if images.is_empty() {
+                        (cfg.width, cfg.height) = (w, h);
+                    } else if (w, h) != (cfg.width, cfg.height) {
+                        bail!(
+                            "{} is {}x{}, not {}x{}",
+                            path.display(),
+                            w,
+                            h,
+                            cfg.width,
+                            cfg.height
+                        );
+                    }
+                    images.push(rgba);
+                }
+                Frames::Png(images)
+            }
+        };
+        Ok(Self {
+            cfg,
+            frames,
+            start: Instant::now(),
+            last: None,
+            data: vec![],
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.cfg.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.cfg.height
+    }
+
+    /// The frame at `elapsed` since the capturer started.
+    pub fn frame_at<'a>(&'a mut self, elapsed: Duration) -> io::Result<Frame<'a>> {
+        let index = (elapsed.as_nanos() * self.cfg.fps as u128 / 1_000_000_000) as u64;
+        let step = index * (self.cfg.damage * 1000.).round() as u64 / 1000;
+        if self.last == Some(step) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let (w, h) = (self.cfg.width, self.cfg.height);
+        let pixfmt = match &mut self.frames {
+            Frames::Pattern => {
+                draw_pattern(&mut self.data, w, h, step);
+                Pixfmt::BGRA
+            }
+            Frames::Yuv { file, count, buf } => {
+                let len = i420_len(w, h);
+                buf.resize(len, 0);
+                file.seek(SeekFrom::Start((step % *count) * len as u64))?;
+                file.read_exact(buf)?;
+                i420_to_bgra(buf, w, h, &mut self.data);
+                Pixfmt::BGRA
+            }
+            Frames::Png(images) => {
+                self.data
+                    .clone_from(&images[(step % images.len() as u64) as usize]);
+                Pixfmt::RGBA
+            }
+        };
+        self.last = Some(step);
+        pixel_buffer(&self.data, pixfmt, w, h)
+    }
+}
+
+impl TraitCapturer for SyntheticCapturer {
+    fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
+        let elapsed = self.start.elapsed();
+        self.frame_at(elapsed)
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        false
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "vram")]
+    fn device(&self) -> AdapterDevice {
+        AdapterDevice::default()
+    }
+
+    #[cfg(feature = "vram")]
+    fn set_output_texture(&mut self, _texture: bool) {}
+}
+
+// FIXME: macos's PixelBuffer cannot be directly created from bytes slice.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+fn pixel_buffer<'a>(data: &'a [u8], pixfmt: Pixfmt, w: usize, h: usize) -> io::Result<Frame<'a>> {
+    Ok(Frame::PixelBuffer(crate::PixelBuffer::new(
+        data, pixfmt, w, h,
+    )))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn pixel_buffer<'a>(_: &'a [u8], _: Pixfmt, _: usize, _: usize) -> io::Result<Frame<'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Synthetic capture is not supported on this platform yet",
+    ))
+}
+
+fn i420_len(w: usize, h: usize) -> usize {
+    w * h + 2 * w.div_ceil(2) * h.div_ceil(2)
+}
+
+// SMPTE like bars, and a box moving one step a frame with a bar of its own
+// position below, so each step looks different
+fn draw_pattern(data: &mut Vec<u8>, w: usize, h: usize, step: u64) {
+    const BARS: [[u8; 4]; 8] = [
+        [192, 192, 192, 255],
+        [0, 192, 192, 255],
+        [192, 192, 0, 255],
+        [0, 192, 0, 255],
+        [192, 0, 192, 255],
+        [0, 0, 192, 255],
+        [192, 0, 0, 255],
+        [16, 16, 16, 255],
+    ];
+    data.resize(w * h * 4, 0);
+    for (x, pixel) in data[..w * 4].chunks_exact_mut(4).enumerate() {
+        pixel.copy_from_slice(&BARS[x * BARS.len() / w]);
+    }
+    for y in 1..h {
+        data.copy_within(..w * 4, y * w * 4);
+    }
+    let size = (w.min(h) / 4).max(1);
+    let x = (step as usize * 4) % (w - size + 1);
+    let y = (h - size) / 2;
+    for row in y..y + size {
+        let line = &mut data[(row * w + x) * 4..(row * w + x + size) * 4];
+        for pixel in line.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[255, 255, 255, 255]);
+        }
+    }
+    let y = (y + size + 2).min(h - 1);
+    let n = (step as usize % w) + 1;
+    for pixel in data[y * w * 4..(y * w + n) * 4].chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[255, 255, 255, 255]);
+    }
+}
+
+// BT.601 limited range
+fn i420_to_bgra(src: &[u8], w: usize, h: usize, dst: &mut Vec<u8>) {
+    let cw = w.div_ceil(2);
+    let (y_plane, uv) = src.split_at(w * h);
+    let (u_plane, v_plane) = uv.split_at(cw * h.div_ceil(2));
+    dst.resize(w * h * 4, 0);
+    for row in 0..h {
+        for col in 0..w {
+            let c = y_plane[row * w + col] as i32 - 16;
+            let d = u_plane[row / 2 * cw + col / 2] as i32 - 128;
+            let e = v_plane[row / 2 * cw + col / 2] as i32 - 128;
+            let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+            let i = (row * w + col) * 4;
+            dst[i] = clamp(298 * c + 516 * d);
+            dst[i + 1] = clamp(298 * c - 100 * d - 208 * e);
+            dst[i + 2] = clamp(298 * c + 409 * e);
+            dst[i + 3] = 255;
+        }
+    }
+}
+
+fn png_files(path: &Path) -> ResultType<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut files: Vec<PathBuf> = std::fs::read_dir(path)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("png")))
+        .collect();
+    if files.is_empty() {
+        bail!("No PNG image in {}", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn decode_png(path: &Path) -> ResultType<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let (w, h) = (info.width as usize, info.height as usize);
+    let buf = &buf[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf.to_vec(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => bail!("{} is not expanded", path.display()),
+    };
+    Ok((w, h, rgba))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TraitPixelBuffer;
+
+    fn frame_data(c: &mut SyntheticCapturer, ms: u64) -> Option<Vec<u8>> {
+        match c.frame_at(Duration::from_millis(ms)) {
+            Ok(Frame::PixelBuffer(f)) => Some(f.data().to_vec()),
+            Ok(_) => panic!("texture"),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let cfg =
+            SyntheticConfig::parse("size=640x480; fps=10;damage=0.25;yuv=/tmp/a.yuv").unwrap();
+        assert_eq!(
+            cfg,
+            SyntheticConfig {
+                width: 640,
+                height: 480,
+                fps: 10,
+                damage: 0.25,
+                source: SyntheticSource::Yuv("/tmp/a.yuv".into()),
+            }
+        );
+        assert_eq!(
+            SyntheticConfig::parse("").unwrap(),
+            SyntheticConfig::default()
+        );
+        assert!(SyntheticConfig::parse("size=640").is_err());
+        assert!(SyntheticConfig::parse("fps=0").is_err());
+        assert!(SyntheticConfig::parse("damage=2").is_err());
+        assert!(SyntheticConfig::parse("depth=8").is_err());
+    }
+
+    #[test]
+    fn test_pattern_damage() {
+        let mut c = SyntheticCapturer::new(SyntheticConfig {
+            width: 64,
+            height: 48,
+            fps: 10,
+            damage: 0.5,
+            source: SyntheticSource::Pattern,
+        })
+        .unwrap();
+        let first = frame_data(&mut c, 0).unwrap();
+        assert_eq!(first.len(), 64 * 48 * 4);
+        // Step 1 at 100ms doesn't change the image, step 2 at 200ms does
+        assert!(frame_data(&mut c, 50).is_none());
+        assert!(frame_data(&mut c, 100).is_none());
+        let second = frame_data(&mut c, 200).unwrap();
+        assert_ne!(first, second);
+        let frames = (0..100)
+            .filter(|i| frame_data(&mut c, 300 + i * 100).is_some())
+            .count();
+        assert_eq!(frames, 50);
+
+        let mut c = SyntheticCapturer::new(SyntheticConfig {
+            damage: 0.,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(frame_data(&mut c, 0).is_some());
+        assert!(frame_data(&mut c, 10_000).is_none());
+    }
+
+    #[test]
+    fn test_yuv_playback() {
+        let path = std::env::temp_dir().join(format!("synthetic_{}.yuv", std::process::id()));
+        // Black then white 4x2 frames
+        let mut yuv = vec![16; 8];
+        yuv.extend([128; 4]);
+        yuv.extend([235; 8]);
+        yuv.extend([128; 4]);
+        std::fs::write(&path, &yuv).unwrap();
+        let mut c = SyntheticCapturer::new(SyntheticConfig {
+            width: 4,
+            height: 2,
+            fps: 1,
+            damage: 1.,
+            source: SyntheticSource::Yuv(path.clone()),
+        })
+        .unwrap();
+        assert_eq!(frame_data(&mut c, 0).unwrap(), [0, 0, 0, 255].repeat(8));
+        assert_eq!(
+            frame_data(&mut c, 1000).unwrap(),
+            [255, 255, 255, 255].repeat(8)
+        );
+        // Looped
+        assert_eq!(frame_data(&mut c, 2000).unwrap(), [0, 0, 0, 255].repeat(8));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_png_playback() {
+        let dir = std::env::temp_dir().join(format!("synthetic_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, color) in [("b.png", [0u8, 0, 255]), ("a.png", [255, 0, 0])] {
+            let file = File::create(dir.join(name)).unwrap();
+            let mut encoder = png::Encoder::new(file, 2, 2);
+            encoder.set_color(png::ColorType::Rgb);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&color.repeat(4)).unwrap();
+        }
+        let mut c = SyntheticCapturer::new(SyntheticConfig {
+            fps: 1,
+            source: SyntheticSource::Png(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!((c.width(), c.height()), (2, 2));
+        assert_eq!(frame_data(&mut c, 0).unwrap(), [255, 0, 0, 255].repeat(4));
+        assert_eq!(
+            frame_data(&mut c, 1000).unwrap(),
+            [0, 0, 255, 255].repeat(4)
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,5 +1,9 @@
 use crate::{common::TraitCapturer, x11, Frame, Pixfmt, TraitPixelBuffer};
-use std::{io, time::Duration};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 pub struct Capturer(x11::Capturer);
 
@@ -33,6 +37,52 @@ impl TraitCapturer for Capturer {
     }
 }
 
+/// Captures a single top-level window, see `x11::WindowCapturer`.
+pub struct WindowCapturer(x11::WindowCapturer);
+
+impl WindowCapturer {
+    pub fn new(window: u32) -> io::Result<WindowCapturer> {
+        let server = match x11::Server::default() {
+            Ok(server) => server,
+            Err(_) => return Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        x11::WindowCapturer::new(server, window).map(WindowCapturer)
+    }
+
+    pub fn windows() -> io::Result<Vec<x11::Window>> {
+        let server = match x11::Server::default() {
+            Ok(server) => server,
+            Err(_) => return Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        Ok(x11::windows(&server))
+    }
+
+    pub fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.0.height()
+    }
+
+    pub fn state(&self) -> Arc<Mutex<x11::WindowState>> {
+        self.0.state()
+    }
+}
+
+impl TraitCapturer for WindowCapturer {
+    fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
+        let width = self.width();
+        let height = self.height();
+        Ok(Frame::PixelBuffer(PixelBuffer::new(
+            self.0.frame()?,
+            Pixfmt::BGRA,
+            width,
+            height,
+        )))
+    }
+}
+
 pub struct PixelBuffer<'a> {
     data: &'a [u8],
     pixfmt: Pixfmt,
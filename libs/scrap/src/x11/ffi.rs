@@ -100,9 +100,171 @@ extern "C" {
         e: *mut *mut xcb_generic_error_t,
     ) -> *mut xcb_get_geometry_reply_t;
 
+    pub fn xcb_intern_atom(
+        c: *mut xcb_connection_t,
+        only_if_exists: u8,
+        name_len: u16,
+        name: *const i8,
+    ) -> xcb_intern_atom_cookie_t;
+
+    pub fn xcb_intern_atom_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_intern_atom_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_intern_atom_reply_t;
+
+    pub fn xcb_get_property(
+        c: *mut xcb_connection_t,
+        delete: u8,
+        window: xcb_window_t,
+        property: xcb_atom_t,
+        type_: xcb_atom_t,
+        long_offset: u32,
+        long_length: u32,
+    ) -> xcb_get_property_cookie_t;
+
+    pub fn xcb_get_property_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_get_property_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_get_property_reply_t;
+
+    pub fn xcb_get_property_value(r: *const xcb_get_property_reply_t) -> *const c_void;
+
+    pub fn xcb_get_property_value_length(r: *const xcb_get_property_reply_t) -> i32;
+
+    pub fn xcb_query_tree(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+    ) -> xcb_query_tree_cookie_t;
+
+    pub fn xcb_query_tree_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_query_tree_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_query_tree_reply_t;
+
+    pub fn xcb_query_tree_children(r: *const xcb_query_tree_reply_t) -> *const xcb_window_t;
+
+    pub fn xcb_query_tree_children_length(r: *const xcb_query_tree_reply_t) -> i32;
+
+    pub fn xcb_translate_coordinates(
+        c: *mut xcb_connection_t,
+        src_window: xcb_window_t,
+        dst_window: xcb_window_t,
+        src_x: i16,
+        src_y: i16,
+    ) -> xcb_translate_coordinates_cookie_t;
+
+    pub fn xcb_translate_coordinates_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_translate_coordinates_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_translate_coordinates_reply_t;
+
+    pub fn xcb_get_window_attributes(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+    ) -> xcb_get_window_attributes_cookie_t;
+
+    pub fn xcb_get_window_attributes_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_get_window_attributes_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_get_window_attributes_reply_t;
+
+    pub fn xcb_get_input_focus(c: *mut xcb_connection_t) -> xcb_get_input_focus_cookie_t;
+
+    pub fn xcb_get_input_focus_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_get_input_focus_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_get_input_focus_reply_t;
+
+    // To make windows in the tests
+    #[cfg(test)]
+    pub fn xcb_create_window(
+        c: *mut xcb_connection_t,
+        depth: u8,
+        wid: xcb_window_t,
+        parent: xcb_window_t,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+        border_width: u16,
+        class: u16,
+        visual: xcb_visualid_t,
+        value_mask: u32,
+        value_list: *const u32,
+    ) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_destroy_window(c: *mut xcb_connection_t, window: xcb_window_t) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_map_window(c: *mut xcb_connection_t, window: xcb_window_t) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_configure_window(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+        value_mask: u16,
+        value_list: *const u32,
+    ) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_change_property(
+        c: *mut xcb_connection_t,
+        mode: u8,
+        window: xcb_window_t,
+        property: xcb_atom_t,
+        type_: xcb_atom_t,
+        format: u8,
+        data_len: u32,
+        data: *const c_void,
+    ) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_set_input_focus(
+        c: *mut xcb_connection_t,
+        revert_to: u8,
+        focus: xcb_window_t,
+        time: xcb_timestamp_t,
+    ) -> xcb_void_cookie_t;
+
+    #[cfg(test)]
+    pub fn xcb_flush(c: *mut xcb_connection_t) -> i32;
 }
 
 pub const XCB_IMAGE_FORMAT_Z_PIXMAP: u8 = 2;
+pub const XCB_MAP_STATE_VIEWABLE: u8 = 2;
+pub const XCB_WINDOW_CLASS_INPUT_ONLY: u16 = 2;
+pub const XCB_GET_PROPERTY_TYPE_ANY: xcb_atom_t = 0;
+pub const XCB_ATOM_NONE: xcb_atom_t = 0;
+pub const XCB_NONE: xcb_window_t = 0;
+pub const XCB_INPUT_FOCUS_POINTER_ROOT: xcb_window_t = 1;
+#[cfg(test)]
+pub const XCB_ATOM_STRING: xcb_atom_t = 31;
+pub const XCB_ATOM_WINDOW: xcb_atom_t = 33;
+pub const XCB_ATOM_WM_NAME: xcb_atom_t = 39;
+pub const XCB_ATOM_WM_CLASS: xcb_atom_t = 67;
+#[cfg(test)]
+pub const XCB_PROP_MODE_REPLACE: u8 = 0;
+#[cfg(test)]
+pub const XCB_WINDOW_CLASS_INPUT_OUTPUT: u16 = 1;
+#[cfg(test)]
+pub const XCB_CW_BACK_PIXEL: u32 = 2;
+#[cfg(test)]
+pub const XCB_CONFIG_WINDOW_X: u16 = 1;
+#[cfg(test)]
+pub const XCB_CONFIG_WINDOW_Y: u16 = 2;
+#[cfg(test)]
+pub const XCB_CONFIG_WINDOW_WIDTH: u16 = 4;
+#[cfg(test)]
+pub const XCB_CONFIG_WINDOW_HEIGHT: u16 = 8;
+#[cfg(test)]
+pub const XCB_CURRENT_TIME: xcb_timestamp_t = 0;
 
 pub type xcb_atom_t = u32;
 pub type xcb_connection_t = c_void;
@@ -213,6 +375,42 @@ pub struct xcb_get_geometry_cookie_t {
     pub sequence: u32,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_intern_atom_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_property_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_query_tree_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_translate_coordinates_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_window_attributes_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_input_focus_cookie_t {
+    pub sequence: u32,
+}
+
 #[repr(C)]
 pub struct xcb_generic_error_t {
     pub response_type: u8,
@@ -281,3 +479,79 @@ pub struct xcb_get_geometry_reply_t {
     pub border_width: u16,
     pub pad0: [u8; 2],
 }
+
+#[repr(C)]
+pub struct xcb_intern_atom_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub atom: xcb_atom_t,
+}
+
+#[repr(C)]
+pub struct xcb_get_property_reply_t {
+    pub response_type: u8,
+    pub format: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub type_: xcb_atom_t,
+    pub bytes_after: u32,
+    pub value_len: u32,
+    pub pad0: [u8; 12],
+}
+
+#[repr(C)]
+pub struct xcb_query_tree_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub root: xcb_window_t,
+    pub parent: xcb_window_t,
+    pub children_len: u16,
+    pub pad1: [u8; 14],
+}
+
+#[repr(C)]
+pub struct xcb_translate_coordinates_reply_t {
+    pub response_type: u8,
+    pub same_screen: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub child: xcb_window_t,
+    pub dst_x: i16,
+    pub dst_y: i16,
+}
+
+#[repr(C)]
+pub struct xcb_get_window_attributes_reply_t {
+    pub response_type: u8,
+    pub backing_store: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub visual: xcb_visualid_t,
+    pub class: u16,
+    pub bit_gravity: u8,
+    pub win_gravity: u8,
+    pub backing_planes: u32,
+    pub backing_pixel: u32,
+    pub save_under: u8,
+    pub map_is_installed: u8,
+    pub map_state: u8,
+    pub override_redirect: u8,
+    pub colormap: xcb_colormap_t,
+    pub all_event_masks: u32,
+    pub your_event_mask: u32,
+    pub do_not_propagate_mask: u16,
+    pub pad0: [u8; 2],
+}
+
+#[repr(C)]
+pub struct xcb_get_input_focus_reply_t {
+    pub response_type: u8,
+    pub revert_to: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub focus: xcb_window_t,
+}
@@ -120,7 +120,7 @@ fn get_atom_name(conn: *mut xcb_connection_t, atom: xcb_atom_t) -> String {
     }
 }
 
-unsafe fn get_pixfmt(conn: *mut xcb_connection_t, root: xcb_window_t) -> Option<Pixfmt> {
+pub(super) unsafe fn get_pixfmt(conn: *mut xcb_connection_t, root: xcb_window_t) -> Option<Pixfmt> {
     let geo_cookie = xcb_get_geometry_unchecked(conn, root);
     let geo = xcb_get_geometry_reply(conn, geo_cookie, ptr::null_mut());
     if geo.is_null() {
@@ -0,0 +1,687 @@
+use hbb_common::libc;
+use std::{
+    io, ptr,
+    rc::Rc,
+    slice,
+    sync::{Arc, Mutex},
+};
+
+use super::ffi::*;
+use super::iter::get_pixfmt;
+use super::{Rect, Server};
+use crate::Pixfmt;
+
+/// A top-level window of the default screen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub id: xcb_window_t,
+    pub title: String,
+    /// The class of `WM_CLASS`, e.g. "firefox"
+    pub class: String,
+    /// In root coordinates, without the border
+    pub rect: Rect,
+}
+
+impl Window {
+    /// If the class or the title contains `pattern`, case insensitive.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        !pattern.is_empty()
+            && (self.class.to_lowercase().contains(&pattern)
+                || self.title.to_lowercase().contains(&pattern))
+    }
+}
+
+/// The viewable top-level windows, bottom to top.
+///
+/// These are the clients of the window manager, or the children of the root
+/// without one, e.g. on Xvfb.
+pub fn windows(server: &Server) -> Vec<Window> {
+    unsafe {
+        let conn = server.raw();
+        let Some((root, _)) = default_root(server) else {
+            return vec![];
+        };
+        let client_list = intern_atom(conn, "_NET_CLIENT_LIST_STACKING");
+        let ids: Vec<xcb_window_t> = match get_property(conn, root, client_list, XCB_ATOM_WINDOW) {
+            Some(v) if !v.is_empty() => v
+                .chunks_exact(4)
+                .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+            _ => query_tree(conn, root)
+                .map(|(_, children)| children)
+                .unwrap_or_default(),
+        };
+        let net_wm_name = intern_atom(conn, "_NET_WM_NAME");
+        ids.into_iter()
+            .filter_map(|id| {
+                let (rect, viewable) = geometry(conn, root, id)?;
+                if !viewable || rect.w <= 1 || rect.h <= 1 {
+                    return None;
+                }
+                let title = get_property(conn, id, net_wm_name, XCB_GET_PROPERTY_TYPE_ANY)
+                    .filter(|v| !v.is_empty())
+                    .or_else(|| get_property(conn, id, XCB_ATOM_WM_NAME, XCB_GET_PROPERTY_TYPE_ANY))
+                    .unwrap_or_default();
+                // "instance\0class\0"
+                let class = get_property(conn, id, XCB_ATOM_WM_CLASS, XCB_GET_PROPERTY_TYPE_ANY)
+                    .unwrap_or_default();
+                let mut names = class.split(|c| *c == 0).filter(|s| !s.is_empty());
+                let instance = names.next().unwrap_or_default();
+                let class = names.next().unwrap_or(instance);
+                Some(Window {
+                    id,
+                    title: String::from_utf8_lossy(&title).to_string(),
+                    class: String::from_utf8_lossy(class).to_string(),
+                    rect,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Where the captured window is and if it can take input, updated by
+/// `WindowCapturer::frame`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowState {
+    /// In root coordinates, without the border
+    pub rect: Rect,
+    /// The windows stacked above it, in root coordinates
+    pub occluders: Vec<Rect>,
+    /// If it, or a child of it, has the input focus
+    pub focused: bool,
+}
+
+impl WindowState {
+    /// If (x, y) in root coordinates is on one of the windows above it.
+    pub fn is_covered(&self, x: i32, y: i32) -> bool {
+        self.occluders.iter().any(|r| {
+            x >= r.x as i32
+                && y >= r.y as i32
+                && x < r.x as i32 + r.w as i32
+                && y < r.y as i32 + r.h as i32
+        })
+    }
+}
+
+/// Captures one window as it moves.
+///
+/// The size is fixed, `frame` fails once the window is resized. Parts of the
+/// window off the screen or under other windows are black, so nothing but the
+/// window is captured.
+pub struct WindowCapturer {
+    server: Rc<Server>,
+    root: xcb_window_t,
+    screen: Rect,
+    window: xcb_window_t,
+    // The ancestor of the window that is a child of the root, e.g. the frame
+    // of the window manager, which is stacked with the other windows
+    toplevel: xcb_window_t,
+    rect: Rect,
+    state: Arc<Mutex<WindowState>>,
+    shmid: i32,
+    xcbid: u32,
+    buffer: *const u8,
+    data: Vec<u8>,
+    saved_raw_data: Vec<u8>,
+}
+
+impl WindowCapturer {
+    pub fn new(server: Rc<Server>, window: xcb_window_t) -> io::Result<WindowCapturer> {
+        let conn = server.raw();
+        let Some((root, screen)) = (unsafe { default_root(&server) }) else {
+            return Err(io::ErrorKind::NotFound.into());
+        };
+        if unsafe { get_pixfmt(conn, root) }.unwrap_or(Pixfmt::BGRA) != Pixfmt::BGRA {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Window capture needs a 24 or 32 bit screen",
+            ));
+        }
+        let Some((rect, _)) = (unsafe { geometry(conn, root, window) }) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Window {:#x} not found", window),
+            ));
+        };
+        let size = rect.w as usize * rect.h as usize * 4;
+
+        let shmid = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o777) };
+        if shmid == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let buffer = unsafe { libc::shmat(shmid, ptr::null(), libc::SHM_RDONLY) } as *mut u8;
+        if buffer as isize == -1 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut()) };
+            return Err(err);
+        }
+        let xcbid = unsafe { xcb_generate_id(conn) };
+        unsafe {
+            xcb_shm_attach(conn, xcbid, shmid as u32, 0);
+        }
+
+        let toplevel = unsafe { toplevel(conn, root, window) };
+        Ok(WindowCapturer {
+            server,
+            root,
+            screen,
+            window,
+            toplevel,
+            rect,
+            // All covered until the first frame
+            state: Arc::new(Mutex::new(WindowState {
+                rect,
+                occluders: vec![screen],
+                focused: false,
+            })),
+            shmid,
+            xcbid,
+            buffer,
+            data: Vec::new(),
+            saved_raw_data: Vec::new(),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.rect.w as _
+    }
+
+    pub fn height(&self) -> usize {
+        self.rect.h as _
+    }
+
+    /// The state of the window, updated by `frame`.
+    pub fn state(&self) -> Arc<Mutex<WindowState>> {
+        self.state.clone()
+    }
+
+    pub fn frame(&mut self) -> io::Result<&[u8]> {
+        let conn = self.server.raw();
+        let Some((rect, viewable)) = (unsafe { geometry(conn, self.root, self.window) }) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The window is closed",
+            ));
+        };
+        if (rect.w, rect.h) != (self.rect.w, self.rect.h) {
+            return Err(io::Error::other("The window is resized"));
+        }
+        // Minimized
+        if !viewable {
+            let mut state = self.state.lock().unwrap();
+            state.occluders = vec![self.screen];
+            state.focused = false;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.rect = rect;
+        self.data.clear();
+        self.data.resize(rect.w as usize * rect.h as usize * 4, 0);
+        if let Some(visible) = intersect(rect, self.screen) {
+            if !unsafe { self.get_image(visible) } {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let len = visible.w as usize * visible.h as usize * 4;
+            let image = unsafe { slice::from_raw_parts(self.buffer, len) };
+            copy_rect(&mut self.data, rect, image, visible);
+        }
+        let occluders = unsafe { self.occluders() };
+        for other in occluders.iter() {
+            black_out(&mut self.data, rect, *other);
+        }
+        let focused = unsafe { self.focused() };
+        *self.state.lock().unwrap() = WindowState {
+            rect,
+            occluders,
+            focused,
+        };
+        crate::would_block_if_equal(&mut self.saved_raw_data, &self.data)?;
+        Ok(&self.data)
+    }
+
+    // `visible` of the window into the shared memory
+    unsafe fn get_image(&self, visible: Rect) -> bool {
+        let conn = self.server.raw();
+        let request = xcb_shm_get_image_unchecked(
+            conn,
+            self.window,
+            (visible.x as i32 - self.rect.x as i32) as _,
+            (visible.y as i32 - self.rect.y as i32) as _,
+            visible.w,
+            visible.h,
+            !0,
+            XCB_IMAGE_FORMAT_Z_PIXMAP,
+            self.xcbid,
+            0,
+        );
+        let response = xcb_shm_get_image_reply(conn, request, ptr::null_mut());
+        let ok = !response.is_null();
+        libc::free(response as *mut _);
+        ok
+    }
+
+    // The windows stacked above the window
+    unsafe fn occluders(&mut self) -> Vec<Rect> {
+        let conn = self.server.raw();
+        let Some((_, children)) = query_tree(conn, self.root) else {
+            return vec![self.screen];
+        };
+        let mut pos = children.iter().position(|w| *w == self.toplevel);
+        if pos.is_none() {
+            // Reparented by the window manager
+            self.toplevel = toplevel(conn, self.root, self.window);
+            pos = children.iter().position(|w| *w == self.toplevel);
+        }
+        let Some(pos) = pos else {
+            return vec![self.screen];
+        };
+        let cookies: Vec<_> = children[pos + 1..]
+            .iter()
+            .map(|w| {
+                (
+                    xcb_get_window_attributes(conn, *w),
+                    xcb_get_geometry_unchecked(conn, *w),
+                )
+            })
+            .collect();
+        cookies
+            .into_iter()
+            .filter_map(|(attr, geo)| {
+                let attr = xcb_get_window_attributes_reply(conn, attr, ptr::null_mut());
+                let geo = xcb_get_geometry_reply(conn, geo, ptr::null_mut());
+                let mut rect = None;
+                if !attr.is_null()
+                    && !geo.is_null()
+                    && (*attr).map_state == XCB_MAP_STATE_VIEWABLE
+                    && (*attr).class != XCB_WINDOW_CLASS_INPUT_ONLY
+                {
+                    // The position is of the outer corner of the border
+                    let border = (*geo).border_width * 2;
+                    rect = Some(Rect {
+                        x: (*geo).x,
+                        y: (*geo).y,
+                        w: (*geo).width.saturating_add(border),
+                        h: (*geo).height.saturating_add(border),
+                    });
+                }
+                libc::free(attr as *mut _);
+                libc::free(geo as *mut _);
+                rect
+            })
+            .collect()
+    }
+
+    // If the input focus is on the window, a child of it or its frame
+    unsafe fn focused(&self) -> bool {
+        let conn = self.server.raw();
+        let reply = xcb_get_input_focus_reply(conn, xcb_get_input_focus(conn), ptr::null_mut());
+        if reply.is_null() {
+            return false;
+        }
+        let focus = (*reply).focus;
+        libc::free(reply as *mut _);
+        // The keys go to the window under the pointer with `PointerRoot`
+        if focus == XCB_NONE || focus == XCB_INPUT_FOCUS_POINTER_ROOT {
+            return false;
+        }
+        focus == self.window || toplevel(conn, self.root, focus) == self.toplevel
+    }
+}
+
+impl Drop for WindowCapturer {
+    fn drop(&mut self) {
+        unsafe {
+            xcb_shm_detach(self.server.raw(), self.xcbid);
+            libc::shmdt(self.buffer as *mut _);
+            libc::shmctl(self.shmid, libc::IPC_RMID, ptr::null_mut());
+        }
+    }
+}
+
+unsafe fn default_root(server: &Server) -> Option<(xcb_window_t, Rect)> {
+    let mut iter = xcb_setup_roots_iterator(server.setup());
+    for _ in 0..server.screenp() {
+        if iter.rem == 0 {
+            return None;
+        }
+        xcb_screen_next(&mut iter);
+    }
+    if iter.rem == 0 {
+        return None;
+    }
+    let screen = &*iter.data;
+    Some((
+        screen.root,
+        Rect {
+            x: 0,
+            y: 0,
+            w: screen.width_in_pixels,
+            h: screen.height_in_pixels,
+        },
+    ))
+}
+
+unsafe fn intern_atom(conn: *mut xcb_connection_t, name: &str) -> xcb_atom_t {
+    let cookie = xcb_intern_atom(conn, 1, name.len() as _, name.as_ptr() as _);
+    let reply = xcb_intern_atom_reply(conn, cookie, ptr::null_mut());
+    if reply.is_null() {
+        return XCB_ATOM_NONE;
+    }
+    let atom = (*reply).atom;
+    libc::free(reply as *mut _);
+    atom
+}
+
+unsafe fn get_property(
+    conn: *mut xcb_connection_t,
+    window: xcb_window_t,
+    property: xcb_atom_t,
+    type_: xcb_atom_t,
+) -> Option<Vec<u8>> {
+    if property == XCB_ATOM_NONE {
+        return None;
+    }
+    // In 32 bit units
+    let cookie = xcb_get_property(conn, 0, window, property, type_, 0, 4096);
+    let reply = xcb_get_property_reply(conn, cookie, ptr::null_mut());
+    if reply.is_null() {
+        return None;
+    }
+    let mut value = None;
+    if (*reply).type_ != XCB_ATOM_NONE {
+        let len = xcb_get_property_value_length(reply) as usize;
+        let data = xcb_get_property_value(reply) as *const u8;
+        value = Some(slice::from_raw_parts(data, len).to_vec());
+    }
+    libc::free(reply as *mut _);
+    value
+}
+
+// The parent and the children, bottom to top
+unsafe fn query_tree(
+    conn: *mut xcb_connection_t,
+    window: xcb_window_t,
+) -> Option<(xcb_window_t, Vec<xcb_window_t>)> {
+    let reply = xcb_query_tree_reply(conn, xcb_query_tree(conn, window), ptr::null_mut());
+    if reply.is_null() {
+        return None;
+    }
+    let len = xcb_query_tree_children_length(reply) as usize;
+    let children = slice::from_raw_parts(xcb_query_tree_children(reply), len).to_vec();
+    let parent = (*reply).parent;
+    libc::free(reply as *mut _);
+    Some((parent, children))
+}
+
+// The place in root coordinates and if it is viewable, `None` if the window
+// doesn't exist
+unsafe fn geometry(
+    conn: *mut xcb_connection_t,
+    root: xcb_window_t,
+    window: xcb_window_t,
+) -> Option<(Rect, bool)> {
+    let attr = xcb_get_window_attributes(conn, window);
+    let geo = xcb_get_geometry_unchecked(conn, window);
+    let pos = xcb_translate_coordinates(conn, window, root, 0, 0);
+    let attr = xcb_get_window_attributes_reply(conn, attr, ptr::null_mut());
+    let geo = xcb_get_geometry_reply(conn, geo, ptr::null_mut());
+    let pos = xcb_translate_coordinates_reply(conn, pos, ptr::null_mut());
+    let mut res = None;
+    if !attr.is_null() && !geo.is_null() && !pos.is_null() {
+        res = Some((
+            Rect {
+                x: (*pos).dst_x,
+                y: (*pos).dst_y,
+                w: (*geo).width,
+                h: (*geo).height,
+            },
+            (*attr).map_state == XCB_MAP_STATE_VIEWABLE,
+        ));
+    }
+    libc::free(attr as *mut _);
+    libc::free(geo as *mut _);
+    libc::free(pos as *mut _);
+    res
+}
+
+unsafe fn toplevel(
+    conn: *mut xcb_connection_t,
+    root: xcb_window_t,
+    mut window: xcb_window_t,
+) -> xcb_window_t {
+    while let Some((parent, _)) = query_tree(conn, window) {
+        if parent == root || parent == 0 {
+            break;
+        }
+        window = parent;
+    }
+    window
+}
+
+fn intersect(a: Rect, b: Rect) -> Option<Rect> {
+    let x0 = (a.x as i32).max(b.x as i32);
+    let y0 = (a.y as i32).max(b.y as i32);
+    let x1 = (a.x as i32 + a.w as i32).min(b.x as i32 + b.w as i32);
+    let y1 = (a.y as i32 + a.h as i32).min(b.y as i32 + b.h as i32);
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some(Rect {
+        x: x0 as _,
+        y: y0 as _,
+        w: (x1 - x0) as _,
+        h: (y1 - y0) as _,
+    })
+}
+
+// Copies the BGRA `image` of `part` into `data`, the frame of `rect`
+fn copy_rect(data: &mut [u8], rect: Rect, image: &[u8], part: Rect) {
+    let stride = rect.w as usize * 4;
+    let len = part.w as usize * 4;
+    let x = (part.x as i32 - rect.x as i32) as usize * 4;
+    let y = (part.y as i32 - rect.y as i32) as usize;
+    for (row, line) in image.chunks_exact(len).take(part.h as _).enumerate() {
+        let start = (y + row) * stride + x;
+        data[start..start + len].copy_from_slice(line);
+    }
+}
+
+// Blacks out the part of `data`, the BGRA frame of `rect`, under `other`
+fn black_out(data: &mut [u8], rect: Rect, other: Rect) {
+    let Some(part) = intersect(rect, other) else {
+        return;
+    };
+    let stride = rect.w as usize * 4;
+    let len = part.w as usize * 4;
+    let x = (part.x as i32 - rect.x as i32) as usize * 4;
+    let y = (part.y as i32 - rect.y as i32) as usize;
+    for row in y..y + part.h as usize {
+        data[row * stride + x..row * stride + x + len].fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i16, y: i16, w: u16, h: u16) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn test_intersect() {
+        assert_eq!(
+            intersect(rect(0, 0, 10, 10), rect(5, -5, 10, 10)),
+            Some(rect(5, 0, 5, 5))
+        );
+        assert_eq!(intersect(rect(0, 0, 10, 10), rect(10, 0, 5, 5)), None);
+        assert_eq!(
+            intersect(rect(-20, 0, 30, 10), rect(0, 0, 1920, 1080)),
+            Some(rect(0, 0, 10, 10))
+        );
+    }
+
+    #[test]
+    fn test_copy_and_black_out() {
+        // A 4x3 window at (-2, 1), half off the screen
+        let window = rect(-2, 1, 4, 3);
+        let visible = intersect(window, rect(0, 0, 100, 100)).unwrap();
+        let mut data = vec![0; 4 * 3 * 4];
+        copy_rect(&mut data, window, &[255; 2 * 3 * 4], visible);
+        let pixel = |data: &[u8], x: usize, y: usize| data[(y * 4 + x) * 4];
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(pixel(&data, x, y), if x < 2 { 0 } else { 255 });
+            }
+        }
+        // Another window over the bottom right pixel
+        black_out(&mut data, window, rect(1, 3, 10, 10));
+        assert_eq!(pixel(&data, 3, 2), 0);
+        assert_eq!(pixel(&data, 2, 2), 255);
+        assert_eq!(pixel(&data, 3, 1), 255);
+    }
+
+    #[test]
+    fn test_is_covered() {
+        let state = WindowState {
+            rect: rect(0, 0, 100, 100),
+            occluders: vec![rect(50, -10, 20, 20)],
+            focused: false,
+        };
+        assert!(state.is_covered(50, 0));
+        assert!(state.is_covered(69, 9));
+        assert!(!state.is_covered(70, 0));
+        assert!(!state.is_covered(50, 10));
+        assert!(!state.is_covered(49, 0));
+    }
+
+    #[test]
+    fn test_matches() {
+        let window = Window {
+            id: 1,
+            title: "Online Banking - Mozilla Firefox".to_owned(),
+            class: "firefox".to_owned(),
+            rect: rect(0, 0, 10, 10),
+        };
+        assert!(window.matches("Firefox"));
+        assert!(window.matches("banking"));
+        assert!(!window.matches("chrome"));
+        assert!(!window.matches(""));
+    }
+
+    unsafe fn create_window(server: &Server, r: Rect, color: u32, title: &str) -> xcb_window_t {
+        let conn = server.raw();
+        let (root, _) = default_root(server).unwrap();
+        let id = xcb_generate_id(conn);
+        let values = [color];
+        xcb_create_window(
+            conn,
+            0,
+            id,
+            root,
+            r.x,
+            r.y,
+            r.w,
+            r.h,
+            0,
+            XCB_WINDOW_CLASS_INPUT_OUTPUT,
+            0,
+            XCB_CW_BACK_PIXEL,
+            values.as_ptr(),
+        );
+        for (atom, value) in [
+            (XCB_ATOM_WM_NAME, title.to_owned()),
+            (XCB_ATOM_WM_CLASS, "familydesk\0FamilyDesk\0".to_owned()),
+        ] {
+            xcb_change_property(
+                conn,
+                XCB_PROP_MODE_REPLACE,
+                id,
+                atom,
+                XCB_ATOM_STRING,
+                8,
+                value.len() as _,
+                value.as_ptr() as _,
+            );
+        }
+        xcb_map_window(conn, id);
+        xcb_flush(conn);
+        id
+    }
+
+    unsafe fn configure_window(server: &Server, window: xcb_window_t, r: Rect) {
+        let mask = XCB_CONFIG_WINDOW_X
+            | XCB_CONFIG_WINDOW_Y
+            | XCB_CONFIG_WINDOW_WIDTH
+            | XCB_CONFIG_WINDOW_HEIGHT;
+        let values = [r.x as i32 as u32, r.y as i32 as u32, r.w as u32, r.h as u32];
+        xcb_configure_window(server.raw(), window, mask, values.as_ptr());
+        xcb_flush(server.raw());
+    }
+
+    fn frame(c: &mut WindowCapturer) -> Vec<u8> {
+        // The background may be painted after the map
+        for _ in 0..50 {
+            match c.frame() {
+                Ok(data) if data.iter().any(|b| *b != 0) => return data.to_vec(),
+                _ => std::thread::sleep(std::time::Duration::from_millis(20)),
+            }
+        }
+        panic!("no frame");
+    }
+
+    // Run under Xvfb, e.g. `xvfb-run cargo test -p scrap`, skipped without a
+    // display.
+    #[test]
+    fn test_xvfb_window_capture() {
+        if std::env::var("DISPLAY").is_err() {
+            return;
+        }
+        let Ok(server) = Server::default() else {
+            return;
+        };
+        unsafe {
+            let red = create_window(&server, rect(10, 20, 64, 48), 0xff0000, "Grandma's browser");
+            let found = windows(&server).into_iter().find(|w| w.id == red).unwrap();
+            assert_eq!(found.title, "Grandma's browser");
+            assert_eq!(found.class, "FamilyDesk");
+            assert_eq!(found.rect, rect(10, 20, 64, 48));
+
+            let mut c = WindowCapturer::new(server.clone(), red).unwrap();
+            let data = frame(&mut c);
+            assert!(data.chunks_exact(4).all(|p| p[..3] == [0, 0, 255]));
+
+            // Follows the window
+            configure_window(&server, red, rect(100, 50, 64, 48));
+            let _ = c.frame();
+            assert_eq!(c.state().lock().unwrap().rect, rect(100, 50, 64, 48));
+            assert!(!c.state().lock().unwrap().focused);
+            assert!(!c.state().lock().unwrap().is_covered(120, 60));
+
+            // Reverts to none
+            xcb_set_input_focus(server.raw(), 0, red, XCB_CURRENT_TIME);
+            xcb_flush(server.raw());
+            let _ = c.frame();
+            assert!(c.state().lock().unwrap().focused);
+
+            // The right half is under another window
+            let blue = create_window(&server, rect(132, 0, 100, 200), 0x0000ff, "Passwords");
+            let data = frame(&mut c);
+            for (i, p) in data.chunks_exact(4).enumerate() {
+                let expected: [u8; 3] = if i % 64 < 32 { [0, 0, 255] } else { [0, 0, 0] };
+                assert_eq!(p[..3], expected);
+            }
+            let state = c.state().lock().unwrap().clone();
+            assert_eq!(state.occluders, vec![rect(132, 0, 100, 200)]);
+            assert!(!state.is_covered(131, 60));
+            assert!(state.is_covered(132, 60));
+
+            configure_window(&server, red, rect(100, 50, 80, 48));
+            assert_eq!(c.frame().unwrap_err().kind(), io::ErrorKind::Other);
+
+            xcb_destroy_window(server.raw(), blue);
+            xcb_destroy_window(server.raw(), red);
+            xcb_flush(server.raw());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Runs synthetic frames through the encoder and the recorder like the video
+//! service does, on a virtual clock.
+#![cfg(any(target_os = "windows", target_os = "linux"))]
+
+use hbb_common::message_proto::Message;
+use scrap::{
+    codec::{Encoder, EncoderCfg},
+    record::{Recorder, RecorderContext},
+    record_transcode,
+    synthetic::{SyntheticCapturer, SyntheticConfig, SyntheticSource},
+    vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
+};
+use std::{io::ErrorKind::WouldBlock, path::PathBuf, time::Duration};
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+
+struct Run {
+    captured: usize,
+    encoded: usize,
+    recordings: Vec<PathBuf>,
+}
+
+// Captures `rate` times a second for `secs`, encodes the new frames and
+// records them into `dir` if set.
+fn run(damage: f32, rate: u64, secs: u64, dir: Option<&PathBuf>) -> Run {
+    let mut capturer = SyntheticCapturer::new(SyntheticConfig {
+        width: WIDTH,
+        height: HEIGHT,
+        fps: 30,
+        damage,
+        source: SyntheticSource::Pattern,
+    })
+    .unwrap();
+    let mut encoder = Encoder::new(
+        EncoderCfg::VPX(VpxEncoderConfig {
+            width: WIDTH as _,
+            height: HEIGHT as _,
+            quality: 1.0,
+            codec: VpxVideoCodecId::VP9,
+            keyframe_interval: None,
+        }),
+        false,
+    )
+    .unwrap();
+    let mut recorder = dir.map(|dir| {
+        Recorder::new(RecorderContext {
+            server: true,
+            id: "synthetic".to_owned(),
+            dir: dir.to_string_lossy().to_string(),
+            display_idx: 0,
+            camera: false,
+            tx: None,
+            encryption_key: "".to_owned(),
+        })
+        .unwrap()
+    });
+    let (mut yuv, mut mid_data) = (vec![], vec![]);
+    let (mut captured, mut encoded) = (0, 0);
+    for i in 0..rate * secs {
+        // Rounded up, to be at or just after the step of the content
+        let elapsed = Duration::from_nanos((i * 1_000_000_000).div_ceil(rate));
+        match capturer.frame_at(elapsed) {
+            Ok(frame) => {
+                captured += 1;
+                let input = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data).unwrap();
+                if let Ok(vf) = encoder.encode_to_message(input, elapsed.as_millis() as _) {
+                    encoded += 1;
+                    let mut msg = Message::new();
+                    msg.set_video_frame(vf);
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.write_message(&msg, WIDTH, HEIGHT);
+                    }
+                }
+            }
+            Err(e) if e.kind() == WouldBlock => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+    drop(recorder);
+    let recordings = dir
+        .map(|dir| {
+            std::fs::read_dir(dir)
+                .unwrap()
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().map_or(false, |e| e == "webm"))
+                .collect()
+        })
+        .unwrap_or_default();
+    Run {
+        captured,
+        encoded,
+        recordings,
+    }
+}
+
+// The encoder may drop a frame now and then for the bitrate
+fn assert_encoded(run: &Run, captured: usize) {
+    assert_eq!(run.captured, captured);
+    assert!(run.encoded <= run.captured);
+    assert!(run.encoded >= run.captured * 9 / 10, "{}", run.encoded);
+}
+
+#[test]
+fn test_damage_rate() {
+    assert_encoded(&run(1.0, 30, 2, None), 60);
+    assert_encoded(&run(0.5, 30, 2, None), 30);
+    assert_encoded(&run(0.1, 30, 2, None), 6);
+    // A static screen is encoded once
+    let run = run(0.0, 30, 2, None);
+    assert_eq!((run.captured, run.encoded), (1, 1));
+}
+
+#[test]
+fn test_capture_rate() {
+    // Capturing slower than the content changes skips the steps between
+    assert_encoded(&run(1.0, 10, 3, None), 30);
+}
+
+#[test]
+fn test_recording() {
+    let dir = std::env::temp_dir().join(format!("synthetic_recording_{}", std::process::id()));
+    let run = run(1.0, 30, 2, Some(&dir));
+    assert_eq!(run.recordings.len(), 1);
+    let probe = record_transcode::probe(&run.recordings[0].to_string_lossy()).unwrap();
+    assert_eq!((probe.width, probe.height), (WIDTH, HEIGHT));
+    assert_eq!(probe.video_frames, run.encoded);
+    assert_eq!(probe.audio_frames, 0);
+    assert!(!probe.keyframes.is_empty());
+    std::fs::remove_dir_all(&dir).ok();
+}
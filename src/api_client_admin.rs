@@ -0,0 +1,243 @@
+/// Client for the admin endpoints of the FamilyDesk API server,
+/// used by the `familydesk-admin` binary.
+///
+/// Requests are authenticated with the JWT from `/api/login`, which is
+/// saved to `familydesk-admin.toml` in the config directory after login.
+use crate::api_models::{
+    parse_response, AccountInfo, AccountListResponse, AccountRequest, AdminRequest, AuditEntry,
+    AuditListResponse, AuditQuery, CreateAccountRequest, DeviceInfo, DeviceListResponse,
+    IssueTokenRequest, ListAuditRequest, LockAccountRequest, LockAccountResponse, LoginRequest,
+    LoginResponse, MessageResponse, ResetPasswordRequest, TokenResponse, UpdateAccountRequest,
+};
+use hbb_common::config::{self, Config};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub const DEFAULT_API_SERVER: &str = "http://nas.haydenstudio.hk:21114";
+// `device_id` sent with the admin tool's login
+const ADMIN_TOOL_DEVICE_ID: &str = "familydesk-admin";
+const SAVED_LOGIN_FILE: &str = "familydesk-admin.toml";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SavedLogin {
+    #[serde(default)]
+    pub server: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub token: String,
+}
+
+impl SavedLogin {
+    pub fn load() -> Self {
+        config::load_path(Config::path(SAVED_LOGIN_FILE))
+    }
+
+    pub fn store(&self) -> Result<(), String> {
+        config::store_path(Config::path(SAVED_LOGIN_FILE), self).map_err(|e| e.to_string())
+    }
+
+    pub fn clear() -> Result<(), String> {
+        Self::default().store()
+    }
+}
+
+pub struct AdminClient {
+    server: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl AdminClient {
+    pub fn new(server: &str, token: &str) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            server: server.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client,
+        })
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Log in with an admin account and keep the token for later requests.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<String, String> {
+        let rsp: LoginResponse = self
+            .post(
+                "/api/login",
+                &LoginRequest {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                    device_id: ADMIN_TOOL_DEVICE_ID.to_owned(),
+                },
+            )
+            .await?;
+        if rsp.role != "admin" {
+            return Err("Not an admin account".to_owned());
+        }
+        self.token = rsp.token;
+        Ok(self.token.clone())
+    }
+
+    pub async fn health(&self) -> Result<(), String> {
+        let response = self
+            .client
+            .get(format!("{}/health", self.server))
+            .send()
+            .await
+            .map_err(|e| format!("Network error: {}", e))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Server returned status: {}", response.status()))
+        }
+    }
+
+    pub async fn list_accounts(&self) -> Result<Vec<AccountInfo>, String> {
+        let rsp: AccountListResponse = self.post("/api/list-accounts", &AdminRequest::default()).await?;
+        Ok(rsp.accounts)
+    }
+
+    pub async fn create_account(
+        &self,
+        username: &str,
+        password: &str,
+        role: &str,
+        can_modify_settings: bool,
+        device_ids: Vec<String>,
+    ) -> Result<(), String> {
+        let req = CreateAccountRequest {
+            admin_password: "".to_owned(),
+            new_username: username.to_string(),
+            new_password: password.to_string(),
+            role: role.to_string(),
+            can_modify_settings,
+            device_ids,
+        };
+        self.post::<_, MessageResponse>("/api/create-account", &req)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn update_account(
+        &self,
+        username: &str,
+        role: Option<String>,
+        can_modify_settings: Option<bool>,
+        device_ids: Option<Vec<String>>,
+    ) -> Result<(), String> {
+        let req = UpdateAccountRequest {
+            admin_password: "".to_owned(),
+            username: username.to_string(),
+            role,
+            can_modify_settings,
+            device_ids,
+        };
+        self.post::<_, MessageResponse>("/api/update-account", &req)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn delete_account(&self, username: &str) -> Result<(), String> {
+        self.post::<_, MessageResponse>("/api/delete-account", &account(username))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn reset_password(&self, username: &str, new_password: &str) -> Result<(), String> {
+        let req = ResetPasswordRequest {
+            admin_password: "".to_owned(),
+            username: username.to_string(),
+            new_password: new_password.to_string(),
+        };
+        self.post::<_, MessageResponse>("/api/reset-password", &req)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn lock_account(&self, username: &str, locked: bool) -> Result<(), String> {
+        let req = LockAccountRequest {
+            admin_password: "".to_owned(),
+            username: username.to_string(),
+            locked,
+        };
+        self.post::<_, LockAccountResponse>("/api/lock-account", &req)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn list_devices(&self) -> Result<Vec<DeviceInfo>, String> {
+        let rsp: DeviceListResponse = self.post("/api/list-devices", &AdminRequest::default()).await?;
+        Ok(rsp.devices)
+    }
+
+    /// Returns the token and its expiry as a unix timestamp.
+    pub async fn issue_token(
+        &self,
+        username: &str,
+        device_id: &str,
+        ttl_days: Option<u64>,
+    ) -> Result<(String, u64), String> {
+        let req = IssueTokenRequest {
+            admin_password: "".to_owned(),
+            username: username.to_string(),
+            device_id: device_id.to_string(),
+            ttl_days,
+        };
+        let rsp: TokenResponse = self.post("/api/issue-token", &req).await?;
+        Ok((rsp.token, rsp.expires_at))
+    }
+
+    pub async fn revoke_tokens(&self, username: &str) -> Result<(), String> {
+        self.post::<_, MessageResponse>("/api/revoke-tokens", &account(username))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn list_audit(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+        let req = ListAuditRequest {
+            admin_password: "".to_owned(),
+            query: query.clone(),
+        };
+        let rsp: AuditListResponse = self.post("/api/list-audit", &req).await?;
+        Ok(rsp.entries)
+    }
+
+    async fn post<Req: Serialize, Rsp: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &Req,
+    ) -> Result<Rsp, String> {
+        let mut req = self
+            .client
+            .post(format!("{}{}", self.server, path))
+            .json(body);
+        if !self.token.is_empty() {
+            req = req.bearer_auth(&self.token);
+        }
+        let response = req
+            .send()
+            .await
+            .map_err(|e| format!("Network error: {}", e))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED && path != "/api/login" {
+            return Err("Not logged in or session expired, run `familydesk-admin login`".to_owned());
+        }
+        let body = response
+            .text()
+            .await
+            .map_err(|e| format!("Network error: {}", e))?;
+        parse_response(status.is_success(), &body)
+    }
+}
+
+fn account(username: &str) -> AccountRequest {
+    AccountRequest {
+        admin_password: "".to_owned(),
+        username: username.to_string(),
+    }
+}
@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use crate::api_models::{parse_response, LoginRequest, VerifySettingsRequest};
+pub use crate::api_models::{LoginResponse, VerifySettingsResponse};
 use std::sync::Arc;
 use once_cell::sync::Lazy;
 
@@ -17,21 +18,6 @@ static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
         .expect("Failed to create HTTP client")
 });
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct VerifySettingsResponse {
-    pub status: String,
-    pub can_modify_settings: bool,
-    pub username: String,
-    pub role: String,
-}
-
-#[derive(Debug, Serialize)]
-struct VerifySettingsRequest {
-    device_id: String,
-    username: String,
-    password: String,
-}
-
 /// Verify user credentials for modifying settings
 /// Uses shared HTTP client for better performance
 pub async fn verify_settings_password(
@@ -54,43 +40,20 @@ pub async fn verify_settings_password(
         .await
         .map_err(|e| format!("Network error: {}", e))?;
 
-    if response.status().is_success() {
-        let result: VerifySettingsResponse = response
-            .json()
-            .await
-            .map_err(|e| format!("Parse error: {}", e))?;
+    let success = response.status().is_success();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+    let result: VerifySettingsResponse = parse_response(success, &body)?;
 
-        if result.can_modify_settings {
-            Ok(result)
-        } else {
-            Err("Account does not have permission to modify settings".to_string())
-        }
+    if result.can_modify_settings {
+        Ok(result)
     } else {
-        let error: serde_json::Value = response
-            .json()
-            .await
-            .unwrap_or_else(|_| serde_json::json!({"message": "Unknown error"}));
-
-        Err(error["message"].as_str().unwrap_or("Authentication failed").to_string())
+        Err("Account does not have permission to modify settings".to_string())
     }
 }
 
-#[derive(Debug, Serialize)]
-struct LoginRequest {
-    username: String,
-    password: String,
-    device_id: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct LoginResponse {
-    pub status: String,
-    pub token: Option<String>,
-    pub username: Option<String>,
-    pub role: Option<String>,
-    pub can_modify_settings: Option<bool>,
-}
-
 /// Login and get JWT token
 /// Uses shared HTTP client for better performance
 pub async fn login(username: &str, password: &str) -> Result<LoginResponse, String> {
@@ -110,10 +73,12 @@ pub async fn login(username: &str, password: &str) -> Result<LoginResponse, Stri
         .await
         .map_err(|e| format!("Network error: {}", e))?;
 
-    response
-        .json()
+    let success = response.status().is_success();
+    let body = response
+        .text()
         .await
-        .map_err(|e| format!("Parse error: {}", e))
+        .map_err(|e| format!("Network error: {}", e))?;
+    parse_response(success, &body)
 }
 
 /// Get connection pool statistics (for monitoring)
@@ -0,0 +1,898 @@
+/// Request and response models of the FamilyDesk API server.
+///
+/// Shared by the server (`api_server`, behind the `api_server` feature) and
+/// the clients (`api_client*`), so both sides agree on the wire format.
+/// Every failed request is answered with an [`ErrorResponse`], and every
+/// model provides an `example()` from which `/api/openapi.json` derives its
+/// schemas.
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub const STATUS_SUCCESS: &str = "success";
+pub const STATUS_ERROR: &str = "error";
+
+pub trait ApiModel: Serialize + DeserializeOwned {
+    /// A representative value: optional fields set and lists non-empty,
+    /// so that the OpenAPI schema derived from it is complete.
+    fn example() -> Self;
+}
+
+fn success() -> String {
+    STATUS_SUCCESS.to_owned()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    // Missing or wrong admin password / token on an admin endpoint
+    Unauthorized,
+    InvalidCredentials,
+    NoDeviceAccess,
+    AccountLocked,
+    NotFound,
+    Conflict,
+    TokenInvalid,
+    TokenExpired,
+    TokenRevoked,
+    Internal,
+}
+
+impl ErrorCode {
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::BadRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::InvalidCredentials,
+        ErrorCode::NoDeviceAccess,
+        ErrorCode::AccountLocked,
+        ErrorCode::NotFound,
+        ErrorCode::Conflict,
+        ErrorCode::TokenInvalid,
+        ErrorCode::TokenExpired,
+        ErrorCode::TokenRevoked,
+        ErrorCode::Internal,
+    ];
+
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ErrorCode::BadRequest => 400,
+            ErrorCode::Unauthorized
+            | ErrorCode::InvalidCredentials
+            | ErrorCode::TokenInvalid
+            | ErrorCode::TokenExpired
+            | ErrorCode::TokenRevoked => 401,
+            ErrorCode::NoDeviceAccess | ErrorCode::AccountLocked => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::Conflict => 409,
+            ErrorCode::Internal => 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status: STATUS_ERROR.to_owned(),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl ApiModel for ErrorResponse {
+    fn example() -> Self {
+        Self::new(ErrorCode::InvalidCredentials, "Invalid username or password")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageResponse {
+    pub status: String,
+    pub message: String,
+}
+
+impl MessageResponse {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            status: success(),
+            message: message.into(),
+        }
+    }
+}
+
+impl ApiModel for MessageResponse {
+    fn example() -> Self {
+        Self::new("Account updated")
+    }
+}
+
+// Authentication
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    pub device_id: String,
+}
+
+impl ApiModel for LoginRequest {
+    fn example() -> Self {
+        Self {
+            username: "john_family".to_owned(),
+            password: "family2025".to_owned(),
+            device_id: "GRANDMA_PC".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub status: String,
+    pub token: String,
+    pub username: String,
+    pub role: String,
+    pub can_modify_settings: bool,
+}
+
+impl ApiModel for LoginResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            token: "eyJhbGc...".to_owned(),
+            username: "john_family".to_owned(),
+            role: "family".to_owned(),
+            can_modify_settings: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifySettingsRequest {
+    pub device_id: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl ApiModel for VerifySettingsRequest {
+    fn example() -> Self {
+        Self {
+            device_id: "GRANDMA_PC".to_owned(),
+            username: "john_family".to_owned(),
+            password: "family2025".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifySettingsResponse {
+    pub status: String,
+    pub can_modify_settings: bool,
+    pub username: String,
+    pub role: String,
+}
+
+impl ApiModel for VerifySettingsResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            can_modify_settings: true,
+            username: "john_family".to_owned(),
+            role: "family".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyTokenRequest {
+    pub device_id: String,
+    pub auth_token: String,
+}
+
+impl ApiModel for VerifyTokenRequest {
+    fn example() -> Self {
+        Self {
+            device_id: "GRANDMA_PC".to_owned(),
+            auth_token: "eyJhbGc...".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyTokenResponse {
+    pub status: String,
+    pub family_member: String,
+}
+
+impl ApiModel for VerifyTokenResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            family_member: "john_family".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub status: String,
+    pub token: String,
+    pub expires_at: u64,
+}
+
+impl ApiModel for TokenResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            token: "eyJhbGc...".to_owned(),
+            expires_at: 1735689600,
+        }
+    }
+}
+
+// Accounts (admin only). Admin requests carry either `admin_password` or
+// an `Authorization: Bearer` token of an admin account.
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdminRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+}
+
+impl ApiModel for AdminRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateAccountRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub new_username: String,
+    pub new_password: String,
+    pub role: String,
+    pub can_modify_settings: bool,
+    pub device_ids: Vec<String>,
+}
+
+impl ApiModel for CreateAccountRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            new_username: "new_user".to_owned(),
+            new_password: "password".to_owned(),
+            role: "family".to_owned(),
+            can_modify_settings: true,
+            device_ids: vec!["DEVICE_01".to_owned()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateAccountRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub can_modify_settings: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_ids: Option<Vec<String>>,
+}
+
+impl ApiModel for UpdateAccountRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            username: "john_family".to_owned(),
+            role: Some("family".to_owned()),
+            can_modify_settings: Some(false),
+            device_ids: Some(vec!["GRANDMA_PC".to_owned()]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub username: String,
+}
+
+impl ApiModel for AccountRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            username: "john_family".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockAccountRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub username: String,
+    pub locked: bool,
+}
+
+impl ApiModel for LockAccountRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            username: "john_family".to_owned(),
+            locked: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResetPasswordRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub username: String,
+    pub new_password: String,
+}
+
+impl ApiModel for ResetPasswordRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            username: "john_family".to_owned(),
+            new_password: "new-password".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueTokenRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub username: String,
+    pub device_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_days: Option<u64>,
+}
+
+impl ApiModel for IssueTokenRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            username: "john_family".to_owned(),
+            device_id: "GRANDMA_PC".to_owned(),
+            ttl_days: Some(7),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub username: String,
+    pub role: String,
+    pub can_modify_settings: bool,
+    pub device_ids: Vec<String>,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountListResponse {
+    pub status: String,
+    pub accounts: Vec<AccountInfo>,
+}
+
+impl ApiModel for AccountListResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            accounts: vec![AccountInfo {
+                username: "john_family".to_owned(),
+                role: "family".to_owned(),
+                can_modify_settings: true,
+                device_ids: vec!["GRANDMA_PC".to_owned()],
+                locked: false,
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockAccountResponse {
+    pub status: String,
+    pub username: String,
+    pub locked: bool,
+}
+
+impl ApiModel for LockAccountResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            username: "john_family".to_owned(),
+            locked: true,
+        }
+    }
+}
+
+// Devices, posted by the client's hbbs sync loop
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    pub id: String,
+    #[serde(default)]
+    pub uuid: String,
+    #[serde(default)]
+    pub ver: i64,
+    #[serde(default)]
+    pub conns: Vec<i32>,
+    #[serde(default)]
+    pub modified_at: i64,
+}
+
+impl ApiModel for HeartbeatRequest {
+    fn example() -> Self {
+        Self {
+            id: "GRANDMA_PC".to_owned(),
+            uuid: "Y2E5ZjM0...".to_owned(),
+            ver: 1004002,
+            conns: vec![3],
+            modified_at: 1735689600000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StrategyOptions {
+    pub config_options: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeartbeatResponse {
+    // Asks the device to upload its sysinfo again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sysinfo: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disconnect: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<StrategyOptions>,
+}
+
+impl ApiModel for HeartbeatResponse {
+    fn example() -> Self {
+        Self {
+            sysinfo: Some(true),
+            disconnect: vec![3],
+            modified_at: Some(1735689600000),
+            strategy: Some(StrategyOptions {
+                config_options: [("approve-mode".to_owned(), "password".to_owned())]
+                    .into_iter()
+                    .collect(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub hostname: String,
+    pub username: String,
+    pub os: String,
+    pub version: String,
+    pub group: String,
+    pub online: bool,
+    pub last_seen: u64,
+    pub conns: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceListResponse {
+    pub status: String,
+    pub devices: Vec<DeviceInfo>,
+}
+
+impl ApiModel for DeviceListResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            devices: vec![DeviceInfo {
+                id: "GRANDMA_PC".to_owned(),
+                hostname: "grandma-laptop".to_owned(),
+                username: "grandma".to_owned(),
+                os: "windows / Windows 11".to_owned(),
+                version: "1.4.2".to_owned(),
+                group: "family".to_owned(),
+                online: true,
+                last_seen: 1735689600,
+                conns: 1,
+            }],
+        }
+    }
+}
+
+// Sessions
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub conn_id: i32,
+    pub session_id: u64,
+    pub peer_id: String,
+    pub peer_name: String,
+    pub ip: String,
+    pub conn_type: i64,
+    pub started_at: u64,
+    pub authorized: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSessionsRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    // All online devices if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+impl ApiModel for ListSessionsRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            device_id: Some("GRANDMA_PC".to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionListResponse {
+    pub status: String,
+    // device id -> sessions
+    pub sessions: HashMap<String, Vec<SessionInfo>>,
+}
+
+impl ApiModel for SessionListResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            sessions: [(
+                "GRANDMA_PC".to_owned(),
+                vec![SessionInfo {
+                    conn_id: 3,
+                    session_id: 8456123,
+                    peer_id: "HELPER_PC".to_owned(),
+                    peer_name: "john".to_owned(),
+                    ip: "10.0.0.2".to_owned(),
+                    conn_type: 0,
+                    started_at: 1735689600,
+                    authorized: true,
+                }],
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisconnectRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub device_id: String,
+    // All sessions of the device if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conn_id: Option<i32>,
+}
+
+impl ApiModel for DisconnectRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            device_id: "GRANDMA_PC".to_owned(),
+            conn_id: Some(3),
+        }
+    }
+}
+
+// Audit log
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: u64,
+    // "conn", "file", "alarm" or "admin"
+    pub kind: String,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub conn_id: Option<i32>,
+    #[serde(default)]
+    pub peer_id: String,
+    #[serde(default)]
+    pub action: String,
+    #[serde(default)]
+    pub info: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListAuditRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    #[serde(flatten)]
+    pub query: AuditQuery,
+}
+
+impl ApiModel for ListAuditRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            query: AuditQuery {
+                device_id: Some("GRANDMA_PC".to_owned()),
+                peer_id: Some("HELPER_PC".to_owned()),
+                kind: Some("conn".to_owned()),
+                since: Some(1735689600),
+                until: Some(1735776000),
+                limit: Some(100),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditListResponse {
+    pub status: String,
+    pub entries: Vec<AuditEntry>,
+}
+
+impl ApiModel for AuditListResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            entries: vec![AuditEntry {
+                time: 1735689600,
+                kind: "conn".to_owned(),
+                device_id: "GRANDMA_PC".to_owned(),
+                conn_id: Some(3),
+                peer_id: "HELPER_PC".to_owned(),
+                action: "login".to_owned(),
+                info: serde_json::json!({ "peer": ["HELPER_PC", "john"], "type": 0 }),
+            }],
+        }
+    }
+}
+
+// Strategies
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Strategy {
+    pub name: String,
+    pub options: HashMap<String, String>,
+    pub modified_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveStrategyRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub name: String,
+    pub options: HashMap<String, String>,
+}
+
+impl ApiModel for SaveStrategyRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            name: "grandma".to_owned(),
+            options: [("enable-lan-discovery".to_owned(), "N".to_owned())]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveStrategyResponse {
+    pub status: String,
+    pub strategy: Strategy,
+}
+
+impl ApiModel for SaveStrategyResponse {
+    fn example() -> Self {
+        let req = SaveStrategyRequest::example();
+        Self {
+            status: success(),
+            strategy: Strategy {
+                name: req.name,
+                options: req.options,
+                modified_at: 1735689600000,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteStrategyRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub name: String,
+}
+
+impl ApiModel for DeleteStrategyRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            name: "grandma".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignStrategyRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    // None or empty to unassign
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    // Exactly one of device_id and group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl ApiModel for AssignStrategyRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            strategy: Some("grandma".to_owned()),
+            device_id: Some("GRANDMA_PC".to_owned()),
+            group: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyListResponse {
+    pub status: String,
+    pub strategies: Vec<Strategy>,
+    // device id -> strategy name
+    pub devices: HashMap<String, String>,
+    // group name -> strategy name
+    pub groups: HashMap<String, String>,
+}
+
+impl ApiModel for StrategyListResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            strategies: vec![SaveStrategyResponse::example().strategy],
+            devices: [("GRANDMA_PC".to_owned(), "grandma".to_owned())]
+                .into_iter()
+                .collect(),
+            groups: [("family".to_owned(), "grandma".to_owned())]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewStrategyRequest {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub admin_password: String,
+    pub device_id: String,
+}
+
+impl ApiModel for PreviewStrategyRequest {
+    fn example() -> Self {
+        Self {
+            admin_password: "admin123".to_owned(),
+            device_id: "GRANDMA_PC".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewStrategyResponse {
+    pub status: String,
+    pub device_id: String,
+    pub group: String,
+    // Applied strategies, lowest precedence first
+    pub strategies: Vec<String>,
+    pub options: HashMap<String, String>,
+    pub modified_at: i64,
+}
+
+impl ApiModel for PreviewStrategyResponse {
+    fn example() -> Self {
+        Self {
+            status: success(),
+            device_id: "GRANDMA_PC".to_owned(),
+            group: "family".to_owned(),
+            strategies: vec!["grandma".to_owned()],
+            options: SaveStrategyRequest::example().options,
+            modified_at: 1735689600000,
+        }
+    }
+}
+
+/// Parse a response body: the expected model on success, the error
+/// envelope's message otherwise.
+pub fn parse_response<T: DeserializeOwned>(success: bool, body: &str) -> Result<T, String> {
+    if success {
+        return serde_json::from_str(body).map_err(|e| format!("Parse error: {}", e));
+    }
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(e) => Err(e.message),
+        Err(_) => Err("Request failed".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: ApiModel>() {
+        let v = serde_json::to_value(T::example()).unwrap();
+        let back: T = serde_json::from_value(v.clone()).unwrap();
+        assert_eq!(serde_json::to_value(back).unwrap(), v);
+    }
+
+    #[test]
+    fn test_examples_round_trip() {
+        round_trip::<ErrorResponse>();
+        round_trip::<LoginRequest>();
+        round_trip::<LoginResponse>();
+        round_trip::<UpdateAccountRequest>();
+        round_trip::<HeartbeatResponse>();
+        round_trip::<ListAuditRequest>();
+        round_trip::<SessionListResponse>();
+        round_trip::<StrategyListResponse>();
+    }
+
+    #[test]
+    fn test_error_envelope() {
+        let v = serde_json::to_value(ErrorResponse::new(ErrorCode::AccountLocked, "locked")).unwrap();
+        assert_eq!(v["status"], "error");
+        assert_eq!(v["code"], "account_locked");
+        let body = v.to_string();
+        assert_eq!(parse_response::<LoginResponse>(false, &body).unwrap_err(), "locked");
+    }
+
+    #[test]
+    fn test_admin_password_optional() {
+        // Token-authenticated clients leave the password out entirely
+        let req = AccountRequest {
+            admin_password: "".to_owned(),
+            username: "john".to_owned(),
+        };
+        let v = serde_json::to_value(&req).unwrap();
+        assert!(v.get("admin_password").is_none());
+        let back: AccountRequest = serde_json::from_value(v).unwrap();
+        assert!(back.admin_password.is_empty());
+    }
+}
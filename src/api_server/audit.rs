@@ -0,0 +1,116 @@
+/// Audit events posted by devices to `/api/audit/{conn,file,alarm}`
+/// (see `post_conn_audit`, `post_file_audit` and `post_alarm_audit` in
+/// `src/server/connection.rs`), plus the admin actions taken here.
+use super::sessions;
+pub use crate::api_models::{AuditEntry, AuditQuery};
+use crate::api_models::{AuditListResponse, ListAuditRequest, STATUS_SUCCESS};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+const MAX_AUDIT_ENTRIES: usize = 10_000;
+
+lazy_static::lazy_static! {
+    static ref AUDIT_LOG: Mutex<VecDeque<AuditEntry>> = Default::default();
+}
+
+pub fn push(entry: AuditEntry) {
+    let mut log = AUDIT_LOG.lock().unwrap();
+    if log.len() >= MAX_AUDIT_ENTRIES {
+        log.pop_front();
+    }
+    log.push_back(entry);
+}
+
+/// Record an action taken through the admin API.
+pub fn admin(action: &str, device_id: &str, info: Value) {
+    push(AuditEntry {
+        time: super::now_secs(),
+        kind: "admin".to_owned(),
+        device_id: device_id.to_owned(),
+        conn_id: None,
+        peer_id: "".to_owned(),
+        action: action.to_owned(),
+        info,
+    });
+}
+
+/// Matching entries, newest first.
+pub fn query(q: &AuditQuery) -> Vec<AuditEntry> {
+    let log = AUDIT_LOG.lock().unwrap();
+    log.iter()
+        .rev()
+        .filter(|e| q.device_id.as_ref().map_or(true, |x| *x == e.device_id))
+        .filter(|e| q.peer_id.as_ref().map_or(true, |x| *x == e.peer_id))
+        .filter(|e| q.kind.as_ref().map_or(true, |x| *x == e.kind))
+        .filter(|e| q.since.map_or(true, |x| e.time >= x))
+        .filter(|e| q.until.map_or(true, |x| e.time <= x))
+        .take(q.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect()
+}
+
+// Connection events: "new" with the ip, the login with "peer" and "type",
+// and "close".
+pub async fn conn(v: web::Json<Value>) -> impl Responder {
+    let device_id = v["id"].as_str().unwrap_or_default().to_string();
+    let conn_id = v["conn_id"].as_i64().map(|x| x as i32);
+    let peer_id = v["peer"][0].as_str().unwrap_or_default().to_string();
+    let action = match v["action"].as_str() {
+        Some(action) => action.to_string(),
+        None if !peer_id.is_empty() => "login".to_owned(),
+        None => "".to_owned(),
+    };
+    if let Some(conn_id) = conn_id {
+        sessions::on_conn_audit(&device_id, conn_id, &action, &v);
+    }
+    push(AuditEntry {
+        time: super::now_secs(),
+        kind: "conn".to_owned(),
+        device_id,
+        conn_id,
+        peer_id,
+        action,
+        info: v.into_inner(),
+    });
+    HttpResponse::Ok().finish()
+}
+
+pub async fn file(v: web::Json<Value>) -> impl Responder {
+    push(AuditEntry {
+        time: super::now_secs(),
+        kind: "file".to_owned(),
+        device_id: v["id"].as_str().unwrap_or_default().to_string(),
+        conn_id: None,
+        peer_id: v["peer_id"].as_str().unwrap_or_default().to_string(),
+        action: v["type"].to_string(),
+        info: v.into_inner(),
+    });
+    HttpResponse::Ok().finish()
+}
+
+pub async fn alarm(v: web::Json<Value>) -> impl Responder {
+    push(AuditEntry {
+        time: super::now_secs(),
+        kind: "alarm".to_owned(),
+        device_id: v["id"].as_str().unwrap_or_default().to_string(),
+        conn_id: None,
+        peer_id: "".to_owned(),
+        action: v["typ"].to_string(),
+        info: v.into_inner(),
+    });
+    HttpResponse::Ok().finish()
+}
+
+// Query the audit log (admin only)
+pub async fn list_audit(http: HttpRequest, req: web::Json<ListAuditRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return super::unauthorized_admin();
+    }
+
+    HttpResponse::Ok().json(AuditListResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        entries: query(&req.query),
+    })
+}
@@ -0,0 +1,532 @@
+/// Server-rendered admin dashboard under `/admin`.
+///
+/// Signing in issues the same JWT as `/api/login`, kept in an http-only
+/// cookie. A token from `/api/login` can also be passed as
+/// `Authorization: Bearer <token>`. Only accounts with the "admin" role
+/// get in, with a token carrying the admin claim of a password login, so
+/// tokens issued without a password don't. Locking an account revokes its
+/// dashboard sessions too.
+use super::{audit, devices, sessions, strategy, Account};
+use actix_web::{
+    cookie::{time::Duration as CookieDuration, Cookie, SameSite},
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use serde::Deserialize;
+
+const COOKIE_NAME: &str = "familydesk_admin";
+const SESSION_TTL_SECS: u64 = 8 * 3600;
+// `device_id` claim of dashboard tokens
+const DASHBOARD_DEVICE_ID: &str = "admin-dashboard";
+const AUDIT_PAGE_SIZE: usize = 200;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/admin", web::get().to(index))
+        .route("/admin/login", web::get().to(login_page))
+        .route("/admin/login", web::post().to(login))
+        .route("/admin/logout", web::post().to(logout))
+        .route("/admin/accounts", web::get().to(accounts))
+        .route("/admin/accounts/lock", web::post().to(lock_account))
+        .route("/admin/devices", web::get().to(devices_page))
+        .route("/admin/policies", web::get().to(policies))
+        .route("/admin/sessions", web::get().to(sessions_page))
+        .route("/admin/sessions/disconnect", web::post().to(disconnect))
+        .route("/admin/audit", web::get().to(audit_page));
+}
+
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+fn format_time(secs: u64) -> String {
+    if secs == 0 {
+        return "-".to_owned();
+    }
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn current_admin(req: &HttpRequest) -> Option<Account> {
+    match req.cookie(COOKIE_NAME) {
+        Some(c) => super::token_admin(c.value()),
+        None => super::bearer_admin(req),
+    }
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+fn page(admin: Option<&Account>, title: &str, body: &str) -> String {
+    let nav = match admin {
+        Some(admin) => format!(
+            r#"<nav>
+<a href="/admin/devices">Devices</a>
+<a href="/admin/sessions">Sessions</a>
+<a href="/admin/accounts">Accounts</a>
+<a href="/admin/policies">Policies</a>
+<a href="/admin/audit">Audit log</a>
+<form method="post" action="/admin/logout"><span>{}</span> <button>Sign out</button></form>
+</nav>"#,
+            escape(&admin.username)
+        ),
+        None => "".to_owned(),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title} - FamilyDesk Admin</title>
+<style>
+body {{ font-family: sans-serif; margin: 0 2em 2em; }}
+nav {{ display: flex; gap: 1em; align-items: center; padding: 1em 0; border-bottom: 1px solid #ccc; }}
+nav form {{ margin-left: auto; }}
+table {{ border-collapse: collapse; margin-top: 1em; }}
+th, td {{ border: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }}
+.online {{ color: green; }}
+.offline {{ color: gray; }}
+.error {{ color: #b00; }}
+</style>
+</head>
+<body>
+{nav}
+<h1>{title}</h1>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+        nav = nav,
+        body = body,
+    )
+}
+
+fn html(status: StatusCode, content: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(content)
+}
+
+macro_rules! require_admin {
+    ($req:expr) => {
+        match current_admin(&$req) {
+            Some(admin) => admin,
+            None => return redirect("/admin/login"),
+        }
+    };
+}
+
+async fn index(req: HttpRequest) -> impl Responder {
+    let _admin = require_admin!(req);
+    redirect("/admin/devices")
+}
+
+fn login_form(error: &str) -> String {
+    let error = if error.is_empty() {
+        "".to_owned()
+    } else {
+        format!(r#"<p class="error">{}</p>"#, escape(error))
+    };
+    format!(
+        r#"{error}<form method="post" action="/admin/login">
+<p><label>Username <input name="username" autofocus></label></p>
+<p><label>Password <input name="password" type="password"></label></p>
+<p><button>Sign in</button></p>
+</form>"#
+    )
+}
+
+async fn login_page() -> impl Responder {
+    html(StatusCode::OK, page(None, "Sign in", &login_form("")))
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+async fn login(form: web::Form<LoginForm>) -> impl Responder {
+    let token = super::authenticate(&form.username, &form.password)
+        .filter(|account| account.role == "admin")
+        .and_then(|account| {
+            super::issue_token(
+                &account.username,
+                DASHBOARD_DEVICE_ID,
+                SESSION_TTL_SECS,
+                true,
+            )
+        });
+    let Some(token) = token else {
+        return html(
+            StatusCode::UNAUTHORIZED,
+            page(None, "Sign in", &login_form("Invalid username or password")),
+        );
+    };
+    let cookie = Cookie::build(COOKIE_NAME, token)
+        .path("/admin")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .max_age(CookieDuration::seconds(SESSION_TTL_SECS as i64))
+        .finish();
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/admin/devices"))
+        .cookie(cookie)
+        .finish()
+}
+
+async fn logout() -> impl Responder {
+    let mut cookie = Cookie::build(COOKIE_NAME, "").path("/admin").finish();
+    cookie.make_removal();
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/admin/login"))
+        .cookie(cookie)
+        .finish()
+}
+
+async fn accounts(req: HttpRequest) -> impl Responder {
+    let admin = require_admin!(req);
+    let mut rows = String::new();
+    for acc in super::accounts_snapshot() {
+        let action = if acc.username == "admin" {
+            "".to_owned()
+        } else {
+            format!(
+                r#"<form method="post" action="/admin/accounts/lock">
+<input type="hidden" name="username" value="{}">
+<input type="hidden" name="locked" value="{}">
+<button>{}</button></form>"#,
+                escape(&acc.username),
+                !acc.locked,
+                if acc.locked { "Unlock" } else { "Lock out" }
+            )
+        };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&acc.username),
+            escape(&acc.role),
+            if acc.can_modify_settings { "yes" } else { "no" },
+            escape(&acc.device_ids.join(", ")),
+            if acc.locked { "locked" } else { "active" },
+            action
+        ));
+    }
+    let body = format!(
+        "<table>\n<tr><th>Username</th><th>Role</th><th>Can modify settings</th>\
+<th>Devices</th><th>State</th><th></th></tr>\n{rows}</table>"
+    );
+    html(StatusCode::OK, page(Some(&admin), "Accounts", &body))
+}
+
+#[derive(Deserialize)]
+pub struct LockForm {
+    username: String,
+    locked: bool,
+}
+
+async fn lock_account(req: HttpRequest, form: web::Form<LockForm>) -> impl Responder {
+    let admin = require_admin!(req);
+    if let Err(e) = super::set_account_locked(&form.username, form.locked) {
+        let body = format!(r#"<p class="error">{}</p>"#, escape(&e));
+        return html(StatusCode::BAD_REQUEST, page(Some(&admin), "Accounts", &body));
+    }
+    redirect("/admin/accounts")
+}
+
+async fn devices_page(req: HttpRequest) -> impl Responder {
+    let admin = require_admin!(req);
+    let now = super::now_secs();
+    let mut rows = String::new();
+    for d in devices::list() {
+        let online = d.is_online(now);
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&d.id),
+            if online { "online" } else { "offline" },
+            if online { "online" } else { "offline" },
+            escape(&d.hostname),
+            escape(&d.username),
+            escape(&d.os),
+            escape(&d.version),
+            escape(&d.group),
+            format_time(d.last_seen)
+        ));
+    }
+    let body = format!(
+        "<table>\n<tr><th>ID</th><th>State</th><th>Hostname</th><th>User</th><th>OS</th>\
+<th>Version</th><th>Group</th><th>Last seen</th></tr>\n{rows}</table>"
+    );
+    html(StatusCode::OK, page(Some(&admin), "Devices", &body))
+}
+
+async fn policies(req: HttpRequest) -> impl Responder {
+    let admin = require_admin!(req);
+    let (strategies, device_assignments, group_assignments) = strategy::snapshot();
+    let mut body = String::from(
+        "<p>Options pushed to devices through the heartbeat. Manage them with \
+<code>/api/save-strategy</code> and <code>/api/assign-strategy</code>.</p>\n",
+    );
+    body.push_str("<table>\n<tr><th>Name</th><th>Options</th><th>Groups</th><th>Devices</th></tr>\n");
+    for s in strategies.iter() {
+        let mut options: Vec<_> = s.options.iter().collect();
+        options.sort();
+        let options: Vec<String> = options
+            .iter()
+            .map(|(k, v)| format!("{} = {}", escape(k), escape(v)))
+            .collect();
+        let assigned = |m: &std::collections::HashMap<String, String>| {
+            let mut v: Vec<_> = m
+                .iter()
+                .filter(|(_, name)| **name == s.name)
+                .map(|(k, _)| escape(k))
+                .collect();
+            v.sort();
+            v.join(", ")
+        };
+        body.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&s.name),
+            options.join("<br>"),
+            assigned(&group_assignments),
+            assigned(&device_assignments)
+        ));
+    }
+    body.push_str("</table>");
+    html(StatusCode::OK, page(Some(&admin), "Policies", &body))
+}
+
+async fn sessions_page(req: HttpRequest) -> impl Responder {
+    let admin = require_admin!(req);
+    let mut rows = String::new();
+    for d in devices::list() {
+        for s in sessions::list(&d.id) {
+            rows.push_str(&format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>
+<form method="post" action="/admin/sessions/disconnect">
+<input type="hidden" name="device_id" value="{}">
+<input type="hidden" name="conn_id" value="{}">
+<button>Disconnect</button></form></td></tr>
+"#,
+                escape(&d.id),
+                s.conn_id,
+                escape(&s.peer_id),
+                escape(&s.peer_name),
+                escape(&s.ip),
+                format_time(s.started_at),
+                escape(&d.id),
+                s.conn_id
+            ));
+        }
+    }
+    let body = format!(
+        "<table>\n<tr><th>Device</th><th>Connection</th><th>Peer ID</th><th>Peer name</th>\
+<th>IP</th><th>Started</th><th></th></tr>\n{rows}</table>"
+    );
+    html(StatusCode::OK, page(Some(&admin), "Sessions", &body))
+}
+
+#[derive(Deserialize)]
+pub struct DisconnectForm {
+    device_id: String,
+    #[serde(default)]
+    conn_id: Option<i32>,
+}
+
+async fn disconnect(req: HttpRequest, form: web::Form<DisconnectForm>) -> impl Responder {
+    let _admin = require_admin!(req);
+    let count = sessions::disconnect(&form.device_id, form.conn_id);
+    audit::admin(
+        "disconnect",
+        &form.device_id,
+        serde_json::json!({ "conn_id": form.conn_id, "count": count }),
+    );
+    redirect("/admin/sessions")
+}
+
+async fn audit_page(req: HttpRequest, query: web::Query<audit::AuditQuery>) -> impl Responder {
+    let admin = require_admin!(req);
+    let mut query = query.into_inner();
+    query.limit = Some(query.limit.unwrap_or(AUDIT_PAGE_SIZE).min(AUDIT_PAGE_SIZE));
+    let mut rows = String::new();
+    for e in audit::query(&query) {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>\n",
+            format_time(e.time),
+            escape(&e.kind),
+            escape(&e.device_id),
+            e.conn_id.map(|x| x.to_string()).unwrap_or_default(),
+            escape(&e.peer_id),
+            escape(&e.action),
+            escape(&e.info.to_string())
+        ));
+    }
+    let body = format!(
+        r#"<form method="get" action="/admin/audit">
+<label>Device <input name="device_id" value="{}"></label>
+<label>Kind <select name="kind">
+<option value="">all</option><option>conn</option><option>file</option><option>alarm</option><option>admin</option>
+</select></label>
+<button>Filter</button></form>
+<table>
+<tr><th>Time</th><th>Kind</th><th>Device</th><th>Connection</th><th>Peer</th><th>Action</th><th>Details</th></tr>
+{rows}</table>"#,
+        escape(query.device_id.as_deref().unwrap_or_default())
+    );
+    html(StatusCode::OK, page(Some(&admin), "Audit log", &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    async fn sign_in() -> Cookie<'static> {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/admin/login")
+            .set_form(&[("username", "admin"), ("password", "admin123")])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        resp.response()
+            .cookies()
+            .find(|c| c.name() == COOKIE_NAME)
+            .expect("session cookie")
+            .into_owned()
+    }
+
+    #[actix_web::test]
+    async fn test_requires_login() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        for uri in ["/admin", "/admin/devices", "/admin/accounts", "/admin/audit"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::SEE_OTHER, "{}", uri);
+            assert_eq!(resp.headers().get(header::LOCATION).unwrap(), "/admin/login");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_wrong_password() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/admin/login")
+            .set_form(&[("username", "admin"), ("password", "wrong")])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_pages_after_login() {
+        let cookie = sign_in().await;
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        // A device checking in shows up online
+        let req = test::TestRequest::post()
+            .uri("/api/sysinfo")
+            .set_json(serde_json::json!({ "id": "DASHBOARD_TEST_PC", "hostname": "<grandma>" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        for (uri, needle) in [
+            ("/admin/devices", "DASHBOARD_TEST_PC"),
+            ("/admin/accounts", "admin"),
+            ("/admin/policies", "Policies"),
+            ("/admin/sessions", "Sessions"),
+            ("/admin/audit", "Audit log"),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .cookie(cookie.clone())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains(needle), "{}", uri);
+            assert!(!body.contains("<grandma>"), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_bearer_token_from_api_login() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .set_json(serde_json::json!({
+                "username": "admin",
+                "password": "admin123",
+                "device_id": "ADMIN_PC"
+            }))
+            .to_request();
+        let rsp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let token = rsp["token"].as_str().unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/admin/devices")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_token_without_admin_login_rejected() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        // Signed for the admin account, but not by a password login
+        let token = crate::api_server::issue_token("admin", "ADMIN_PC", 3600, false).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/admin/devices")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::SEE_OTHER
+        );
+        let req = test::TestRequest::get()
+            .uri("/admin/devices")
+            .cookie(Cookie::new(COOKIE_NAME, token))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::SEE_OTHER
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_non_admin_rejected() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/create-account")
+            .set_json(serde_json::json!({
+                "admin_password": "admin123",
+                "new_username": "dashboard_family",
+                "new_password": "family",
+                "role": "family",
+                "can_modify_settings": false,
+                "device_ids": ["*"]
+            }))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/admin/login")
+            .set_form(&[("username", "dashboard_family"), ("password", "family")])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,181 @@
+/// Device registry fed by the client's hbbs sync loop
+/// (`src/hbbs_http/sync.rs`), which posts to `/api/sysinfo` and
+/// `/api/heartbeat` whenever `api-server` points at this server.
+use super::{sessions, strategy};
+use crate::api_models::{
+    AdminRequest, DeviceInfo, DeviceListResponse, HeartbeatRequest, HeartbeatResponse,
+    StrategyOptions, STATUS_SUCCESS,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use hbb_common::config::keys;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A device is shown as online if it sent a heartbeat within this window.
+/// The client sends one every 3s with connections, and at least every 15s.
+pub const ONLINE_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Default)]
+pub struct Device {
+    pub id: String,
+    pub uuid: String,
+    pub hostname: String,
+    pub username: String,
+    pub os: String,
+    pub version: String,
+    /// `preset-device-group-name` reported in sysinfo
+    pub group: String,
+    /// `preset-strategy-name` reported in sysinfo
+    pub preset_strategy: String,
+    pub last_seen: u64,
+    /// Connection ids reported in the last heartbeat
+    pub conns: Vec<i32>,
+}
+
+impl Device {
+    pub fn is_online(&self, now: u64) -> bool {
+        self.last_seen + ONLINE_TIMEOUT_SECS >= now
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DEVICES: Mutex<HashMap<String, Device>> = Default::default();
+}
+
+pub fn get(id: &str) -> Option<Device> {
+    DEVICES.lock().unwrap().get(id).cloned()
+}
+
+pub fn list() -> Vec<Device> {
+    let mut devices: Vec<_> = DEVICES.lock().unwrap().values().cloned().collect();
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    devices
+}
+
+// Receive sysinfo uploaded by a device. Once a device has a uuid, only the
+// same machine, or an admin, can update it.
+pub async fn sysinfo(http: HttpRequest, info: web::Json<Value>) -> impl Responder {
+    let id = info["id"].as_str().unwrap_or_default();
+    if id.is_empty() {
+        return HttpResponse::BadRequest().body("ID_NOT_FOUND");
+    }
+    let field = |k: &str| info[k].as_str().unwrap_or_default().to_string();
+    let uuid = field("uuid");
+    let uuid_taken = get(id).is_some_and(|d| !d.uuid.is_empty() && d.uuid != uuid);
+    if uuid_taken && super::bearer_admin(&http).is_none() {
+        return HttpResponse::Forbidden().body("UUID_MISMATCH");
+    }
+    let group = field(keys::OPTION_PRESET_DEVICE_GROUP_NAME);
+    let preset_strategy = field(keys::OPTION_PRESET_STRATEGY_NAME);
+    let changed = {
+        let mut devices = DEVICES.lock().unwrap();
+        let device = devices.entry(id.to_string()).or_insert_with(|| Device {
+            id: id.to_string(),
+            ..Default::default()
+        });
+        let changed = device.group != group || device.preset_strategy != preset_strategy;
+        device.uuid = uuid;
+        device.hostname = field("hostname");
+        device.username = field("username");
+        device.os = field("os");
+        device.version = field("version");
+        device.group = group;
+        device.preset_strategy = preset_strategy;
+        device.last_seen = super::now_secs();
+        changed
+    };
+    if changed {
+        strategy::touch_device(id);
+    }
+    HttpResponse::Ok().body("SYSINFO_UPDATED")
+}
+
+// Periodic device heartbeat, answered with pending strategy updates
+pub async fn heartbeat(req: web::Json<HeartbeatRequest>) -> impl Responder {
+    let device = {
+        let mut devices = DEVICES.lock().unwrap();
+        match devices.get_mut(&req.id) {
+            Some(device) if device.uuid.is_empty() || device.uuid == req.uuid => {
+                device.last_seen = super::now_secs();
+                device.conns = req.conns.clone();
+                device.clone()
+            }
+            // Unknown device, or the id was taken over by another machine
+            _ => {
+                return HttpResponse::Ok().json(HeartbeatResponse {
+                    sysinfo: Some(true),
+                    ..Default::default()
+                })
+            }
+        }
+    };
+
+    let mut rsp = HeartbeatResponse {
+        disconnect: sessions::on_heartbeat(&device.id, &device.conns, &super::locked_peer_ids()),
+        ..Default::default()
+    };
+    if let Some(update) = strategy::pending_update(&device, req.modified_at) {
+        rsp.modified_at = Some(update.modified_at);
+        rsp.strategy = Some(StrategyOptions {
+            config_options: update.config_options,
+        });
+    }
+    HttpResponse::Ok().json(rsp)
+}
+
+// List devices with their online state (admin only)
+pub async fn list_devices(http: HttpRequest, req: web::Json<AdminRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return super::unauthorized_admin();
+    }
+
+    let now = super::now_secs();
+    let devices = list()
+        .into_iter()
+        .map(|d| DeviceInfo {
+            online: d.is_online(now),
+            conns: d.conns.len(),
+            id: d.id,
+            hostname: d.hostname,
+            username: d.username,
+            os: d.os,
+            version: d.version,
+            group: d.group,
+            last_seen: d.last_seen,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(DeviceListResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        devices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    #[actix_web::test]
+    async fn test_sysinfo_keeps_uuid() {
+        let app = test::init_service(App::new().configure(crate::api_server::configure)).await;
+        let sysinfo = |uuid: &str| {
+            test::TestRequest::post().uri("/api/sysinfo").set_json(
+                serde_json::json!({ "id": "UUID_TEST_PC", "uuid": uuid, "hostname": uuid }),
+            )
+        };
+        let req = sysinfo("first").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = sysinfo("first").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = sysinfo("second").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(get("UUID_TEST_PC").unwrap().uuid, "first");
+        assert_eq!(get("UUID_TEST_PC").unwrap().hostname, "first");
+    }
+}
@@ -1,16 +1,34 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{http::header, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
+use crate::api_models::{
+    AccountInfo, AccountListResponse, AccountRequest, AdminRequest, CreateAccountRequest,
+    ErrorCode, ErrorResponse, IssueTokenRequest, LockAccountRequest, LockAccountResponse,
+    LoginRequest, LoginResponse, MessageResponse, ResetPasswordRequest, TokenResponse,
+    UpdateAccountRequest, VerifySettingsRequest, VerifySettingsResponse, VerifyTokenRequest,
+    VerifyTokenResponse, STATUS_SUCCESS,
+};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
 
+mod audit;
+mod dashboard;
+mod devices;
+mod openapi;
+mod sessions;
+mod strategy;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub device_id: String,
     pub family_member: String,
     pub exp: u64,
     pub iat: u64,
+    /// Set only on tokens from a password login of an admin account, the
+    /// only tokens admin endpoints and the dashboard accept.
+    #[serde(default)]
+    pub admin: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,29 +38,19 @@ pub struct Account {
     pub role: String, // "admin", "family", "student"
     pub can_modify_settings: bool,
     pub device_ids: Vec<String>, // Devices this account can access
-}
-
-#[derive(Deserialize)]
-pub struct DeviceAuth {
-    device_id: String,
-    auth_token: String,
-}
-
-#[derive(Deserialize)]
-pub struct LoginRequest {
-    username: String,
-    password: String,
-    device_id: String,
-}
-
-#[derive(Deserialize)]
-pub struct VerifySettingsRequest {
-    device_id: String,
-    username: String,
-    password: String,
+    #[serde(default)]
+    pub locked: bool,
+    // Tokens issued at or before this time are rejected
+    #[serde(default)]
+    pub tokens_valid_after: u64,
+    // Devices this account logged in from, used to find its sessions
+    #[serde(default)]
+    pub login_device_ids: Vec<String>,
 }
 
 const JWT_SECRET: &[u8] = b"your-secret-key-change-in-production";
+// Longest validity of a token issued by an admin
+const MAX_TOKEN_TTL_DAYS: u64 = 365;
 
 // In-memory account storage (replace with database in production)
 lazy_static::lazy_static! {
@@ -55,6 +63,9 @@ lazy_static::lazy_static! {
             role: "admin".to_string(),
             can_modify_settings: true,
             device_ids: vec!["*".to_string()], // All devices
+            locked: false,
+            tokens_valid_after: 0,
+            login_device_ids: vec![],
         });
         Mutex::new(m)
     };
@@ -83,53 +94,203 @@ fn verify_password(password: &str, hash: &str) -> bool {
     false
 }
 
+pub(crate) fn verify_admin_password(password: &str) -> bool {
+    let accounts = ACCOUNTS.lock().unwrap();
+    accounts
+        .get("admin")
+        .map(|admin| verify_password(password, &admin.password_hash))
+        .unwrap_or(false)
+}
+
+/// The account behind an `Authorization: Bearer` token, if it's an admin.
+pub(crate) fn bearer_admin(req: &HttpRequest) -> Option<Account> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    token_admin(token)
+}
+
+pub(crate) fn token_admin(token: &str) -> Option<Account> {
+    let claims = decode_token(token)?;
+    if !claims.admin {
+        return None;
+    }
+    let account = get_account(&claims.family_member)?;
+    if account.role != "admin" {
+        return None;
+    }
+    Some(account)
+}
+
+/// Admin endpoints accept either the admin password in the body or a
+/// bearer token of an admin account, as used by `familydesk-admin`.
+pub(crate) fn authorize_admin(req: &HttpRequest, admin_password: &str) -> bool {
+    if !admin_password.is_empty() && verify_admin_password(admin_password) {
+        return true;
+    }
+    bearer_admin(req).is_some()
+}
+
+/// The error envelope shared by all endpoints, with the status code of
+/// its error code.
+pub(crate) fn api_error(code: ErrorCode, message: impl Into<String>) -> HttpResponse {
+    let status = actix_web::http::StatusCode::from_u16(code.http_status())
+        .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponse::build(status).json(ErrorResponse::new(code, message))
+}
+
+pub(crate) fn unauthorized_admin() -> HttpResponse {
+    api_error(ErrorCode::Unauthorized, "Invalid admin password")
+}
+
+pub(crate) fn message(message: &str) -> HttpResponse {
+    HttpResponse::Ok().json(MessageResponse::new(message))
+}
+
+// Sessions from these peer ids belong to locked out accounts
+pub(crate) fn locked_peer_ids() -> HashSet<String> {
+    let accounts = ACCOUNTS.lock().unwrap();
+    accounts
+        .values()
+        .filter(|acc| acc.locked)
+        .flat_map(|acc| acc.login_device_ids.iter().cloned())
+        .collect()
+}
+
+fn token_revoked(claims: &Claims) -> bool {
+    let accounts = ACCOUNTS.lock().unwrap();
+    match accounts.get(&claims.family_member) {
+        Some(account) => account.locked || claims.iat <= account.tokens_valid_after,
+        None => false,
+    }
+}
+
+pub(crate) fn get_account(username: &str) -> Option<Account> {
+    ACCOUNTS.lock().unwrap().get(username).cloned()
+}
+
+pub(crate) fn accounts_snapshot() -> Vec<Account> {
+    let mut accounts: Vec<_> = ACCOUNTS.lock().unwrap().values().cloned().collect();
+    accounts.sort_by(|a, b| a.username.cmp(&b.username));
+    accounts
+}
+
+/// Check username and password of an account that isn't locked.
+pub(crate) fn authenticate(username: &str, password: &str) -> Option<Account> {
+    let account = get_account(username)?;
+    if account.locked || !verify_password(password, &account.password_hash) {
+        return None;
+    }
+    Some(account)
+}
+
+/// `admin` must only be set after checking the password of an admin account.
+pub(crate) fn issue_token(
+    username: &str,
+    device_id: &str,
+    ttl_secs: u64,
+    admin: bool,
+) -> Option<String> {
+    let now = now_secs();
+    let claims = Claims {
+        device_id: device_id.to_string(),
+        family_member: username.to_string(),
+        exp: now + ttl_secs,
+        iat: now,
+        admin,
+    };
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET)).ok()
+}
+
+/// Claims of a token that is correctly signed, not expired and not revoked.
+pub(crate) fn decode_token(token: &str) -> Option<Claims> {
+    let claims = decode::<Claims>(token, &DecodingKey::from_secret(JWT_SECRET), &Validation::default())
+        .ok()?
+        .claims;
+    if claims.exp <= now_secs() || token_revoked(&claims) {
+        return None;
+    }
+    Some(claims)
+}
+
+pub(crate) fn set_account_locked(username: &str, locked: bool) -> Result<(), String> {
+    if username == "admin" {
+        return Err("The admin account can't be locked".to_owned());
+    }
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    let account = accounts
+        .get_mut(username)
+        .ok_or_else(|| "Account not found".to_owned())?;
+    account.locked = locked;
+    if locked {
+        account.tokens_valid_after = now_secs();
+    }
+    audit::admin(
+        if locked { "lock_account" } else { "unlock_account" },
+        "",
+        serde_json::json!({ "username": username }),
+    );
+    Ok(())
+}
+
+fn account_locked() -> HttpResponse {
+    api_error(ErrorCode::AccountLocked, "Account is locked")
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub(crate) fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
 // Login and get JWT token
 pub async fn login(req: web::Json<LoginRequest>) -> impl Responder {
-    let accounts = ACCOUNTS.lock().unwrap();
+    let mut accounts = ACCOUNTS.lock().unwrap();
 
-    if let Some(account) = accounts.get(&req.username) {
+    if let Some(account) = accounts.get_mut(&req.username) {
         if verify_password(&req.password, &account.password_hash) {
+            if account.locked {
+                return account_locked();
+            }
             // Check if account has access to this device
             if account.device_ids.contains(&"*".to_string())
                 || account.device_ids.contains(&req.device_id) {
 
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                let claims = Claims {
-                    device_id: req.device_id.clone(),
-                    family_member: account.username.clone(),
-                    exp: now + (86400 * 7), // 7 days
-                    iat: now,
-                };
-
-                match encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET)) {
-                    Ok(token) => {
-                        return HttpResponse::Ok().json(serde_json::json!({
-                            "status": "success",
-                            "token": token,
-                            "username": account.username,
-                            "role": account.role,
-                            "can_modify_settings": account.can_modify_settings
-                        }));
+                if !account.login_device_ids.contains(&req.device_id) {
+                    account.login_device_ids.push(req.device_id.clone());
+                }
+
+                let admin = account.role == "admin";
+                match issue_token(&account.username, &req.device_id, 86400 * 7, admin) { // 7 days
+                    Some(token) => {
+                        return HttpResponse::Ok().json(LoginResponse {
+                            status: STATUS_SUCCESS.to_owned(),
+                            token,
+                            username: account.username.clone(),
+                            role: account.role.clone(),
+                            can_modify_settings: account.can_modify_settings,
+                        });
                     }
-                    Err(_) => {}
+                    None => return api_error(ErrorCode::Internal, "Failed to issue token"),
                 }
             } else {
-                return HttpResponse::Forbidden().json(serde_json::json!({
-                    "status": "error",
-                    "message": "No access to this device"
-                }));
+                return api_error(ErrorCode::NoDeviceAccess, "No access to this device");
             }
         }
     }
 
-    HttpResponse::Unauthorized().json(serde_json::json!({
-        "status": "error",
-        "message": "Invalid username or password"
-    }))
+    api_error(ErrorCode::InvalidCredentials, "Invalid username or password")
 }
 
 // Verify if user can modify settings
@@ -138,61 +299,37 @@ pub async fn verify_settings_access(req: web::Json<VerifySettingsRequest>) -> im
 
     if let Some(account) = accounts.get(&req.username) {
         if verify_password(&req.password, &account.password_hash) {
+            if account.locked {
+                return account_locked();
+            }
             // Check device access
             if account.device_ids.contains(&"*".to_string())
                 || account.device_ids.contains(&req.device_id) {
 
-                return HttpResponse::Ok().json(serde_json::json!({
-                    "status": "success",
-                    "can_modify_settings": account.can_modify_settings,
-                    "username": account.username,
-                    "role": account.role
-                }));
+                return HttpResponse::Ok().json(VerifySettingsResponse {
+                    status: STATUS_SUCCESS.to_owned(),
+                    can_modify_settings: account.can_modify_settings,
+                    username: account.username.clone(),
+                    role: account.role.clone(),
+                });
             }
         }
     }
 
-    HttpResponse::Unauthorized().json(serde_json::json!({
-        "status": "error",
-        "message": "Invalid credentials or no access"
-    }))
+    api_error(ErrorCode::InvalidCredentials, "Invalid credentials or no access")
 }
 
 // Create new account (admin only)
-#[derive(Deserialize)]
-pub struct CreateAccountRequest {
-    admin_password: String,
-    new_username: String,
-    new_password: String,
-    role: String,
-    can_modify_settings: bool,
-    device_ids: Vec<String>,
-}
-
-pub async fn create_account(req: web::Json<CreateAccountRequest>) -> impl Responder {
-    let mut accounts = ACCOUNTS.lock().unwrap();
-
+pub async fn create_account(http: HttpRequest, req: web::Json<CreateAccountRequest>) -> impl Responder {
     // Verify admin credentials
-    if let Some(admin) = accounts.get("admin") {
-        if !verify_password(&req.admin_password, &admin.password_hash) {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "status": "error",
-                "message": "Invalid admin password"
-            }));
-        }
-    } else {
-        return HttpResponse::InternalServerError().json(serde_json::json!({
-            "status": "error",
-            "message": "Admin account not found"
-        }));
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
     }
+    let mut accounts = ACCOUNTS.lock().unwrap();
 
     // Check if username already exists
     if accounts.contains_key(&req.new_username) {
-        return HttpResponse::Conflict().json(serde_json::json!({
-            "status": "error",
-            "message": "Username already exists"
-        }));
+        return api_error(ErrorCode::Conflict, "Username already exists");
     }
 
     // Create new account
@@ -202,93 +339,234 @@ pub async fn create_account(req: web::Json<CreateAccountRequest>) -> impl Respon
         role: req.role.clone(),
         can_modify_settings: req.can_modify_settings,
         device_ids: req.device_ids.clone(),
+        locked: false,
+        tokens_valid_after: 0,
+        login_device_ids: vec![],
     };
 
     accounts.insert(req.new_username.clone(), new_account);
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "success",
-        "message": "Account created successfully",
-        "username": req.new_username
-    }))
+    message("Account created successfully")
 }
 
 // List all accounts (admin only)
-#[derive(Deserialize)]
-pub struct AdminRequest {
-    admin_password: String,
+pub async fn list_accounts(http: HttpRequest, req: web::Json<AdminRequest>) -> impl Responder {
+    // Verify admin
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    let accounts = accounts_snapshot()
+        .into_iter()
+        .map(|acc| AccountInfo {
+            username: acc.username,
+            role: acc.role,
+            can_modify_settings: acc.can_modify_settings,
+            device_ids: acc.device_ids,
+            locked: acc.locked,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(AccountListResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        accounts,
+    })
 }
 
-pub async fn list_accounts(req: web::Json<AdminRequest>) -> impl Responder {
-    let accounts = ACCOUNTS.lock().unwrap();
+// Lock out an account: reject its logins, revoke its tokens and
+// disconnect its sessions on the next heartbeats (admin only)
+pub async fn lock_account(http: HttpRequest, req: web::Json<LockAccountRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if req.username != "admin" && get_account(&req.username).is_none() {
+        return account_not_found();
+    }
+    if let Err(e) = set_account_locked(&req.username, req.locked) {
+        return api_error(ErrorCode::BadRequest, e);
+    }
 
-    // Verify admin
-    if let Some(admin) = accounts.get("admin") {
-        if !verify_password(&req.admin_password, &admin.password_hash) {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "status": "error",
-                "message": "Invalid admin password"
-            }));
+    HttpResponse::Ok().json(LockAccountResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        username: req.username.clone(),
+        locked: req.locked,
+    })
+}
+
+// Change role, settings permission or devices of an account (admin only)
+pub async fn update_account(http: HttpRequest, req: web::Json<UpdateAccountRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if req.username == "admin" && req.role.as_ref().map_or(false, |r| r != "admin") {
+        return api_error(ErrorCode::BadRequest, "The admin account must keep the admin role");
+    }
+
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    let Some(account) = accounts.get_mut(&req.username) else {
+        return account_not_found();
+    };
+    if let Some(role) = &req.role {
+        account.role = role.clone();
+    }
+    if let Some(can_modify_settings) = req.can_modify_settings {
+        account.can_modify_settings = can_modify_settings;
+    }
+    if let Some(device_ids) = &req.device_ids {
+        account.device_ids = device_ids.clone();
+    }
+    audit::admin("update_account", "", serde_json::json!({ "username": req.username }));
+
+    message("Account updated")
+}
+
+// Delete an account (admin only)
+pub async fn delete_account(http: HttpRequest, req: web::Json<AccountRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if req.username == "admin" {
+        return api_error(ErrorCode::BadRequest, "The admin account can't be deleted");
+    }
+
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    if accounts.remove(&req.username).is_none() {
+        return account_not_found();
+    }
+    audit::admin("delete_account", "", serde_json::json!({ "username": req.username }));
+
+    message("Account deleted")
+}
+
+// Set a new password, revoking the tokens issued so far (admin only)
+pub async fn reset_password(http: HttpRequest, req: web::Json<ResetPasswordRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if req.new_password.is_empty() {
+        return api_error(ErrorCode::BadRequest, "Password must not be empty");
+    }
+
+    let password_hash = hash_password(&req.new_password);
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    let Some(account) = accounts.get_mut(&req.username) else {
+        return account_not_found();
+    };
+    account.password_hash = password_hash;
+    account.tokens_valid_after = now_secs();
+    audit::admin("reset_password", "", serde_json::json!({ "username": req.username }));
+
+    message("Password reset")
+}
+
+// Issue a token for an account and device without its password (admin only)
+pub async fn issue_account_token(http: HttpRequest, req: web::Json<IssueTokenRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if get_account(&req.username).is_none() {
+        return account_not_found();
+    }
+
+    let ttl_days = req.ttl_days.unwrap_or(7);
+    if ttl_days == 0 || ttl_days > MAX_TOKEN_TTL_DAYS {
+        return api_error(
+            ErrorCode::BadRequest,
+            format!("ttl_days must be between 1 and {}", MAX_TOKEN_TTL_DAYS),
+        );
+    }
+    match issue_token(&req.username, &req.device_id, 86400 * ttl_days, false) {
+        Some(token) => {
+            audit::admin(
+                "issue_token",
+                &req.device_id,
+                serde_json::json!({ "username": req.username, "ttl_days": ttl_days }),
+            );
+            HttpResponse::Ok().json(TokenResponse {
+                status: STATUS_SUCCESS.to_owned(),
+                token,
+                expires_at: now_secs() + 86400 * ttl_days,
+            })
         }
+        None => api_error(ErrorCode::Internal, "Failed to issue token"),
     }
+}
 
-    let account_list: Vec<_> = accounts.values()
-        .map(|acc| serde_json::json!({
-            "username": acc.username,
-            "role": acc.role,
-            "can_modify_settings": acc.can_modify_settings,
-            "device_ids": acc.device_ids
-        }))
-        .collect();
+// Revoke all tokens issued to an account so far (admin only)
+pub async fn revoke_tokens(http: HttpRequest, req: web::Json<AccountRequest>) -> impl Responder {
+    if !authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
 
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "success",
-        "accounts": account_list
-    }))
+    let mut accounts = ACCOUNTS.lock().unwrap();
+    let Some(account) = accounts.get_mut(&req.username) else {
+        return account_not_found();
+    };
+    account.tokens_valid_after = now_secs();
+    audit::admin("revoke_tokens", "", serde_json::json!({ "username": req.username }));
+
+    message("Tokens revoked")
 }
 
-pub async fn verify_device(auth: web::Json<DeviceAuth>) -> impl Responder {
-    match decode::<Claims>(
+fn account_not_found() -> HttpResponse {
+    api_error(ErrorCode::NotFound, "Account not found")
+}
+
+pub async fn verify_device(auth: web::Json<VerifyTokenRequest>) -> impl Responder {
+    let claims = match decode::<Claims>(
         &auth.auth_token,
         &DecodingKey::from_secret(JWT_SECRET),
         &Validation::default(),
     ) {
-        Ok(token_data) => {
-            if token_data.claims.device_id == auth.device_id {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                if token_data.claims.exp > now {
-                    return HttpResponse::Ok().json(serde_json::json!({
-                        "status": "valid",
-                        "family_member": token_data.claims.family_member
-                    }));
-                }
-            }
-            HttpResponse::Unauthorized().json(serde_json::json!({"status": "expired"}))
+        Ok(token_data) => token_data.claims,
+        Err(e) if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::ExpiredSignature) => {
+            return api_error(ErrorCode::TokenExpired, "Token expired");
         }
-        Err(_) => HttpResponse::Unauthorized().json(serde_json::json!({"status": "invalid"})),
+        Err(_) => return api_error(ErrorCode::TokenInvalid, "Invalid token"),
+    };
+    if claims.device_id != auth.device_id {
+        return api_error(ErrorCode::TokenInvalid, "Token was issued for another device");
+    }
+    if token_revoked(&claims) {
+        return api_error(ErrorCode::TokenRevoked, "Token revoked");
+    }
+    if claims.exp <= now_secs() {
+        return api_error(ErrorCode::TokenExpired, "Token expired");
     }
+    HttpResponse::Ok().json(VerifyTokenResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        family_member: claims.family_member,
+    })
 }
 
-pub async fn generate_token(device: web::Json<serde_json::Value>) -> impl Responder {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    let claims = Claims {
-        device_id: device["device_id"].as_str().unwrap_or("").to_string(),
-        family_member: device["family_member"].as_str().unwrap_or("").to_string(),
-        exp: now + (86400 * 365),
-        iat: now,
-    };
-
-    match encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET)) {
-        Ok(token) => HttpResponse::Ok().json(serde_json::json!({"token": token})),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/login", web::post().to(login))
+        .route("/api/verify-settings", web::post().to(verify_settings_access))
+        .route("/api/create-account", web::post().to(create_account))
+        .route("/api/list-accounts", web::post().to(list_accounts))
+        .route("/api/verify", web::post().to(verify_device))
+        .route("/api/sysinfo", web::post().to(devices::sysinfo))
+        .route("/api/heartbeat", web::post().to(devices::heartbeat))
+        .route("/api/audit/conn", web::post().to(audit::conn))
+        .route("/api/audit/file", web::post().to(audit::file))
+        .route("/api/audit/alarm", web::post().to(audit::alarm))
+        .route("/api/list-audit", web::post().to(audit::list_audit))
+        .route("/api/list-sessions", web::post().to(sessions::list_sessions))
+        .route("/api/disconnect-session", web::post().to(sessions::disconnect_session))
+        .route("/api/lock-account", web::post().to(lock_account))
+        .route("/api/update-account", web::post().to(update_account))
+        .route("/api/delete-account", web::post().to(delete_account))
+        .route("/api/reset-password", web::post().to(reset_password))
+        .route("/api/issue-token", web::post().to(issue_account_token))
+        .route("/api/revoke-tokens", web::post().to(revoke_tokens))
+        .route("/api/list-devices", web::post().to(devices::list_devices))
+        .route("/api/list-strategies", web::post().to(strategy::list_strategies))
+        .route("/api/save-strategy", web::post().to(strategy::save_strategy))
+        .route("/api/delete-strategy", web::post().to(strategy::delete_strategy))
+        .route("/api/assign-strategy", web::post().to(strategy::assign_strategy))
+        .route("/api/preview-strategy", web::post().to(strategy::preview_strategy))
+        .route("/api/openapi.json", web::get().to(openapi::spec))
+        .route("/health", web::get().to(|| async { HttpResponse::Ok().body("OK") }));
+    dashboard::configure(cfg);
 }
 
 #[actix_web::main]
@@ -302,17 +580,73 @@ pub async fn start_api_server() -> std::io::Result<()> {
     println!("⚠️  IMPORTANT: Change the admin password immediately!");
     println!("===========================================");
 
-    HttpServer::new(|| {
-        App::new()
-            .route("/api/login", web::post().to(login))
-            .route("/api/verify-settings", web::post().to(verify_settings_access))
-            .route("/api/create-account", web::post().to(create_account))
-            .route("/api/list-accounts", web::post().to(list_accounts))
-            .route("/api/verify", web::post().to(verify_device))
-            .route("/api/generate", web::post().to(generate_token))
-            .route("/health", web::get().to(|| async { HttpResponse::Ok().body("OK") }))
-    })
-    .bind(("0.0.0.0", 21114))?
-    .run()
-    .await
+    HttpServer::new(|| App::new().configure(configure))
+        .bind(("0.0.0.0", 21114))?
+        .run()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test};
+
+    #[actix_web::test]
+    async fn test_issued_token_not_admin() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/issue-token")
+            .set_json(serde_json::json!({
+                "admin_password": "admin123",
+                "username": "admin",
+                "device_id": "ADMIN_PC"
+            }))
+            .to_request();
+        let rsp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let token = rsp["token"].as_str().unwrap();
+
+        // Valid for the device, but not for admin endpoints
+        assert!(decode_token(token).is_some());
+        let req = test::TestRequest::post()
+            .uri("/api/list-accounts")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(serde_json::json!({ "admin_password": "" }))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_no_unauthenticated_token_endpoint() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/generate")
+            .set_json(serde_json::json!({ "device_id": "ADMIN_PC", "family_member": "admin" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_issue_token_ttl_limit() {
+        let app = test::init_service(App::new().configure(configure)).await;
+        for ttl_days in [0, MAX_TOKEN_TTL_DAYS + 1, u64::MAX] {
+            let req = test::TestRequest::post()
+                .uri("/api/issue-token")
+                .set_json(serde_json::json!({
+                    "admin_password": "admin123",
+                    "username": "admin",
+                    "device_id": "ADMIN_PC",
+                    "ttl_days": ttl_days
+                }))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::BAD_REQUEST,
+                "{}",
+                ttl_days
+            );
+        }
+    }
 }
@@ -0,0 +1,319 @@
+/// OpenAPI 3 description of the API, served at `/api/openapi.json`.
+///
+/// The document is generated from [`endpoints`] and the `example()` of the
+/// models in `crate::api_models`, so it can't drift from the types the
+/// handlers and clients actually use. The tests below call every endpoint
+/// and check the responses against it.
+use crate::api_models::*;
+use actix_web::{HttpResponse, Responder};
+use serde_json::{json, Map, Value};
+
+#[derive(Clone, Copy)]
+pub struct Model {
+    pub name: &'static str,
+    pub example: fn() -> Value,
+    // Whether a JSON value deserializes into the model
+    pub accepts: fn(&Value) -> bool,
+}
+
+fn example<T: ApiModel>() -> Value {
+    serde_json::to_value(T::example()).unwrap_or_default()
+}
+
+fn accepts<T: ApiModel>(v: &Value) -> bool {
+    serde_json::from_value::<T>(v.clone()).is_ok()
+}
+
+macro_rules! model {
+    ($t:ty) => {
+        Model {
+            name: stringify!($t),
+            example: example::<$t>,
+            accepts: accepts::<$t>,
+        }
+    };
+}
+
+#[derive(Clone, Copy)]
+pub enum Body {
+    Empty,
+    Text(&'static str),
+    // Arbitrary JSON object, as posted by the client's sync and audit code
+    Object(&'static str),
+    Json(Model),
+}
+
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub summary: &'static str,
+    // Requires the admin password or an admin bearer token
+    pub admin: bool,
+    pub request: Body,
+    pub response: Body,
+}
+
+const fn post(path: &'static str, summary: &'static str, request: Body, response: Body) -> Endpoint {
+    Endpoint {
+        method: "post",
+        path,
+        summary,
+        admin: false,
+        request,
+        response,
+    }
+}
+
+const fn admin(path: &'static str, summary: &'static str, request: Body, response: Body) -> Endpoint {
+    Endpoint {
+        admin: true,
+        ..post(path, summary, request, response)
+    }
+}
+
+pub fn endpoints() -> Vec<Endpoint> {
+    use Body::*;
+    let message = Json(model!(MessageResponse));
+    let admin_request = Json(model!(AdminRequest));
+    let account_request = Json(model!(AccountRequest));
+    vec![
+        post("/api/login", "Log in and get a JWT", Json(model!(LoginRequest)), Json(model!(LoginResponse))),
+        post(
+            "/api/verify-settings",
+            "Check credentials for changing settings on a device",
+            Json(model!(VerifySettingsRequest)),
+            Json(model!(VerifySettingsResponse)),
+        ),
+        post("/api/verify", "Verify a device token", Json(model!(VerifyTokenRequest)), Json(model!(VerifyTokenResponse))),
+        post(
+            "/api/sysinfo",
+            "Upload device information, answered with SYSINFO_UPDATED",
+            Object("Device information from the client's sync loop"),
+            Text("SYSINFO_UPDATED"),
+        ),
+        post("/api/heartbeat", "Device heartbeat", Json(model!(HeartbeatRequest)), Json(model!(HeartbeatResponse))),
+        post("/api/audit/conn", "Connection audit event", Object("Connection audit event"), Empty),
+        post("/api/audit/file", "File transfer audit event", Object("File transfer audit event"), Empty),
+        post("/api/audit/alarm", "Alarm audit event", Object("Alarm audit event"), Empty),
+        admin("/api/create-account", "Create an account", Json(model!(CreateAccountRequest)), message),
+        admin("/api/list-accounts", "List accounts", admin_request, Json(model!(AccountListResponse))),
+        admin("/api/update-account", "Change an account", Json(model!(UpdateAccountRequest)), message),
+        admin("/api/delete-account", "Delete an account", account_request, message),
+        admin("/api/lock-account", "Lock or unlock an account", Json(model!(LockAccountRequest)), Json(model!(LockAccountResponse))),
+        admin("/api/reset-password", "Set a new password", Json(model!(ResetPasswordRequest)), message),
+        admin("/api/issue-token", "Issue a token for an account", Json(model!(IssueTokenRequest)), Json(model!(TokenResponse))),
+        admin("/api/revoke-tokens", "Revoke the tokens of an account", account_request, message),
+        admin("/api/list-devices", "List devices", admin_request, Json(model!(DeviceListResponse))),
+        admin("/api/list-audit", "Query the audit log", Json(model!(ListAuditRequest)), Json(model!(AuditListResponse))),
+        admin("/api/list-sessions", "List live sessions", Json(model!(ListSessionsRequest)), Json(model!(SessionListResponse))),
+        admin("/api/disconnect-session", "Disconnect sessions of a device", Json(model!(DisconnectRequest)), message),
+        admin("/api/list-strategies", "List strategies and assignments", admin_request, Json(model!(StrategyListResponse))),
+        admin("/api/save-strategy", "Create or replace a strategy", Json(model!(SaveStrategyRequest)), Json(model!(SaveStrategyResponse))),
+        admin("/api/delete-strategy", "Delete a strategy", Json(model!(DeleteStrategyRequest)), message),
+        admin("/api/assign-strategy", "Assign a strategy to a device or group", Json(model!(AssignStrategyRequest)), message),
+        admin(
+            "/api/preview-strategy",
+            "Effective options of a device",
+            Json(model!(PreviewStrategyRequest)),
+            Json(model!(PreviewStrategyResponse)),
+        ),
+        Endpoint {
+            method: "get",
+            path: "/health",
+            summary: "Health check",
+            admin: false,
+            request: Empty,
+            response: Text("OK"),
+        },
+    ]
+}
+
+// Schema of a JSON value, taken from its shape.
+fn schema_of(v: &Value) -> Value {
+    match v {
+        Value::Null => json!({ "nullable": true }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(n) if n.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(a) => json!({
+            "type": "array",
+            "items": a.first().map(schema_of).unwrap_or_else(|| json!({})),
+        }),
+        Value::Object(o) => {
+            let properties: Map<String, Value> =
+                o.iter().map(|(k, v)| (k.clone(), schema_of(v))).collect();
+            json!({ "type": "object", "properties": properties })
+        }
+    }
+}
+
+/// Schema of a model. A field is required if the example stops
+/// deserializing without it.
+fn model_schema(model: &Model) -> Value {
+    let example = (model.example)();
+    let mut schema = schema_of(&example);
+    if let Value::Object(o) = &example {
+        let required: Vec<&String> = o
+            .keys()
+            .filter(|k| {
+                let mut o = o.clone();
+                o.remove(k.as_str());
+                !(model.accepts)(&Value::Object(o))
+            })
+            .collect();
+        if !required.is_empty() {
+            schema["required"] = json!(required);
+        }
+    }
+    if model.name == "ErrorResponse" {
+        schema["properties"]["code"]["enum"] = json!(ErrorCode::ALL);
+    }
+    schema["example"] = example;
+    schema
+}
+
+fn content(body: &Body, schemas: &mut Map<String, Value>) -> Option<Value> {
+    match body {
+        Body::Empty => None,
+        Body::Text(example) => Some(json!({
+            "text/plain": { "schema": { "type": "string" }, "example": example }
+        })),
+        Body::Object(description) => Some(json!({
+            "application/json": { "schema": { "type": "object", "description": description } }
+        })),
+        Body::Json(model) => {
+            schemas.insert(model.name.to_owned(), model_schema(model));
+            Some(json!({
+                "application/json": {
+                    "schema": { "$ref": format!("#/components/schemas/{}", model.name) }
+                }
+            }))
+        }
+    }
+}
+
+pub fn document() -> Value {
+    let mut schemas = Map::new();
+    let error = content(&Body::Json(model!(ErrorResponse)), &mut schemas);
+    let mut paths = Map::new();
+    for e in endpoints() {
+        let mut op = json!({ "summary": e.summary });
+        if e.admin {
+            op["security"] = json!([{ "bearerAuth": [] }, {}]);
+            op["description"] = json!(
+                "Admin only: pass `admin_password` in the body or an admin account's token as bearer."
+            );
+        }
+        if let Some(c) = content(&e.request, &mut schemas) {
+            op["requestBody"] = json!({ "required": true, "content": c });
+        }
+        let mut ok = json!({ "description": "Success" });
+        if let Some(c) = content(&e.response, &mut schemas) {
+            ok["content"] = c;
+        }
+        op["responses"] = json!({
+            "200": ok,
+            "default": { "description": "Error", "content": error },
+        });
+        if let Value::Object(p) = paths.entry(e.path).or_insert_with(|| json!({})) {
+            p.insert(e.method.to_owned(), op);
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "FamilyDesk API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }
+            }
+        }
+    })
+}
+
+pub async fn spec() -> impl Responder {
+    HttpResponse::Ok().json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[test]
+    fn test_document_refs_resolve() {
+        let doc = document();
+        let text = doc.to_string();
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        for name in text.split("#/components/schemas/").skip(1) {
+            let name = &name[..name.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+        // Optional fields are not required, mandatory ones are
+        let update = &schemas["UpdateAccountRequest"]["required"];
+        assert_eq!(update, &json!(["username"]));
+        assert_eq!(schemas["LoginRequest"]["required"].as_array().unwrap().len(), 3);
+    }
+
+    // Call every documented endpoint with its example request: a success
+    // must deserialize into the documented model, a failure into the
+    // error envelope with the status of its code.
+    #[actix_web::test]
+    async fn test_server_matches_document() {
+        let app = test::init_service(App::new().configure(super::super::configure)).await;
+        for e in endpoints() {
+            let req = match e.request {
+                Body::Json(model) => test::TestRequest::post().set_json((model.example)()),
+                Body::Object(_) => test::TestRequest::post().set_json(json!({})),
+                _ => test::TestRequest::get(),
+            };
+            let rsp = test::call_service(&app, req.uri(e.path).to_request()).await;
+            let status = rsp.status();
+            let body = test::read_body(rsp).await;
+            let value: Value = serde_json::from_slice(&body).unwrap_or_default();
+            if status.is_success() {
+                match e.response {
+                    Body::Json(model) => assert!(
+                        (model.accepts)(&value),
+                        "{}: {} doesn't match {}",
+                        e.path,
+                        value,
+                        model.name
+                    ),
+                    Body::Text(_) => assert!(!body.is_empty(), "{}", e.path),
+                    _ => {}
+                }
+            } else if e.path != "/api/sysinfo" {
+                let error: ErrorResponse = serde_json::from_value(value.clone())
+                    .unwrap_or_else(|_| panic!("{}: {} {} is not an error envelope", e.path, status, value));
+                assert_eq!(status.as_u16(), error.code.http_status(), "{}", e.path);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_client_reads_server_errors() {
+        let app = test::init_service(App::new().configure(super::super::configure)).await;
+        let login = LoginRequest {
+            password: "wrong".to_owned(),
+            ..LoginRequest::example()
+        };
+        let req = test::TestRequest::post().uri("/api/login").set_json(&login).to_request();
+        let rsp = test::call_service(&app, req).await;
+        let success = rsp.status().is_success();
+        let body = String::from_utf8(test::read_body(rsp).await.to_vec()).unwrap();
+        assert_eq!(
+            parse_response::<LoginResponse>(success, &body).unwrap_err(),
+            "Invalid username or password"
+        );
+
+        let req = test::TestRequest::get().uri("/api/openapi.json").to_request();
+        let doc: Value = test::call_and_read_body_json(&app, req).await;
+        assert!(doc["paths"]["/api/login"]["post"].is_object());
+    }
+}
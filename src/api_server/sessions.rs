@@ -0,0 +1,191 @@
+/// Live sessions per device and remote disconnects.
+///
+/// Which connections are alive comes from the `conns` in each heartbeat,
+/// who is behind them comes from the connection audit. Disconnects are
+/// delivered in the `disconnect` list of the heartbeat response, which
+/// `hbbs_http::sync` forwards to the matching `Connection`.
+use super::{audit, devices};
+pub use crate::api_models::SessionInfo as Session;
+use crate::api_models::{DisconnectRequest, ListSessionsRequest, SessionListResponse, STATUS_SUCCESS};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct SessionStore {
+    // device id -> conn id -> session
+    sessions: HashMap<String, HashMap<i32, Session>>,
+    // device id -> conn ids to disconnect
+    pending_disconnect: HashMap<String, HashSet<i32>>,
+}
+
+lazy_static::lazy_static! {
+    static ref SESSIONS: Mutex<SessionStore> = Default::default();
+}
+
+pub fn on_conn_audit(device_id: &str, conn_id: i32, action: &str, v: &Value) {
+    let mut guard = SESSIONS.lock().unwrap();
+    let store = &mut *guard;
+    let sessions = store.sessions.entry(device_id.to_string()).or_default();
+    if action == "close" {
+        sessions.remove(&conn_id);
+        if let Some(pending) = store.pending_disconnect.get_mut(device_id) {
+            pending.remove(&conn_id);
+        }
+        return;
+    }
+    let session = sessions.entry(conn_id).or_insert_with(|| Session {
+        conn_id,
+        started_at: super::now_secs(),
+        ..Default::default()
+    });
+    if let Some(session_id) = v["session_id"].as_u64() {
+        session.session_id = session_id;
+    }
+    if let Some(ip) = v["ip"].as_str() {
+        session.ip = ip.to_string();
+    }
+    if let Some(peer_id) = v["peer"][0].as_str() {
+        session.peer_id = peer_id.to_string();
+        session.peer_name = v["peer"][1].as_str().unwrap_or_default().to_string();
+        session.conn_type = v["type"].as_i64().unwrap_or_default();
+        session.authorized = true;
+    }
+}
+
+/// Sessions of a device that are still reported alive by its heartbeat.
+/// Connections without audit data are listed with their id only.
+pub fn list(device_id: &str) -> Vec<Session> {
+    let conns = devices::get(device_id)
+        .filter(|d| d.is_online(super::now_secs()))
+        .map(|d| d.conns)
+        .unwrap_or_default();
+    let store = SESSIONS.lock().unwrap();
+    let known = store.sessions.get(device_id);
+    conns
+        .iter()
+        .map(|id| {
+            known
+                .and_then(|s| s.get(id))
+                .cloned()
+                .unwrap_or_else(|| Session {
+                    conn_id: *id,
+                    ..Default::default()
+                })
+        })
+        .collect()
+}
+
+/// Called on each heartbeat; returns the connections to drop.
+///
+/// Pending disconnects are repeated until the connection is gone from the
+/// heartbeat, so a lost response doesn't lose the kick. Sessions of locked
+/// out helpers are dropped as well.
+pub fn on_heartbeat(device_id: &str, conns: &[i32], locked_peers: &HashSet<String>) -> Vec<i32> {
+    let alive: HashSet<i32> = conns.iter().cloned().collect();
+    let mut store = SESSIONS.lock().unwrap();
+    if let Some(sessions) = store.sessions.get_mut(device_id) {
+        sessions.retain(|id, _| alive.contains(id));
+    }
+    let mut res: HashSet<i32> = HashSet::new();
+    if let Some(pending) = store.pending_disconnect.get_mut(device_id) {
+        pending.retain(|id| alive.contains(id));
+        res.extend(pending.iter());
+    }
+    if let Some(sessions) = store.sessions.get(device_id) {
+        res.extend(
+            sessions
+                .values()
+                .filter(|s| locked_peers.contains(&s.peer_id))
+                .map(|s| s.conn_id),
+        );
+    }
+    let mut res: Vec<i32> = res.into_iter().collect();
+    res.sort();
+    res
+}
+
+/// Queue a disconnect of one connection, or all if `conn_id` is None.
+/// Returns the number of connections queued.
+pub fn disconnect(device_id: &str, conn_id: Option<i32>) -> usize {
+    let conns: Vec<i32> = match conn_id {
+        Some(id) => vec![id],
+        None => list(device_id).iter().map(|s| s.conn_id).collect(),
+    };
+    let mut store = SESSIONS.lock().unwrap();
+    let pending = store
+        .pending_disconnect
+        .entry(device_id.to_string())
+        .or_default();
+    pending.extend(conns.iter());
+    conns.len()
+}
+
+// List live sessions (admin only)
+pub async fn list_sessions(http: HttpRequest, req: web::Json<ListSessionsRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return super::unauthorized_admin();
+    }
+
+    let device_ids: Vec<String> = match &req.device_id {
+        Some(id) => vec![id.clone()],
+        None => devices::list().into_iter().map(|d| d.id).collect(),
+    };
+    let mut sessions = HashMap::new();
+    for id in device_ids {
+        let list = list(&id);
+        if !list.is_empty() {
+            sessions.insert(id, list);
+        }
+    }
+
+    HttpResponse::Ok().json(SessionListResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        sessions,
+    })
+}
+
+// Terminate one or all sessions of a device on its next heartbeat (admin only)
+pub async fn disconnect_session(http: HttpRequest, req: web::Json<DisconnectRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return super::unauthorized_admin();
+    }
+
+    let count = disconnect(&req.device_id, req.conn_id);
+    audit::admin(
+        "disconnect",
+        &req.device_id,
+        serde_json::json!({ "conn_id": req.conn_id, "count": count }),
+    );
+
+    super::message(&format!("{} session(s) will be disconnected", count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_disconnect_until_gone() {
+        let device = "test_pending_disconnect";
+        let none = HashSet::new();
+        disconnect(device, Some(7));
+        assert_eq!(on_heartbeat(device, &[7, 8], &none), vec![7]);
+        // Response lost, still alive: sent again
+        assert_eq!(on_heartbeat(device, &[7, 8], &none), vec![7]);
+        assert!(on_heartbeat(device, &[8], &none).is_empty());
+    }
+
+    #[test]
+    fn test_locked_peer_disconnected() {
+        let device = "test_locked_peer";
+        let v = serde_json::json!({ "peer": ["HELPER_PC", "helper"], "type": 0 });
+        on_conn_audit(device, 3, "login", &v);
+        on_conn_audit(device, 4, "new", &serde_json::json!({ "ip": "10.0.0.2" }));
+        let locked: HashSet<String> = ["HELPER_PC".to_string()].into_iter().collect();
+        assert_eq!(on_heartbeat(device, &[3, 4], &locked), vec![3]);
+        on_conn_audit(device, 3, "close", &Value::Null);
+        assert!(on_heartbeat(device, &[4], &locked).is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+/// Strategies: named option maps pushed to devices through the heartbeat.
+///
+/// A device receives the options of the strategy assigned to its group,
+/// overlaid by the strategy assigned to the device itself (or the
+/// `preset-strategy-name` it reported). The client applies them in
+/// `hbbs_http::sync::handle_config_options` and echoes back `modified_at`,
+/// so every change here must produce a new, larger `modified_at`.
+use super::devices::{self, Device};
+use super::{api_error, message, unauthorized_admin};
+pub use crate::api_models::Strategy;
+use crate::api_models::{
+    AdminRequest, AssignStrategyRequest, DeleteStrategyRequest, ErrorCode,
+    PreviewStrategyRequest, PreviewStrategyResponse, SaveStrategyRequest, SaveStrategyResponse,
+    StrategyListResponse, STATUS_SUCCESS,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use hbb_common::config::keys;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct StrategyStore {
+    strategies: HashMap<String, Strategy>,
+    // device id -> strategy name
+    device_assignments: HashMap<String, String>,
+    // group name -> strategy name
+    group_assignments: HashMap<String, String>,
+    // Last assignment change per device / group
+    device_changed_at: HashMap<String, i64>,
+    group_changed_at: HashMap<String, i64>,
+    // Option keys sent to a device and not yet acknowledged as removed
+    applied_keys: HashMap<String, HashSet<String>>,
+    last_revision: i64,
+}
+
+pub struct StrategyUpdate {
+    pub modified_at: i64,
+    pub config_options: HashMap<String, String>,
+}
+
+lazy_static::lazy_static! {
+    static ref STRATEGIES: Mutex<StrategyStore> = Default::default();
+}
+
+impl StrategyStore {
+    // Millisecond timestamp, strictly increasing so that two edits within
+    // the same millisecond still look different to the device.
+    fn next_revision(&mut self) -> i64 {
+        let now = super::now_millis() as i64;
+        self.last_revision = now.max(self.last_revision + 1);
+        self.last_revision
+    }
+
+    fn touch_device(&mut self, device_id: &str) {
+        let rev = self.next_revision();
+        self.device_changed_at.insert(device_id.to_string(), rev);
+    }
+
+    fn touch_group(&mut self, group: &str) {
+        let rev = self.next_revision();
+        self.group_changed_at.insert(group.to_string(), rev);
+    }
+
+    /// Strategies that apply to the device, lowest precedence first.
+    fn applied_strategies(&self, device: &Device) -> Vec<&Strategy> {
+        let mut res = vec![];
+        if let Some(s) = self
+            .group_assignments
+            .get(&device.group)
+            .and_then(|name| self.strategies.get(name))
+        {
+            res.push(s);
+        }
+        let name = self
+            .device_assignments
+            .get(&device.id)
+            .unwrap_or(&device.preset_strategy);
+        if let Some(s) = self.strategies.get(name) {
+            res.push(s);
+        }
+        res
+    }
+
+    fn effective(&self, device: &Device) -> StrategyUpdate {
+        let strategies = self.applied_strategies(device);
+        let mut config_options = HashMap::new();
+        for s in strategies.iter() {
+            config_options.extend(s.options.clone());
+        }
+        let modified_at = strategies
+            .iter()
+            .map(|s| s.modified_at)
+            .chain(self.device_changed_at.get(&device.id).cloned())
+            .chain(self.group_changed_at.get(&device.group).cloned())
+            .max()
+            .unwrap_or(0);
+        StrategyUpdate {
+            modified_at,
+            config_options,
+        }
+    }
+
+    fn pending_update(&mut self, device: &Device, device_modified_at: i64) -> Option<StrategyUpdate> {
+        let mut update = self.effective(device);
+        let current: HashSet<String> = update.config_options.keys().cloned().collect();
+        if update.modified_at == device_modified_at {
+            // Acknowledged, keys no longer in the strategy have been cleared.
+            if current.is_empty() {
+                self.applied_keys.remove(&device.id);
+            } else {
+                self.applied_keys.insert(device.id.clone(), current);
+            }
+            return None;
+        }
+        let applied = self.applied_keys.entry(device.id.clone()).or_default();
+        for k in applied.difference(&current) {
+            // An empty value removes the option on the device.
+            update.config_options.insert(k.clone(), "".to_owned());
+        }
+        applied.extend(current);
+        Some(update)
+    }
+}
+
+/// Called when a device's group or preset strategy changes.
+pub fn touch_device(device_id: &str) {
+    STRATEGIES.lock().unwrap().touch_device(device_id);
+}
+
+/// The options to send in a heartbeat response, if the device is behind.
+pub fn pending_update(device: &Device, device_modified_at: i64) -> Option<StrategyUpdate> {
+    STRATEGIES
+        .lock()
+        .unwrap()
+        .pending_update(device, device_modified_at)
+}
+
+/// Strategies sorted by name, with the device and group assignments.
+pub fn snapshot() -> (Vec<Strategy>, HashMap<String, String>, HashMap<String, String>) {
+    let store = STRATEGIES.lock().unwrap();
+    let mut strategies: Vec<_> = store.strategies.values().cloned().collect();
+    strategies.sort_by(|a, b| a.name.cmp(&b.name));
+    (
+        strategies,
+        store.device_assignments.clone(),
+        store.group_assignments.clone(),
+    )
+}
+
+fn validate_options(options: &HashMap<String, String>) -> Result<(), String> {
+    for k in options.keys() {
+        if !keys::KEYS_SETTINGS.contains(&k.as_str()) {
+            return Err(format!("Unsupported option: {}", k));
+        }
+    }
+    Ok(())
+}
+
+// List strategies and their assignments (admin only)
+pub async fn list_strategies(http: HttpRequest, req: web::Json<AdminRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    let (strategies, devices, groups) = snapshot();
+
+    HttpResponse::Ok().json(StrategyListResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        strategies,
+        devices,
+        groups,
+    })
+}
+
+// Create or replace a strategy (admin only)
+pub async fn save_strategy(http: HttpRequest, req: web::Json<SaveStrategyRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+    if req.name.is_empty() {
+        return api_error(ErrorCode::BadRequest, "Strategy name is required");
+    }
+    if let Err(e) = validate_options(&req.options) {
+        return api_error(ErrorCode::BadRequest, e);
+    }
+
+    let mut store = STRATEGIES.lock().unwrap();
+    let strategy = Strategy {
+        name: req.name.clone(),
+        options: req.options.clone(),
+        modified_at: store.next_revision(),
+    };
+    store.strategies.insert(req.name.clone(), strategy.clone());
+
+    HttpResponse::Ok().json(SaveStrategyResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        strategy,
+    })
+}
+
+// Delete a strategy and drop its assignments (admin only)
+pub async fn delete_strategy(http: HttpRequest, req: web::Json<DeleteStrategyRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+
+    // Devices using it as their preset strategy must be told as well.
+    let preset_devices: Vec<String> = devices::list()
+        .into_iter()
+        .filter(|d| d.preset_strategy == req.name)
+        .map(|d| d.id)
+        .collect();
+    let mut store = STRATEGIES.lock().unwrap();
+    if store.strategies.remove(&req.name).is_none() {
+        return api_error(ErrorCode::NotFound, "Strategy not found");
+    }
+    let devices: Vec<String> = store
+        .device_assignments
+        .iter()
+        .filter(|(_, s)| **s == req.name)
+        .map(|(d, _)| d.clone())
+        .collect();
+    let groups: Vec<String> = store
+        .group_assignments
+        .iter()
+        .filter(|(_, s)| **s == req.name)
+        .map(|(g, _)| g.clone())
+        .collect();
+    for d in devices {
+        store.device_assignments.remove(&d);
+        store.touch_device(&d);
+    }
+    for g in groups {
+        store.group_assignments.remove(&g);
+        store.touch_group(&g);
+    }
+    for d in preset_devices {
+        store.touch_device(&d);
+    }
+
+    message("Strategy deleted")
+}
+
+// Assign a strategy to a device or a group, or unassign it (admin only)
+pub async fn assign_strategy(http: HttpRequest, req: web::Json<AssignStrategyRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+
+    let mut store = STRATEGIES.lock().unwrap();
+    let strategy = req.strategy.clone().filter(|s| !s.is_empty());
+    if let Some(name) = &strategy {
+        if !store.strategies.contains_key(name) {
+            return api_error(ErrorCode::NotFound, "Strategy not found");
+        }
+    }
+
+    match (&req.device_id, &req.group) {
+        (Some(device_id), None) => {
+            match strategy {
+                Some(name) => store.device_assignments.insert(device_id.clone(), name),
+                None => store.device_assignments.remove(device_id),
+            };
+            store.touch_device(device_id);
+        }
+        (None, Some(group)) => {
+            match strategy {
+                Some(name) => store.group_assignments.insert(group.clone(), name),
+                None => store.group_assignments.remove(group),
+            };
+            store.touch_group(group);
+        }
+        _ => {
+            return api_error(ErrorCode::BadRequest, "Specify exactly one of device_id or group");
+        }
+    }
+
+    message("Strategy assignment updated")
+}
+
+// Show the options a device would end up with (admin only)
+pub async fn preview_strategy(http: HttpRequest, req: web::Json<PreviewStrategyRequest>) -> impl Responder {
+    if !super::authorize_admin(&http, &req.admin_password) {
+        return unauthorized_admin();
+    }
+
+    // Devices that never checked in can still be previewed by id.
+    let device = devices::get(&req.device_id).unwrap_or_else(|| Device {
+        id: req.device_id.clone(),
+        ..Default::default()
+    });
+    let store = STRATEGIES.lock().unwrap();
+    let strategies: Vec<_> = store
+        .applied_strategies(&device)
+        .iter()
+        .map(|s| s.name.clone())
+        .collect();
+    let effective = store.effective(&device);
+
+    HttpResponse::Ok().json(PreviewStrategyResponse {
+        status: STATUS_SUCCESS.to_owned(),
+        device_id: device.id,
+        group: device.group,
+        strategies,
+        options: effective.config_options,
+        modified_at: effective.modified_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(store: &mut StrategyStore, name: &str, options: &[(&str, &str)]) {
+        let s = Strategy {
+            name: name.to_string(),
+            options: options
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            modified_at: store.next_revision(),
+        };
+        store.strategies.insert(name.to_string(), s);
+    }
+
+    fn device(id: &str, group: &str) -> Device {
+        Device {
+            id: id.to_string(),
+            group: group.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_device_overrides_group() {
+        let mut store = StrategyStore::default();
+        strategy(&mut store, "school", &[("enable-lan-discovery", "N"), ("approve-mode", "password")]);
+        strategy(&mut store, "lab", &[("approve-mode", "click")]);
+        store.group_assignments.insert("students".to_string(), "school".to_string());
+        store.device_assignments.insert("123".to_string(), "lab".to_string());
+
+        let effective = store.effective(&device("123", "students"));
+        assert_eq!(effective.config_options["enable-lan-discovery"], "N");
+        assert_eq!(effective.config_options["approve-mode"], "click");
+        assert_eq!(effective.modified_at, store.strategies["lab"].modified_at);
+    }
+
+    #[test]
+    fn test_pending_update_until_acknowledged() {
+        let mut store = StrategyStore::default();
+        strategy(&mut store, "school", &[("whitelist", "10.0.0.0/8")]);
+        store.device_assignments.insert("123".to_string(), "school".to_string());
+        store.touch_device("123");
+        let d = device("123", "");
+
+        let update = store.pending_update(&d, 0).unwrap();
+        assert_eq!(update.config_options["whitelist"], "10.0.0.0/8");
+        assert!(store.pending_update(&d, update.modified_at).is_none());
+    }
+
+    #[test]
+    fn test_unassign_clears_options() {
+        let mut store = StrategyStore::default();
+        strategy(&mut store, "school", &[("whitelist", "10.0.0.0/8")]);
+        store.device_assignments.insert("123".to_string(), "school".to_string());
+        store.touch_device("123");
+        let d = device("123", "");
+        let update = store.pending_update(&d, 0).unwrap();
+        store.pending_update(&d, update.modified_at);
+
+        store.device_assignments.remove("123");
+        store.touch_device("123");
+        let clear = store.pending_update(&d, update.modified_at).unwrap();
+        assert!(clear.modified_at > update.modified_at);
+        assert_eq!(clear.config_options["whitelist"], "");
+        // Lost response: the clear is sent again until acknowledged.
+        let again = store.pending_update(&d, update.modified_at).unwrap();
+        assert_eq!(again.config_options["whitelist"], "");
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let mut options = HashMap::new();
+        options.insert("approve-mode".to_string(), "password".to_string());
+        assert!(validate_options(&options).is_ok());
+        options.insert("not-an-option".to_string(), "Y".to_string());
+        assert!(validate_options(&options).is_err());
+    }
+}
@@ -1,17 +1,32 @@
 use crate::client::*;
 use async_trait::async_trait;
 use hbb_common::{
+    bail,
     config::PeerConfig,
-    config::READ_TIMEOUT,
+    config::{keys, LocalConfig, PortForwardMode, PortForwardProfile, READ_TIMEOUT},
+    fs::{self, can_enable_overwrite_detection, DigestCheckResult, JobType, TransferJob},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
-    Stream,
+    timeout,
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration, Instant},
+    },
+    ResultType, Stream,
 };
-use std::sync::{Arc, RwLock};
+use std::{
+    io::{Read, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+/// Told whether a login succeeded, with the error if not.
+pub type LoginCallback = Arc<dyn Fn(Result<(), String>) + Send + Sync>;
 
 #[derive(Clone)]
 pub struct Session {
@@ -19,35 +34,67 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    interactive: bool,
+    on_login: Option<LoginCallback>,
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
-        let mut password = "".to_owned();
-        if PeerConfig::load(id).password.is_empty() {
-            password = rpassword::prompt_password("Enter password: ").unwrap();
-        }
+    pub fn new(
+        id: &str,
+        conn_type: ConnType,
+        password: Option<String>,
+        sender: mpsc::UnboundedSender<Data>,
+    ) -> Self {
+        let password = match password {
+            Some(password) => password,
+            None if PeerConfig::load(id).password.is_empty() => {
+                rpassword::prompt_password("Enter password: ").unwrap_or_default()
+            }
+            None => "".to_owned(),
+        };
         let session = Self {
             id: id.to_owned(),
             sender,
             password,
             lc: Default::default(),
+            interactive: true,
+            on_login: None,
         };
         session.lc.write().unwrap().initialize(
             id.to_owned(),
-            ConnType::PORT_FORWARD,
+            conn_type,
             None,
             false,
             None,
             None,
+            None,
         );
         session
     }
+
+    /// Gives up instead of prompting if the password is wrong, e.g. in a
+    /// background service.
+    pub fn non_interactive(mut self) -> Self {
+        self.interactive = false;
+        self
+    }
+
+    /// Reports each login, e.g. for the status of a background service.
+    pub fn on_login(mut self, f: impl Fn(Result<(), String>) + Send + Sync + 'static) -> Self {
+        self.on_login = Some(Arc::new(f));
+        self
+    }
+
+    fn report_login(&self, res: Result<(), String>) {
+        if let Some(f) = self.on_login.as_ref() {
+            f(res);
+        }
+    }
 }
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
@@ -55,18 +102,30 @@ impl Interface for Session {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
+            "re-input-password" if !self.interactive => {
+                log::error!("{}: {}", title, text);
+                self.sender.send(Data::Close).ok();
+            }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
                         log::error!("reinput password failed, {:?}", e);
+                        // No terminal, e.g. run by cron
+                        self.sender.send(Data::Close).ok();
                     }
                 }
             }
@@ -80,13 +139,26 @@ impl Interface for Session {
     }
 
     fn handle_login_error(&self, err: &str) -> bool {
-        handle_login_error(self.lc.clone(), err, self)
+        let try_again = handle_login_error(self.lc.clone(), err, self);
+        // A wrong password isn't asked for again
+        if !try_again || (!self.interactive && err == LOGIN_MSG_PASSWORD_WRONG) {
+            self.report_login(Err(err.to_owned()));
+        }
+        try_again
     }
 
     fn handle_peer_info(&self, pi: PeerInfo) {
         self.lc.write().unwrap().handle_peer_info(&pi);
+        self.report_login(Ok(()));
     }
 
+    fn on_establish_connection_error(&self, err: String) {
+        self.msgbox("error", "Connection Error", &err, "");
+        self.report_login(Err(err));
+    }
+
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
@@ -125,13 +197,13 @@ impl Interface for Session {
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn connect_test(id: &str, key: String, token: String) {
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let (sender, _receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, sender);
     match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
         }
-        Ok((mut stream, direct)) => {
+        Ok(((mut stream, direct, ..), _)) => {
             log::info!("direct: {}", direct);
             // rpassword::prompt_password("Input anything to exit").ok();
             loop {
@@ -144,7 +216,7 @@ pub async fn connect_test(id: &str, key: String, token: String) {
                         Ok(Some(Ok(bytes))) => {
                             if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                                 match msg_in.union {
-                                    Some(message::Union::Hash(hash)) => {
+                                    Some(message::Union::Hash(_hash)) => {
                                         log::info!("Got hash");
                                         break;
                                     }
@@ -172,7 +244,7 @@ pub async fn start_one_port_forward(
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, sender);
     if let Err(err) = crate::port_forward::listen(
         handler.id.clone(),
         handler.password.clone(),
@@ -184,6 +256,7 @@ pub async fn start_one_port_forward(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        None,
     )
     .await
     {
@@ -191,3 +264,1637 @@ pub async fn start_one_port_forward(
     }
     log::info!("port forward (:{}) exit", port);
 }
+
+/// Exit codes of `--cli`
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_CONNECT: i32 = 2;
+pub const EXIT_TRANSFER: i32 = 3;
+
+const FILE_USAGE: &str = "Usage: rustdesk --cli [OPTIONS] <COMMAND>
+
+Commands:
+  ls <ID> <PATH>              List a remote directory
+  push <ID> <LOCAL> <REMOTE>  Copy a local file or directory to the peer
+  pull <ID> <REMOTE> <LOCAL>  Copy a remote file or directory from the peer
+
+A destination ending with a path separator is a directory to copy into.
+
+Options:
+  -k, --key <KEY>          Key of the rendezvous server
+      --password <PWD>     Password of the peer, or set RUSTDESK_PASSWORD
+  -a, --all                Include hidden files
+      --overwrite          Replace files that differ (default)
+      --skip-existing      Keep files that already exist
+      --resume             Continue interrupted transfers
+  -q, --quiet              No progress output
+
+Exit codes: 0 done, 1 usage, 2 connection or login failed, 3 transfer failed";
+
+#[derive(Debug, PartialEq)]
+enum FileCommand {
+    Ls { path: String },
+    Push { local: String, remote: String },
+    Pull { remote: String, local: String },
+}
+
+#[derive(Default)]
+struct FileOptions {
+    key: String,
+    password: Option<String>,
+    include_hidden: bool,
+    // Identical files are skipped anyway
+    skip_existing: bool,
+    resume: bool,
+    quiet: bool,
+}
+
+fn file_usage() -> i32 {
+    eprintln!("{}", FILE_USAGE);
+    EXIT_USAGE
+}
+
+/// `rustdesk --cli <COMMAND>`, returns the exit code.
+pub fn run_file_command(args: &[String]) -> i32 {
+    match parse_file_command(args) {
+        Ok((id, cmd, opts)) => file_command(id, cmd, opts),
+        Err(code) => code,
+    }
+}
+
+// The peer, command and options, or the exit code if there is nothing to run
+fn parse_file_command(args: &[String]) -> Result<(String, FileCommand, FileOptions), i32> {
+    let mut opts = FileOptions {
+        password: std::env::var("RUSTDESK_PASSWORD").ok(),
+        ..Default::default()
+    };
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-k" | "--key" => match iter.next() {
+                Some(v) => opts.key = v.clone(),
+                None => return Err(file_usage()),
+            },
+            "--password" => match iter.next() {
+                Some(v) => opts.password = Some(v.clone()),
+                None => return Err(file_usage()),
+            },
+            "-a" | "--all" => opts.include_hidden = true,
+            "--overwrite" => opts.skip_existing = false,
+            "--skip-existing" => opts.skip_existing = true,
+            "--resume" => opts.resume = true,
+            "-q" | "--quiet" => opts.quiet = true,
+            "-h" | "--help" => {
+                println!("{}", FILE_USAGE);
+                return Err(EXIT_OK);
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                eprintln!("Unknown option: {}", x);
+                return Err(file_usage());
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let cmd = match (positional.first().map(|x| x.as_str()), positional.len()) {
+        (Some("ls"), 3) => FileCommand::Ls {
+            path: positional[2].clone(),
+        },
+        (Some("push"), 4) => FileCommand::Push {
+            local: positional[2].clone(),
+            remote: positional[3].clone(),
+        },
+        (Some("pull"), 4) => FileCommand::Pull {
+            remote: positional[2].clone(),
+            local: positional[3].clone(),
+        },
+        _ => return Err(file_usage()),
+    };
+    Ok((positional[1].clone(), cmd, opts))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn file_command(id: String, cmd: FileCommand, opts: FileOptions) -> i32 {
+    let token = LocalConfig::get_option("access_token");
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::FILE_TRANSFER, opts.password.clone(), sender);
+    let ((mut stream, direct, ..), (feedback, rendezvous_server)) = match Client::start(
+        &id,
+        &opts.key,
+        &token,
+        ConnType::FILE_TRANSFER,
+        handler.clone(),
+    )
+    .await
+    {
+        Ok(res) => res,
+        Err(err) => {
+            eprintln!("Failed to connect {}: {}", id, err);
+            return EXIT_CONNECT;
+        }
+    };
+    handler.update_direct(Some(direct));
+    let _keep_it = hc_connection(feedback, rendezvous_server, &token).await;
+    if let Err(err) = login(&handler, &mut receiver, &mut stream).await {
+        eprintln!("Failed to log in to {}: {}", id, err);
+        return EXIT_CONNECT;
+    }
+    let res = match cmd {
+        FileCommand::Ls { path } => list_remote_dir(&mut stream, path, opts.include_hidden).await,
+        FileCommand::Push { local, remote } => {
+            Transfer::new(&handler, &opts)
+                .push(&mut stream, local, remote)
+                .await
+        }
+        FileCommand::Pull { remote, local } => {
+            Transfer::new(&handler, &opts)
+                .pull(&mut stream, remote, local)
+                .await
+        }
+    };
+    match res {
+        Ok(()) => EXIT_OK,
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_TRANSFER
+        }
+    }
+}
+
+async fn login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+) -> ResultType<()> {
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => bail!("Timeout"),
+                Ok(Some(Ok(bytes))) => {
+                    handler.update_received(true);
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            handler.handle_hash(&handler.password, hash, stream).await;
+                        }
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    bail!(err);
+                                }
+                            }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi);
+                                return Ok(());
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+                _ => bail!("Reset by the peer"),
+            },
+            d = receiver.recv() => match d {
+                Some(Data::Login((os_username, os_password, password, remember))) => {
+                    handler.handle_login_from_ui(os_username, os_password, password, remember, stream).await;
+                }
+                Some(Data::Close) | None => bail!("Wrong password"),
+                _ => {}
+            },
+        }
+    }
+}
+
+/// The next file response, answering delay tests meanwhile. It times out
+/// `READ_TIMEOUT` after the last message from the peer, `last_recv`, which
+/// is kept by the caller across calls.
+async fn next_file_response(
+    stream: &mut Stream,
+    last_recv: &mut Instant,
+) -> ResultType<FileResponse> {
+    loop {
+        match time::timeout_at(*last_recv + READ_TIMEOUT, stream.next()).await {
+            Err(_) => bail!("Timeout"),
+            Ok(Some(Ok(bytes))) => {
+                *last_recv = Instant::now();
+                let msg_in = Message::parse_from_bytes(&bytes)?;
+                match msg_in.union {
+                    Some(message::Union::FileResponse(fr)) => return Ok(fr),
+                    Some(message::Union::TestDelay(t)) => handle_test_delay(t, stream).await,
+                    Some(message::Union::Misc(misc)) => {
+                        if let Some(misc::Union::CloseReason(reason)) = misc.union {
+                            bail!("Closed by the peer: {}", reason);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+            Ok(None) => bail!("Reset by the peer"),
+        }
+    }
+}
+
+async fn list_remote_dir(
+    stream: &mut Stream,
+    path: String,
+    include_hidden: bool,
+) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    let mut file_action = FileAction::new();
+    file_action.set_read_dir(ReadDir {
+        path,
+        include_hidden,
+        ..Default::default()
+    });
+    msg_out.set_file_action(file_action);
+    stream.send(&msg_out).await?;
+    loop {
+        match next_file_response(stream, &mut Instant::now()).await?.union {
+            Some(file_response::Union::Dir(fd)) => {
+                for entry in fd.entries.iter() {
+                    let kind = match entry.entry_type.enum_value() {
+                        Ok(FileType::Dir) | Ok(FileType::DirDrive) => 'd',
+                        Ok(FileType::DirLink) | Ok(FileType::FileLink) => 'l',
+                        _ => '-',
+                    };
+                    let modified = chrono::DateTime::from_timestamp(entry.modified_time as _, 0)
+                        .map(|t| {
+                            t.with_timezone(&chrono::Local)
+                                .format("%Y-%m-%d %H:%M")
+                                .to_string()
+                        })
+                        .unwrap_or_default();
+                    println!(
+                        "{} {:>12} {:>16} {}",
+                        kind, entry.size, modified, entry.name
+                    );
+                }
+                return Ok(());
+            }
+            Some(file_response::Union::Error(e)) => bail!("{}", e.error),
+            _ => {}
+        }
+    }
+}
+
+fn readable_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Last path component, remote paths may use either separator
+fn base_name(path: &str) -> ResultType<String> {
+    match path
+        .rsplit(|c| c == '/' || c == '\\')
+        .find(|x| !x.is_empty())
+    {
+        Some(name) => Ok(name.to_owned()),
+        None => bail!("No file name in {}", path),
+    }
+}
+
+fn is_dir_path(path: &str) -> bool {
+    path.ends_with('/') || path.ends_with('\\')
+}
+
+// The destination of copying `source` to `dest`, into it if it is a directory
+fn destination_path(dest: String, source: &str) -> ResultType<String> {
+    let mut dest = dest;
+    if is_dir_path(&dest) {
+        dest.push_str(&base_name(source)?);
+    }
+    Ok(dest)
+}
+
+/// One push or pull job, answering digests with the overwrite policy of
+/// the options instead of asking as the file manager does.
+struct Transfer<'a> {
+    handler: &'a Session,
+    opts: &'a FileOptions,
+    id: i32,
+    read_jobs: Vec<TransferJob>,
+    write_jobs: Vec<TransferJob>,
+    // finished, total, bytes/s
+    progress: (u64, u64, u64),
+    last_progress: Option<Instant>,
+    files: usize,
+    skipped: usize,
+}
+
+impl<'a> Transfer<'a> {
+    fn new(handler: &'a Session, opts: &'a FileOptions) -> Self {
+        Self {
+            handler,
+            opts,
+            id: fs::get_next_job_id(),
+            read_jobs: Vec::new(),
+            write_jobs: Vec::new(),
+            progress: (0, 0, 0),
+            last_progress: None,
+            files: 0,
+            skipped: 0,
+        }
+    }
+
+    fn peer_platform(&self) -> String {
+        self.handler.lc.read().unwrap().info.platform.clone()
+    }
+
+    fn overwrite_detection(&self) -> bool {
+        can_enable_overwrite_detection(self.handler.lc.read().unwrap().version)
+    }
+
+    async fn push(&mut self, stream: &mut Stream, local: String, remote: String) -> ResultType<()> {
+        let remote = destination_path(remote, &local)?;
+        let mut job = TransferJob::new_read(
+            self.id,
+            JobType::Generic,
+            remote.clone(),
+            fs::DataSource::FilePath(PathBuf::from(&local)),
+            0,
+            self.opts.include_hidden,
+            true,
+            self.overwrite_detection(),
+        )?;
+        job.is_resume = self.opts.resume;
+        #[cfg(not(windows))]
+        let files = job.files().clone();
+        #[cfg(windows)]
+        let mut files = job.files().clone();
+        #[cfg(windows)]
+        if self.peer_platform() != "Windows" {
+            // peer is not windows, need transform \ to /
+            fs::transform_windows_path(&mut files);
+        }
+        let total_size = job.total_size();
+        self.files = files.len();
+        self.read_jobs.push(job);
+        stream
+            .send(&fs::new_receive(self.id, remote, 0, files, total_size))
+            .await?;
+        self.run(stream).await
+    }
+
+    async fn pull(&mut self, stream: &mut Stream, remote: String, local: String) -> ResultType<()> {
+        let local = destination_path(local, &remote)?;
+        let mut job = TransferJob::new_write(
+            self.id,
+            JobType::Generic,
+            remote.clone(),
+            fs::DataSource::FilePath(PathBuf::from(&local)),
+            0,
+            self.opts.include_hidden,
+            true,
+            Vec::new(),
+            self.overwrite_detection(),
+        );
+        job.is_resume = self.opts.resume;
+        self.write_jobs.push(job);
+        stream
+            .send(&fs::new_send(
+                self.id,
+                JobType::Generic,
+                remote,
+                0,
+                self.opts.include_hidden,
+            ))
+            .await?;
+        self.run(stream).await
+    }
+
+    async fn run(&mut self, stream: &mut Stream) -> ResultType<()> {
+        let mut timer = crate::rustdesk_interval(time::interval(Duration::from_millis(1)));
+        // Not reset by the timer, a stalled peer times out while we send
+        let mut last_recv = Instant::now();
+        loop {
+            tokio::select! {
+                res = next_file_response(stream, &mut last_recv) => {
+                    if self.handle_file_response(res?, stream).await? {
+                        break;
+                    }
+                }
+                _ = timer.tick(), if !self.read_jobs.is_empty() => {
+                    fs::handle_read_jobs(&mut self.read_jobs, stream).await?;
+                }
+            }
+            self.show_progress(false);
+        }
+        self.progress.0 = self.progress.1;
+        self.show_progress(true);
+        Ok(())
+    }
+
+    // Returns true when the job is done
+    async fn handle_file_response(
+        &mut self,
+        fr: FileResponse,
+        stream: &mut Stream,
+    ) -> ResultType<bool> {
+        match fr.union {
+            Some(file_response::Union::Dir(fd)) => {
+                let peer_platform = self.peer_platform();
+                if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) {
+                    let mut entries = fd.entries.to_vec();
+                    if !cfg!(windows) && peer_platform == "Windows" {
+                        fs::transform_windows_path(&mut entries);
+                    }
+                    self.files = entries.len();
+                    job.set_files(entries);
+                    job.set_finished_size_on_resume();
+                }
+            }
+            Some(file_response::Union::Digest(digest)) => {
+                let req = if digest.is_upload {
+                    self.confirm_upload(&digest)
+                } else {
+                    self.confirm_download(&digest).await
+                };
+                if let Some(req) = req {
+                    let jobs = if digest.is_upload {
+                        &mut self.read_jobs
+                    } else {
+                        &mut self.write_jobs
+                    };
+                    if let Some(job) = fs::get_job(digest.id, jobs) {
+                        job.confirm(&req).await;
+                    }
+                    stream.send(&fs::new_send_confirm(req)).await?;
+                }
+            }
+            Some(file_response::Union::Block(block)) => {
+                if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
+                    job.write(block).await?;
+                }
+            }
+            Some(file_response::Union::Done(d)) if d.id == self.id => {
+                if let Some(job) = fs::remove_job(d.id, &mut self.write_jobs) {
+                    job.modify_time();
+                    match job.job_error() {
+                        Some(err) if !job.job_skipped() => bail!(err),
+                        _ => {}
+                    }
+                }
+                return Ok(true);
+            }
+            Some(file_response::Union::Error(e)) if e.id == self.id => {
+                // A single file that is skipped
+                if e.error == "skipped" {
+                    return Ok(true);
+                }
+                // The download file is kept for --resume
+                bail!("{}", e.error);
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn confirm_request(
+        &self,
+        digest: &FileTransferDigest,
+        overwrite: bool,
+        offset: u64,
+    ) -> FileTransferSendConfirmRequest {
+        FileTransferSendConfirmRequest {
+            id: digest.id,
+            file_num: digest.file_num,
+            union: Some(if overwrite {
+                file_transfer_send_confirm_request::Union::OffsetBlk(offset as _)
+            } else {
+                file_transfer_send_confirm_request::Union::Skip(true)
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn confirm_upload(
+        &mut self,
+        digest: &FileTransferDigest,
+    ) -> Option<FileTransferSendConfirmRequest> {
+        let skip_existing = self.opts.skip_existing;
+        let job = fs::get_job(digest.id, &mut self.read_jobs)?;
+        job.set_delta_signatures(digest.file_num, digest.delta_signatures.clone());
+        let (overwrite, offset) =
+            if digest.is_identical && job.is_resume && digest.transferred_size > 0 {
+                (true, digest.transferred_size)
+            } else if digest.is_identical {
+                (false, 0)
+            } else {
+                (!skip_existing, 0)
+            };
+        if !overwrite {
+            self.skipped += 1;
+        }
+        Some(self.confirm_request(digest, overwrite, offset))
+    }
+
+    async fn confirm_download(
+        &mut self,
+        digest: &FileTransferDigest,
+    ) -> Option<FileTransferSendConfirmRequest> {
+        let skip_existing = self.opts.skip_existing;
+        let peer_ver = self.handler.lc.read().unwrap().version;
+        let is_support_resume = crate::is_support_file_transfer_resume_num(peer_ver);
+        let job = fs::get_job(digest.id, &mut self.write_jobs)?;
+        let file = job.files().get(digest.file_num as usize)?;
+        let write_path = match &job.data_source {
+            fs::DataSource::FilePath(p) => fs::get_string(&TransferJob::join(p, &file.name)),
+            _ => return None,
+        };
+        job.set_digest(digest.file_size, digest.last_modified);
+        job.set_content_hash(fs::digest_content_hash(digest));
+        let (overwrite, offset) = match fs::is_write_need_confirmation(
+            is_support_resume && job.is_resume,
+            &write_path,
+            digest,
+        )
+        .await
+        {
+            Ok(DigestCheckResult::IsSame) => (false, 0),
+            Ok(DigestCheckResult::NeedConfirm(digest)) => {
+                job.set_delta_signatures(digest.file_num, digest.delta_signatures.clone());
+                if digest.is_identical && job.is_resume && digest.transferred_size > 0 {
+                    (true, digest.transferred_size)
+                } else {
+                    (!skip_existing, 0)
+                }
+            }
+            Ok(DigestCheckResult::NoSuchFile) => (true, 0),
+            Ok(DigestCheckResult::NeedContentHash) => {
+                return Some(FileTransferSendConfirmRequest {
+                    id: digest.id,
+                    file_num: digest.file_num,
+                    need_content_hash: true,
+                    ..Default::default()
+                });
+            }
+            Err(err) => {
+                eprintln!("\nSkip {}: {}", write_path, err);
+                (false, 0)
+            }
+        };
+        let delta_signatures = if overwrite && offset == 0 {
+            job.take_delta_signatures(digest.file_num)
+        } else {
+            Default::default()
+        };
+        if !overwrite {
+            self.skipped += 1;
+        }
+        Some(FileTransferSendConfirmRequest {
+            delta_signatures,
+            ..self.confirm_request(digest, overwrite, offset)
+        })
+    }
+
+    fn show_progress(&mut self, done: bool) {
+        if self.opts.quiet {
+            return;
+        }
+        if let Some(job) = self.read_jobs.first().or(self.write_jobs.first()) {
+            self.progress = (
+                job.finished_size(),
+                job.total_size(),
+                job.stats().throughput,
+            );
+        }
+        // A line every few seconds when logged, e.g. by cron
+        let is_terminal = std::io::IsTerminal::is_terminal(&std::io::stderr());
+        let interval = Duration::from_secs(if is_terminal { 1 } else { 10 });
+        if !done
+            && self
+                .last_progress
+                .map(|t| t.elapsed() < interval)
+                .unwrap_or(false)
+        {
+            return;
+        }
+        self.last_progress = Some(Instant::now());
+        let (finished, total, speed) = self.progress;
+        let line = format!(
+            "{} / {}, {}/s",
+            readable_size(finished),
+            readable_size(total),
+            readable_size(speed)
+        );
+        if is_terminal {
+            eprint!("\r{:<48}", line);
+        } else {
+            eprintln!("{}", line);
+        }
+        if done {
+            if is_terminal {
+                eprintln!();
+            }
+            eprintln!("{} files, {} skipped", self.files, self.skipped);
+        }
+        std::io::stderr().flush().ok();
+    }
+}
+
+/// Exit code of `--terminal` if opening or running the terminal failed.
+pub const EXIT_TERMINAL: i32 = 3;
+
+const TERMINAL_USAGE: &str = "Usage: rustdesk --terminal [OPTIONS] <ID>
+
+Opens a terminal of the peer in this one, Ctrl+] disconnects. A persistent
+terminal keeps running on the peer and can be attached to again.
+
+Options:
+  -k, --key <KEY>          Key of the rendezvous server
+      --password <PWD>     Password of the peer, or set RUSTDESK_PASSWORD
+      --persistent         Keep the terminals running after disconnecting
+      --no-persistent      Close the terminals on disconnecting, the peer's
+                           setting of the terminal window is kept if neither is given
+      --service-id <SID>   Terminal service to attach to, defaults to the last one
+      --new                Start a new terminal service
+  -t, --terminal-id <N>    Terminal of the service to open or attach to [default: 0]
+  -l, --list               List the terminals of the service and exit
+
+Exit codes: 0 done, 1 usage, 2 connection or login failed, 3 terminal failed";
+
+// Ctrl+], as telnet
+const TERMINAL_ESCAPE: u8 = 0x1d;
+
+#[derive(Debug, Default, PartialEq)]
+struct TerminalOptions {
+    key: String,
+    password: Option<String>,
+    persistent: Option<bool>,
+    // Empty for a new service
+    service_id: Option<String>,
+    terminal_id: i32,
+    list: bool,
+}
+
+fn terminal_usage() -> i32 {
+    eprintln!("{}", TERMINAL_USAGE);
+    EXIT_USAGE
+}
+
+/// `rustdesk --terminal <ID>`, returns the exit code.
+pub fn run_terminal_command(args: &[String]) -> i32 {
+    match parse_terminal_command(args) {
+        Ok((id, opts)) => terminal_command(id, opts),
+        Err(code) => code,
+    }
+}
+
+// The peer and options, or the exit code if there is nothing to run
+fn parse_terminal_command(args: &[String]) -> Result<(String, TerminalOptions), i32> {
+    let mut opts = TerminalOptions {
+        password: std::env::var("RUSTDESK_PASSWORD").ok(),
+        ..Default::default()
+    };
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-k" | "--key" => match iter.next() {
+                Some(v) => opts.key = v.clone(),
+                None => return Err(terminal_usage()),
+            },
+            "--password" => match iter.next() {
+                Some(v) => opts.password = Some(v.clone()),
+                None => return Err(terminal_usage()),
+            },
+            "--persistent" => opts.persistent = Some(true),
+            "--no-persistent" => opts.persistent = Some(false),
+            "--service-id" => match iter.next() {
+                Some(v) if !v.is_empty() => opts.service_id = Some(v.clone()),
+                _ => return Err(terminal_usage()),
+            },
+            "--new" => opts.service_id = Some("".to_owned()),
+            "-t" | "--terminal-id" => match iter.next().map(|v| v.parse()) {
+                Some(Ok(v)) => opts.terminal_id = v,
+                _ => return Err(terminal_usage()),
+            },
+            "-l" | "--list" => opts.list = true,
+            "-h" | "--help" => {
+                println!("{}", TERMINAL_USAGE);
+                return Err(EXIT_OK);
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                eprintln!("Unknown option: {}", x);
+                return Err(terminal_usage());
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if positional.len() != 1 {
+        return Err(terminal_usage());
+    }
+    Ok((positional[0].clone(), opts))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn terminal_command(id: String, opts: TerminalOptions) -> i32 {
+    let token = LocalConfig::get_option("access_token");
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::TERMINAL, opts.password.clone(), sender);
+    {
+        // Read by the login request
+        let mut lc = handler.lc.write().unwrap();
+        if let Some(service_id) = &opts.service_id {
+            let key = lc.get_key_terminal_service_id().to_owned();
+            lc.set_option(key, service_id.clone());
+        }
+        if let Some(persistent) = opts.persistent {
+            let mut config = lc.load_config();
+            config.terminal_persistent.v = persistent;
+            lc.save_config(config);
+        }
+    }
+    let ((mut stream, direct, ..), (feedback, rendezvous_server)) =
+        match Client::start(&id, &opts.key, &token, ConnType::TERMINAL, handler.clone()).await {
+            Ok(res) => res,
+            Err(err) => {
+                eprintln!("Failed to connect {}: {}", id, err);
+                return EXIT_CONNECT;
+            }
+        };
+    handler.update_direct(Some(direct));
+    let _keep_it = hc_connection(feedback, rendezvous_server, &token).await;
+    if let Err(err) = login(&handler, &mut receiver, &mut stream).await {
+        eprintln!("Failed to log in to {}: {}", id, err);
+        return EXIT_CONNECT;
+    }
+    let res = if opts.list {
+        list_terminals(&mut stream).await
+    } else {
+        open_terminal(&handler, &mut receiver, &mut stream, opts.terminal_id).await
+    };
+    match res {
+        Ok(()) => EXIT_OK,
+        Err(err) => {
+            eprintln!("{}", err);
+            EXIT_TERMINAL
+        }
+    }
+}
+
+fn terminal_message(action: terminal_action::Union) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_terminal_action(TerminalAction {
+        union: Some(action),
+        ..Default::default()
+    });
+    msg_out
+}
+
+/// The next terminal response, answering delay tests meanwhile.
+async fn next_terminal_response(stream: &mut Stream) -> ResultType<TerminalResponse> {
+    loop {
+        match timeout(READ_TIMEOUT, stream.next()).await {
+            Err(_) => bail!("Timeout"),
+            Ok(Some(Ok(bytes))) => {
+                let msg_in = Message::parse_from_bytes(&bytes)?;
+                match msg_in.union {
+                    Some(message::Union::TerminalResponse(tr)) => return Ok(tr),
+                    Some(message::Union::TestDelay(t)) => handle_test_delay(t, stream).await,
+                    Some(message::Union::Misc(misc)) => {
+                        if let Some(misc::Union::CloseReason(reason)) = misc.union {
+                            bail!("Closed by the peer: {}", reason);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+            Ok(None) => bail!("Reset by the peer"),
+        }
+    }
+}
+
+async fn next_terminal_list(stream: &mut Stream) -> ResultType<TerminalList> {
+    loop {
+        match next_terminal_response(stream).await?.union {
+            Some(terminal_response::Union::List(list)) => return Ok(list),
+            Some(terminal_response::Union::Error(e)) => bail!("{}", e.message),
+            _ => {}
+        }
+    }
+}
+
+async fn list_terminals(stream: &mut Stream) -> ResultType<()> {
+    stream
+        .send(&terminal_message(terminal_action::Union::List(
+            ListTerminals::new(),
+        )))
+        .await?;
+    // Older peers ignore the request, delay tests keep the connection busy
+    let list = match timeout(READ_TIMEOUT, next_terminal_list(stream)).await {
+        Ok(res) => res?,
+        Err(_) => bail!("The peer can't list terminals"),
+    };
+    eprintln!("Service {}", list.service_id);
+    for t in list.terminals.iter() {
+        println!(
+            "{}\t{}\t{}:{:02}:{:02}\t{}",
+            t.terminal_id,
+            t.pid,
+            t.age_secs / 3600,
+            t.age_secs / 60 % 60,
+            t.age_secs % 60,
+            t.title
+        );
+    }
+    Ok(())
+}
+
+async fn open_terminal(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+    terminal_id: i32,
+) -> ResultType<()> {
+    let is_tty = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let (rows, cols) = tty::size().unwrap_or((24, 80));
+    stream
+        .send(&terminal_message(terminal_action::Union::Open(
+            OpenTerminal {
+                terminal_id,
+                rows: rows as _,
+                cols: cols as _,
+                ..Default::default()
+            },
+        )))
+        .await?;
+    let opened = loop {
+        match next_terminal_response(stream).await?.union {
+            Some(terminal_response::Union::Opened(opened)) if opened.terminal_id == terminal_id => {
+                break opened
+            }
+            Some(terminal_response::Union::Error(e)) => bail!("{}", e.message),
+            _ => {}
+        }
+    };
+    if !opened.success {
+        bail!(
+            "Failed to open terminal {}: {}",
+            terminal_id,
+            opened.message
+        );
+    }
+    let persistent = {
+        let mut lc = handler.lc.write().unwrap();
+        if !opened.service_id.is_empty() {
+            let key = lc.get_key_terminal_service_id().to_owned();
+            lc.set_option(key, opened.service_id.clone());
+        }
+        lc.get_toggle_option(keys::OPTION_TERMINAL_PERSISTENT)
+    };
+    if is_tty {
+        eprintln!(
+            "{}: terminal {} of service {}{}, Ctrl+] to disconnect",
+            opened.message,
+            terminal_id,
+            opened.service_id,
+            if persistent { ", persistent" } else { "" }
+        );
+    }
+    if !opened.persistent_sessions.is_empty() {
+        let others: Vec<_> = opened
+            .persistent_sessions
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        eprintln!("Other terminals of the service: {}", others.join(", "));
+    }
+
+    let res = {
+        let _raw = if is_tty {
+            Some(tty::RawMode::enable()?)
+        } else {
+            None
+        };
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || read_stdin(input_tx));
+        terminal_loop(receiver, stream, terminal_id, input_rx, is_tty).await
+    };
+    match res? {
+        Some(exit_code) => eprintln!("Terminal closed, exit code {}", exit_code),
+        None => eprintln!("Disconnected"),
+    }
+    Ok(())
+}
+
+fn read_stdin(tx: mpsc::UnboundedSender<Vec<u8>>) {
+    let mut stdin = std::io::stdin();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stdin.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The input to send and whether the escape ended it, piped input may
+// have the escape byte in its data
+fn split_escape(data: &[u8], is_tty: bool) -> (&[u8], bool) {
+    match data.iter().position(|b| *b == TERMINAL_ESCAPE) {
+        Some(i) if is_tty => (&data[..i], true),
+        _ => (data, false),
+    }
+}
+
+/// Returns the exit code if the terminal closed, none if disconnected here.
+async fn terminal_loop(
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+    terminal_id: i32,
+    mut input_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    is_tty: bool,
+) -> ResultType<Option<i32>> {
+    let mut resized = tty::Resized::new()?;
+    let mut size = tty::size();
+    // Piped input is sent up to its end, the output is read until the terminal closes
+    let mut input_open = true;
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => bail!("Timeout"),
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::TerminalResponse(tr)) => {
+                            if let Some(exit_code) = write_terminal_output(tr, terminal_id)? {
+                                return Ok(Some(exit_code));
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => handle_test_delay(t, stream).await,
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::CloseReason(reason)) = misc.union {
+                                bail!("Closed by the peer: {}", reason);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+                Ok(None) => bail!("Reset by the peer"),
+            },
+            data = input_rx.recv(), if input_open => match data {
+                Some(data) => {
+                    let (data, escape) = split_escape(&data, is_tty);
+                    if !data.is_empty() {
+                        stream
+                            .send(&terminal_message(terminal_action::Union::Data(TerminalData {
+                                terminal_id,
+                                data: data.to_vec().into(),
+                                ..Default::default()
+                            })))
+                            .await?;
+                    }
+                    if escape {
+                        return Ok(None);
+                    }
+                }
+                None => input_open = false,
+            },
+            _ = resized.next() => {
+                let new_size = tty::size();
+                if new_size != size {
+                    if let Some((rows, cols)) = new_size {
+                        stream
+                            .send(&terminal_message(terminal_action::Union::Resize(ResizeTerminal {
+                                terminal_id,
+                                rows: rows as _,
+                                cols: cols as _,
+                                ..Default::default()
+                            })))
+                            .await?;
+                    }
+                    size = new_size;
+                }
+            }
+            d = receiver.recv() => {
+                if let Some(Data::Close) | None = d {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Writes the output of the terminal, returns its exit code if it closed.
+fn write_terminal_output(tr: TerminalResponse, terminal_id: i32) -> ResultType<Option<i32>> {
+    match tr.union {
+        Some(terminal_response::Union::Data(data)) if data.terminal_id == terminal_id => {
+            let data = if data.compressed {
+                hbb_common::compress::decompress(&data.data)
+            } else {
+                data.data.to_vec()
+            };
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+        Some(terminal_response::Union::Closed(closed)) if closed.terminal_id == terminal_id => {
+            return Ok(Some(closed.exit_code));
+        }
+        Some(terminal_response::Union::Error(e)) => bail!("{}", e.message),
+        _ => {}
+    }
+    Ok(None)
+}
+
+/// Exit code of `--port-forward-profile` if the service isn't running or
+/// failed.
+pub const EXIT_SERVICE: i32 = 2;
+
+const PROFILE_USAGE: &str = "Usage: rustdesk --port-forward-profile <COMMAND>
+
+Commands:
+  list                                List the profiles
+  add [OPTIONS] <NAME> <ID> <FORWARD> Add a profile, or replace the one of NAME
+  remove <NAME>                       Remove a profile
+  status                              Show the state of the profiles in the service
+  run                                 Run the service keeping the profiles up
+
+Forwards:
+  [BIND:]PORT HOST:PORT    Local PORT to HOST:PORT as seen from the peer
+  [BIND:]PORT socks5       Local PORT as a SOCKS5 proxy through the peer
+  -R PORT HOST:PORT        PORT of the peer's loopback to local HOST:PORT
+
+Options:
+      --password <PWD>     Password of the peer, the remembered one if omitted
+      --no-reconnect       Leave the forward stopped once it ends
+
+Exit codes: 0 done, 1 usage, 2 the service isn't running or failed";
+
+fn profile_usage() -> i32 {
+    eprintln!("{}", PROFILE_USAGE);
+    EXIT_USAGE
+}
+
+/// `rustdesk --port-forward-profile <COMMAND>`, returns the exit code.
+pub fn run_port_forward_profile_command(args: &[String]) -> i32 {
+    use crate::port_forward::supervisor;
+    match args.first().map(|x| x.as_str()) {
+        Some("list") if args.len() == 1 => {
+            for p in LocalConfig::get_port_forward_profiles() {
+                println!(
+                    "{}\t{}\t{}{}",
+                    p.name,
+                    p.peer_id,
+                    describe_forward(&p),
+                    if p.auto_reconnect {
+                        ""
+                    } else {
+                        "\t(no reconnect)"
+                    }
+                );
+            }
+            EXIT_OK
+        }
+        Some("add") => match parse_profile(&args[1..]) {
+            Some(profile) => {
+                let mut profiles = LocalConfig::get_port_forward_profiles();
+                profiles.retain(|p| p.name != profile.name);
+                profiles.push(profile);
+                LocalConfig::set_port_forward_profiles(profiles);
+                EXIT_OK
+            }
+            None => profile_usage(),
+        },
+        Some("remove") if args.len() == 2 => {
+            let mut profiles = LocalConfig::get_port_forward_profiles();
+            let len = profiles.len();
+            profiles.retain(|p| p.name != args[1]);
+            if profiles.len() == len {
+                eprintln!("No profile named {}", args[1]);
+                return EXIT_USAGE;
+            }
+            LocalConfig::set_port_forward_profiles(profiles);
+            EXIT_OK
+        }
+        Some("status") if args.len() == 1 => profile_status(),
+        Some("run") if args.len() == 1 => match supervisor::run() {
+            Ok(()) => EXIT_OK,
+            Err(err) => {
+                eprintln!("{}", err);
+                EXIT_SERVICE
+            }
+        },
+        Some("-h" | "--help") => {
+            println!("{}", PROFILE_USAGE);
+            EXIT_OK
+        }
+        _ => profile_usage(),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn profile_status() -> i32 {
+    match crate::port_forward::supervisor::get_status().await {
+        Ok(status) => {
+            for (name, state) in status {
+                println!("{}\t{}", name, state);
+            }
+            EXIT_OK
+        }
+        Err(err) => {
+            eprintln!("The port forward service isn't running: {}", err);
+            EXIT_SERVICE
+        }
+    }
+}
+
+fn parse_profile(args: &[String]) -> Option<PortForwardProfile> {
+    let mut profile = PortForwardProfile::default();
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--password" => profile.password = iter.next()?.clone(),
+            "--no-reconnect" => profile.auto_reconnect = false,
+            "-R" => {
+                profile.mode = PortForwardMode::Reverse;
+                positional.push(arg.as_str());
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                eprintln!("Unknown option: {}", x);
+                return None;
+            }
+            x => positional.push(x),
+        }
+    }
+    match positional.as_slice() {
+        [name, id, "-R", port, target] => {
+            profile.remote_port = parse_port(port)?;
+            let (host, port) = split_host_port(target)?;
+            // Connected to, not put in a message
+            profile.local_host = unbracket(host).to_owned();
+            profile.local_port = parse_port(port)?;
+            profile.name = name.to_string();
+            profile.peer_id = id.to_string();
+        }
+        [name, id, local, remote] => {
+            let (bind, port) = match split_host_port(local) {
+                Some((bind, port)) => (unbracket(bind).parse::<IpAddr>().ok()?.to_string(), port),
+                None => ("".to_owned(), *local),
+            };
+            profile.local_host = bind;
+            profile.local_port = parse_port(port)?;
+            if *remote == crate::port_forward::DYNAMIC {
+                profile.mode = PortForwardMode::Dynamic;
+            } else {
+                let (host, port) = split_host_port(remote)?;
+                profile.remote_host = host.to_owned();
+                profile.remote_port = parse_port(port)?;
+            }
+            profile.name = name.to_string();
+            profile.peer_id = id.to_string();
+        }
+        _ => return None,
+    }
+    (!profile.name.is_empty() && !profile.peer_id.is_empty()).then_some(profile)
+}
+
+fn describe_forward(p: &PortForwardProfile) -> String {
+    let bind = if p.local_host.is_empty() {
+        "".to_owned()
+    } else if p.local_host.contains(':') {
+        format!("[{}]:", p.local_host)
+    } else {
+        format!("{}:", p.local_host)
+    };
+    match p.mode {
+        PortForwardMode::Local => format!(
+            "{}{} -> {}:{}",
+            bind, p.local_port, p.remote_host, p.remote_port
+        ),
+        PortForwardMode::Dynamic => format!("{}{} socks5", bind, p.local_port),
+        PortForwardMode::Reverse => format!("-R {} -> {}{}", p.remote_port, bind, p.local_port),
+    }
+}
+
+fn split_host_port(s: &str) -> Option<(&str, &str)> {
+    s.rsplit_once(':').filter(|(host, _)| !host.is_empty())
+}
+
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn parse_port(s: &str) -> Option<i32> {
+    s.parse::<u16>().ok().filter(|p| *p > 0).map(|p| p as _)
+}
+
+#[cfg(unix)]
+mod tty {
+    use hbb_common::{
+        libc,
+        tokio::signal::unix::{signal, SignalKind},
+        ResultType,
+    };
+
+    /// Raw mode of stdin, restored on drop.
+    pub struct RawMode(libc::termios);
+
+    impl RawMode {
+        pub fn enable() -> std::io::Result<Self> {
+            unsafe {
+                let mut termios: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let mut raw = termios;
+                libc::cfmakeraw(&mut raw);
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(Self(termios))
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+            }
+        }
+    }
+
+    /// Rows and columns of the terminal.
+    pub fn size() -> Option<(u16, u16)> {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } != 0
+            || ws.ws_row == 0
+            || ws.ws_col == 0
+        {
+            return None;
+        }
+        Some((ws.ws_row, ws.ws_col))
+    }
+
+    /// SIGWINCH
+    pub struct Resized(tokio::signal::unix::Signal);
+
+    impl Resized {
+        pub fn new() -> ResultType<Self> {
+            Ok(Self(signal(SignalKind::window_change())?))
+        }
+
+        pub async fn next(&mut self) {
+            self.0.recv().await;
+        }
+    }
+}
+
+#[cfg(windows)]
+mod tty {
+    use hbb_common::{
+        tokio::time::{self, Duration, Interval},
+        ResultType,
+    };
+    use winapi::{
+        shared::minwindef::DWORD,
+        um::{
+            consoleapi::{GetConsoleMode, SetConsoleMode},
+            processenv::GetStdHandle,
+            winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
+            wincon::{
+                GetConsoleScreenBufferInfo, CONSOLE_SCREEN_BUFFER_INFO,
+                DISABLE_NEWLINE_AUTO_RETURN, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT,
+                ENABLE_PROCESSED_INPUT, ENABLE_VIRTUAL_TERMINAL_INPUT,
+                ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+            },
+            winnt::HANDLE,
+        },
+    };
+
+    /// Raw mode of the console with VT sequences, restored on drop.
+    pub struct RawMode {
+        input: (HANDLE, DWORD),
+        output: (HANDLE, DWORD),
+    }
+
+    impl RawMode {
+        pub fn enable() -> std::io::Result<Self> {
+            unsafe {
+                let input = GetStdHandle(STD_INPUT_HANDLE);
+                let output = GetStdHandle(STD_OUTPUT_HANDLE);
+                let (mut input_mode, mut output_mode) = (0, 0);
+                if GetConsoleMode(input, &mut input_mode) == 0
+                    || GetConsoleMode(output, &mut output_mode) == 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let raw_input = (input_mode
+                    & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT))
+                    | ENABLE_VIRTUAL_TERMINAL_INPUT;
+                let raw_output =
+                    output_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING | DISABLE_NEWLINE_AUTO_RETURN;
+                if SetConsoleMode(input, raw_input) == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if SetConsoleMode(output, raw_output) == 0 {
+                    let err = std::io::Error::last_os_error();
+                    SetConsoleMode(input, input_mode);
+                    return Err(err);
+                }
+                Ok(Self {
+                    input: (input, input_mode),
+                    output: (output, output_mode),
+                })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                SetConsoleMode(self.input.0, self.input.1);
+                SetConsoleMode(self.output.0, self.output.1);
+            }
+        }
+    }
+
+    /// Rows and columns of the console window.
+    pub fn size() -> Option<(u16, u16)> {
+        unsafe {
+            let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+            if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) == 0 {
+                return None;
+            }
+            let w = info.srWindow;
+            Some(((w.Bottom - w.Top + 1) as u16, (w.Right - w.Left + 1) as u16))
+        }
+    }
+
+    /// Windows has no SIGWINCH, the size is polled.
+    pub struct Resized(Interval);
+
+    impl Resized {
+        pub fn new() -> ResultType<Self> {
+            Ok(Self(time::interval(Duration::from_millis(500))))
+        }
+
+        pub async fn next(&mut self) {
+            self.0.tick().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|x| x.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_parse_file_command() {
+        let (id, cmd, opts) =
+            parse_file_command(&args("-k key --password pwd -a -q ls 123 /home")).unwrap();
+        assert_eq!(id, "123");
+        assert_eq!(
+            cmd,
+            FileCommand::Ls {
+                path: "/home".to_owned()
+            }
+        );
+        assert_eq!(opts.key, "key");
+        assert_eq!(opts.password.as_deref(), Some("pwd"));
+        assert!(opts.include_hidden && opts.quiet && !opts.skip_existing && !opts.resume);
+
+        // The last of the overwrite policies wins, `-` is a path
+        let (_, cmd, opts) = parse_file_command(&args(
+            "push --resume --skip-existing 123 - C:\\tmp\\ --overwrite",
+        ))
+        .unwrap();
+        assert_eq!(
+            cmd,
+            FileCommand::Push {
+                local: "-".to_owned(),
+                remote: "C:\\tmp\\".to_owned()
+            }
+        );
+        assert!(opts.resume && !opts.skip_existing);
+        let (_, cmd, opts) =
+            parse_file_command(&args("pull 123 /a/b.txt . --skip-existing")).unwrap();
+        assert_eq!(
+            cmd,
+            FileCommand::Pull {
+                remote: "/a/b.txt".to_owned(),
+                local: ".".to_owned()
+            }
+        );
+        assert!(opts.skip_existing);
+
+        for usage in [
+            "",
+            "ls 123",
+            "ls 123 /a /b",
+            "push 123 a",
+            "pull 123 a b c",
+            "cp 123 a b",
+            "ls 123 / -k",
+            "ls 123 / --password",
+            "ls 123 / --bogus",
+            "-x ls 123 /",
+        ] {
+            assert_eq!(
+                parse_file_command(&args(usage)).err(),
+                Some(EXIT_USAGE),
+                "{}",
+                usage
+            );
+        }
+        assert_eq!(
+            parse_file_command(&args("ls 123 / --help")).err(),
+            Some(EXIT_OK)
+        );
+    }
+
+    #[test]
+    fn test_destination_path() {
+        for (dest, source, expected) in [
+            ("/tmp/c.txt", "/a/b.txt", "/tmp/c.txt"),
+            ("/tmp/", "/a/b.txt", "/tmp/b.txt"),
+            ("/tmp/", "C:\\a\\b.txt", "/tmp/b.txt"),
+            ("C:\\tmp\\", "/a/dir/", "C:\\tmp\\dir"),
+            ("./", "b.txt", "./b.txt"),
+        ] {
+            assert_eq!(destination_path(dest.to_owned(), source).unwrap(), expected);
+        }
+        assert!(destination_path("/tmp/".to_owned(), "/").is_err());
+        assert!(destination_path("/tmp/".to_owned(), "\\\\").is_err());
+    }
+
+    #[test]
+    fn test_parse_terminal_command() {
+        let expected = |f: fn(&mut TerminalOptions)| {
+            let mut opts = TerminalOptions {
+                password: Some("pwd".to_owned()),
+                ..Default::default()
+            };
+            f(&mut opts);
+            opts
+        };
+        for (a, opts) in [
+            ("123", expected(|_| {})),
+            (
+                "-k key --persistent -t 2 123",
+                expected(|o| {
+                    o.key = "key".to_owned();
+                    o.persistent = Some(true);
+                    o.terminal_id = 2;
+                }),
+            ),
+            (
+                "123 --persistent --no-persistent --service-id sid -l",
+                expected(|o| {
+                    o.persistent = Some(false);
+                    o.service_id = Some("sid".to_owned());
+                    o.list = true;
+                }),
+            ),
+            (
+                "123 --service-id sid --new --terminal-id 1",
+                expected(|o| {
+                    o.service_id = Some("".to_owned());
+                    o.terminal_id = 1;
+                }),
+            ),
+        ] {
+            let (id, parsed) =
+                parse_terminal_command(&args(&format!("--password pwd {}", a))).unwrap();
+            assert_eq!(id, "123");
+            assert_eq!(parsed, opts, "{}", a);
+        }
+
+        for usage in [
+            "",
+            "123 456",
+            "123 -k",
+            "123 --password",
+            "123 --service-id",
+            "123 -t",
+            "123 -t x",
+            "123 --terminal-id -",
+            "123 --bogus",
+        ] {
+            assert_eq!(
+                parse_terminal_command(&args(usage)).err(),
+                Some(EXIT_USAGE),
+                "{}",
+                usage
+            );
+        }
+        assert_eq!(parse_terminal_command(&args("-h 123")).err(), Some(EXIT_OK));
+    }
+
+    #[test]
+    fn test_split_escape() {
+        let data = b"ls\r\x1dexit\r";
+        assert_eq!(split_escape(data, true), (&b"ls\r"[..], true));
+        // Piped input is sent as is
+        assert_eq!(split_escape(data, false), (&data[..], false));
+        assert_eq!(split_escape(b"ls\r", true), (&b"ls\r"[..], false));
+        assert_eq!(split_escape(b"\x1d", true), (&b""[..], true));
+        assert_eq!(split_escape(b"", true), (&b""[..], false));
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        for (s, expected) in [
+            ("host:80", Some(("host", "80"))),
+            ("192.168.1.2:80", Some(("192.168.1.2", "80"))),
+            ("[::1]:80", Some(("[::1]", "80"))),
+            ("[fd00::2]:", Some(("[fd00::2]", ""))),
+            ("8080", None),
+            (":80", None),
+            ("", None),
+        ] {
+            assert_eq!(split_host_port(s), expected, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        for (a, expected) in [
+            ("8080 192.168.1.2:80", Some("8080 -> 192.168.1.2:80")),
+            (
+                "127.0.0.1:8080 nas.lan:443",
+                Some("127.0.0.1:8080 -> nas.lan:443"),
+            ),
+            (
+                "[::1]:8080 [fd00::2]:22",
+                Some("[::1]:8080 -> [fd00::2]:22"),
+            ),
+            ("1080 socks5", Some("1080 socks5")),
+            ("[::]:1080 socks5", Some("[::]:1080 socks5")),
+            ("-R 2222 localhost:22", Some("-R 2222 -> localhost:22")),
+            ("-R 2222 [::1]:22", Some("-R 2222 -> [::1]:22")),
+            ("0 host:80", None),
+            ("65536 host:80", None),
+            ("8080 host:0", None),
+            ("8080 host:http", None),
+            ("8080 host", None),
+            ("8080 :80", None),
+            ("nas.lan:8080 host:80", None),
+            ("[::1:8080 host:80", None),
+            ("::1:8080 host:80", Some("[::1]:8080 -> host:80")),
+            ("-R 0 host:22", None),
+            ("-R 2222 host", None),
+            ("-R 2222 host:99999", None),
+            ("8080", None),
+            ("8080 host:80 extra", None),
+            ("8080 host:80 --bogus", None),
+            ("8080 host:80 --password", None),
+        ] {
+            let profile = parse_profile(&args(&format!("nas 123 {}", a)));
+            assert_eq!(
+                profile.as_ref().map(describe_forward).as_deref(),
+                expected,
+                "{}",
+                a
+            );
+            if let Some(p) = profile {
+                assert_eq!((p.name.as_str(), p.peer_id.as_str()), ("nas", "123"));
+                assert!(p.auto_reconnect && p.password.is_empty());
+            }
+        }
+
+        assert_eq!(
+            parse_profile(&args(
+                "--no-reconnect nas 123 -R 2222 [::1]:22 --password pwd"
+            )),
+            Some(PortForwardProfile {
+                name: "nas".to_owned(),
+                peer_id: "123".to_owned(),
+                mode: PortForwardMode::Reverse,
+                local_host: "::1".to_owned(),
+                local_port: 22,
+                remote_port: 2222,
+                auto_reconnect: false,
+                password: "pwd".to_owned(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(parse_profile(&args("nas")), None);
+    }
+}
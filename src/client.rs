@@ -1540,6 +1540,7 @@ pub struct VideoHandler {
     pub texture: ImageTexture,
     recorder: Arc<Mutex<Option<Recorder>>>,
     record: bool,
+    audio_channels: u32,
     _display: usize, // useful for debug
     fail_counter: usize,
     first_frame: bool,
@@ -1572,6 +1573,7 @@ impl VideoHandler {
             texture: Default::default(),
             recorder: Default::default(),
             record: false,
+            audio_channels: 2,
             _display,
             fail_counter: 0,
             first_frame: true,
@@ -1651,6 +1653,9 @@ impl VideoHandler {
     pub fn record_screen(&mut self, start: bool, id: String, display_idx: usize, camera: bool) {
         self.record = false;
         if start {
+            scrap::record_retention::start_sweeper(crate::ui_interface::video_save_directory(
+                false,
+            ));
             self.recorder = Recorder::new(RecorderContext {
                 server: false,
                 id,
@@ -1658,14 +1663,44 @@ impl VideoHandler {
                 display_idx,
                 camera,
                 tx: None,
+                encryption_key: Config::get_option(keys::OPTION_RECORD_ENCRYPTION_KEY),
             })
-            .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+            .map_or(Default::default(), |mut r| {
+                r.set_audio_channels(self.audio_channels);
+                Arc::new(Mutex::new(Some(r)))
+            });
         } else {
             self.recorder = Default::default();
         }
 
         self.record = start;
     }
+
+    /// Set the format of the audio to record.
+    pub fn set_audio_format(&mut self, format: &AudioFormat) {
+        self.audio_channels = format.channels;
+        if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+            r.set_audio_channels(format.channels);
+        }
+    }
+
+    /// Write an audio frame to the screen record.
+    pub fn record_audio(&mut self, frame: &AudioFrame) {
+        if self.record {
+            if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+                r.write_audio(&frame.data);
+            }
+        }
+    }
+
+    /// See [`Recorder::take_disk_notice`].
+    pub fn take_record_disk_notice(&mut self) -> Option<bool> {
+        self.recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|r| r.take_disk_notice())
+    }
 }
 
 // The source of sent password
@@ -1733,6 +1768,8 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    // The peer listens on the port of `port_forward`
+    pub port_forward_reverse: bool,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -2682,11 +2719,16 @@ impl LoginConfigHandler {
             ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                mux_version: crate::port_forward::mux::MUX_VERSION,
+                reverse: self.port_forward_reverse,
+                dynamic: crate::port_forward::is_dynamic(&self.port_forward.0, self.port_forward.1),
                 ..Default::default()
             }),
             ConnType::TERMINAL => {
                 let mut terminal = Terminal::new();
                 terminal.service_id = self.get_option(self.get_key_terminal_service_id());
+                terminal.restricted = self.get_option(keys::OPTION_TERMINAL_RESTRICTED) == "Y";
                 lr.set_terminal(terminal);
             }
             _ => {}
@@ -2785,7 +2827,8 @@ pub fn start_video_thread<F, T>(
         #[cfg(windows)]
         sync_cpu_usage();
         get_hwcodec_config();
-        let mut video_handler = None;
+        let mut video_handler: Option<VideoHandler> = None;
+        let mut audio_format = None;
         let mut count = 0;
         let mut duration = std::time::Duration::ZERO;
         let mut skip_beginning = 0;
@@ -2821,6 +2864,9 @@ pub fn start_video_thread<F, T>(
                             let record_state = session.lc.read().unwrap().record_state;
                             let record_permission = session.lc.read().unwrap().record_permission;
                             let id = session.lc.read().unwrap().id.clone();
+                            if let Some(f) = audio_format.as_ref() {
+                                handler.set_audio_format(f);
+                            }
                             if record_state && record_permission {
                                 handler.record_screen(true, id, display, is_view_camera);
                             }
@@ -2872,6 +2918,19 @@ pub fn start_video_thread<F, T>(
                                 }
                                 _ => {}
                             }
+                            if let Some(low) = handler.take_record_disk_notice() {
+                                let text = if low {
+                                    "record-paused-low-disk-tip"
+                                } else {
+                                    "record-resumed-tip"
+                                };
+                                session.msgbox(
+                                    "custom-nook-nocancel-hasclose",
+                                    "Recording",
+                                    text,
+                                    "",
+                                );
+                            }
                         }
 
                         // check invalid decoders
@@ -2906,7 +2965,17 @@ pub fn start_video_thread<F, T>(
                             handler.record_screen(start, id, display, is_view_camera);
                         }
                     }
-                    _ => {}
+                    MediaData::AudioFrame(af) => {
+                        if let Some(handler) = video_handler.as_mut() {
+                            handler.record_audio(&af);
+                        }
+                    }
+                    MediaData::AudioFormat(f) => {
+                        if let Some(handler) = video_handler.as_mut() {
+                            handler.set_audio_format(&f);
+                        }
+                        audio_format = Some(f);
+                    }
                 }
             } else {
                 break;
@@ -3665,6 +3734,8 @@ pub enum Data {
     RemoveFile((i32, String, i32, bool)),
     CreateDir((i32, String, bool)),
     CancelJob(i32),
+    // id, paused, rate limit in KiB/s
+    ScheduleJob((i32, bool, u32)),
     RemovePortForward(i32),
     AddPortForward((i32, String, i32)),
     #[cfg(all(target_os = "windows", not(feature = "flutter")))]
@@ -55,6 +55,10 @@ pub trait FileManager: Interface {
         self.send(Data::CancelJob(id));
     }
 
+    fn schedule_job(&self, id: i32, paused: bool, rate_limit: i32) {
+        self.send(Data::ScheduleJob((id, paused, rate_limit.max(0) as _)));
+    }
+
     fn read_empty_dirs(&self, path: String, include_hidden: bool) {
         let mut msg_out = Message::new();
         let mut file_action = FileAction::new();
@@ -77,6 +77,8 @@ pub struct Remote<T: InvokeUiSession> {
     video_threads: HashMap<usize, VideoThread>,
     chroma: Arc<RwLock<Option<Chroma>>>,
     last_record_state: bool,
+    // Recorded along with the video
+    audio_format: Option<AudioFormat>,
     sent_close_reason: bool,
 }
 
@@ -126,6 +128,7 @@ impl<T: InvokeUiSession> Remote<T> {
             video_threads: Default::default(),
             chroma: Default::default(),
             last_record_state: false,
+            audio_format: None,
             sent_close_reason: false,
         }
     }
@@ -793,6 +796,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                         let mut msg = Message::new();
                         let mut file_action = FileAction::new();
+                        let delta_signatures = job.take_delta_signatures(file_num);
                         let req = FileTransferSendConfirmRequest {
                             id,
                             file_num,
@@ -801,6 +805,11 @@ impl<T: InvokeUiSession> Remote<T> {
                             } else {
                                 Some(file_transfer_send_confirm_request::Union::Skip(true))
                             },
+                            delta_signatures: if need_override {
+                                delta_signatures
+                            } else {
+                                Default::default()
+                            },
                             ..Default::default()
                         };
                         job.confirm(&req).await;
@@ -859,6 +868,24 @@ impl<T: InvokeUiSession> Remote<T> {
                 let _ = fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
             }
+            Data::ScheduleJob((id, paused, rate_limit)) => {
+                if let Some(job) = fs::get_job(id, &mut self.read_jobs) {
+                    job.set_paused(paused);
+                    job.set_rate_limit(rate_limit as u64 * 1024);
+                } else if fs::get_job(id, &mut self.write_jobs).is_some() {
+                    // The peer reads the files of download jobs
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_schedule(FileTransferSchedule {
+                        id,
+                        paused,
+                        rate_limit,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                }
+            }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
@@ -1485,6 +1512,10 @@ impl<T: InvokeUiSession> Remote<T> {
                                         if let fs::DataSource::FilePath(p) = &job.data_source {
                                             let read_path =
                                                 get_string(&fs::TransferJob::join(p, &file.name));
+                                            job.set_delta_signatures(
+                                                digest.file_num,
+                                                digest.delta_signatures.clone(),
+                                            );
                                             let mut overwrite_strategy =
                                                 job.default_overwrite_strategy();
                                             let mut offset = 0;
@@ -1529,6 +1560,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                             let write_path =
                                                 get_string(&fs::TransferJob::join(p, &file.name));
                                             job.set_digest(digest.file_size, digest.last_modified);
+                                            job.set_content_hash(fs::digest_content_hash(&digest));
                                             let peer_ver = self.handler.lc.read().unwrap().version;
                                             let is_support_resume =
                                                 crate::is_support_file_transfer_resume_num(
@@ -1538,7 +1570,9 @@ impl<T: InvokeUiSession> Remote<T> {
                                                 is_support_resume && job.is_resume,
                                                 &write_path,
                                                 &digest,
-                                            ) {
+                                            )
+                                            .await
+                                            {
                                                 Ok(res) => match res {
                                                     DigestCheckResult::IsSame => {
                                                         let req = FileTransferSendConfirmRequest {
@@ -1562,6 +1596,10 @@ impl<T: InvokeUiSession> Remote<T> {
                                                             overwrite_strategy = Some(true);
                                                             offset = digest.transferred_size as _;
                                                         }
+                                                        job.set_delta_signatures(
+                                                            digest.file_num,
+                                                            digest.delta_signatures.clone(),
+                                                        );
                                                         if let Some(overwrite) = overwrite_strategy
                                                         {
                                                             let req =
@@ -1573,6 +1611,11 @@ impl<T: InvokeUiSession> Remote<T> {
                                                                     } else {
                                                                         file_transfer_send_confirm_request::Union::Skip(true)
                                                                     }),
+                                                                    delta_signatures: if overwrite && offset == 0 {
+                                                                        job.take_delta_signatures(digest.file_num)
+                                                                    } else {
+                                                                        Default::default()
+                                                                    },
                                                                     ..Default::default()
                                                                 };
                                                             job.confirm(&req).await;
@@ -1599,6 +1642,16 @@ impl<T: InvokeUiSession> Remote<T> {
                                                         let msg = new_send_confirm(req);
                                                         allow_err!(peer.send(&msg).await);
                                                     }
+                                                    DigestCheckResult::NeedContentHash => {
+                                                        let req = FileTransferSendConfirmRequest {
+                                                            id: digest.id,
+                                                            file_num: digest.file_num,
+                                                            need_content_hash: true,
+                                                            ..Default::default()
+                                                        };
+                                                        let msg = new_send_confirm(req);
+                                                        allow_err!(peer.send(&msg).await);
+                                                    }
                                                 },
                                                 Err(err) => {
                                                     println!("error receiving digest: {}", err);
@@ -1690,6 +1743,12 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::AudioFormat(f)) => {
+                        for (_, v) in self.video_threads.iter() {
+                            v.video_sender
+                                .send(MediaData::AudioFormat(f.clone()))
+                                .ok();
+                        }
+                        self.audio_format = Some(f.clone());
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
                     Some(misc::Union::ChatMessage(c)) => {
@@ -1899,6 +1958,13 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if self.last_record_state {
+                            for (_, v) in self.video_threads.iter() {
+                                v.video_sender
+                                    .send(MediaData::AudioFrame(Box::new(frame.clone())))
+                                    .ok();
+                            }
+                        }
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -2317,6 +2383,12 @@ impl<T: InvokeUiSession> Remote<T> {
             fps_control: Default::default(),
             discard_queue: discard_queue.clone(),
         };
+        if let Some(f) = self.audio_format.clone() {
+            video_thread
+                .video_sender
+                .send(MediaData::AudioFormat(f))
+                .ok();
+        }
         let handler = self.handler.ui_handler.clone();
         crate::client::start_video_thread(
             self.handler.clone(),
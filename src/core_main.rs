@@ -131,6 +131,19 @@ pub fn core_main() -> Option<Vec<String>> {
             println!("{}", crate::BUILD_DATE);
             return None;
         }
+        // The windows to pick the "share-window" option from
+        #[cfg(target_os = "linux")]
+        if args[0] == "--list-windows" {
+            match scrap::WindowCapturer::windows() {
+                Ok(windows) => {
+                    for w in windows.iter().rev() {
+                        println!("{:#x}\t{}\t{}", w.id, w.class, w.title);
+                    }
+                }
+                Err(e) => eprintln!("Failed to list windows: {}", e),
+            }
+            return None;
+        }
     }
     #[cfg(windows)]
     {
@@ -398,6 +411,17 @@ pub fn core_main() -> Option<Vec<String>> {
                 }
             }
             return None;
+        } else if args[0] == "--cli" {
+            let code = crate::cli::run_file_command(&args[1..]);
+            std::process::exit(code);
+        } else if args[0] == "--terminal" {
+            std::process::exit(crate::cli::run_terminal_command(&args[1..]));
+        } else if args[0] == "--port-forward-profile" {
+            std::process::exit(crate::cli::run_port_forward_profile_command(&args[1..]));
+        } else if args[0] == "--recording" {
+            std::process::exit(crate::record_tool::run(&args[1..]));
+        } else if args[0] == crate::server::restricted_terminal::ARG {
+            std::process::exit(crate::server::restricted_terminal::run());
         } else if args[0] == "--get-id" {
             println!("{}", crate::ipc::get_id());
             return None;
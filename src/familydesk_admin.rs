@@ -0,0 +1,392 @@
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use hbb_common::tokio;
+use librustdesk::api_client_admin::{AdminClient, SavedLogin, DEFAULT_API_SERVER};
+use librustdesk::api_models::{AuditEntry, AuditQuery};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+fn cli() -> Command {
+    let filters = [
+        Arg::new("device").long("device").help("Device id"),
+        Arg::new("peer").long("peer").help("Peer (helper) id"),
+        Arg::new("kind")
+            .long("kind")
+            .value_parser(["conn", "file", "alarm", "admin"]),
+        Arg::new("since")
+            .long("since")
+            .help("Unix timestamp or YYYY-MM-DD"),
+        Arg::new("until")
+            .long("until")
+            .help("Unix timestamp or YYYY-MM-DD"),
+        Arg::new("limit")
+            .long("limit")
+            .value_parser(clap::value_parser!(usize)),
+    ];
+    Command::new("familydesk-admin")
+        .version(librustdesk::VERSION)
+        .about("FamilyDesk API server administration")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .global(true)
+                .help("API server, defaults to the one used at login"),
+        )
+        .subcommand(
+            Command::new("login")
+                .about("Log in with an admin account and save the token")
+                .arg(Arg::new("username").required(true)),
+        )
+        .subcommand(Command::new("logout").about("Forget the saved token"))
+        .subcommand(Command::new("health").about("Check that the server is up"))
+        .subcommand(
+            Command::new("accounts")
+                .about("Manage accounts")
+                .subcommand_required(true)
+                .subcommand(Command::new("list"))
+                .subcommand(
+                    Command::new("create")
+                        .arg(Arg::new("username").required(true))
+                        .arg(
+                            Arg::new("role")
+                                .long("role")
+                                .default_value("family")
+                                .value_parser(["admin", "family", "student"]),
+                        )
+                        .arg(
+                            Arg::new("can-modify-settings")
+                                .long("can-modify-settings")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("devices")
+                                .long("devices")
+                                .value_delimiter(',')
+                                .help("Comma-separated device ids, or *"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("update")
+                        .arg(Arg::new("username").required(true))
+                        .arg(
+                            Arg::new("role")
+                                .long("role")
+                                .value_parser(["admin", "family", "student"]),
+                        )
+                        .arg(
+                            Arg::new("can-modify-settings")
+                                .long("can-modify-settings")
+                                .value_parser(clap::value_parser!(bool)),
+                        )
+                        .arg(Arg::new("devices").long("devices").value_delimiter(',')),
+                )
+                .subcommand(Command::new("delete").arg(Arg::new("username").required(true)))
+                .subcommand(
+                    Command::new("reset-password").arg(Arg::new("username").required(true)),
+                )
+                .subcommand(Command::new("lock").arg(Arg::new("username").required(true)))
+                .subcommand(Command::new("unlock").arg(Arg::new("username").required(true))),
+        )
+        .subcommand(
+            Command::new("devices")
+                .about("Show devices")
+                .subcommand_required(true)
+                .subcommand(Command::new("list")),
+        )
+        .subcommand(
+            Command::new("tokens")
+                .about("Issue or revoke account tokens")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("issue")
+                        .arg(Arg::new("username").required(true))
+                        .arg(Arg::new("device_id").required(true))
+                        .arg(
+                            Arg::new("ttl-days")
+                                .long("ttl-days")
+                                .value_parser(clap::value_parser!(u64)),
+                        ),
+                )
+                .subcommand(Command::new("revoke").arg(Arg::new("username").required(true))),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Query or export the audit log")
+                .subcommand_required(true)
+                .subcommand(Command::new("query").args(filters.clone()))
+                .subcommand(
+                    Command::new("export")
+                        .args(filters)
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .default_value("jsonl")
+                                .value_parser(["jsonl", "csv"]),
+                        )
+                        .arg(Arg::new("output").long("output").short('o').required(true)),
+                ),
+        )
+}
+
+fn parse_time(s: &str) -> Result<u64, String> {
+    if let Ok(t) = s.parse::<u64>() {
+        return Ok(t);
+    }
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc().timestamp() as u64)
+        .ok_or_else(|| format!("Invalid time: {}", s))
+}
+
+fn audit_filter(m: &ArgMatches) -> Result<AuditQuery, String> {
+    let time = |name: &str| -> Result<Option<u64>, String> {
+        m.get_one::<String>(name).map(|s| parse_time(s)).transpose()
+    };
+    Ok(AuditQuery {
+        device_id: m.get_one::<String>("device").cloned(),
+        peer_id: m.get_one::<String>("peer").cloned(),
+        kind: m.get_one::<String>("kind").cloned(),
+        since: time("since")?,
+        until: time("until")?,
+        limit: m.get_one::<usize>("limit").cloned(),
+    })
+}
+
+fn str_field(v: &Value, k: &str) -> String {
+    match &v[k] {
+        Value::String(s) => s.clone(),
+        Value::Null => "".to_owned(),
+        v => v.to_string(),
+    }
+}
+
+fn print_table<T: Serialize>(rows: &[T], columns: &[&str]) {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|r| serde_json::to_value(r).unwrap_or_default())
+        .map(|r| columns.iter().map(|c| str_field(&r, c)).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| cells.iter().map(|r| r[i].len()).chain([c.len()]).max().unwrap_or(0))
+        .collect();
+    let line = |values: Vec<String>| {
+        let v: Vec<String> = values
+            .iter()
+            .zip(widths.iter())
+            .map(|(v, w)| format!("{:w$}", v, w = *w))
+            .collect();
+        println!("{}", v.join("  ").trim_end());
+    };
+    line(columns.iter().map(|c| c.to_uppercase()).collect());
+    for r in cells {
+        line(r);
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(|c: char| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn export_audit(entries: &[AuditEntry], format: &str, output: &str) -> Result<(), String> {
+    let mut file = std::fs::File::create(output).map_err(|e| e.to_string())?;
+    let mut write = |line: String| writeln!(file, "{}", line).map_err(|e| e.to_string());
+    const COLUMNS: [&str; 7] = ["time", "kind", "device_id", "conn_id", "peer_id", "action", "info"];
+    if format == "csv" {
+        write(COLUMNS.join(","))?;
+    }
+    // Oldest first, as a log file reads
+    for e in entries.iter().rev() {
+        let e = serde_json::to_value(e).map_err(|e| e.to_string())?;
+        if format == "csv" {
+            let row: Vec<String> = COLUMNS.iter().map(|c| csv_field(&str_field(&e, c))).collect();
+            write(row.join(","))?;
+        } else {
+            write(e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn prompt_password(prompt: &str) -> Result<String, String> {
+    rpassword::prompt_password(prompt).map_err(|e| e.to_string())
+}
+
+async fn run(matches: ArgMatches) -> Result<(), String> {
+    let mut saved = SavedLogin::load();
+    let server = matches
+        .get_one::<String>("server")
+        .cloned()
+        .or_else(|| Some(saved.server.clone()).filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| DEFAULT_API_SERVER.to_owned());
+    // A token is only valid for the server that issued it
+    let token = if saved.server.trim_end_matches('/') == server.trim_end_matches('/') {
+        saved.token.clone()
+    } else {
+        "".to_owned()
+    };
+    let mut client = AdminClient::new(&server, &token)?;
+
+    match matches.subcommand() {
+        Some(("login", m)) => {
+            let username = m.get_one::<String>("username").unwrap();
+            let password = prompt_password("Password: ")?;
+            saved.token = client.login(username, &password).await?;
+            saved.server = client.server().to_string();
+            saved.username = username.clone();
+            saved.store()?;
+            println!("Logged in to {} as {}", saved.server, username);
+        }
+        Some(("logout", _)) => {
+            SavedLogin::clear()?;
+            println!("Logged out");
+        }
+        Some(("health", _)) => {
+            client.health().await?;
+            println!("{} is healthy", client.server());
+        }
+        Some(("accounts", m)) => match m.subcommand() {
+            Some(("list", _)) => {
+                let accounts = client.list_accounts().await?;
+                print_table(
+                    &accounts,
+                    &["username", "role", "can_modify_settings", "device_ids", "locked"],
+                );
+            }
+            Some(("create", m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                let password = prompt_password("New password: ")?;
+                let devices: Vec<String> = m
+                    .get_many::<String>("devices")
+                    .map(|v| v.cloned().collect())
+                    .unwrap_or_default();
+                client
+                    .create_account(
+                        username,
+                        &password,
+                        m.get_one::<String>("role").unwrap(),
+                        m.get_flag("can-modify-settings"),
+                        devices,
+                    )
+                    .await?;
+                println!("Account {} created", username);
+            }
+            Some(("update", m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                client
+                    .update_account(
+                        username,
+                        m.get_one::<String>("role").cloned(),
+                        m.get_one::<bool>("can-modify-settings").cloned(),
+                        m.get_many::<String>("devices").map(|v| v.cloned().collect()),
+                    )
+                    .await?;
+                println!("Account {} updated", username);
+            }
+            Some(("delete", m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                client.delete_account(username).await?;
+                println!("Account {} deleted", username);
+            }
+            Some(("reset-password", m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                let password = prompt_password("New password: ")?;
+                client.reset_password(username, &password).await?;
+                println!("Password of {} reset, its tokens are revoked", username);
+            }
+            Some((cmd @ ("lock" | "unlock"), m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                client.lock_account(username, cmd == "lock").await?;
+                println!("Account {} {}ed", username, cmd);
+            }
+            _ => unreachable!(),
+        },
+        Some(("devices", m)) => match m.subcommand() {
+            Some(("list", _)) => {
+                let devices = client.list_devices().await?;
+                print_table(
+                    &devices,
+                    &["id", "online", "hostname", "username", "os", "version", "group", "conns"],
+                );
+            }
+            _ => unreachable!(),
+        },
+        Some(("tokens", m)) => match m.subcommand() {
+            Some(("issue", m)) => {
+                let (token, expires_at) = client
+                    .issue_token(
+                        m.get_one::<String>("username").unwrap(),
+                        m.get_one::<String>("device_id").unwrap(),
+                        m.get_one::<u64>("ttl-days").cloned(),
+                    )
+                    .await?;
+                eprintln!("Expires at {}", expires_at);
+                println!("{}", token);
+            }
+            Some(("revoke", m)) => {
+                let username = m.get_one::<String>("username").unwrap();
+                client.revoke_tokens(username).await?;
+                println!("Tokens of {} revoked", username);
+            }
+            _ => unreachable!(),
+        },
+        Some(("audit", m)) => match m.subcommand() {
+            Some(("query", m)) => {
+                let entries = client.list_audit(&audit_filter(m)?).await?;
+                print_table(
+                    &entries,
+                    &["time", "kind", "device_id", "conn_id", "peer_id", "action"],
+                );
+            }
+            Some(("export", m)) => {
+                let entries = client.list_audit(&audit_filter(m)?).await?;
+                let output = m.get_one::<String>("output").unwrap();
+                export_audit(&entries, m.get_one::<String>("format").unwrap(), output)?;
+                println!("{} entries written to {}", entries.len(), output);
+            }
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let matches = cli().get_matches();
+    if let Err(e) = run(matches).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000"), Ok(1700000000));
+        assert_eq!(parse_time("2024-01-02"), Ok(1704153600));
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("{\"x\":1}"), "\"{\"\"x\"\":1}\"");
+    }
+
+    #[test]
+    fn test_cli() {
+        cli().debug_assert();
+    }
+}
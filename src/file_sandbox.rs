@@ -0,0 +1,432 @@
+/// File transfer sandbox for the controlled side.
+///
+/// Confines file transfer to the directories of the
+/// `file-transfer-allowed-roots` option, optionally read-only and with size
+/// and extension limits. Paths are resolved (`..`, `.` and symlinks) before
+/// they are compared with the canonical roots, so neither relative
+/// components nor links lead out of a root.
+///
+/// Checked twice: by `Connection` on every `FileAction`, and by the
+/// connection manager before it touches the filesystem for `ipc::FS`.
+use crate::ipc;
+use hbb_common::{
+    bail,
+    config::{keys, Config},
+    fs,
+    log,
+    message_proto::*,
+    ResultType,
+};
+use std::path::{Component, Path, PathBuf};
+
+pub const ACCESS_DENIED: &str = "Access denied by the file transfer sandbox";
+pub const READ_ONLY: &str = "File transfer is read-only";
+
+#[derive(Debug, Default, Clone)]
+pub struct FileSandbox {
+    // Canonical, empty for no restriction
+    roots: Vec<PathBuf>,
+    read_only: bool,
+    // In bytes, 0 for no limit
+    max_file_size: u64,
+    // Lowercase, without dot
+    allowed_extensions: Vec<String>,
+    blocked_extensions: Vec<String>,
+}
+
+fn split(v: &str, sep: char) -> Vec<String> {
+    v.split(sep)
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+fn extensions(v: &str) -> Vec<String> {
+    split(v, ',')
+        .into_iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .collect()
+}
+
+impl FileSandbox {
+    pub fn load() -> Self {
+        let max_size: u64 = Config::get_option(keys::OPTION_FILE_TRANSFER_MAX_SIZE)
+            .trim()
+            .parse()
+            .unwrap_or(0);
+        Self::new(
+            &Config::get_option(keys::OPTION_FILE_TRANSFER_ALLOWED_ROOTS),
+            Config::get_option(keys::OPTION_FILE_TRANSFER_READ_ONLY) == "Y",
+            max_size * 1024 * 1024,
+            &Config::get_option(keys::OPTION_FILE_TRANSFER_ALLOWED_EXTENSIONS),
+            &Config::get_option(keys::OPTION_FILE_TRANSFER_BLOCKED_EXTENSIONS),
+        )
+    }
+
+    pub fn new(
+        roots: &str,
+        read_only: bool,
+        max_file_size: u64,
+        allowed_extensions: &str,
+        blocked_extensions: &str,
+    ) -> Self {
+        let configured = split(roots, ';');
+        let mut roots = Vec::new();
+        for root in configured.iter() {
+            let path = match root.strip_prefix('~') {
+                Some(rest) => Config::get_home().join(rest.trim_start_matches(['/', '\\'])),
+                None => PathBuf::from(root),
+            };
+            match path.canonicalize() {
+                Ok(p) => roots.push(p),
+                Err(e) => log::warn!("Ignore file transfer root {}: {}", root, e),
+            }
+        }
+        if !configured.is_empty() && roots.is_empty() {
+            // Configured but none exists: allow nothing rather than everything
+            roots.push(PathBuf::new());
+        }
+        Self {
+            roots,
+            read_only,
+            max_file_size,
+            allowed_extensions: extensions(allowed_extensions),
+            blocked_extensions: extensions(blocked_extensions),
+        }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        !self.roots.is_empty()
+            || self.read_only
+            || self.max_file_size > 0
+            || !self.allowed_extensions.is_empty()
+            || !self.blocked_extensions.is_empty()
+    }
+
+    // `path` with `..`, `.` and symlinks resolved. The part which doesn't
+    // exist yet must consist of plain names.
+    fn resolve(path: &Path) -> ResultType<PathBuf> {
+        if !path.is_absolute() {
+            bail!(ACCESS_DENIED);
+        }
+        let mut existing = path.to_path_buf();
+        let mut rest = Vec::new();
+        loop {
+            if let Ok(mut p) = existing.canonicalize() {
+                for name in rest.iter().rev() {
+                    p.push(name);
+                }
+                return Ok(p);
+            }
+            match existing.components().next_back() {
+                Some(Component::Normal(name)) => rest.push(name.to_owned()),
+                _ => bail!(ACCESS_DENIED),
+            }
+            if !existing.pop() {
+                bail!(ACCESS_DENIED);
+            }
+        }
+    }
+
+    fn within_roots(&self, path: &Path) -> bool {
+        self.roots
+            .iter()
+            .any(|r| !r.as_os_str().is_empty() && path.starts_with(r))
+    }
+
+    pub fn check_read(&self, path: &Path) -> ResultType<()> {
+        if self.roots.is_empty() {
+            return Ok(());
+        }
+        if !self.within_roots(&Self::resolve(path)?) {
+            bail!(ACCESS_DENIED);
+        }
+        Ok(())
+    }
+
+    pub fn check_write(&self, path: &Path) -> ResultType<()> {
+        if self.read_only {
+            bail!(READ_ONLY);
+        }
+        self.check_read(path)
+    }
+
+    pub fn check_extension(&self, name: &str) -> ResultType<()> {
+        let ext = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if (!self.allowed_extensions.is_empty() && !self.allowed_extensions.contains(&ext))
+            || self.blocked_extensions.contains(&ext)
+        {
+            bail!("File type not allowed: {}", name);
+        }
+        Ok(())
+    }
+
+    pub fn check_file(&self, name: &str, size: u64) -> ResultType<()> {
+        if self.max_file_size > 0 && size > self.max_file_size {
+            bail!("File too large: {}", name);
+        }
+        self.check_extension(name)
+    }
+
+    /// Files of a job below `base`, `write` for the receiving side.
+    pub fn check_files(&self, base: &Path, files: &[FileEntry], write: bool) -> ResultType<()> {
+        if !self.is_restricted() {
+            return Ok(());
+        }
+        let base = base.to_path_buf();
+        for f in files {
+            let path = fs::TransferJob::join(&base, &f.name);
+            if write {
+                self.check_write(&path)?;
+            } else {
+                self.check_read(&path)?;
+            }
+            self.check_file(&f.name, f.size)?;
+        }
+        Ok(())
+    }
+
+    /// For reading a directory: `None` if allowed, the names of the entries
+    /// leading to roots if it's a parent of roots.
+    pub fn check_read_dir(&self, path: &Path) -> ResultType<Option<Vec<String>>> {
+        if self.roots.is_empty() {
+            return Ok(None);
+        }
+        let path = Self::resolve(path)?;
+        if self.within_roots(&path) {
+            return Ok(None);
+        }
+        let mut names: Vec<String> = self
+            .roots
+            .iter()
+            .filter_map(|r| r.strip_prefix(&path).ok()?.components().next())
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        if names.is_empty() {
+            bail!(ACCESS_DENIED);
+        }
+        names.sort();
+        names.dedup();
+        Ok(Some(names))
+    }
+
+    pub fn check_action(&self, action: &file_action::Union) -> ResultType<()> {
+        use file_action::Union;
+        if !self.is_restricted() {
+            return Ok(());
+        }
+        let p = fs::get_path;
+        match action {
+            Union::ReadEmptyDirs(rd) => self.check_read(&p(&rd.path)),
+            Union::AllFiles(f) => self.check_read(&p(&f.path)),
+            Union::Send(s) => {
+                if fs::JobType::from_proto(s.file_type) == fs::JobType::Printer {
+                    return Ok(());
+                }
+                self.check_read(&p(&s.path))
+            }
+            Union::Receive(r) => {
+                self.check_write(&p(&r.path))?;
+                self.check_files(&p(&r.path), &r.files, true)
+            }
+            Union::RemoveDir(d) => self.check_write(&p(&d.path)),
+            Union::RemoveFile(f) => self.check_write(&p(&f.path)),
+            Union::Create(c) => self.check_write(&p(&c.path)),
+            Union::Rename(r) => self.check_rename(&r.path, &r.new_name),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_rename(&self, path: &str, new_name: &str) -> ResultType<()> {
+        let path = fs::get_path(path);
+        self.check_write(&path)?;
+        let parent = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        self.check_write(&parent.join(new_name))?;
+        self.check_extension(new_name)
+    }
+
+    /// For the connection manager, which writes on behalf of the connection.
+    pub fn check_fs(&self, fs: &ipc::FS) -> ResultType<()> {
+        if !self.is_restricted() {
+            return Ok(());
+        }
+        let p = fs::get_path;
+        match fs {
+            ipc::FS::ReadEmptyDirs { dir, .. } => self.check_read(&p(dir)),
+            ipc::FS::RemoveDir { path, .. }
+            | ipc::FS::RemoveFile { path, .. }
+            | ipc::FS::CreateDir { path, .. } => self.check_write(&p(path)),
+            ipc::FS::Rename { path, new_name, .. } => self.check_rename(path, new_name),
+            ipc::FS::NewWrite { path, files, .. } => {
+                let base = p(path);
+                self.check_write(&base)?;
+                for (name, _) in files {
+                    self.check_write(&fs::TransferJob::join(&base, name))?;
+                    self.check_extension(name)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Job id and file number to report an error of a file action with.
+pub fn action_job(action: &file_action::Union) -> (i32, i32) {
+    use file_action::Union;
+    match action {
+        Union::ReadEmptyDirs(_) | Union::ReadDir(_) => (0, -1),
+        Union::AllFiles(f) => (f.id, -1),
+        Union::Send(s) => (s.id, 0),
+        Union::Receive(r) => (r.id, 0),
+        Union::RemoveDir(d) => (d.id, 0),
+        Union::RemoveFile(f) => (f.id, f.file_num),
+        Union::Create(c) => (c.id, 0),
+        Union::Rename(r) => (r.id, 0),
+        _ => (0, 0),
+    }
+}
+
+/// Job id and file number to report an error of an `ipc::FS` request with,
+/// `None` if it has no job.
+pub fn fs_job(fs: &ipc::FS) -> Option<(i32, i32)> {
+    match fs {
+        ipc::FS::RemoveDir { id, .. }
+        | ipc::FS::CreateDir { id, .. }
+        | ipc::FS::Rename { id, .. }
+        | ipc::FS::NewWrite { id, .. } => Some((*id, 0)),
+        ipc::FS::RemoveFile { id, file_num, .. } => Some((*id, *file_num)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dirs {
+        tmp: PathBuf,
+        root: PathBuf,
+        outside: PathBuf,
+    }
+
+    fn setup(name: &str) -> Dirs {
+        let tmp = std::env::temp_dir().join(format!("file_sandbox_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&tmp).unwrap();
+        let tmp = tmp.canonicalize().unwrap();
+        let root = tmp.join("root");
+        let outside = tmp.join("outside");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("docs").join("a.txt"), "a").unwrap();
+        std::fs::write(outside.join("secret.txt"), "s").unwrap();
+        Dirs { tmp, root, outside }
+    }
+
+    fn s(p: &Path) -> String {
+        p.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_traversal() {
+        let d = setup("traversal");
+        let sandbox = FileSandbox::new(&s(&d.root), false, 0, "", "");
+        assert!(sandbox.check_read(&d.root.join("docs").join("a.txt")).is_ok());
+        assert!(sandbox.check_write(&d.root.join("new").join("b.txt")).is_ok());
+        assert!(sandbox.check_read(&d.outside.join("secret.txt")).is_err());
+        // `..` through existing and not yet existing directories
+        assert!(sandbox
+            .check_read(&d.root.join("..").join("outside").join("secret.txt"))
+            .is_err());
+        assert!(sandbox
+            .check_write(&d.root.join("new").join("..").join("..").join("outside").join("x"))
+            .is_err());
+        assert!(sandbox.check_read(Path::new("relative/path")).is_err());
+        // A sibling sharing the root's name as prefix
+        std::fs::create_dir_all(d.tmp.join("root2")).unwrap();
+        assert!(sandbox.check_read(&d.tmp.join("root2")).is_err());
+
+        let rename = file_action::Union::Rename(FileRename {
+            path: s(&d.root.join("docs").join("a.txt")),
+            new_name: "../../outside/a.txt".to_owned(),
+            ..Default::default()
+        });
+        assert!(sandbox.check_action(&rename).is_err());
+        let receive = file_action::Union::Receive(FileTransferReceiveRequest {
+            path: s(&d.root),
+            files: vec![FileEntry {
+                name: "../outside/evil.txt".to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert!(sandbox.check_action(&receive).is_err());
+        let new_write = ipc::FS::NewWrite {
+            path: s(&d.root),
+            id: 1,
+            file_num: 0,
+            files: vec![("docs/../../outside/evil.txt".to_owned(), 0)],
+            overwrite_detection: false,
+            total_size: 0,
+            conn_id: 0,
+        };
+        assert!(sandbox.check_fs(&new_write).is_err());
+        assert_eq!(fs_job(&new_write), Some((1, 0)));
+        std::fs::remove_dir_all(&d.tmp).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape() {
+        let d = setup("symlink");
+        std::os::unix::fs::symlink(&d.outside, d.root.join("link")).unwrap();
+        let sandbox = FileSandbox::new(&s(&d.root), false, 0, "", "");
+        assert!(sandbox.check_read(&d.root.join("link").join("secret.txt")).is_err());
+        assert!(sandbox.check_write(&d.root.join("link").join("new.txt")).is_err());
+        let send = file_action::Union::Send(FileTransferSendRequest {
+            path: s(&d.root.join("link")),
+            ..Default::default()
+        });
+        assert!(sandbox.check_action(&send).is_err());
+        std::fs::remove_dir_all(&d.tmp).ok();
+    }
+
+    #[test]
+    fn test_parent_listing_and_limits() {
+        let d = setup("limits");
+        let sandbox = FileSandbox::new(&s(&d.root.join("docs")), true, 10, "txt, .PDF", "");
+        assert_eq!(
+            sandbox.check_read_dir(&d.tmp).unwrap(),
+            Some(vec!["root".to_owned()])
+        );
+        assert_eq!(sandbox.check_read_dir(&d.root.join("docs")).unwrap(), None);
+        assert!(sandbox.check_read_dir(&d.outside).is_err());
+
+        // Read-only
+        assert!(sandbox.check_read(&d.root.join("docs").join("a.txt")).is_ok());
+        assert_eq!(
+            sandbox
+                .check_write(&d.root.join("docs").join("b.txt"))
+                .unwrap_err()
+                .to_string(),
+            READ_ONLY
+        );
+
+        assert!(sandbox.check_file("a.txt", 10).is_ok());
+        assert!(sandbox.check_file("a.pdf", 1).is_ok());
+        assert!(sandbox.check_file("a.txt", 11).is_err());
+        assert!(sandbox.check_file("a.exe", 1).is_err());
+        let blocked = FileSandbox::new("", false, 0, "", "exe,bat");
+        assert!(blocked.check_file("x.EXE", 1).is_err());
+        assert!(blocked.check_file("x.txt", 1).is_ok());
+        assert!(!FileSandbox::new("", false, 0, "", "").is_restricted());
+        // Roots which don't exist allow nothing
+        let missing = FileSandbox::new(&s(&d.tmp.join("missing")), false, 0, "", "");
+        assert!(missing.check_read(&d.root).is_err());
+        std::fs::remove_dir_all(&d.tmp).ok();
+    }
+}
@@ -761,6 +761,12 @@ pub fn session_cancel_job(session_id: SessionID, act_id: i32) {
     }
 }
 
+pub fn session_schedule_job(session_id: SessionID, act_id: i32, paused: bool, rate_limit: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.schedule_job(act_id, paused, rate_limit);
+    }
+}
+
 pub fn session_create_dir(session_id: SessionID, act_id: i32, path: String, is_remote: bool) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.create_dir(act_id, path, is_remote);
@@ -1415,6 +1421,14 @@ pub fn main_video_save_directory(root: bool) -> SyncReturn<String> {
     SyncReturn(video_save_directory(root))
 }
 
+pub fn main_list_recordings(root: bool) -> String {
+    list_recordings(root)
+}
+
+pub fn main_delete_recording(root: bool, path: String) -> String {
+    delete_recording(root, path)
+}
+
 pub fn main_set_user_default_option(key: String, value: String) {
     set_user_default_option(key, value);
 }
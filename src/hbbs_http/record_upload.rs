@@ -164,6 +164,8 @@ impl RecordUploader {
                 }
                 Err(e) => bail!(e.to_string()),
             },
+            // Encrypted recordings of the hardware muxer are only created when finished
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
             Err(e) => bail!(e.to_string()),
         }
     }
@@ -83,6 +83,9 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        delta_copy_blocks: Vec<u32>,
+        delta_block_size: u32,
+        content_hash: Vec<u8>,
     },
     WriteDone {
         id: i32,
@@ -105,6 +108,8 @@ pub enum FS {
         last_modified: u64,
         is_upload: bool,
         is_resume: bool,
+        supports_content_hash: bool,
+        content_hash: Vec<u8>,
     },
     SendConfirm(Vec<u8>),
     Rename {
@@ -286,11 +291,14 @@ pub enum Data {
     ControllingSessionCount(usize),
     #[cfg(target_os = "linux")]
     TerminalSessionCount(usize),
-    #[cfg(target_os = "windows")]
     PortForwardSessionCount(Option<usize>),
+    // Names and states of the profiles of the port forward service
+    PortForwardProfileStatus(Option<Vec<(String, String)>>),
     SocksWs(Option<Box<(Option<config::Socks5Server>, String)>>),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Whiteboard((String, crate::whiteboard::CustomEvent)),
+    // Recording of the controlled side paused for low disk space, or resumed
+    RecordDiskNotice(bool),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -258,5 +258,7 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("websocket_tip", "When using WebSocket, only relay connections are supported."),
         ("terminal-admin-login-tip", "Please input the administrator username and password of the controlled side."),
         ("elevation_username_tip", "Input username or domain\\username"),
+        ("record-paused-low-disk-tip", "Recording is paused, the disk of the recordings is low on space. It resumes once there is enough space."),
+        ("record-resumed-tip", "Recording is resumed."),
     ].iter().cloned().collect();
 }
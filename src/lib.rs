@@ -37,7 +37,7 @@ pub mod flutter;
 pub mod flutter_ffi;
 use common::*;
 mod auth_2fa;
-#[cfg(feature = "cli")]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod cli;
 #[cfg(not(target_os = "ios"))]
 mod clipboard;
@@ -47,6 +47,8 @@ mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod record_tool;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -82,4 +84,8 @@ mod kcp_stream;
 pub mod api_server;
 #[cfg(feature = "simple_permissions")]
 pub mod simple_permissions;
+pub mod api_models;
+#[cfg(not(any(target_os = "ios")))]
+pub mod file_sandbox;
 pub mod api_client;
+pub mod api_client_admin;
@@ -43,8 +43,24 @@ fn main() {
     }
     use clap::App;
     use hbb_common::log;
+    let cli_args: Vec<String> = std::env::args().skip(1).collect();
+    if cli_args.first().map(|x| x == "--cli").unwrap_or(false) {
+        let code = cli::run_file_command(&cli_args[1..]);
+        common::global_clean();
+        std::process::exit(code);
+    }
+    if cli_args.first().map(|x| x == "--recording").unwrap_or(false) {
+        std::process::exit(record_tool::run(&cli_args[1..]));
+    }
+    if cli_args
+        .first()
+        .map(|x| x == "--port-forward-profile")
+        .unwrap_or(false)
+    {
+        std::process::exit(cli::run_port_forward_profile_command(&cli_args[1..]));
+    }
     let args = format!(
-        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host], or remote-id:local-port:socks5 for a SOCKS5 proxy'
         -c, --connect=[REMOTE_ID] 'test only'
         -k, --key=[KEY] ''
        -s, --server=[] 'Start server'",
@@ -71,14 +87,18 @@ fn main() {
             return;
         }
         let mut remote_port = 0;
+        // Dynamic forwarding, the remote host is `socks5` with port 0
+        let dynamic = options[2] == "socks5";
         if let Ok(v) = options[2].parse::<i32>() {
             remote_port = v;
-        } else {
+        } else if !dynamic {
             log::error!("Wrong remote-port");
             return;
         }
         let mut remote_host = "localhost".to_owned();
-        if options.len() > 3 {
+        if dynamic {
+            remote_host = options[2].clone();
+        } else if options.len() > 3 {
             remote_host = options[3].clone();
         }
         common::test_rendezvous_server();
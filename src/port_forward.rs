@@ -1,9 +1,13 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, RwLock},
+};
 
 use crate::client::*;
 use hbb_common::{
     allow_err, bail,
-    config::READ_TIMEOUT,
+    bytes::Bytes,
+    config::{keys, LocalConfig, READ_TIMEOUT},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
@@ -12,8 +16,36 @@ use hbb_common::{
     tcp, timeout,
     tokio::{self, net::TcpStream, sync::mpsc},
     tokio_util::codec::{BytesCodec, Framed},
-    ResultType, Stream,
+    ResultType, Stream, TargetAddr,
 };
+use mux::Mux;
+
+pub mod allowlist;
+pub mod mux;
+pub mod socks5;
+pub mod supervisor;
+
+/// The remote host of dynamic forwarding, with remote port 0: the local port
+/// is a SOCKS5 proxy and the controlled side connects to the requested
+/// destinations its allowlist permits.
+pub const DYNAMIC: &str = "socks5";
+
+pub fn is_dynamic(remote_host: &str, remote_port: i32) -> bool {
+    remote_host == DYNAMIC && remote_port == 0
+}
+
+/// The address local ports are forwarded from, loopback unless the option
+/// tells otherwise.
+pub fn bind_address() -> IpAddr {
+    let address = LocalConfig::get_option(keys::OPTION_PORT_FORWARD_BIND_ADDRESS);
+    match address.trim() {
+        "" => Ipv4Addr::LOCALHOST.into(),
+        address => address.parse().unwrap_or_else(|_| {
+            log::warn!("Invalid port forward bind address {:?}", address);
+            Ipv4Addr::LOCALHOST.into()
+        }),
+    }
+}
 
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
@@ -53,38 +85,72 @@ pub async fn listen(
     lc: Arc<RwLock<LoginConfigHandler>>,
     remote_host: String,
     remote_port: i32,
+    bind: Option<IpAddr>,
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let is_rdp = port == 0;
+    // The RDP client connects to localhost
+    let bind = if is_rdp {
+        Ipv4Addr::LOCALHOST.into()
+    } else {
+        bind.unwrap_or_else(bind_address)
+    };
+    let listener = tcp::new_listener(SocketAddr::new(bind, u16::try_from(port)?), true).await?;
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
-    let is_rdp = port == 0;
     if is_rdp {
         run_rdp(addr.port());
     }
+    let dynamic = is_dynamic(&remote_host, remote_port);
     let mut ui_receiver = ui_receiver;
+    // All connections go through one session if the peer supports it
+    let mut tunnel: Option<Stream> = None;
+    let mut mux: Option<Mux> = None;
+    // Connections of dynamic forwarding after the SOCKS5 handshake
+    let (socks_tx, mut socks_rx) = mpsc::unbounded_channel::<(TcpStream, TargetAddr<'static>)>();
     loop {
-        tokio::select! {
-            Ok((forward, addr)) = listener.accept() => {
+        let (forward, host, port, reply) = tokio::select! {
+            Ok((mut forward, addr)) = listener.accept() => {
                 log::info!("new connection from {:?}", addr);
-                lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
-                let id = id.clone();
-                let password = password.clone();
-                let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
-                    Ok(Some(stream)) => {
-                        let interface = interface.clone();
-                        tokio::spawn(async move {
-                            if let Err(err) = run_forward(forward, stream).await {
-                                interface.msgbox("error", "Error", &err.to_string(), "");
+                if !dynamic {
+                    (forward, remote_host.clone(), remote_port, None)
+                } else {
+                    let socks_tx = socks_tx.clone();
+                    tokio::spawn(async move {
+                        match timeout(READ_TIMEOUT, socks5::accept(&mut forward)).await {
+                            Ok(Ok(target)) => {
+                                socks_tx.send((forward, target)).ok();
                             }
-                            log::info!("connection from {:?} closed", addr);
-                       });
-                    }
-                    Err(err) => {
-                        interface.on_establish_connection_error(err.to_string());
+                            Ok(Err(err)) => log::info!("SOCKS5 handshake with {:?} failed: {}", addr, err),
+                            Err(_) => log::info!("SOCKS5 handshake with {:?} timed out", addr),
+                        }
+                    });
+                    continue;
+                }
+            }
+            Some((forward, target)) = socks_rx.recv() => {
+                let (host, port) = socks5::host_port(&target);
+                (forward, host, port, Some(socks5::reply as mux::Reply))
+            }
+            res = async { match tunnel.as_mut() { Some(s) => s.next().await, None => std::future::pending().await } } => {
+                let (Some(stream), Some(m)) = (tunnel.as_mut(), mux.as_mut()) else {
+                    continue;
+                };
+                if let Err(err) = handle_tunnel_message(res, stream, m, &interface, dynamic).await {
+                    log::info!("port forwarding session closed: {}", err);
+                    tunnel = None;
+                    mux = None;
+                }
+                continue;
+            }
+            msg = async { match mux.as_mut() { Some(m) => m.next().await, None => std::future::pending().await } } => {
+                if let Some(stream) = tunnel.as_mut() {
+                    if let Err(err) = stream.send(&msg).await {
+                        log::info!("port forwarding session closed: {}", err);
+                        tunnel = None;
+                        mux = None;
                     }
-                    _ => {}
                 }
+                continue;
             }
             d = ui_receiver.recv() => {
                 match d {
@@ -97,22 +163,195 @@ pub async fn listen(
                     }
                     _ => {}
                 }
+                continue;
+            }
+        };
+        if let (Some(stream), Some(mux)) = (tunnel.as_mut(), mux.as_mut()) {
+            let msg = mux.open(forward, vec![], &host, port, reply);
+            allow_err!(stream.send(&msg).await);
+            continue;
+        }
+        let peer = forward.peer_addr().ok();
+        {
+            let mut lc = lc.write().unwrap();
+            // The login is for the listener, each stream names its own
+            // destination, e.g. the ones of SOCKS5
+            lc.port_forward = (remote_host.clone(), remote_port);
+            lc.port_forward_reverse = false;
+        }
+        let id = id.clone();
+        let password = password.clone();
+        let mut forward = Framed::new(forward, BytesCodec::new());
+        match connect_and_login(
+            &id,
+            &password,
+            &mut ui_receiver,
+            interface.clone(),
+            Some(&mut forward),
+            key,
+            token,
+            is_rdp,
+        )
+        .await
+        {
+            Ok(Some((mut stream, mux_version, mut data))) if mux_version > 0 => {
+                log::info!(
+                    "port forwarding session with tunnel version {}",
+                    mux_version
+                );
+                let parts = forward.into_parts();
+                data.extend_from_slice(&parts.read_buf);
+                let mut m = Mux::new(true, mux::refuse_all());
+                let msg = m.open(parts.io, data, &host, port, reply);
+                allow_err!(stream.send(&msg).await);
+                tunnel = Some(stream);
+                mux = Some(m);
+            }
+            Ok(Some((stream, ..))) => {
+                if let Some(reply) = reply {
+                    allow_err!(forward.send(Bytes::from(reply(true))).await);
+                }
+                let interface = interface.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = run_forward(forward, stream).await {
+                        interface.msgbox("error", "Error", &err.to_string(), "");
+                    }
+                    log::info!("connection from {:?} closed", peer);
+                });
+            }
+            res => {
+                if let Some(reply) = reply {
+                    allow_err!(forward.send(Bytes::from(reply(false))).await);
+                }
+                if let Err(err) = res {
+                    interface.on_establish_connection_error(err.to_string());
+                }
             }
         }
     }
     Ok(())
 }
 
+/// Reverse forwarding: the peer listens on `remote_port` of its loopback and
+/// the connections are forwarded to `local_host:local_port` of this side.
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    remote_port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    local_host: String,
+    local_port: i32,
+) -> ResultType<()> {
+    let local_port = u16::try_from(local_port)?;
+    {
+        let mut lc = lc.write().unwrap();
+        lc.port_forward = ("".to_owned(), remote_port);
+        lc.port_forward_reverse = true;
+    }
+    let mut ui_receiver = ui_receiver;
+    let Some((mut stream, mux_version, _)) = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+    if mux_version < 2 {
+        bail!("The remote side doesn't support reverse port forwarding");
+    }
+    log::info!(
+        "reverse port forwarding of remote port {} to {}:{}",
+        remote_port,
+        local_host,
+        local_port
+    );
+    let mut mux = Mux::new(true, reverse_connector(remote_port, local_host, local_port));
+    loop {
+        tokio::select! {
+            res = stream.next() => {
+                handle_tunnel_message(res, &mut stream, &mut mux, &interface, false).await?;
+            }
+            msg = mux.next() => {
+                stream.send(&msg).await?;
+            }
+            d = ui_receiver.recv() => {
+                if let Some(Data::Close) | None = d {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Only the streams of the reverse forward are connected
+fn reverse_connector(remote_port: i32, local_host: String, local_port: u16) -> mux::Connector {
+    Arc::new(move |_, port| {
+        let local_host = local_host.clone();
+        Box::pin(async move {
+            if port != remote_port {
+                bail!("Port {} is not forwarded", port);
+            }
+            Ok(TcpStream::connect((local_host.as_str(), local_port)).await?)
+        })
+    })
+}
+
+async fn handle_tunnel_message(
+    res: Option<Result<hbb_common::bytes::BytesMut, std::io::Error>>,
+    stream: &mut Stream,
+    mux: &mut Mux,
+    interface: &impl Interface,
+    dynamic: bool,
+) -> ResultType<()> {
+    let bytes = match res {
+        Some(Ok(bytes)) => bytes,
+        Some(Err(err)) => bail!("Connection closed: {}", err),
+        None => bail!("Reset by the peer"),
+    };
+    let msg_in = Message::parse_from_bytes(&bytes)?;
+    match msg_in.union {
+        Some(message::Union::Tunnel(tm)) => match mux.handle(tm) {
+            Ok(Some(msg)) => stream.send(&msg).await?,
+            Ok(None) => {}
+            // Browsers try many destinations, a refused one isn't worth a message box
+            Err(err) if dynamic => log::info!("SOCKS5 connection refused: {}", err),
+            Err(err) => interface.msgbox("error", "Error", &err.to_string(), ""),
+        },
+        Some(message::Union::Misc(misc)) => {
+            if let Some(misc::Union::CloseReason(reason)) = misc.union {
+                bail!("Closed by the peer: {}", reason);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns the session, the version of its tunnel messages and the data read
+/// from `forward` meanwhile. Without tunnel messages the session is a raw
+/// stream to the target the data was sent to.
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
-) -> ResultType<Option<Stream>> {
+) -> ResultType<Option<(Stream, u32, Vec<u8>)>> {
     let conn_type = if is_rdp {
         ConnType::RDP
     } else {
@@ -123,6 +362,7 @@ async fn connect_and_login(
     interface.update_direct(Some(direct));
     let mut buffer = Vec::new();
     let mut received = false;
+    let mut mux_version = 0;
 
     let _keep_it = hc_connection(feedback, rendezvous_server, token).await;
 
@@ -149,6 +389,7 @@ async fn connect_and_login(
                                 }
                             }
                             Some(login_response::Union::PeerInfo(pi)) => {
+                                mux_version = pi.features.port_forward_mux.min(mux::MUX_VERSION);
                                 interface.handle_peer_info(pi);
                                 break;
                             }
@@ -175,10 +416,14 @@ async fn connect_and_login(
                     Some(Data::Message(msg)) => {
                         allow_err!(stream.send(&msg).await);
                     }
+                    Some(Data::Close) => {
+                        return Ok(None);
+                    }
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            // Well within the window the first data of a tunnel may fill
+            res = async { match forward.as_mut() { Some(f) => f.next().await, None => std::future::pending().await } }, if buffer.len() < mux::WINDOW as usize / 2 => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -187,11 +432,14 @@ async fn connect_and_login(
             },
         }
     }
+    if mux_version > 0 {
+        return Ok(Some((stream, mux_version, buffer)));
+    }
     stream.set_raw();
     if !buffer.is_empty() {
         allow_err!(stream.send_bytes(buffer.into()).await);
     }
-    Ok(Some(stream))
+    Ok(Some((stream, 0, vec![])))
 }
 
 async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
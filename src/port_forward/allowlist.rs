@@ -0,0 +1,328 @@
+//! Destinations the controlled side lets port forwarding connect to.
+//!
+//! Entries are separated by `;` or new lines. An entry is a destination and
+//! optionally `:` and its ports, e.g. `192.168.1.0/24:80,443`,
+//! `[fd00::/64]:8000-8080`, `nas.lan`, `*.home.arpa:443` or `*:22`. An IPv6
+//! destination with ports is put in brackets. A name is allowed if it matches
+//! a name entry, else the addresses it resolves to are checked against the
+//! networks. An empty allowlist allows nothing, only the approved target of
+//! a fixed forward is then connected to.
+use hbb_common::{
+    bail,
+    config::{keys, Config},
+    log,
+    tokio::net::lookup_host,
+    ResultType,
+};
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Destination {
+    Any,
+    Network(IpAddr, u8),
+    // Lower case, a leading `*.` matches any subdomain
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    destination: Destination,
+    // Inclusive ranges, empty for any port
+    ports: Vec<(u16, u16)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    entries: Vec<Entry>,
+}
+
+impl Allowlist {
+    /// The allowlist of the options.
+    pub fn load() -> Self {
+        Self::parse(&Config::get_option(keys::OPTION_PORT_FORWARD_ALLOWLIST))
+    }
+
+    /// Entries that can't be parsed are skipped with a warning.
+    pub fn parse(s: &str) -> Self {
+        let mut allowlist = Self::default();
+        for line in s.split([';', '\n']) {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse_entry(line) {
+                Some(entry) => allowlist.entries.push(entry),
+                None => log::warn!("Invalid port forward allowlist entry {:?}", line),
+            }
+        }
+        allowlist
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The addresses of `host:port`, the approved target of a fixed forward,
+    /// only checked if there is an allowlist.
+    pub async fn resolve_target(&self, host: &str, port: i32) -> ResultType<Vec<SocketAddr>> {
+        if !self.is_empty() {
+            return self.resolve(host, port).await;
+        }
+        let Ok(port) = u16::try_from(port) else {
+            bail!("Invalid port {}", port);
+        };
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        match lookup_host((host, port)).await {
+            Ok(addrs) => Ok(addrs.collect()),
+            Err(err) => bail!("Failed to resolve {}: {}", host, err),
+        }
+    }
+
+    /// The addresses `host:port` may be connected to, an error if none is
+    /// allowed.
+    pub async fn resolve(&self, host: &str, port: i32) -> ResultType<Vec<SocketAddr>> {
+        let Ok(port) = u16::try_from(port) else {
+            bail!("Invalid port {}", port);
+        };
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            if !self.allows_ip(ip, port) {
+                bail!("Not allowed to connect to {}", SocketAddr::new(ip, port));
+            }
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let addrs: Vec<SocketAddr> = match lookup_host((host, port)).await {
+            Ok(addrs) => addrs.collect(),
+            Err(err) => bail!("Failed to resolve {}: {}", host, err),
+        };
+        if self.allows_name(host, port) {
+            return Ok(addrs);
+        }
+        let addrs: Vec<SocketAddr> = addrs
+            .into_iter()
+            .filter(|a| self.allows_ip(a.ip(), port))
+            .collect();
+        if addrs.is_empty() {
+            bail!("Not allowed to connect to {}:{}", host, port);
+        }
+        Ok(addrs)
+    }
+
+    fn allows_ip(&self, ip: IpAddr, port: u16) -> bool {
+        let ip = canonical(ip);
+        self.entries.iter().any(|e| {
+            allows_port(&e.ports, port)
+                && match e.destination {
+                    Destination::Any => true,
+                    Destination::Network(network, prefix) => in_network(ip, network, prefix),
+                    Destination::Name(_) => false,
+                }
+        })
+    }
+
+    fn allows_name(&self, name: &str, port: u16) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        self.entries.iter().any(|e| {
+            allows_port(&e.ports, port)
+                && match &e.destination {
+                    Destination::Any => true,
+                    Destination::Network(..) => false,
+                    Destination::Name(pattern) => match pattern.strip_prefix("*.") {
+                        Some(suffix) => name
+                            .strip_suffix(suffix)
+                            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                        None => *pattern == name,
+                    },
+                }
+        })
+    }
+}
+
+fn parse_entry(s: &str) -> Option<Entry> {
+    let (destination, ports) = if let Some(rest) = s.strip_prefix('[') {
+        let (destination, rest) = rest.split_once(']')?;
+        match rest {
+            "" => (destination, None),
+            _ => (destination, Some(rest.strip_prefix(':')?)),
+        }
+    } else if s.matches(':').count() > 1 {
+        // IPv6 without ports
+        (s, None)
+    } else {
+        match s.split_once(':') {
+            Some((destination, ports)) => (destination, Some(ports)),
+            None => (s, None),
+        }
+    };
+    Some(Entry {
+        destination: parse_destination(destination.trim())?,
+        ports: match ports.map(|p| p.trim()) {
+            None | Some("*") => vec![],
+            Some(ports) => ports
+                .split(',')
+                .map(|p| parse_ports(p.trim()))
+                .collect::<Option<_>>()?,
+        },
+    })
+}
+
+fn parse_destination(s: &str) -> Option<Destination> {
+    if s == "*" {
+        return Some(Destination::Any);
+    }
+    if let Some((ip, prefix)) = s.split_once('/') {
+        let ip = canonical(ip.parse().ok()?);
+        let prefix = prefix.parse().ok()?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        return (prefix <= max).then_some(Destination::Network(ip, prefix));
+    }
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        let ip = canonical(ip);
+        return Some(Destination::Network(
+            ip,
+            if ip.is_ipv4() { 32 } else { 128 },
+        ));
+    }
+    let name = s.trim_end_matches('.').to_lowercase();
+    let valid = |label: &str| {
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let labels = name.strip_prefix("*.").unwrap_or(&name);
+    labels
+        .split('.')
+        .all(valid)
+        .then_some(Destination::Name(name))
+}
+
+fn parse_ports(s: &str) -> Option<(u16, u16)> {
+    let (from, to) = match s.split_once('-') {
+        Some((from, to)) => (from.trim().parse().ok()?, to.trim().parse().ok()?),
+        None => {
+            let port = s.parse().ok()?;
+            (port, port)
+        }
+    };
+    (from <= to).then_some((from, to))
+}
+
+fn allows_port(ports: &[(u16, u16)], port: u16) -> bool {
+    ports.is_empty() || ports.iter().any(|(from, to)| (*from..=*to).contains(&port))
+}
+
+// IPv4 mapped IPv6 addresses are matched as IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::tokio;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let allowlist = Allowlist::parse(
+            "192.168.1.0/24:80,443; [fd00::/64]:8000-8080\nnas.lan;  ; *:22; fe80::1; bad/99; a:b",
+        );
+        assert_eq!(
+            allowlist.entries,
+            vec![
+                Entry {
+                    destination: Destination::Network(ip("192.168.1.0"), 24),
+                    ports: vec![(80, 80), (443, 443)],
+                },
+                Entry {
+                    destination: Destination::Network(ip("fd00::"), 64),
+                    ports: vec![(8000, 8080)],
+                },
+                Entry {
+                    destination: Destination::Name("nas.lan".to_owned()),
+                    ports: vec![],
+                },
+                Entry {
+                    destination: Destination::Any,
+                    ports: vec![(22, 22)],
+                },
+                Entry {
+                    destination: Destination::Network(ip("fe80::1"), 128),
+                    ports: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allows() {
+        let allowlist = Allowlist::parse(
+            "192.168.1.0/24:80,443; [fd00::/64]:8000-8080; NAS.lan.; *.home.arpa:443; 10.0.0.1",
+        );
+        assert!(allowlist.allows_ip(ip("192.168.1.1"), 80));
+        assert!(allowlist.allows_ip(ip("::ffff:192.168.1.254"), 443));
+        assert!(!allowlist.allows_ip(ip("192.168.1.1"), 22));
+        assert!(!allowlist.allows_ip(ip("192.168.2.1"), 80));
+        assert!(allowlist.allows_ip(ip("fd00::abcd"), 8080));
+        assert!(!allowlist.allows_ip(ip("fd00:1::abcd"), 8080));
+        assert!(allowlist.allows_ip(ip("10.0.0.1"), 1));
+        assert!(!allowlist.allows_ip(ip("10.0.0.2"), 1));
+        assert!(allowlist.allows_name("nas.LAN", 5000));
+        assert!(allowlist.allows_name("printer.home.arpa", 443));
+        assert!(!allowlist.allows_name("home.arpa", 443));
+        assert!(!allowlist.allows_name("evilhome.arpa", 443));
+        assert!(!allowlist.allows_name("printer.home.arpa", 80));
+        assert!(!allowlist.allows_name("router.lan", 80));
+        assert!(!Allowlist::default().allows_ip(ip("8.8.8.8"), 53));
+        assert!(!Allowlist::default().allows_name("example.com", 443));
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let allowlist = Allowlist::parse("127.0.0.0/8:80; [::1]:80");
+        assert_eq!(
+            allowlist.resolve("[::1]", 80).await.unwrap(),
+            vec!["[::1]:80".parse().unwrap()]
+        );
+        assert!(allowlist.resolve("127.0.0.1", 81).await.is_err());
+        assert!(allowlist.resolve("127.0.0.1", 70000).await.is_err());
+        assert!(allowlist.resolve("10.0.0.1", 80).await.is_err());
+        let addrs = allowlist.resolve("localhost", 80).await.unwrap();
+        assert!(addrs.iter().all(|a| a.ip().is_loopback()));
+        assert!(allowlist.resolve_target("10.0.0.1", 80).await.is_err());
+        // Without an allowlist only approved targets are connected to
+        let allowlist = Allowlist::default();
+        assert!(allowlist.resolve("127.0.0.1", 80).await.is_err());
+        assert_eq!(
+            allowlist.resolve_target("127.0.0.1", 80).await.unwrap(),
+            vec!["127.0.0.1:80".parse().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,850 @@
+//! Many TCP streams over one port forwarding session.
+//!
+//! A stream is opened with a [`TunnelOpen`] by either side, the controlling
+//! side uses odd ids and the controlled side even ones, and the other side
+//! connects to the target. Each direction has a window of [`WINDOW`] bytes:
+//! the socket is only read while the peer has acknowledged enough of the
+//! data sent before, so a stalled stream doesn't hold back the others. A
+//! [`TunnelEof`] half-closes a stream, a [`TunnelClose`] resets it.
+use super::allowlist::Allowlist;
+use hbb_common::{
+    bail,
+    bytes::Bytes,
+    futures::future::BoxFuture,
+    log,
+    message_proto::*,
+    timeout,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpListener, TcpStream,
+        },
+        sync::{mpsc, Semaphore},
+        task::JoinHandle,
+    },
+    ResultType,
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Highest version of [`TunnelMessage`] spoken here, 2 adds reverse
+/// forwarding.
+pub const MUX_VERSION: u32 = 2;
+/// Bytes of a stream that may be unacknowledged in each direction.
+pub const WINDOW: u32 = 256 * 1024;
+const CHUNK: usize = 16 * 1024;
+const CONNECT_TIMEOUT: u64 = 3_000;
+const MAX_STREAMS: usize = 256;
+
+/// Connects a stream the peer opened, or refuses it with an error.
+pub type Connector =
+    Arc<dyn Fn(String, i32) -> BoxFuture<'static, ResultType<TcpStream>> + Send + Sync>;
+
+/// Written to a socket opened here once the peer connected it or not, before
+/// any data, e.g. a SOCKS5 reply.
+pub type Reply = fn(bool) -> Vec<u8>;
+
+/// Refuses all streams of the peer.
+pub fn refuse_all() -> Connector {
+    Arc::new(|host, port| {
+        Box::pin(async move { bail!("Not allowed to connect to {}:{}", host, port) })
+    })
+}
+
+/// Connects the streams of the peer to `target`, the host and port of the
+/// login, or to any destination of `allowlist` if it's none for dynamic
+/// forwarding.
+pub fn connector(allowlist: Allowlist, target: Option<(String, i32)>) -> Connector {
+    let allowlist = Arc::new(allowlist);
+    Arc::new(move |host, port| {
+        let allowlist = allowlist.clone();
+        let target = target.clone();
+        Box::pin(async move {
+            let (host, port) = match (host.as_str(), port) {
+                ("RDP", 0) => ("localhost".to_owned(), 3389),
+                ("", _) => ("localhost".to_owned(), port),
+                _ => (host, port),
+            };
+            let addrs = match &target {
+                Some((target_host, target_port)) => {
+                    if !target_host.eq_ignore_ascii_case(&host) || *target_port != port {
+                        log::warn!(
+                            "Port forwarding to {}:{} refused, the session is for {}:{}",
+                            host,
+                            port,
+                            target_host,
+                            target_port
+                        );
+                        bail!("Not allowed to connect to {}:{}", host, port);
+                    }
+                    allowlist.resolve_target(&host, port).await
+                }
+                None => allowlist.resolve(&host, port).await,
+            };
+            let addrs = match addrs {
+                Ok(addrs) => addrs,
+                Err(err) => {
+                    log::warn!("Port forwarding to {}:{} refused: {}", host, port, err);
+                    return Err(err);
+                }
+            };
+            Ok(TcpStream::connect(&addrs[..]).await?)
+        })
+    })
+}
+
+enum Event {
+    Accepted(TcpStream, String, i32),
+    Connected(u32, ResultType<TcpStream>),
+    Data(u32, Bytes),
+    Eof(u32),
+    Written(u32, usize),
+    Failed(u32, String),
+}
+
+struct MuxStream {
+    // Data to write to the socket, none to shut it down
+    to_socket: Option<mpsc::UnboundedSender<Option<Bytes>>>,
+    // A permit is a byte that may be sent to the peer
+    credit: Arc<Semaphore>,
+    // Sent and not acknowledged by the peer yet
+    in_flight: u32,
+    // Received and not written yet
+    unwritten: u32,
+    // Written and not acknowledged yet
+    unacked: u32,
+    local_eof: bool,
+    remote_eof: bool,
+    // Opened here, waiting for the peer, with the data read before
+    pending: Option<(TcpStream, Vec<u8>, Option<Reply>)>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Default for MuxStream {
+    fn default() -> Self {
+        Self {
+            to_socket: None,
+            credit: Arc::new(Semaphore::new(0)),
+            in_flight: 0,
+            unwritten: 0,
+            unacked: 0,
+            local_eof: false,
+            remote_eof: false,
+            pending: None,
+            tasks: vec![],
+        }
+    }
+}
+
+impl MuxStream {
+    fn abort(&self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+pub struct Mux {
+    next_id: u32,
+    streams: HashMap<u32, MuxStream>,
+    connector: Connector,
+    listeners: Vec<JoinHandle<()>>,
+    tx: mpsc::UnboundedSender<Event>,
+    rx: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Mux {
+    /// `is_client` is true on the controlling side.
+    pub fn new(is_client: bool, connector: Connector) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            next_id: if is_client { 1 } else { 2 },
+            streams: HashMap::new(),
+            connector,
+            listeners: vec![],
+            tx,
+            rx,
+        }
+    }
+
+    /// Opens a stream of `socket` to `host:port` of the peer, `data` was read
+    /// from the socket before and is sent first.
+    pub fn open(
+        &mut self,
+        socket: TcpStream,
+        data: Vec<u8>,
+        host: &str,
+        port: i32,
+        reply: Option<Reply>,
+    ) -> Message {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(2);
+        self.streams.insert(
+            id,
+            MuxStream {
+                pending: Some((socket, data, reply)),
+                ..Default::default()
+            },
+        );
+        tunnel_message(tunnel_message::Union::Open(TunnelOpen {
+            id,
+            host: host.to_owned(),
+            port,
+            ..Default::default()
+        }))
+    }
+
+    /// Opens a stream to `host:port` of the peer for each connection of
+    /// `listener`.
+    pub fn listen(&mut self, listener: TcpListener, host: String, port: i32) {
+        let tx = self.tx.clone();
+        self.listeners.push(tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, addr)) => {
+                        log::info!("Tunnel connection from {:?} to {}:{}", addr, host, port);
+                        tx.send(Event::Accepted(socket, host.clone(), port)).ok();
+                    }
+                    Err(err) => {
+                        log::error!("Failed to accept tunnel connection: {}", err);
+                        return;
+                    }
+                }
+            }
+        }));
+    }
+
+    /// The next message to send to the peer.
+    pub async fn next(&mut self) -> Message {
+        while let Some(event) = self.rx.recv().await {
+            if let Some(msg) = self.handle_event(event) {
+                return msg;
+            }
+        }
+        // Unreachable, `self` keeps a sender
+        std::future::pending().await
+    }
+
+    fn handle_event(&mut self, event: Event) -> Option<Message> {
+        match event {
+            Event::Accepted(socket, host, port) => {
+                Some(self.open(socket, vec![], &host, port, None))
+            }
+            Event::Connected(id, res) => {
+                if !self.streams.contains_key(&id) {
+                    return None;
+                }
+                let mut opened = TunnelOpened {
+                    id,
+                    ..Default::default()
+                };
+                match res {
+                    Ok(socket) => {
+                        self.start(id, socket, vec![], vec![]);
+                        opened.success = true;
+                    }
+                    Err(err) => {
+                        self.streams.remove(&id);
+                        opened.error = err.to_string();
+                    }
+                }
+                Some(tunnel_message(tunnel_message::Union::Opened(opened)))
+            }
+            Event::Data(id, data) => {
+                let stream = self.streams.get_mut(&id)?;
+                stream.in_flight += data.len() as u32;
+                Some(tunnel_message(tunnel_message::Union::Data(TunnelData {
+                    id,
+                    data,
+                    ..Default::default()
+                })))
+            }
+            Event::Eof(id) => {
+                let stream = self.streams.get_mut(&id)?;
+                stream.local_eof = true;
+                self.remove_if_done(id);
+                Some(tunnel_message(tunnel_message::Union::Eof(TunnelEof {
+                    id,
+                    ..Default::default()
+                })))
+            }
+            Event::Written(id, n) => {
+                let stream = self.streams.get_mut(&id)?;
+                stream.unwritten = stream.unwritten.saturating_sub(n as _);
+                stream.unacked += n as u32;
+                // Acknowledged in batches, the peer can go on while it's under way
+                if stream.unacked < WINDOW / 4 && stream.unwritten > 0 {
+                    return None;
+                }
+                let size = std::mem::take(&mut stream.unacked);
+                Some(tunnel_message(tunnel_message::Union::Ack(TunnelAck {
+                    id,
+                    size,
+                    ..Default::default()
+                })))
+            }
+            Event::Failed(id, error) => {
+                self.streams.remove(&id)?.abort();
+                log::debug!("Tunnel stream {} failed: {}", id, error);
+                Some(close_message(id, error))
+            }
+        }
+    }
+
+    /// Handles a message of the peer, returns the reply. An error is a
+    /// stream of this side the peer refused, the others go on.
+    pub fn handle(&mut self, msg: TunnelMessage) -> ResultType<Option<Message>> {
+        match msg.union {
+            Some(tunnel_message::Union::Open(open)) => {
+                if open.id % 2 == self.next_id % 2 || self.streams.contains_key(&open.id) {
+                    return Ok(Some(close_message(open.id, "Invalid stream id".to_owned())));
+                }
+                if self.streams.len() >= MAX_STREAMS {
+                    return Ok(Some(tunnel_message(tunnel_message::Union::Opened(
+                        TunnelOpened {
+                            id: open.id,
+                            error: "Too many streams".to_owned(),
+                            ..Default::default()
+                        },
+                    ))));
+                }
+                let connect = (self.connector)(open.host, open.port);
+                let tx = self.tx.clone();
+                let id = open.id;
+                let task = tokio::spawn(async move {
+                    let res = match timeout(CONNECT_TIMEOUT, connect).await {
+                        Ok(res) => res,
+                        Err(_) => Err(hbb_common::anyhow::anyhow!("Connection timed out")),
+                    };
+                    tx.send(Event::Connected(id, res)).ok();
+                });
+                self.streams.insert(
+                    id,
+                    MuxStream {
+                        tasks: vec![task],
+                        ..Default::default()
+                    },
+                );
+            }
+            Some(tunnel_message::Union::Opened(opened)) => {
+                let Some(stream) = self.streams.get_mut(&opened.id) else {
+                    return Ok(None);
+                };
+                let Some((mut socket, data, reply)) = stream.pending.take() else {
+                    return Ok(None);
+                };
+                if !opened.success {
+                    self.streams.remove(&opened.id);
+                    if let Some(reply) = reply {
+                        tokio::spawn(async move { socket.write_all(&reply(false)).await.ok() });
+                    }
+                    bail!("{}", opened.error);
+                }
+                let reply = reply.map(|reply| reply(true)).unwrap_or_default();
+                self.start(opened.id, socket, data, reply);
+            }
+            Some(tunnel_message::Union::Data(data)) => {
+                let Some(stream) = self.streams.get_mut(&data.id) else {
+                    return Ok(None);
+                };
+                stream.unwritten += data.data.len() as u32;
+                if stream.unwritten + stream.unacked > WINDOW {
+                    if let Some(stream) = self.streams.remove(&data.id) {
+                        stream.abort();
+                    }
+                    return Ok(Some(close_message(data.id, "Window exceeded".to_owned())));
+                }
+                if let Some(tx) = stream.to_socket.as_ref() {
+                    tx.send(Some(data.data)).ok();
+                }
+            }
+            Some(tunnel_message::Union::Ack(ack)) => {
+                if let Some(stream) = self.streams.get_mut(&ack.id) {
+                    // The window can't grow, too many permits would panic
+                    let size = ack.size.min(stream.in_flight);
+                    stream.in_flight -= size;
+                    stream.credit.add_permits(size as _);
+                }
+            }
+            Some(tunnel_message::Union::Eof(eof)) => {
+                let Some(stream) = self.streams.get_mut(&eof.id) else {
+                    return Ok(None);
+                };
+                stream.remote_eof = true;
+                if let Some(tx) = stream.to_socket.take() {
+                    tx.send(None).ok();
+                }
+                self.remove_if_done(eof.id);
+            }
+            Some(tunnel_message::Union::Close(close)) => {
+                if let Some(stream) = self.streams.remove(&close.id) {
+                    stream.abort();
+                    log::debug!(
+                        "Tunnel stream {} closed by the peer: {}",
+                        close.id,
+                        close.error
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn start(&mut self, id: u32, socket: TcpStream, data: Vec<u8>, reply: Vec<u8>) {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        socket.set_nodelay(true).ok();
+        let (reader, writer) = socket.into_split();
+        let (to_socket, from_mux) = mpsc::unbounded_channel();
+        let credit = Arc::new(Semaphore::new((WINDOW as usize).saturating_sub(data.len())));
+        if !data.is_empty() {
+            self.tx.send(Event::Data(id, data.into())).ok();
+        }
+        stream.to_socket = Some(to_socket);
+        stream.credit = credit.clone();
+        stream.tasks = vec![
+            tokio::spawn(read_socket(id, reader, credit, self.tx.clone())),
+            tokio::spawn(write_socket(id, writer, reply, from_mux, self.tx.clone())),
+        ];
+    }
+
+    fn remove_if_done(&mut self, id: u32) {
+        if let Some(stream) = self.streams.get(&id) {
+            // The writer finishes on its own
+            if stream.local_eof && stream.remote_eof {
+                self.streams.remove(&id);
+            }
+        }
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        for listener in self.listeners.iter() {
+            listener.abort();
+        }
+        for stream in self.streams.values() {
+            stream.abort();
+        }
+    }
+}
+
+fn tunnel_message(union: tunnel_message::Union) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_tunnel(TunnelMessage {
+        union: Some(union),
+        ..Default::default()
+    });
+    msg_out
+}
+
+fn close_message(id: u32, error: String) -> Message {
+    tunnel_message(tunnel_message::Union::Close(TunnelClose {
+        id,
+        error,
+        ..Default::default()
+    }))
+}
+
+async fn read_socket(
+    id: u32,
+    mut reader: OwnedReadHalf,
+    credit: Arc<Semaphore>,
+    tx: mpsc::UnboundedSender<Event>,
+) {
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let Ok(permit) = credit.clone().acquire_many_owned(CHUNK as _).await else {
+            return;
+        };
+        match reader.read(&mut buf).await {
+            Ok(0) => {
+                tx.send(Event::Eof(id)).ok();
+                return;
+            }
+            Ok(n) => {
+                permit.forget();
+                credit.add_permits(CHUNK - n);
+                tx.send(Event::Data(id, Bytes::copy_from_slice(&buf[..n])))
+                    .ok();
+            }
+            Err(err) => {
+                tx.send(Event::Failed(id, err.to_string())).ok();
+                return;
+            }
+        }
+    }
+}
+
+async fn write_socket(
+    id: u32,
+    mut writer: OwnedWriteHalf,
+    reply: Vec<u8>,
+    mut rx: mpsc::UnboundedReceiver<Option<Bytes>>,
+    tx: mpsc::UnboundedSender<Event>,
+) {
+    // Not data of the peer, so not acknowledged
+    if let Err(err) = writer.write_all(&reply).await {
+        tx.send(Event::Failed(id, err.to_string())).ok();
+        return;
+    }
+    while let Some(data) = rx.recv().await {
+        let Some(data) = data else {
+            writer.shutdown().await.ok();
+            return;
+        };
+        if let Err(err) = writer.write_all(&data).await {
+            tx.send(Event::Failed(id, err.to_string())).ok();
+            return;
+        }
+        tx.send(Event::Written(id, data.len())).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Fails a test that hangs
+    async fn within<F: std::future::Future>(future: F) -> F::Output {
+        tokio::time::timeout(Duration::from_secs(10), future)
+            .await
+            .expect("timed out")
+    }
+
+    // Runs `mux` with the peer at the other ends of the channels, opens the
+    // sockets of `opens`, and sends the errors of the streams the peer
+    // refused to `refused`
+    async fn run(
+        mut mux: Mux,
+        mut from_peer: mpsc::UnboundedReceiver<Message>,
+        to_peer: mpsc::UnboundedSender<Message>,
+        mut opens: mpsc::UnboundedReceiver<(TcpStream, String, i32)>,
+        refused: mpsc::UnboundedSender<String>,
+    ) {
+        loop {
+            let msg = tokio::select! {
+                msg = mux.next() => msg,
+                Some((socket, host, port)) = opens.recv() => {
+                    mux.open(socket, vec![], &host, port, None)
+                }
+                msg = from_peer.recv() => {
+                    let Some(message::Union::Tunnel(msg)) = msg.and_then(|m| m.union) else {
+                        return;
+                    };
+                    match mux.handle(msg) {
+                        Ok(Some(msg)) => msg,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            refused.send(err.to_string()).ok();
+                            continue;
+                        }
+                    }
+                }
+            };
+            if to_peer.send(msg).is_err() {
+                return;
+            }
+        }
+    }
+
+    struct Client {
+        opens: mpsc::UnboundedSender<(TcpStream, String, i32)>,
+        refused: mpsc::UnboundedReceiver<String>,
+        // Messages to each side as if sent by the other
+        to_server: mpsc::UnboundedSender<Message>,
+        to_client: mpsc::UnboundedSender<Message>,
+    }
+
+    impl Client {
+        // A loopback socket of a stream to `host:port` of the controlled side
+        async fn open(&self, host: &str, port: u16) -> TcpStream {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let socket = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (accepted, _) = listener.accept().await.unwrap();
+            self.opens
+                .send((accepted, host.to_owned(), port as _))
+                .unwrap();
+            socket
+        }
+    }
+
+    // A session between a controlling and a controlled side connecting with
+    // `connector`
+    fn session(connector: Connector) -> Client {
+        let (to_server, from_client) = mpsc::unbounded_channel();
+        let (to_client, from_server) = mpsc::unbounded_channel();
+        let (opens, client_opens) = mpsc::unbounded_channel();
+        let (_, server_opens) = mpsc::unbounded_channel();
+        let (client_refused, refused) = mpsc::unbounded_channel();
+        let (server_refused, _) = mpsc::unbounded_channel();
+        tokio::spawn(run(
+            Mux::new(true, refuse_all()),
+            from_server,
+            to_server.clone(),
+            client_opens,
+            client_refused,
+        ));
+        tokio::spawn(run(
+            Mux::new(false, connector),
+            from_client,
+            to_client.clone(),
+            server_opens,
+            server_refused,
+        ));
+        Client {
+            opens,
+            refused,
+            to_server,
+            to_client,
+        }
+    }
+
+    // Echoes each connection until its EOF
+    async fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = socket.into_split();
+                    tokio::io::copy(&mut reader, &mut writer).await.ok();
+                    writer.shutdown().await.ok();
+                });
+            }
+        });
+        port
+    }
+
+    // Keeps each connection without reading it, sends it to `accepted`
+    async fn stalled_server(accepted: mpsc::UnboundedSender<TcpStream>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                accepted.send(socket).ok();
+            }
+        });
+        port
+    }
+
+    fn allowed(port: u16) -> Connector {
+        connector(Allowlist::parse(&format!("127.0.0.1:{}", port)), None)
+    }
+
+    fn tunnel(union: tunnel_message::Union) -> TunnelMessage {
+        TunnelMessage {
+            union: Some(union),
+            ..Default::default()
+        }
+    }
+
+    fn open(id: u32, port: u16) -> TunnelMessage {
+        tunnel(tunnel_message::Union::Open(TunnelOpen {
+            id,
+            host: "127.0.0.1".to_owned(),
+            port: port as _,
+            ..Default::default()
+        }))
+    }
+
+    fn close_error(msg: Option<Message>) -> String {
+        match msg.and_then(|m| m.union) {
+            Some(message::Union::Tunnel(TunnelMessage {
+                union: Some(tunnel_message::Union::Close(close)),
+                ..
+            })) => close.error,
+            msg => panic!("not a close: {:?}", msg),
+        }
+    }
+
+    async fn echo(socket: &mut TcpStream, data: &[u8]) {
+        socket.write_all(data).await.unwrap();
+        let mut buf = vec![0; data.len()];
+        within(socket.read_exact(&mut buf)).await.unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[tokio::test]
+    async fn test_data_and_ack() {
+        let port = echo_server().await;
+        let client = session(allowed(port));
+        let socket = client.open("127.0.0.1", port).await;
+        let (mut reader, mut writer) = socket.into_split();
+        // Goes through only as the peer acknowledges it
+        let data: Vec<u8> = (0..WINDOW * 4).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let writing = tokio::spawn(async move { writer.write_all(&sent).await });
+        let mut buf = vec![0; data.len()];
+        within(reader.read_exact(&mut buf)).await.unwrap();
+        assert!(buf == data);
+        within(writing).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_eof() {
+        let port = echo_server().await;
+        let client = session(allowed(port));
+        let mut socket = client.open("127.0.0.1", port).await;
+        socket.write_all(b"bye").await.unwrap();
+        // Half-closed, the echo still comes back, then the EOF of the server
+        socket.shutdown().await.unwrap();
+        let mut buf = vec![];
+        within(socket.read_to_end(&mut buf)).await.unwrap();
+        assert_eq!(buf, b"bye");
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (accepted, mut targets) = mpsc::unbounded_channel();
+        let port = stalled_server(accepted).await;
+        let client = session(allowed(port));
+        let mut socket = client.open("127.0.0.1", port).await;
+        let mut target = within(targets.recv()).await.unwrap();
+        // The first stream of the controlling side
+        client
+            .to_server
+            .send(close_message(1, "".to_owned()))
+            .unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(within(target.read(&mut buf)).await.unwrap(), 0);
+        client
+            .to_client
+            .send(close_message(1, "".to_owned()))
+            .unwrap();
+        assert!(!matches!(within(socket.read(&mut buf)).await, Ok(n) if n > 0));
+    }
+
+    #[tokio::test]
+    async fn test_window_exceeded() {
+        let port = echo_server().await;
+        let mut mux = Mux::new(false, allowed(port));
+        assert!(mux.handle(open(1, port)).unwrap().is_none());
+        // Opened
+        within(mux.next()).await;
+        let data = |len| {
+            tunnel(tunnel_message::Union::Data(TunnelData {
+                id: 1,
+                data: vec![0; len].into(),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(
+            close_error(mux.handle(data(WINDOW as usize + 1)).unwrap()),
+            "Window exceeded"
+        );
+        assert!(mux.streams.is_empty());
+        assert!(mux.handle(data(1)).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_max_streams() {
+        let connector: Connector = Arc::new(|_, _| Box::pin(std::future::pending()));
+        let mut mux = Mux::new(false, connector);
+        // The ids of the controlled side
+        assert_eq!(
+            close_error(mux.handle(open(2, 80)).unwrap()),
+            "Invalid stream id"
+        );
+        for i in 0..MAX_STREAMS as u32 {
+            assert!(mux.handle(open(i * 2 + 1, 80)).unwrap().is_none());
+        }
+        assert_eq!(
+            close_error(mux.handle(open(1, 80)).unwrap()),
+            "Invalid stream id"
+        );
+        let msg = mux.handle(open(MAX_STREAMS as u32 * 2 + 1, 80)).unwrap();
+        match msg.and_then(|m| m.union) {
+            Some(message::Union::Tunnel(TunnelMessage {
+                union: Some(tunnel_message::Union::Opened(opened)),
+                ..
+            })) => {
+                assert!(!opened.success);
+                assert_eq!(opened.error, "Too many streams");
+            }
+            msg => panic!("not opened: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream() {
+        let (accepted, mut targets) = mpsc::unbounded_channel();
+        let stalled = stalled_server(accepted).await;
+        let port = echo_server().await;
+        let connector = connector(
+            Allowlist::parse(&format!("127.0.0.1:{},{}", stalled, port)),
+            None,
+        );
+        let client = session(connector);
+        let mut socket = client.open("127.0.0.1", stalled).await;
+        let _target = within(targets.recv()).await.unwrap();
+        // Far more than the window and the socket buffers
+        let writing = tokio::spawn(async move {
+            socket.write_all(&vec![0; 64 << 20]).await.ok();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!writing.is_finished());
+        let mut other = client.open("127.0.0.1", port).await;
+        echo(&mut other, &vec![1; WINDOW as usize * 2]).await;
+        writing.abort();
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_destinations() {
+        let (router, nas) = (echo_server().await, echo_server().await);
+        let allowlist = Allowlist::parse(&format!("127.0.0.1:{},{}", router, nas));
+        // One dynamic session to two destinations
+        let mut client = session(connector(allowlist, None));
+        let mut to_router = client.open("127.0.0.1", router).await;
+        let mut to_nas = client.open("127.0.0.1", nas).await;
+        echo(&mut to_router, b"router").await;
+        echo(&mut to_nas, b"nas").await;
+        let _ = client.open("127.0.0.1", echo_server().await).await;
+        let refused = within(client.refused.recv()).await.unwrap();
+        assert!(refused.starts_with("Not allowed"), "{}", refused);
+
+        // A session for the router only
+        let allowlist = Allowlist::parse(&format!("127.0.0.1:{},{}", router, nas));
+        let mut client = session(connector(
+            allowlist,
+            Some(("127.0.0.1".to_owned(), router as _)),
+        ));
+        echo(&mut client.open("127.0.0.1", router).await, b"router").await;
+        let _ = client.open("127.0.0.1", nas).await;
+        let refused = within(client.refused.recv()).await.unwrap();
+        assert!(refused.starts_with("Not allowed"), "{}", refused);
+    }
+
+    #[tokio::test]
+    async fn test_ack_bounded() {
+        let mut mux = Mux::new(true, refuse_all());
+        let credit = Arc::new(Semaphore::new(WINDOW as _));
+        mux.streams.insert(
+            2,
+            MuxStream {
+                credit: credit.clone(),
+                ..Default::default()
+            },
+        );
+        credit.acquire_many(1000).await.unwrap().forget();
+        mux.handle_event(Event::Data(2, vec![0; 1000].into()));
+        let ack = |size| TunnelMessage {
+            union: Some(tunnel_message::Union::Ack(TunnelAck {
+                id: 2,
+                size,
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        mux.handle(ack(u32::MAX)).unwrap();
+        assert_eq!(credit.available_permits(), WINDOW as usize);
+        mux.handle(ack(u32::MAX)).unwrap();
+        assert_eq!(credit.available_permits(), WINDOW as usize);
+    }
+}
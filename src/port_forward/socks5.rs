@@ -0,0 +1,158 @@
+//! The server side of SOCKS5 (RFC 1928) for dynamic port forwarding, CONNECT
+//! without authentication.
+use hbb_common::{
+    bail,
+    tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    ResultType, TargetAddr,
+};
+use std::{
+    borrow::Cow,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const SUCCEEDED: u8 = 0;
+const GENERAL_FAILURE: u8 = 1;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// Reads the greeting and the request, returns the destination. The caller
+/// answers with [`reply`] once the destination is connected or not.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+) -> ResultType<TargetAddr<'static>> {
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        bail!("Not SOCKS5, version {}", header[0]);
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        socket.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        bail!("SOCKS5 client requires authentication");
+    }
+    socket.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[0] != VERSION {
+        bail!("Not SOCKS5, version {}", request[0]);
+    }
+    if request[1] != CONNECT {
+        socket.write_all(&reply_code(COMMAND_NOT_SUPPORTED)).await?;
+        bail!("SOCKS5 command {} is not supported", request[1]);
+    }
+    let target = match request[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            socket.read_exact(&mut ip).await?;
+            TargetAddr::Ip(SocketAddr::new(
+                Ipv4Addr::from(ip).into(),
+                socket.read_u16().await?,
+            ))
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            socket.read_exact(&mut ip).await?;
+            TargetAddr::Ip(SocketAddr::new(
+                Ipv6Addr::from(ip).into(),
+                socket.read_u16().await?,
+            ))
+        }
+        ATYP_DOMAIN => {
+            let mut name = vec![0u8; socket.read_u8().await? as usize];
+            socket.read_exact(&mut name).await?;
+            let Ok(name) = String::from_utf8(name) else {
+                socket.write_all(&reply_code(GENERAL_FAILURE)).await?;
+                bail!("Invalid SOCKS5 domain name");
+            };
+            TargetAddr::Domain(Cow::Owned(name), socket.read_u16().await?)
+        }
+        atyp => {
+            socket
+                .write_all(&reply_code(ADDRESS_TYPE_NOT_SUPPORTED))
+                .await?;
+            bail!("SOCKS5 address type {} is not supported", atyp);
+        }
+    };
+    Ok(target)
+}
+
+/// The host and port of a tunnel to `target`, IPv6 addresses in brackets.
+pub fn host_port(target: &TargetAddr) -> (String, i32) {
+    match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => (addr.ip().to_string(), addr.port() as _),
+        TargetAddr::Ip(SocketAddr::V6(addr)) => (format!("[{}]", addr.ip()), addr.port() as _),
+        TargetAddr::Domain(name, port) => (name.to_string(), *port as _),
+    }
+}
+
+/// The reply to the request, the bound address isn't told.
+pub fn reply(success: bool) -> Vec<u8> {
+    reply_code(if success { SUCCEEDED } else { GENERAL_FAILURE }).to_vec()
+}
+
+fn reply_code(code: u8) -> [u8; 10] {
+    [VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::tokio::{self, io::duplex};
+
+    async fn handshake(request: &[u8]) -> (ResultType<TargetAddr<'static>>, Vec<u8>) {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(request).await.unwrap();
+        let res = accept(&mut server).await;
+        drop(server);
+        let mut answer = vec![];
+        client.read_to_end(&mut answer).await.unwrap();
+        (res, answer)
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let (res, answer) = handshake(&[5, 2, 2, 0, 5, 1, 0, 1, 192, 168, 1, 1, 0, 80]).await;
+        assert_eq!(
+            res.unwrap(),
+            TargetAddr::Ip("192.168.1.1:80".parse().unwrap())
+        );
+        assert_eq!(answer, [5, 0]);
+
+        let mut request = vec![5, 1, 0, 5, 1, 0, 4];
+        request.extend("fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        request.extend([0x1f, 0x90]);
+        let target = handshake(&request).await.0.unwrap();
+        assert_eq!(target, TargetAddr::Ip("[fd00::1]:8080".parse().unwrap()));
+        assert_eq!(host_port(&target), ("[fd00::1]".to_owned(), 8080));
+
+        let mut request = vec![5, 1, 0, 5, 1, 0, 3, 7];
+        request.extend(b"nas.lan");
+        request.extend([1, 187]);
+        let target = handshake(&request).await.0.unwrap();
+        assert_eq!(host_port(&target), ("nas.lan".to_owned(), 443));
+    }
+
+    #[tokio::test]
+    async fn test_refuse() {
+        let (res, answer) = handshake(&[5, 1, 2]).await;
+        assert!(res.is_err());
+        assert_eq!(answer, [5, 0xff]);
+
+        // BIND
+        let (res, answer) = handshake(&[5, 1, 0, 5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+        assert!(res.is_err());
+        assert_eq!(answer[2..4], [5, 7]);
+
+        let (res, _) = handshake(&[4, 1, 0, 80, 127, 0, 0, 1, 0]).await;
+        assert!(res.is_err());
+    }
+}
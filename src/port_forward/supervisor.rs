@@ -0,0 +1,321 @@
+//! The port forward service, keeps the port forward profiles of the local
+//! config up.
+//!
+//! Each profile runs in its own task. A forward that fails is restarted with
+//! exponential backoff if the profile reconnects automatically, and all of
+//! them are restarted when the addresses of the network interfaces change,
+//! the sessions over the old ones being dead. Profiles are reloaded from the
+//! config file, so they can be edited while the service runs. The status is
+//! served over the ipc of [`POSTFIX`].
+use crate::{
+    cli::Session,
+    client::{Data, Interface},
+    ipc::{self, Data as IpcData},
+};
+use hbb_common::{
+    allow_err, bail,
+    config::{LocalConfig, PortForwardMode, PortForwardProfile},
+    futures::StreamExt,
+    log,
+    rendezvous_proto::ConnType,
+    tokio::{
+        self,
+        sync::mpsc,
+        task::JoinHandle,
+        time::{self, Duration, Instant},
+    },
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+pub const POSTFIX: &str = "_port_forward";
+
+// How often the profiles and the network are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(3);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Starting,
+    Running,
+    Retrying(String, Instant),
+    Stopped(String),
+}
+
+impl State {
+    fn text(&self) -> String {
+        match self {
+            Self::Starting => "starting".to_owned(),
+            Self::Running => "running".to_owned(),
+            Self::Retrying(err, at) => format!(
+                "retrying in {}s: {}",
+                at.saturating_duration_since(Instant::now()).as_secs(),
+                err
+            ),
+            Self::Stopped(err) => format!("stopped: {}", err),
+        }
+    }
+}
+
+type States = Arc<Mutex<HashMap<String, State>>>;
+
+struct Forward {
+    profile: PortForwardProfile,
+    task: JoinHandle<()>,
+}
+
+/// Runs the profiles until the process is killed.
+#[tokio::main]
+pub async fn run() -> ResultType<()> {
+    let Ok(mut incoming) = ipc::new_listener(POSTFIX).await else {
+        bail!("The port forward service is already running");
+    };
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let states: States = Default::default();
+    let mut forwards: HashMap<String, Forward> = HashMap::new();
+    let mut addrs = local_addrs();
+    let mut interval = time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            Some(Ok(conn)) = incoming.next() => {
+                tokio::spawn(serve(ipc::Connection::new(conn), states.clone()));
+            }
+            _ = interval.tick() => {
+                let new_addrs = local_addrs();
+                let network_changed = new_addrs != addrs;
+                if network_changed {
+                    log::info!("network changed, restarting port forwards");
+                    addrs = new_addrs;
+                }
+                let profiles = LocalConfig::get_port_forward_profiles_from_file();
+                forwards.retain(|name, forward| {
+                    let keep = profiles.contains(&forward.profile)
+                        && !(network_changed
+                            && (forward.profile.auto_reconnect || !forward.task.is_finished()));
+                    if !keep {
+                        log::info!("stopping port forward {}", name);
+                        forward.task.abort();
+                    }
+                    keep
+                });
+                states.lock().unwrap().retain(|name, _| forwards.contains_key(name));
+                for profile in profiles {
+                    if profile.name.is_empty() || forwards.contains_key(&profile.name) {
+                        continue;
+                    }
+                    log::info!("starting port forward {}", profile.name);
+                    let task = tokio::spawn(keep_up(profile.clone(), states.clone()));
+                    forwards.insert(profile.name.clone(), Forward { profile, task });
+                }
+            }
+        }
+    }
+}
+
+async fn keep_up(profile: PortForwardProfile, states: States) {
+    let set_state = {
+        let name = profile.name.clone();
+        move |state: State| {
+            states.lock().unwrap().insert(name.clone(), state);
+        }
+    };
+    let mut failures = 0;
+    loop {
+        set_state(State::Starting);
+        let started = Instant::now();
+        let running = set_state.clone();
+        let res = forward(&profile, move || running(State::Running)).await;
+        let err = match res {
+            Ok(()) => "closed".to_owned(),
+            Err(err) => err.to_string(),
+        };
+        log::info!("port forward {} ended: {}", profile.name, err);
+        if !profile.auto_reconnect {
+            set_state(State::Stopped(err));
+            return;
+        }
+        // A forward that ran for a while starts over
+        if started.elapsed() > MAX_BACKOFF {
+            failures = 0;
+        }
+        let delay = backoff(failures);
+        failures += 1;
+        set_state(State::Retrying(err, Instant::now() + delay));
+        time::sleep(delay).await;
+    }
+}
+
+// `running` is called once the peer accepted a login, a failed login ends the
+// forward with its error
+async fn forward(
+    profile: &PortForwardProfile,
+    running: impl Fn() + Send + Sync + 'static,
+) -> ResultType<()> {
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let login_error: Arc<Mutex<Option<String>>> = Default::default();
+    let handler = Session::new(
+        &profile.peer_id,
+        ConnType::PORT_FORWARD,
+        Some(profile.password.clone()),
+        sender.clone(),
+    )
+    .non_interactive()
+    .on_login({
+        let login_error = login_error.clone();
+        move |res| match res {
+            Ok(()) => running(),
+            Err(err) => {
+                login_error.lock().unwrap().get_or_insert(err);
+                sender.send(Data::Close).ok();
+            }
+        }
+    });
+    let token = LocalConfig::get_option("access_token");
+    let lc = handler.get_lch();
+    let res = match profile.mode {
+        PortForwardMode::Local | PortForwardMode::Dynamic => {
+            let bind = match profile.local_host.trim() {
+                "" => None,
+                host => Some(host.parse::<IpAddr>()?),
+            };
+            let (remote_host, remote_port) = if profile.mode == PortForwardMode::Dynamic {
+                (super::DYNAMIC.to_owned(), 0)
+            } else {
+                (profile.remote_host.clone(), profile.remote_port)
+            };
+            super::listen(
+                profile.peer_id.clone(),
+                profile.password.clone(),
+                profile.local_port,
+                handler,
+                receiver,
+                "",
+                &token,
+                lc,
+                remote_host,
+                remote_port,
+                bind,
+            )
+            .await
+        }
+        PortForwardMode::Reverse => {
+            super::listen_reverse(
+                profile.peer_id.clone(),
+                profile.password.clone(),
+                profile.remote_port,
+                handler,
+                receiver,
+                "",
+                &token,
+                lc,
+                profile.local_host.clone(),
+                profile.local_port,
+            )
+            .await
+        }
+    };
+    let login_error = login_error.lock().unwrap().take();
+    match login_error {
+        Some(err) if res.is_ok() => bail!("{}", err),
+        _ => res,
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    MIN_BACKOFF
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF)
+}
+
+fn local_addrs() -> Vec<IpAddr> {
+    let mut addrs: Vec<IpAddr> = default_net::get_interfaces()
+        .into_iter()
+        .flat_map(|i| {
+            i.ipv4
+                .into_iter()
+                .map(|a| IpAddr::V4(a.addr))
+                .chain(i.ipv6.into_iter().map(|a| IpAddr::V6(a.addr)))
+        })
+        .collect();
+    addrs.sort();
+    addrs
+}
+
+async fn serve(mut conn: ipc::Connection, states: States) {
+    loop {
+        match conn.next().await {
+            Err(_) => break,
+            Ok(Some(IpcData::PortForwardSessionCount(None))) => {
+                let count = states
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|s| **s == State::Running)
+                    .count();
+                allow_err!(
+                    conn.send(&IpcData::PortForwardSessionCount(Some(count)))
+                        .await
+                );
+            }
+            Ok(Some(IpcData::PortForwardProfileStatus(None))) => {
+                let mut status: Vec<(String, String)> = states
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(name, state)| (name.clone(), state.text()))
+                    .collect();
+                status.sort();
+                allow_err!(
+                    conn.send(&IpcData::PortForwardProfileStatus(Some(status)))
+                        .await
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The status of each profile of the running service.
+pub async fn get_status() -> ResultType<Vec<(String, String)>> {
+    let mut c = ipc::connect(1000, POSTFIX).await?;
+    c.send(&IpcData::PortForwardProfileStatus(None)).await?;
+    if let Some(IpcData::PortForwardProfileStatus(Some(status))) = c.next_timeout(1000).await? {
+        return Ok(status);
+    }
+    bail!("Failed to get the port forward status");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(32));
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_state_text() {
+        assert_eq!(State::Running.text(), "running");
+        assert_eq!(
+            State::Stopped("Reset by the peer".to_owned()).text(),
+            "stopped: Reset by the peer"
+        );
+        let state = State::Retrying(
+            "Timeout".to_owned(),
+            Instant::now() + Duration::from_secs(8),
+        );
+        assert!(state.text().starts_with("retrying in "));
+        assert!(state.text().ends_with("s: Timeout"));
+    }
+}
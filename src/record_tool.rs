@@ -0,0 +1,401 @@
+//! `rustdesk --recording <COMMAND>`, tools for session recordings.
+use hbb_common::chrono::TimeZone;
+use scrap::{
+    record_crypt::{self, ENC_EXT},
+    record_retention,
+    record_transcode::{self, ThumbnailOptions, TranscodeOptions},
+    CodecFormat,
+};
+use std::{collections::HashMap, path::Path};
+
+/// Exit codes of `--recording`
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_FAILED: i32 = 2;
+
+const USAGE: &str = "Usage: rustdesk --recording <COMMAND>
+
+Commands:
+  keygen                     Print a key pair for encrypted recordings
+  decrypt <INPUT> [OUTPUT]   Decrypt a recording, OUTPUT defaults to INPUT without .enc
+  list                       List the recordings of the recording directories, oldest first
+  delete <FILE>...           Remove recordings of the recording directories with their sidecars
+  sweep                      Apply the retention options to the recording directories now
+  info <INPUT>               Print the codec, size, duration and key frames of a .webm recording
+  transcode <INPUT> <OUTPUT> Re-encode or trim a .webm recording
+  concat <OUTPUT> <INPUT>... Join .webm recordings, e.g. those split on resolution changes
+  thumbnails <INPUT> <PNG>   Save a strip of key frame thumbnails
+
+Options of decrypt:
+      --key-file <FILE>      File with the secret key, or set RUSTDESK_RECORDING_KEY
+
+Options of transcode and concat:
+      --codec <CODEC>        vp8, vp9 or av1, defaults to that of the (first) input
+      --bitrate <KBPS>       Video bitrate, the video is copied if neither this nor
+                             the codec or size change
+      --start <SECONDS>      Start of the output, copied video starts at the key frame
+                             before if it is at most a second earlier
+      --end <SECONDS>        End of the output
+
+Options of thumbnails:
+      --interval <SECONDS>   At least this long between thumbnails [default: 10]
+      --height <PIXELS>      Height of the thumbnails [default: 90]
+      --max <N>              At most this many thumbnails [default: 20]
+
+The public key goes to the record-encryption-key option of the recording
+device, the secret key must be kept elsewhere.
+
+The recording directories are that of the user and, on Windows, that of the
+service, where incoming sessions are recorded.
+
+Exit codes: 0 done, 1 usage, 2 failed";
+
+fn usage() -> i32 {
+    eprintln!("{}", USAGE);
+    EXIT_USAGE
+}
+
+/// Returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let mut key = std::env::var("RUSTDESK_RECORDING_KEY").ok();
+    let mut positional = Vec::new();
+    let mut values = HashMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--key-file" => match iter.next().map(std::fs::read_to_string) {
+                Some(Ok(v)) => key = Some(v),
+                Some(Err(e)) => {
+                    eprintln!("Failed to read the key file: {}", e);
+                    return EXIT_FAILED;
+                }
+                None => return usage(),
+            },
+            "--codec" | "--bitrate" | "--start" | "--end" | "--interval" | "--height" | "--max" => {
+                match iter.next() {
+                    Some(v) => {
+                        values.insert(arg.as_str(), v.clone());
+                    }
+                    None => return usage(),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return EXIT_OK;
+            }
+            x if x.starts_with('-') && x.len() > 1 => {
+                eprintln!("Unknown option: {}", x);
+                return usage();
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+    match (positional.first().map(|x| x.as_str()), positional.len()) {
+        (Some("keygen"), 1) => {
+            let (pk, sk) = record_crypt::gen_keypair();
+            println!("public key: {}", pk);
+            println!("secret key: {}", sk);
+            EXIT_OK
+        }
+        (Some("decrypt"), 2 | 3) => {
+            let Some(key) = key else {
+                eprintln!("No secret key, use --key-file or set RUSTDESK_RECORDING_KEY");
+                return EXIT_USAGE;
+            };
+            decrypt(&key, &positional[1], positional.get(2))
+        }
+        (Some("list"), 1) => list(),
+        (Some("delete"), n) if n > 1 => delete(&positional[1..]),
+        (Some("sweep"), 1) => sweep(),
+        (Some("info"), 2) => info(&positional[1]),
+        (Some("transcode"), 3) => match transcode_options(&values) {
+            Ok(opts) => transcode(&[positional[1].as_str()], &positional[2], &opts),
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_USAGE
+            }
+        },
+        (Some("concat"), n) if n > 2 => match transcode_options(&values) {
+            Ok(opts) => {
+                let inputs: Vec<&str> = positional[2..].iter().map(|x| x.as_str()).collect();
+                transcode(&inputs, &positional[1], &opts)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_USAGE
+            }
+        },
+        (Some("thumbnails"), 3) => match thumbnail_options(&values) {
+            Ok(opts) => thumbnails(&positional[1], &positional[2], &opts),
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_USAGE
+            }
+        },
+        _ => usage(),
+    }
+}
+
+fn decrypt(key: &str, input: &str, output: Option<&String>) -> i32 {
+    let sk = match record_crypt::parse_secret_key(key) {
+        Ok(sk) => sk,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let output = match output {
+        Some(output) => output.clone(),
+        None => match input.strip_suffix(ENC_EXT) {
+            Some(output) => output.to_owned(),
+            None => {
+                eprintln!("{} has no {} extension, give an output", input, ENC_EXT);
+                return EXIT_USAGE;
+            }
+        },
+    };
+    if Path::new(&output).exists() {
+        eprintln!("{} exists", output);
+        return EXIT_FAILED;
+    }
+    match record_crypt::decrypt_file(input, &output, &sk) {
+        Ok(true) => {
+            println!("{}", output);
+            EXIT_OK
+        }
+        Ok(false) => {
+            eprintln!(
+                "{} was cut off, it is decrypted up to its last complete part",
+                input
+            );
+            println!("{}", output);
+            EXIT_OK
+        }
+        Err(e) => {
+            std::fs::remove_file(&output).ok();
+            eprintln!("Failed to decrypt {}: {}", input, e);
+            EXIT_FAILED
+        }
+    }
+}
+
+// Of the user and, as the service records incoming sessions as root there,
+// of the service on Windows
+fn recording_dirs() -> Vec<String> {
+    #[allow(unused_mut)]
+    let mut dirs = vec![crate::ui_interface::video_save_directory(false)];
+    #[cfg(windows)]
+    {
+        let root = crate::ui_interface::video_save_directory(true);
+        if !dirs.contains(&root) && Path::new(&root).exists() {
+            dirs.push(root);
+        }
+    }
+    dirs
+}
+
+fn list() -> i32 {
+    let mut recordings = vec![];
+    for dir in recording_dirs() {
+        match record_retention::list_recordings(&dir) {
+            Ok(v) => recordings.extend(v),
+            Err(e) => {
+                eprintln!("Failed to list {}: {}", dir, e);
+                return EXIT_FAILED;
+            }
+        }
+    }
+    recordings.sort_by_key(|r| r.start);
+    for r in recordings {
+        let start = hbb_common::chrono::Local
+            .timestamp_millis_opt(r.start)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!("{}\t{}\t{}\t{}", start, r.id, r.size, r.path);
+    }
+    EXIT_OK
+}
+
+// The recording directory of `path` and its full path, file names are of the
+// first directory they are in
+fn locate(dirs: &[String], path: &str) -> (String, String) {
+    match Path::new(path).parent() {
+        Some(p) if !p.as_os_str().is_empty() => {
+            let parent = p.canonicalize().ok();
+            let dir = dirs
+                .iter()
+                .find(|d| parent.is_some() && Path::new(d).canonicalize().ok() == parent)
+                .unwrap_or(&dirs[0]);
+            (dir.clone(), path.to_owned())
+        }
+        _ => {
+            let dir = dirs
+                .iter()
+                .find(|d| Path::new(d).join(path).exists())
+                .unwrap_or(&dirs[0]);
+            let path = Path::new(dir).join(path).to_string_lossy().to_string();
+            (dir.clone(), path)
+        }
+    }
+}
+
+fn delete(paths: &[String]) -> i32 {
+    let dirs = recording_dirs();
+    let mut code = EXIT_OK;
+    for path in paths {
+        let (dir, path) = locate(&dirs, path);
+        if let Err(e) = record_retention::delete_recording(&dir, &path) {
+            eprintln!("Failed to delete {}: {}", path, e);
+            code = EXIT_FAILED;
+        }
+    }
+    code
+}
+
+fn sweep() -> i32 {
+    let policy = record_retention::RetentionPolicy::load();
+    let mut code = EXIT_OK;
+    for dir in recording_dirs() {
+        match record_retention::sweep(&dir, &policy) {
+            Ok(removed) => {
+                for path in removed {
+                    println!("{}", path);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to sweep {}: {}", dir, e);
+                code = EXIT_FAILED;
+            }
+        }
+    }
+    code
+}
+
+fn parse_value<T: std::str::FromStr>(
+    values: &HashMap<&str, String>,
+    name: &str,
+) -> Result<Option<T>, String> {
+    match values.get(name) {
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid {}: {}", name, v)),
+        None => Ok(None),
+    }
+}
+
+fn parse_ms(values: &HashMap<&str, String>, name: &str) -> Result<Option<u64>, String> {
+    match parse_value::<f64>(values, name)? {
+        Some(s) if s >= 0. && s.is_finite() => Ok(Some((s * 1000.) as u64)),
+        Some(s) => Err(format!("Invalid {}: {}", name, s)),
+        None => Ok(None),
+    }
+}
+
+fn transcode_options(values: &HashMap<&str, String>) -> Result<TranscodeOptions, String> {
+    let format = match values.get("--codec").map(|x| x.to_lowercase()).as_deref() {
+        Some("vp8") => Some(CodecFormat::VP8),
+        Some("vp9") => Some(CodecFormat::VP9),
+        Some("av1") => Some(CodecFormat::AV1),
+        Some(x) => return Err(format!("Invalid --codec: {}, use vp8, vp9 or av1", x)),
+        None => None,
+    };
+    Ok(TranscodeOptions {
+        format,
+        bitrate: parse_value(values, "--bitrate")?,
+        start_ms: parse_ms(values, "--start")?,
+        end_ms: parse_ms(values, "--end")?,
+    })
+}
+
+fn thumbnail_options(values: &HashMap<&str, String>) -> Result<ThumbnailOptions, String> {
+    let mut opts = ThumbnailOptions::default();
+    if let Some(ms) = parse_ms(values, "--interval")? {
+        opts.interval_ms = ms;
+    }
+    if let Some(height) = parse_value(values, "--height")? {
+        opts.height = height;
+    }
+    if let Some(max) = parse_value(values, "--max")? {
+        opts.max_count = max;
+    }
+    Ok(opts)
+}
+
+fn info(input: &str) -> i32 {
+    match record_transcode::probe(input) {
+        Ok(p) => {
+            let seconds = |ns: u64| format!("{:.3}", ns as f64 / 1e9);
+            println!("codec: {:?}", p.format);
+            println!("size: {}x{}", p.width, p.height);
+            if let Some(channels) = p.audio_channels {
+                println!(
+                    "audio: opus, {} channels, {} frames",
+                    channels, p.audio_frames
+                );
+            }
+            println!("duration: {}", seconds(p.duration_ns));
+            println!("video frames: {}", p.video_frames);
+            let keyframes: Vec<_> = p.keyframes.iter().map(|k| seconds(*k)).collect();
+            println!("key frames: {}", keyframes.join(" "));
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input, e);
+            EXIT_FAILED
+        }
+    }
+}
+
+fn transcode(inputs: &[&str], output: &str, opts: &TranscodeOptions) -> i32 {
+    if Path::new(output).exists() {
+        eprintln!("{} exists", output);
+        return EXIT_FAILED;
+    }
+    match record_transcode::concat(inputs, output, opts) {
+        Ok(res) => {
+            eprintln!(
+                "{} video and {} audio frames, {:.3} s{}",
+                res.video_frames,
+                res.audio_frames,
+                res.duration_ms as f64 / 1000.,
+                if res.reencoded { ", re-encoded" } else { "" }
+            );
+            println!("{}", output);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output, e);
+            EXIT_FAILED
+        }
+    }
+}
+
+fn thumbnails(input: &str, output: &str, opts: &ThumbnailOptions) -> i32 {
+    let v = match record_transcode::thumbnails(input, opts) {
+        Ok(v) if !v.is_empty() => v,
+        Ok(_) => {
+            eprintln!("No key frame of {} could be decoded", input);
+            return EXIT_FAILED;
+        }
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input, e);
+            return EXIT_FAILED;
+        }
+    };
+    for t in v.iter() {
+        eprintln!("{:.3}", t.ms as f64 / 1000.);
+    }
+    let images: Vec<_> = v.into_iter().map(|t| t.image).collect();
+    match record_transcode::strip(&images).save_png(output) {
+        Ok(()) => {
+            println!("{}", output);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output, e);
+            EXIT_FAILED
+        }
+    }
+}
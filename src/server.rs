@@ -34,6 +34,8 @@ use crate::ipc::Data;
 
 pub mod audio_service;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod restricted_terminal;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
 cfg_if::cfg_if! {
 if #[cfg(not(target_os = "ios"))] {
@@ -72,6 +74,7 @@ pub mod display_service;
 pub mod portable_service;
 mod service;
 mod video_qos;
+mod video_redact;
 pub mod video_service;
 
 #[cfg(all(target_os = "windows", feature = "flutter"))]
@@ -306,6 +309,8 @@ impl Server {
     fn is_video_service_name(name: &str) -> bool {
         name.starts_with(VideoSource::Monitor.service_name_prefix())
             || name.starts_with(VideoSource::Camera.service_name_prefix())
+            || name.starts_with(VideoSource::Synthetic.service_name_prefix())
+            || name.starts_with(VideoSource::Window.service_name_prefix())
     }
 
     pub fn try_add_primary_camera_service(&mut self) {
@@ -323,15 +328,10 @@ impl Server {
     }
 
     pub fn try_add_primay_video_service(&mut self) {
-        let primary_video_service_name = video_service::get_service_name(
-            VideoSource::Monitor,
-            *display_service::PRIMARY_DISPLAY_IDX,
-        );
+        let (source, idx) = video_service::primary_video_service();
+        let primary_video_service_name = video_service::get_service_name(source, idx);
         if !self.contains(&primary_video_service_name) {
-            self.add_service(Box::new(video_service::new(
-                VideoSource::Monitor,
-                *display_service::PRIMARY_DISPLAY_IDX,
-            )));
+            self.add_service(Box::new(video_service::new(source, idx)));
         }
     }
 
@@ -347,10 +347,8 @@ impl Server {
     }
 
     pub fn add_connection(&mut self, conn: ConnInner, noperms: &Vec<&'static str>) {
-        let primary_video_service_name = video_service::get_service_name(
-            VideoSource::Monitor,
-            *display_service::PRIMARY_DISPLAY_IDX,
-        );
+        let (source, idx) = video_service::primary_video_service();
+        let primary_video_service_name = video_service::get_service_name(source, idx);
         for s in self.services.values() {
             let name = s.name();
             if Self::is_video_service_name(&name) && name != primary_video_service_name {
@@ -17,7 +17,9 @@ use crate::{
     client::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
-    display_service, ipc, privacy_mode, video_service, VERSION,
+    display_service,
+    file_sandbox::{self, FileSandbox},
+    ipc, privacy_mode, video_service, VERSION,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
@@ -29,6 +31,7 @@ use hbb_common::protobuf::EnumOrUnknown;
 use hbb_common::{
     config::{self, keys, Config, TrustedDevice},
     fs::{self, can_enable_overwrite_detection, JobType},
+    fs_rate,
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
@@ -44,7 +47,10 @@ use hbb_common::{
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use scrap::android::{call_main_service_key_event, call_main_service_pointer_input};
-use scrap::camera;
+use scrap::{
+    camera,
+    record_event::{RecordEvent, RecordEventType},
+};
 use serde_derive::Serialize;
 use serde_json::{json, value::Value};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -218,6 +224,12 @@ pub struct Connection {
     view_camera: bool,
     terminal: bool,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    // Version of the tunnel messages, the streams are then opened on demand
+    port_forward_mux: u32,
+    // Loopback port listened on for reverse forwarding, 0 for none
+    port_forward_reverse: i32,
+    // The approved target the streams connect to, none for dynamic forwarding
+    port_forward_target: Option<(String, i32)>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -291,6 +303,7 @@ pub struct Connection {
     tx_post_seq: mpsc::UnboundedSender<(String, Value)>,
     terminal_service_id: String,
     terminal_persistent: bool,
+    terminal_restricted: bool,
     // The user token must be set when terminal is enabled.
     // 0 indicates SYSTEM user
     // other values indicate current user
@@ -396,6 +409,9 @@ impl Connection {
             view_camera: false,
             terminal: false,
             port_forward_socket: None,
+            port_forward_mux: 0,
+            port_forward_reverse: 0,
+            port_forward_target: None,
             port_forward_address: "".to_owned(),
             tx_to_cm,
             authorized: false,
@@ -463,6 +479,7 @@ impl Connection {
             tx_post_seq,
             terminal_service_id: "".to_owned(),
             terminal_persistent: false,
+            terminal_restricted: false,
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             terminal_user_token: None,
             terminal_generic_service: None,
@@ -506,7 +523,7 @@ impl Connection {
         let mut last_recv_time = Instant::now();
 
         conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() || conn.terminal {
+            if conn.file_transfer.is_some() || conn.is_port_forward() || conn.terminal {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
@@ -548,7 +565,7 @@ impl Connection {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -567,6 +584,7 @@ impl Connection {
                             }
                         }
                         ipc::Data::ChatMessage{text} => {
+                            conn.record_event(RecordEventType::Chat, json!({"from": "local", "text": &text}));
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -579,6 +597,7 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            conn.record_event(RecordEventType::Permission, json!({"name": &name, "enabled": enabled}));
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -736,7 +755,7 @@ impl Connection {
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
-                                    if conn.port_forward_socket.is_some() && conn.authorized {
+                                    if conn.is_port_forward() && conn.authorized {
                                         log::info!("Port forward, last_test_delay is none: {}", conn.last_test_delay.is_none());
                                         // Avoid TestDelay reply injection into rdp data stream
                                         if conn.last_test_delay.is_none() {
@@ -826,6 +845,14 @@ impl Connection {
                             if let Some(new_msg) = conn.retina.on_cursor_pos(&pos, conn.display_idx) {
                                 msg = Arc::new(new_msg);
                             }
+                            #[cfg(target_os = "linux")]
+                            if conn.video_source() == VideoSource::Window {
+                                let mut pos = pos.clone();
+                                video_service::screen_to_window(&mut pos);
+                                let mut new_msg = Message::new();
+                                new_msg.set_cursor_position(pos);
+                                msg = Arc::new(new_msg);
+                            }
                         }
                         Some(message::Union::MultiClipboards(_multi_clipboards)) => {
                             #[cfg(not(target_os = "ios"))]
@@ -856,6 +883,9 @@ impl Connection {
                                 conn.send_remote_printing_disallowed().await;
                             }
                         }
+                        ipc::Data::RecordDiskNotice(low) => {
+                            conn.send_record_disk_notice(low).await;
+                        }
                         _ => {}
                     }
                 }
@@ -879,7 +909,7 @@ impl Connection {
                         break;
                     }
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
-                    if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
+                    if conn.last_test_delay.is_none() && !(conn.is_port_forward() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
@@ -891,7 +921,9 @@ impl Connection {
                     }
                     if conn.is_authed_remote_conn() || conn.view_camera {
                         if let Some(last_test_delay) = conn.last_test_delay {
-                            video_service::VIDEO_QOS.lock().unwrap().user_delay_response_elapsed(id, last_test_delay.elapsed().as_millis());
+                            let mut qos = video_service::VIDEO_QOS.lock().unwrap();
+                            qos.user_delay_response_elapsed(id, last_test_delay.elapsed().as_millis());
+                            fs_rate::set_deprioritized(qos.high_delay());
                         }
                     }
                 }
@@ -1113,9 +1145,97 @@ impl Connection {
                 }
             }
         }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.port_forward_mux > 0 && self.authorized {
+            self.tunnel_loop(rx_from_cm).await?;
+        }
         Ok(())
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn tunnel_loop(
+        &mut self,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        use crate::port_forward::{
+            allowlist::Allowlist,
+            mux::{connector, refuse_all, Mux},
+        };
+        log::info!(
+            "Running port forwarding tunnel, version {}",
+            self.port_forward_mux
+        );
+        // Reverse streams are only opened here
+        let connector = if self.port_forward_reverse > 0 {
+            refuse_all()
+        } else {
+            connector(Allowlist::load(), self.port_forward_target.clone())
+        };
+        let mut mux = Mux::new(false, connector);
+        if self.port_forward_reverse > 0 {
+            let addr = SocketAddr::from((
+                std::net::Ipv4Addr::LOCALHOST,
+                self.port_forward_reverse as u16,
+            ));
+            match hbb_common::tcp::new_listener(addr, true).await {
+                Ok(listener) => {
+                    log::info!("Listening on {} for reverse port forwarding", addr);
+                    mux.listen(listener, "".to_owned(), self.port_forward_reverse);
+                }
+                Err(err) => {
+                    let reason = format!("Failed to listen on {}: {}", addr, err);
+                    self.send_close_reason_no_retry(&reason).await;
+                    bail!(reason);
+                }
+            }
+        }
+        let mut last_recv_time = Instant::now();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                msg = mux.next() => {
+                    self.stream.send(&msg).await?;
+                }
+                res = self.stream.next() => {
+                    let Some(res) = res else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    let msg_in = Message::parse_from_bytes(&res?)?;
+                    if let Some(message::Union::Tunnel(tm)) = msg_in.union {
+                        match mux.handle(tm) {
+                            Ok(Some(msg)) => self.stream.send(&msg).await?,
+                            Ok(None) => {}
+                            Err(err) => log::warn!("Tunnel stream refused: {}", err),
+                        }
+                    }
+                }
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -1292,6 +1412,16 @@ impl Connection {
         crate::post_request(url, v.to_string(), "").await
     }
 
+    // To the recordings of incoming sessions
+    fn record_event(&self, event: RecordEventType, detail: Value) {
+        video_service::record_event(RecordEvent {
+            conn_id: self.inner.id,
+            peer_id: self.lr.my_id.clone(),
+            peer_name: self.lr.my_name.clone(),
+            ..RecordEvent::new(event, detail)
+        });
+    }
+
     async fn send_logon_response(&mut self) {
         if self.authorized {
             return;
@@ -1330,7 +1460,7 @@ impl Connection {
         self.authorized = true;
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             (2, AuthConnType::PortForward)
         } else if self.view_camera {
             (3, AuthConnType::ViewCamera)
@@ -1354,6 +1484,10 @@ impl Connection {
         self.post_conn_audit(
             json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
         );
+        self.record_event(
+            RecordEventType::Login,
+            json!({"type": format!("{auth_conn_type:?}"), "ip": &self.ip}),
+        );
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1436,7 +1570,7 @@ impl Connection {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1500,6 +1634,7 @@ impl Connection {
             privacy_mode: privacy_mode::is_privacy_mode_supported(),
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             terminal,
+            port_forward_mux: self.port_forward_mux,
             ..Default::default()
         })
         .into();
@@ -1547,7 +1682,14 @@ impl Connection {
 
             try_activate_screen();
 
-            match super::display_service::update_get_sync_displays_on_login().await {
+            let (source, _) = video_service::primary_video_service();
+            let displays = if !source.is_monitor() {
+                self.display_idx = 0;
+                video_service::single_display_info(source).map(|d| vec![d])
+            } else {
+                super::display_service::update_get_sync_displays_on_login().await
+            };
+            match displays {
                 Err(err) => {
                     res.set_error(format!("{}", err));
                 }
@@ -1629,10 +1771,15 @@ impl Connection {
         }
     }
 
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some() || self.port_forward_mux > 0
+    }
+
     #[inline]
     fn is_remote(&self) -> bool {
         self.file_transfer.is_none()
-            && self.port_forward_socket.is_none()
+            && !self.is_port_forward()
             && !self.view_camera
             && !self.terminal
     }
@@ -2056,6 +2203,9 @@ impl Connection {
                             o.terminal_persistent.enum_value() == Ok(BoolOption::Yes);
                     }
                     self.terminal_service_id = terminal.service_id;
+                    // Requested by the peer, or forced for all
+                    self.terminal_restricted = terminal.restricted
+                        || Config::get_option(keys::OPTION_TERMINAL_RESTRICTED) == "Y";
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if let Some(msg) =
                         self.fill_terminal_user_token(&lr.os_login.username, &lr.os_login.password)
@@ -2083,6 +2233,19 @@ impl Connection {
                             }
                         }
                     }
+
+                    // A restricted connection must not reattach to a shell
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if let Some(is_restricted) =
+                        terminal_service::is_service_restricted(&self.terminal_service_id)
+                    {
+                        if is_restricted != self.terminal_restricted {
+                            self.send_login_error("Terminal service restriction mismatch.")
+                                .await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                    }
                 }
                 Some(login_request::Union::PortForward(mut pf)) => {
                     if !Connection::permission("enable-tunnel") {
@@ -2101,21 +2264,77 @@ impl Connection {
                     }
                     let mut addr = format!("{}:{}", pf.host, pf.port);
                     self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
-                        }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if pf.mux_version > 0 {
+                        // Each stream connects on its own
+                        self.port_forward_mux =
+                            pf.mux_version.min(crate::port_forward::mux::MUX_VERSION);
+                        if pf.dynamic {
+                            // The peer picks the destinations, only allowed ones
+                            if crate::port_forward::allowlist::Allowlist::load().is_empty() {
+                                self.send_login_error(
+                                    "Dynamic port forwarding needs a port forward allowlist",
+                                )
+                                .await;
+                                sleep(1.).await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
+                            self.port_forward_address = "socks5 (dynamic)".to_owned();
+                        } else {
+                            self.port_forward_target = Some((pf.host.clone(), pf.port));
+                        }
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if pf.reverse {
+                        if !Connection::permission(keys::OPTION_ALLOW_REVERSE_TUNNEL) {
+                            self.send_login_error("No permission of reverse port forwarding")
+                                .await;
+                            sleep(1.).await;
                             return false;
                         }
+                        if self.port_forward_mux < 2
+                            || pf.dynamic
+                            || !(1..=65535).contains(&pf.port)
+                        {
+                            self.send_login_error("Invalid reverse port forwarding")
+                                .await;
+                            return false;
+                        }
+                        self.port_forward_reverse = pf.port;
+                        self.port_forward_address = format!("localhost:{} (reverse)", pf.port);
+                    }
+                    if self.port_forward_mux == 0 {
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        let addrs = match crate::port_forward::allowlist::Allowlist::load()
+                            .resolve_target(&pf.host, pf.port)
+                            .await
+                        {
+                            Ok(addrs) => addrs,
+                            Err(err) => {
+                                log::warn!("Port forwarding to {} refused: {}", addr, err);
+                                self.send_login_error(err.to_string()).await;
+                                return false;
+                            }
+                        };
+                        #[cfg(any(target_os = "android", target_os = "ios"))]
+                        let addrs = addr.clone();
+                        match timeout(3000, TcpStream::connect(&addrs[..])).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
+                            }
+                        }
                     }
                 }
                 _ => {
@@ -2272,10 +2491,11 @@ impl Connection {
                 if let Some(tm) = self.last_test_delay {
                     self.last_test_delay = None;
                     let new_delay = tm.elapsed().as_millis() as u32;
-                    video_service::VIDEO_QOS
-                        .lock()
-                        .unwrap()
-                        .user_network_delay(self.inner.id(), new_delay);
+                    let mut qos = video_service::VIDEO_QOS.lock().unwrap();
+                    qos.user_network_delay(self.inner.id(), new_delay);
+                    // File blocks share the uplink with the video
+                    fs_rate::set_deprioritized(qos.high_delay());
+                    drop(qos);
                     self.network_delay = new_delay;
                 }
             }
@@ -2305,7 +2525,7 @@ impl Connection {
                 }
             }
         } else if self.authorized {
-            if self.port_forward_socket.is_some() {
+            if self.is_port_forward() {
                 return true;
             }
             match msg.union {
@@ -2322,6 +2542,11 @@ impl Connection {
                         return true;
                     }
 
+                    if self.video_source() == VideoSource::Window
+                        && !video_service::window_to_screen(&mut me)
+                    {
+                        return true;
+                    }
                     #[cfg(any(target_os = "android", target_os = "ios"))]
                     if let Err(e) = call_main_service_pointer_input("mouse", me.mask, me.x, me.y) {
                         log::debug!("call_main_service_pointer_input fail:{}", e);
@@ -2358,7 +2583,10 @@ impl Connection {
                     self.update_auto_disconnect_timer();
                 }
                 Some(message::Union::PointerDeviceEvent(pde)) => {
-                    if self.is_authed_view_camera_conn() {
+                    // Not moved to the shared window
+                    if self.is_authed_view_camera_conn()
+                        || self.video_source() == VideoSource::Window
+                    {
                         return true;
                     }
                     #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -2474,6 +2702,13 @@ impl Connection {
                         return true;
                     }
 
+                    // Not to type into the other windows
+                    if self.video_source() == VideoSource::Window
+                        && !video_service::shared_window_focused()
+                    {
+                        return true;
+                    }
+
                     if self.peer_keyboard_enabled() {
                         if is_enter(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
@@ -2539,6 +2774,7 @@ impl Connection {
                     }
 
                     if self.clipboard {
+                        self.record_event(RecordEventType::Clipboard, clipboard_event(&cb));
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Host);
                         // ios as the controlled side is actually not supported for now.
@@ -2566,6 +2802,10 @@ impl Connection {
                     }
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
+                    if self.clipboard {
+                        let detail: Vec<_> = _mcb.clipboards.iter().map(clipboard_event).collect();
+                        self.record_event(RecordEventType::Clipboard, json!(detail));
+                    }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
                         update_clipboard(_mcb.clipboards, ClipboardSide::Host);
@@ -2696,6 +2936,18 @@ impl Connection {
                                 return true;
                             }
                         }
+                        let sandbox = FileSandbox::load();
+                        if let Some(action) = fa.union.as_ref() {
+                            if let Err(err) = sandbox.check_action(action) {
+                                log::warn!("File action denied: {}", err);
+                                let (id, file_num) = file_sandbox::action_job(action);
+                                self.send(fs::new_error(id, err, file_num)).await;
+                                return true;
+                            }
+                            if let Some(detail) = file_action_event(action) {
+                                self.record_event(RecordEventType::File, detail);
+                            }
+                        }
                         match fa.union {
                             Some(file_action::Union::ReadEmptyDirs(rd)) => {
                                 self.read_empty_dirs(&rd.path, rd.include_hidden);
@@ -2752,7 +3004,13 @@ impl Connection {
                                     s.include_hidden,
                                     false,
                                     od,
-                                ) {
+                                )
+                                .and_then(|job| {
+                                    // Symlinks below the path mustn't lead outside either
+                                    sandbox
+                                        .check_files(&PathBuf::from(&path), job.files(), false)
+                                        .map(|_| job)
+                                }) {
                                     Err(err) => {
                                         self.send(fs::new_error(id, err, 0)).await;
                                     }
@@ -2850,6 +3108,12 @@ impl Connection {
                                     )));
                                 }
                             }
+                            Some(file_action::Union::Schedule(s)) => {
+                                if let Some(job) = fs::get_job(s.id, &mut self.read_jobs) {
+                                    job.set_paused(s.paused);
+                                    job.set_rate_limit(s.rate_limit as u64 * 1024);
+                                }
+                            }
                             Some(file_action::Union::SendConfirm(r)) => {
                                 if let Some(job) = fs::get_job(r.id, &mut self.read_jobs) {
                                     job.confirm(&r).await;
@@ -2886,6 +3150,9 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            delta_copy_blocks: block.delta_copy_blocks,
+                            delta_block_size: block.delta_block_size,
+                            content_hash: block.content_hash.to_vec(),
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -2901,6 +3168,8 @@ impl Connection {
                         last_modified: d.last_modified,
                         is_upload: true,
                         is_resume: d.is_resume,
+                        supports_content_hash: fs::supports_content_hash(&d),
+                        content_hash: fs::digest_content_hash(&d),
                     }),
                     Some(file_response::Union::Error(e)) => {
                         self.send_fs(ipc::FS::WriteError {
@@ -2913,12 +3182,17 @@ impl Connection {
                 },
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::SwitchDisplay(s)) => {
+                        self.record_event(RecordEventType::Display, json!({"display": s.display}));
                         self.handle_switch_display(s).await;
                     }
                     Some(misc::Union::CaptureDisplays(displays)) => {
                         let add = displays.add.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let sub = displays.sub.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
+                        self.record_event(
+                            RecordEventType::Display,
+                            json!({"add": &add, "sub": &sub, "set": &set}),
+                        );
                         self.capture_displays(&add, &sub, &set).await;
                     }
                     #[cfg(windows)]
@@ -2929,6 +3203,10 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        self.record_event(
+                            RecordEventType::Chat,
+                            json!({"from": "peer", "text": &c.text}),
+                        );
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -3318,7 +3596,7 @@ impl Connection {
         if self.view_camera {
             VideoSource::Camera
         } else {
-            VideoSource::Monitor
+            video_service::primary_video_service().0
         }
     }
 
@@ -4191,6 +4469,24 @@ impl Connection {
         self.send(msg_out).await;
     }
 
+    async fn send_record_disk_notice(&mut self, low: bool) {
+        let mut msg_out = Message::new();
+        let res = MessageBox {
+            msgtype: "custom-nook-nocancel-hasclose".to_owned(),
+            title: "Recording".to_owned(),
+            text: if low {
+                "record-paused-low-disk-tip"
+            } else {
+                "record-resumed-tip"
+            }
+            .to_owned(),
+            link: "".to_owned(),
+            ..Default::default()
+        };
+        msg_out.set_message_box(res);
+        self.send(msg_out).await;
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     async fn update_terminal_persistence(&mut self, persistent: bool) {
         self.terminal_persistent = persistent;
@@ -4212,6 +4508,7 @@ impl Connection {
             self.terminal_service_id.clone(),
             self.terminal_persistent,
             user_token.to_terminal_service_token(),
+            self.terminal_restricted,
         ));
         s.on_subscribe(self.inner.clone());
         self.terminal_generic_service = Some(s);
@@ -4228,6 +4525,7 @@ impl Connection {
             self.terminal_service_id.clone(),
             Some(self.terminal_persistent),
             user_token.to_terminal_service_token(),
+            Some(self.terminal_restricted),
         );
 
         match proxy.handle_action(&action) {
@@ -4254,6 +4552,30 @@ impl Connection {
     }
 }
 
+// Only the format and size, the content may be a password
+fn clipboard_event(cb: &Clipboard) -> Value {
+    json!({
+        "format": format!("{:?}", cb.format.enum_value_or_default()),
+        "size": cb.content.len(),
+    })
+}
+
+// Actions changing files, for the recordings of incoming sessions
+fn file_action_event(action: &file_action::Union) -> Option<Value> {
+    let (action, path) = match action {
+        file_action::Union::Send(s) => ("download", &s.path),
+        file_action::Union::Receive(r) => ("upload", &r.path),
+        file_action::Union::Create(c) => ("create", &c.path),
+        file_action::Union::RemoveDir(d) => ("remove", &d.path),
+        file_action::Union::RemoveFile(f) => ("remove", &f.path),
+        file_action::Union::Rename(r) => {
+            return Some(json!({"action": "rename", "path": &r.path, "new_name": &r.new_name}))
+        }
+        _ => return None,
+    };
+    Some(json!({"action": action, "path": path}))
+}
+
 pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
     SWITCH_SIDES_UUID
         .lock()
@@ -4389,8 +4711,11 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            delta_copy_blocks,
+                            delta_block_size,
+                            content_hash}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, delta_copy_blocks, delta_block_size, content_hash})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -4600,6 +4925,19 @@ pub fn on_printer_data(data: Vec<u8>) {
         });
 }
 
+/// Tell the peers of remote sessions that the recording of this side paused
+/// for low disk space, or resumed.
+pub fn on_record_disk_notice(low: bool) {
+    for c in crate::server::AUTHED_CONNS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|c| c.conn_type == AuthConnType::Remote)
+    {
+        c.sender.send(Data::RecordDiskNotice(low)).ok();
+    }
+}
+
 #[cfg(windows)]
 pub struct PortableState {
     pub last_uac: bool,
@@ -4915,12 +5253,15 @@ mod raii {
         fn drop(&mut self) {
             if self.1 == AuthConnType::Remote || self.1 == AuthConnType::ViewCamera {
                 scrap::codec::Encoder::update(scrap::codec::EncodingUpdate::Remove(self.0));
-                video_service::VIDEO_QOS
-                    .lock()
-                    .unwrap()
-                    .on_connection_close(self.0);
+                let mut qos = video_service::VIDEO_QOS.lock().unwrap();
+                qos.on_connection_close(self.0);
+                fs_rate::set_deprioritized(qos.high_delay());
             }
             AUTHED_CONNS.lock().unwrap().retain(|c| c.conn_id != self.0);
+            video_service::record_event(RecordEvent {
+                conn_id: self.0,
+                ..RecordEvent::new(RecordEventType::Close, Value::Null)
+            });
             let remote_count = AUTHED_CONNS
                 .lock()
                 .unwrap()
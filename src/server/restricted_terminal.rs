@@ -0,0 +1,391 @@
+//! Restricted terminals, a command dispatcher instead of a shell.
+//!
+//! The dispatcher is this executable run with [`ARG`] in the PTY. It reads a
+//! command line, runs it without a shell if it matches the allowlist, and
+//! logs every invocation. Allowlist entries are separated by `;` or new
+//! lines, e.g. `ipconfig; ping *; systemctl status ...`. The first word of
+//! an entry is the command, each other word matches one argument with `*`
+//! and `?` wildcards, and a last `...` matches any further arguments.
+//! Wildcards and `...` never match options, which must be spelled out, so
+//! `systemctl status ...` doesn't allow `systemctl status --pager`.
+//!
+//! Commands get no pager, see [`NO_PAGER_ENV`], but interactive programs,
+//! e.g. `less`, `vi`, `top` or `journalctl` with a pager, must never be
+//! allowed: they run their own commands or a shell.
+use hbb_common::log;
+#[cfg(windows)]
+use std::fs::OpenOptions;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    process::Command,
+};
+
+/// The argument that runs the dispatcher.
+pub const ARG: &str = "--restricted-terminal";
+/// The allowlist is passed to the dispatcher in this variable.
+pub const ENV_ALLOWLIST: &str = "RUSTDESK_TERMINAL_ALLOWLIST";
+const ANY_ARGS: &str = "...";
+/// Set for the commands, a pager would allow `!sh`.
+const NO_PAGER_ENV: &[(&str, &str)] = &[
+    ("PAGER", "cat"),
+    ("SYSTEMD_PAGER", "cat"),
+    ("SYSTEMD_PAGERSECURE", "1"),
+    ("GIT_PAGER", "cat"),
+    ("MANPAGER", "cat"),
+    ("LESSSECURE", "1"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    command: String,
+    args: Vec<String>,
+    any_args: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    entries: Vec<Entry>,
+    // As configured, for `help`
+    lines: Vec<String>,
+}
+
+impl Allowlist {
+    /// Entries that can't be parsed are skipped with a warning.
+    pub fn parse(s: &str) -> Self {
+        let mut allowlist = Self::default();
+        for line in s.split(|c| c == ';' || c == '\n') {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = match split_args(line) {
+                Ok(words) => words,
+                Err(e) => {
+                    log::warn!("Invalid terminal allowlist entry {:?}: {}", line, e);
+                    continue;
+                }
+            };
+            let any_args = words.len() > 1 && words.last().map(|w| w.as_str()) == Some(ANY_ARGS);
+            if any_args {
+                words.pop();
+            }
+            let command = words.remove(0);
+            allowlist.entries.push(Entry {
+                command,
+                args: words,
+                any_args,
+            });
+            allowlist.lines.push(line.to_owned());
+        }
+        allowlist
+    }
+
+    pub fn is_allowed(&self, args: &[String]) -> bool {
+        let Some((command, args)) = args.split_first() else {
+            return false;
+        };
+        self.entries.iter().any(|e| {
+            let same_command = if cfg!(windows) {
+                e.command.eq_ignore_ascii_case(command)
+            } else {
+                e.command == *command
+            };
+            same_command
+                && (args.len() == e.args.len() || (e.any_args && args.len() > e.args.len()))
+                && e.args.iter().zip(args).all(|(p, a)| matches(p, a))
+                && args[e.args.len()..].iter().all(|a| !is_option(a))
+        })
+    }
+}
+
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') || (cfg!(windows) && arg.starts_with('/'))
+}
+
+// Options only match literally
+fn matches(pattern: &str, arg: &str) -> bool {
+    if is_option(arg) {
+        pattern == arg
+    } else {
+        glob(pattern, arg)
+    }
+}
+
+// `*` matches any characters, `?` one
+fn glob(pattern: &str, s: &str) -> bool {
+    let (p, s): (Vec<char>, Vec<char>) = (pattern.chars().collect(), s.chars().collect());
+    let (mut pi, mut si) = (0, 0);
+    // After the last `*` and the position in `s` it was matched up to
+    let mut star = None;
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi + 1, si));
+            pi += 1;
+        } else if let Some((sp, ss)) = star {
+            pi = sp;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Words of a command line, `'` quotes literally, `"` allows `\"` and `\\`.
+pub fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    words.push(w);
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => return Err("unterminated quote"),
+                        },
+                        Some(c) => w.push(c),
+                        None => return Err("unterminated quote"),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        words.push(w);
+    }
+    Ok(words)
+}
+
+// Ctrl+C stops the running command, not the dispatcher
+#[cfg(unix)]
+fn ignore_interrupt() {
+    unsafe {
+        hbb_common::libc::signal(hbb_common::libc::SIGINT, hbb_common::libc::SIG_IGN);
+        hbb_common::libc::signal(hbb_common::libc::SIGQUIT, hbb_common::libc::SIG_IGN);
+    }
+}
+
+#[cfg(windows)]
+fn ignore_interrupt() {
+    use winapi::{
+        shared::minwindef::{BOOL, DWORD, TRUE},
+        um::consoleapi::SetConsoleCtrlHandler,
+    };
+    // A handler isn't inherited, unlike ignoring
+    unsafe extern "system" fn handler(_: DWORD) -> BOOL {
+        TRUE
+    }
+    unsafe {
+        SetConsoleCtrlHandler(Some(handler), TRUE);
+    }
+}
+
+// The console of the PTY, the std handles of this GUI executable aren't it on Windows
+#[cfg(windows)]
+fn console() -> std::io::Result<(File, File)> {
+    let open = |name| OpenOptions::new().read(true).write(true).open(name);
+    Ok((open("CONIN$")?, open("CONOUT$")?))
+}
+
+#[cfg(not(windows))]
+fn console() -> std::io::Result<(File, File)> {
+    use std::os::fd::AsFd;
+    let input = std::io::stdin().as_fd().try_clone_to_owned()?;
+    let output = std::io::stdout().as_fd().try_clone_to_owned()?;
+    Ok((File::from(input), File::from(output)))
+}
+
+fn command(args: &[String], input: &File, output: &File) -> std::io::Result<Command> {
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..])
+        .stdin(input.try_clone()?)
+        .stdout(output.try_clone()?)
+        .stderr(output.try_clone()?)
+        .envs(NO_PAGER_ENV.iter().copied());
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        cmd.pre_exec(|| {
+            hbb_common::libc::signal(hbb_common::libc::SIGINT, hbb_common::libc::SIG_DFL);
+            hbb_common::libc::signal(hbb_common::libc::SIGQUIT, hbb_common::libc::SIG_DFL);
+            Ok(())
+        });
+    }
+    Ok(cmd)
+}
+
+/// Run the dispatcher on the console, returns the exit code.
+pub fn run() -> i32 {
+    let allowlist = Allowlist::parse(&std::env::var(ENV_ALLOWLIST).unwrap_or_default());
+    let (input, mut out) = match console() {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Restricted terminal without console: {}", e);
+            return 1;
+        }
+    };
+    ignore_interrupt();
+    log::info!(
+        "Restricted terminal started, {} entries",
+        allowlist.entries.len()
+    );
+    let mut reader = BufReader::new(input);
+    writeln!(
+        out,
+        "Restricted terminal, type `help` for the allowed commands."
+    )
+    .ok();
+    loop {
+        write!(out, "> ").ok();
+        out.flush().ok();
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return 0,
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Restricted terminal read error: {}", e);
+                return 1;
+            }
+        }
+        let line = line.trim();
+        let args = match split_args(line) {
+            Ok(args) if !args.is_empty() => args,
+            Ok(_) => continue,
+            Err(e) => {
+                writeln!(out, "{}", e).ok();
+                continue;
+            }
+        };
+        match (args[0].as_str(), args.len()) {
+            ("exit" | "logout", 1) => return 0,
+            ("help", 1) => {
+                if allowlist.lines.is_empty() {
+                    writeln!(out, "No commands are allowed.").ok();
+                }
+                for l in allowlist.lines.iter() {
+                    writeln!(out, "  {}", l).ok();
+                }
+                writeln!(out, "  help\n  exit").ok();
+                continue;
+            }
+            _ => {}
+        }
+        if !allowlist.is_allowed(&args) {
+            log::warn!("Restricted terminal denied: {}", line);
+            writeln!(out, "Not allowed: {}", line).ok();
+            continue;
+        }
+        log::info!("Restricted terminal runs: {}", line);
+        match command(&args, reader.get_ref(), &out).and_then(|mut cmd| cmd.status()) {
+            Ok(status) => log::info!("Restricted terminal command exited: {}", status),
+            Err(e) => {
+                log::warn!("Restricted terminal failed to run {}: {}", args[0], e);
+                writeln!(out, "{}: {}", args[0], e).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        split_args(s).unwrap()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(args("  ping  -c 1 host "), vec!["ping", "-c", "1", "host"]);
+        assert_eq!(
+            args(r#"echo 'a b' "c \"d\"" e'f'"#),
+            vec!["echo", "a b", r#"c "d""#, "ef"]
+        );
+        assert_eq!(args(r#""a\nb" ''"#), vec![r"a\nb", ""]);
+        assert!(split_args("echo 'a").is_err());
+        assert!(args("").is_empty());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*", ""));
+        assert!(glob("*.local", "nas.local"));
+        assert!(!glob("*.local", "nas.locals"));
+        assert!(glob("192.168.?.*", "192.168.1.20"));
+        assert!(glob("a*b*c", "aXbYbc"));
+        assert!(!glob("a*b", "a"));
+        assert!(glob("exact", "exact"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = Allowlist::parse("ipconfig; ping *\nsystemctl status ...;  ; bad 'entry");
+        assert_eq!(allowlist.lines.len(), 3);
+        assert!(allowlist.is_allowed(&args("ipconfig")));
+        assert!(!allowlist.is_allowed(&args("ipconfig /all")));
+        assert!(allowlist.is_allowed(&args("ping 10.0.0.1")));
+        assert!(!allowlist.is_allowed(&args("ping -c 1 10.0.0.1")));
+        assert!(allowlist.is_allowed(&args("systemctl status")));
+        assert!(allowlist.is_allowed(&args("systemctl status sshd cups")));
+        assert!(!allowlist.is_allowed(&args("systemctl stop sshd")));
+        assert!(!allowlist.is_allowed(&args("sh -c ping")));
+        // Wildcards don't match options
+        assert!(!allowlist.is_allowed(&args("ping -t")));
+        assert!(!allowlist.is_allowed(&args("systemctl status sshd --pager")));
+        assert!(!allowlist.is_allowed(&args("systemctl status -n100")));
+        assert!(!allowlist.is_allowed(&[]));
+        assert!(!Allowlist::parse("").is_allowed(&args("ls")));
+        // `...` alone is a command
+        assert!(Allowlist::parse("...").is_allowed(&args("...")));
+        let allowlist = Allowlist::parse("ping -c * *");
+        assert!(allowlist.is_allowed(&args("ping -c 1 10.0.0.1")));
+        assert!(!allowlist.is_allowed(&args("ping -c -f 10.0.0.1")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_no_pager() {
+        let path = std::env::temp_dir().join(format!("rustdesk_rt_env_{}", std::process::id()));
+        let output = File::create(&path).unwrap();
+        let input = File::open("/dev/null").unwrap();
+        let status = command(&args("env"), &input, &output)
+            .unwrap()
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let env = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        for (k, v) in NO_PAGER_ENV {
+            assert!(env.lines().any(|l| l == format!("{k}={v}")), "{k}");
+        }
+    }
+}
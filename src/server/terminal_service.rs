@@ -1,9 +1,10 @@
 use super::*;
 use hbb_common::{
     anyhow::{anyhow, Context, Result},
-    compress,
+    compress, config,
 };
 use portable_pty::{Child, CommandBuilder, PtySize};
+use scrap::record_cast::{CastContext, CastRecorder};
 use std::{
     collections::{HashMap, VecDeque},
     io::{Read, Write},
@@ -102,11 +103,16 @@ pub fn is_service_specified_user(service_id: &str) -> Option<bool> {
     get_service(service_id).map(|s| s.lock().unwrap().is_specified_user)
 }
 
+pub fn is_service_restricted(service_id: &str) -> Option<bool> {
+    get_service(service_id).map(|s| s.lock().unwrap().is_restricted)
+}
+
 /// Get or create a persistent terminal service
 fn get_or_create_service(
     service_id: String,
     is_persistent: bool,
     is_specified_user: bool,
+    is_restricted: bool,
 ) -> Result<Arc<Mutex<PersistentTerminalService>>> {
     let mut services = TERMINAL_SERVICES.lock().unwrap();
 
@@ -122,14 +128,16 @@ fn get_or_create_service(
         .entry(service_id.clone())
         .or_insert_with(|| {
             log::info!(
-                "Creating new terminal service: {} (persistent: {})",
+                "Creating new terminal service: {} (persistent: {}, restricted: {})",
                 service_id,
-                is_persistent
+                is_persistent,
+                is_restricted
             );
             Arc::new(Mutex::new(PersistentTerminalService::new(
                 service_id.clone(),
                 is_persistent,
                 is_specified_user,
+                is_restricted,
             )))
         })
         .clone();
@@ -310,12 +318,14 @@ pub fn new(
     service_id: String,
     is_persistent: bool,
     user_token: Option<UserToken>,
+    is_restricted: bool,
 ) -> GenericService {
     // Create the service with initial persistence setting
     allow_err!(get_or_create_service(
         service_id.clone(),
         is_persistent,
-        user_token.is_some()
+        user_token.is_some(),
+        is_restricted
     ));
     let svc = TerminalService {
         sp: GenericService::new(service_id.clone(), false),
@@ -327,8 +337,9 @@ pub fn new(
 
 fn run(sp: TerminalService, service_id: String) -> ResultType<()> {
     while sp.ok() {
-        let responses = TerminalServiceProxy::new(service_id.clone(), None, sp.user_token.clone())
-            .read_outputs();
+        let responses =
+            TerminalServiceProxy::new(service_id.clone(), None, sp.user_token.clone(), None)
+                .read_outputs();
         for response in responses {
             let mut msg_out = Message::new();
             msg_out.set_terminal_response(response);
@@ -349,6 +360,42 @@ fn run(sp: TerminalService, service_id: String) -> ResultType<()> {
     Ok(())
 }
 
+// Terminals are recorded with the video of incoming sessions
+fn new_recorder(terminal_id: i32, rows: u16, cols: u16, shell: &str) -> Option<CastRecorder> {
+    if !config::option2bool(
+        "allow-auto-record-incoming",
+        &Config::get_option("allow-auto-record-incoming"),
+    ) {
+        return None;
+    }
+    let (dir, tx) = video_service::start_incoming_record();
+    CastRecorder::new(CastContext {
+        id: Config::get_id(),
+        dir,
+        terminal_id,
+        cols,
+        rows,
+        shell: shell.to_owned(),
+        tx,
+        encryption_key: Config::get_option(config::keys::OPTION_RECORD_ENCRYPTION_KEY),
+    })
+    .map_err(|e| log::error!("Failed to create terminal recorder: {e}"))
+    .ok()
+}
+
+// The dispatcher with the allowlist of the options
+fn restricted_command() -> Result<(String, CommandBuilder)> {
+    use super::restricted_terminal;
+
+    let exe = std::env::current_exe().context("Failed to get the executable")?;
+    let allowlist = Config::get_option(config::keys::OPTION_TERMINAL_ALLOWLIST);
+    log::info!("Restricted terminal with allowlist: {}", allowlist);
+    let mut cmd = CommandBuilder::new(&exe);
+    cmd.arg(restricted_terminal::ARG);
+    cmd.env(restricted_terminal::ENV_ALLOWLIST, allowlist);
+    Ok((exe.to_string_lossy().to_string(), cmd))
+}
+
 /// Output buffer for terminal session
 struct OutputBuffer {
     lines: VecDeque<Vec<u8>>,
@@ -458,6 +505,7 @@ pub struct TerminalSession {
     // Track if we've already sent the closed message
     closed_message_sent: bool,
     is_opened: bool,
+    recorder: Option<CastRecorder>,
 }
 
 impl TerminalSession {
@@ -479,6 +527,7 @@ impl TerminalSession {
             cols,
             closed_message_sent: false,
             is_opened: false,
+            recorder: None,
         }
     }
 
@@ -557,10 +606,17 @@ pub struct PersistentTerminalService {
     pub is_persistent: bool,
     needs_session_sync: bool,
     is_specified_user: bool,
+    // Terminals run the command dispatcher, see `restricted_terminal`
+    is_restricted: bool,
 }
 
 impl PersistentTerminalService {
-    pub fn new(service_id: String, is_persistent: bool, is_specified_user: bool) -> Self {
+    pub fn new(
+        service_id: String,
+        is_persistent: bool,
+        is_specified_user: bool,
+        is_restricted: bool,
+    ) -> Self {
         Self {
             service_id,
             sessions: HashMap::new(),
@@ -569,6 +625,7 @@ impl PersistentTerminalService {
             is_persistent,
             needs_session_sync: false,
             is_specified_user,
+            is_restricted,
         }
     }
 
@@ -625,6 +682,7 @@ impl PersistentTerminalService {
 pub struct TerminalServiceProxy {
     service_id: String,
     is_persistent: bool,
+    is_restricted: bool,
     #[cfg(target_os = "windows")]
     user_token: Option<UserToken>,
 }
@@ -643,6 +701,7 @@ impl TerminalServiceProxy {
         service_id: String,
         is_persistent: Option<bool>,
         _user_token: Option<UserToken>,
+        is_restricted: Option<bool>,
     ) -> Self {
         // Get persistence from the service if it exists
         let is_persistent =
@@ -651,9 +710,13 @@ impl TerminalServiceProxy {
             } else {
                 false
             });
+        let is_restricted = is_restricted.unwrap_or_else(|| {
+            get_service(&service_id).map_or(false, |s| s.lock().unwrap().is_restricted)
+        });
         TerminalServiceProxy {
             service_id,
             is_persistent,
+            is_restricted,
             #[cfg(target_os = "windows")]
             user_token: _user_token,
         }
@@ -700,6 +763,9 @@ impl TerminalServiceProxy {
             Some(terminal_action::Union::Close(close)) => {
                 self.handle_close(&mut service.lock().unwrap(), close)
             }
+            Some(terminal_action::Union::List(_)) => {
+                Ok(Some(self.handle_list(&service.lock().unwrap())))
+            }
             _ => Ok(None),
         }
     }
@@ -711,6 +777,20 @@ impl TerminalServiceProxy {
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
 
+        // A restricted connection must not reach a shell, and the other way round
+        if self.is_restricted != service.is_restricted {
+            log::warn!(
+                "Terminal {} of service {} refused, restricted: {}",
+                open.terminal_id,
+                service.service_id,
+                self.is_restricted
+            );
+            let mut error = TerminalError::new();
+            error.message = "Terminal service restriction mismatch".to_owned();
+            response.set_error(error);
+            return Ok(Some(response));
+        }
+
         // Check if terminal already exists
         if let Some(session_arc) = service.sessions.get(&open.terminal_id) {
             // Reconnect to existing terminal
@@ -767,12 +847,16 @@ impl TerminalServiceProxy {
         let pty_system = portable_pty::native_pty_system();
         let pty_pair = pty_system.openpty(pty_size).context("Failed to open PTY")?;
 
-        // Use default shell for the platform
-        let shell = get_default_shell();
-        log::debug!("Using shell: {}", shell);
-
         #[allow(unused_mut)]
-        let mut cmd = CommandBuilder::new(&shell);
+        let (shell, mut cmd) = if self.is_restricted {
+            restricted_command()?
+        } else {
+            // Use default shell for the platform
+            let shell = get_default_shell();
+            let cmd = CommandBuilder::new(&shell);
+            (shell, cmd)
+        };
+        log::debug!("Using shell: {}", shell);
 
         #[cfg(target_os = "windows")]
         if let Some(token) = &self.user_token {
@@ -886,6 +970,7 @@ impl TerminalServiceProxy {
         session.reader_thread = Some(reader_thread);
         session.writer_thread = Some(writer_thread);
         session.is_opened = true;
+        session.recorder = new_recorder(open.terminal_id, open.rows as _, open.cols as _, &shell);
 
         let mut opened = TerminalOpened::new();
         opened.terminal_id = open.terminal_id;
@@ -925,6 +1010,9 @@ impl TerminalServiceProxy {
             session.update_activity();
             session.rows = resize.rows as u16;
             session.cols = resize.cols as u16;
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.resize(resize.cols as u16, resize.rows as u16);
+            }
 
             if let Some(pty_pair) = &session.pty_pair {
                 pty_pair.master.resize(PtySize {
@@ -946,6 +1034,9 @@ impl TerminalServiceProxy {
         if let Some(session_arc) = session {
             let mut session = session_arc.lock().unwrap();
             session.update_activity();
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.input(&data.data);
+            }
             if let Some(input_tx) = &session.input_tx {
                 // Send data to writer thread
                 if let Err(e) = input_tx.send(data.data.to_vec()) {
@@ -989,6 +1080,26 @@ impl TerminalServiceProxy {
         }
     }
 
+    fn handle_list(&self, service: &PersistentTerminalService) -> TerminalResponse {
+        let mut list = TerminalList::new();
+        list.service_id = service.service_id.clone();
+        let mut terminals = service.list_terminals();
+        terminals.sort_by_key(|t| t.0);
+        list.terminals = terminals
+            .into_iter()
+            .map(|(terminal_id, title, pid, created_at)| TerminalInfo {
+                terminal_id,
+                title,
+                pid,
+                age_secs: created_at.elapsed().as_secs(),
+                ..Default::default()
+            })
+            .collect();
+        let mut response = TerminalResponse::new();
+        response.set_list(list);
+        response
+    }
+
     pub fn read_outputs(&self) -> Vec<TerminalResponse> {
         let service = match get_service(&self.service_id) {
             Some(s) => s,
@@ -1049,6 +1160,9 @@ impl TerminalServiceProxy {
                 // Update buffer after reading
                 for data in &received_data {
                     session.output_buffer.append(data);
+                    if let Some(recorder) = session.recorder.as_mut() {
+                        recorder.output(data);
+                    }
                 }
 
                 // Process received data for responses
@@ -1091,6 +1205,7 @@ impl TerminalServiceProxy {
                     if let Some(session_arc) = sessions.remove(&terminal_id) {
                         service.lock().unwrap().sessions.remove(&terminal_id);
                         let mut session = session_arc.lock().unwrap();
+                        session.recorder = None;
                         // Take the child and add to zombie reaper
                         if let Some(mut child) = session.child.take() {
                             // Try to get exit code if available
@@ -1104,6 +1219,7 @@ impl TerminalServiceProxy {
                     // For persistent sessions, just clear the child reference
                     if let Some(session_arc) = sessions.get(&terminal_id) {
                         let mut session = session_arc.lock().unwrap();
+                        session.recorder = None;
                         if let Some(mut child) = session.child.take() {
                             // Try to get exit code if available
                             if let Ok(Some(status)) = child.try_wait() {
@@ -168,6 +168,14 @@ impl VideoQoS {
         self.users.iter().any(|u| u.1.record)
     }
 
+    // Check if any user's network delay is too high for smooth video,
+    // file transfers then give way
+    pub fn high_delay(&self) -> bool {
+        self.users
+            .iter()
+            .any(|u| u.1.delay.avg_delay() >= DELAY_THRESHOLD_150MS)
+    }
+
     pub fn set_support_changing_quality(&mut self, video_service_name: &str, support: bool) {
         if let Some(display) = self.displays.get_mut(video_service_name) {
             display.support_changing_quality = support;
@@ -593,3 +601,123 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scrap::{
+        codec::{Encoder, EncoderCfg},
+        synthetic::{SyntheticCapturer, SyntheticConfig},
+        vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
+    };
+
+    const DISPLAY: &str = "display0";
+
+    // Synthetic frames encoded at the pace of the QoS the way the video
+    // service does, on a virtual clock
+    struct Pipeline {
+        qos: VideoQoS,
+        capturer: SyntheticCapturer,
+        encoder: Encoder,
+        ratio: f32,
+        elapsed: Duration,
+        yuv: Vec<u8>,
+        mid_data: Vec<u8>,
+    }
+
+    impl Pipeline {
+        fn new() -> Self {
+            let mut qos = VideoQoS::default();
+            qos.on_connection_open(1);
+            qos.new_display(DISPLAY.to_owned());
+            qos.set_support_changing_quality(DISPLAY, true);
+            // Past the initial fps of a new connection
+            qos.new_user_instant = Instant::now() - Duration::from_secs(1);
+            let (width, height) = (160, 120);
+            let capturer = SyntheticCapturer::new(SyntheticConfig {
+                width,
+                height,
+                ..Default::default()
+            })
+            .unwrap();
+            let ratio = qos.ratio();
+            let encoder = Encoder::new(
+                EncoderCfg::VPX(VpxEncoderConfig {
+                    width: width as _,
+                    height: height as _,
+                    quality: ratio,
+                    codec: VpxVideoCodecId::VP9,
+                    keyframe_interval: None,
+                }),
+                false,
+            )
+            .unwrap();
+            qos.store_bitrate(encoder.bitrate());
+            Self {
+                qos,
+                capturer,
+                encoder,
+                ratio,
+                elapsed: Duration::ZERO,
+                yuv: vec![],
+                mid_data: vec![],
+            }
+        }
+
+        // Runs `secs` seconds with `delay` reported once a second, returns
+        // the frames encoded in the last one
+        fn run(&mut self, delay: u32, secs: u64) -> usize {
+            let (start, mut encoded) = (self.elapsed, 0);
+            for second in 1..=secs {
+                self.qos.user_network_delay(1, delay);
+                let ratio = self.qos.ratio();
+                if ratio != self.ratio {
+                    self.ratio = ratio;
+                    self.encoder.set_quality(ratio).unwrap();
+                    self.qos.store_bitrate(self.encoder.bitrate());
+                }
+                encoded = 0;
+                let end = start + Duration::from_secs(second);
+                while self.elapsed < end {
+                    if let Ok(frame) = self.capturer.frame_at(self.elapsed) {
+                        let input = frame
+                            .to(self.encoder.yuvfmt(), &mut self.yuv, &mut self.mid_data)
+                            .unwrap();
+                        let ms = self.elapsed.as_millis() as _;
+                        if self.encoder.encode_to_message(input, ms).is_ok() {
+                            encoded += 1;
+                        }
+                    }
+                    self.elapsed += self.qos.spf();
+                }
+                // The ratio is adjusted every few seconds of real time
+                if second % ADJUST_RATIO_INTERVAL as u64 == 0 {
+                    self.qos.adjust_ratio_instant =
+                        Instant::now() - Duration::from_secs(ADJUST_RATIO_INTERVAL as _);
+                }
+                self.qos.update_display_data(DISPLAY, encoded);
+            }
+            encoded
+        }
+    }
+    #[test]
+    fn test_synthetic_reaction() {
+        let mut p = Pipeline::new();
+        let good = p.run(20, 9);
+        let (good_fps, good_ratio) = (p.qos.fps(), p.qos.ratio());
+        assert!(good_fps >= 10, "{}", good_fps);
+        assert!(good + 2 >= good_fps as usize, "{} {}", good, good_fps);
+
+        let bad = p.run(600, 9);
+        let (bad_fps, bad_ratio) = (p.qos.fps(), p.qos.ratio());
+        assert!(bad_fps < good_fps / 2, "{} {}", bad_fps, good_fps);
+        assert!(bad < good / 2, "{} {}", bad, good);
+        assert!(bad_ratio < good_ratio, "{} {}", bad_ratio, good_ratio);
+
+        // Recovers with the network
+        let recovered = p.run(20, 18);
+        assert!(p.qos.fps() > bad_fps);
+        assert!(recovered > bad, "{} {}", recovered, bad);
+        assert!(p.qos.ratio() > bad_ratio, "{} {}", p.qos.ratio(), bad_ratio);
+    }
+}
@@ -0,0 +1,224 @@
+//! Redaction of screen regions before the frames are encoded, configured on
+//! the controlled side with `OPTION_REDACT_REGIONS`, see `scrap::redact`.
+//!
+//! It fails closed: an invalid option, or window rules where windows can't be
+//! listed, redact the whole frame.
+//!
+//! Listing the windows takes a few round trips to the X server per window, so
+//! the matched windows are listed again every `WINDOWS_INTERVAL` rather than
+//! every frame, and padded by `WINDOW_PADDING` as they may have moved since.
+use super::*;
+#[cfg(target_os = "linux")]
+use crate::platform::linux::is_x11;
+use hbb_common::config::keys::OPTION_REDACT_REGIONS;
+use scrap::redact::{RedactConfig, RedactRect, RedactStyle};
+#[cfg(target_os = "linux")]
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const WINDOWS_INTERVAL: Duration = Duration::from_millis(100);
+/// In screen pixels around each matched window
+#[cfg(target_os = "linux")]
+const WINDOW_PADDING: i32 = 32;
+
+pub fn option() -> String {
+    Config::get_option(OPTION_REDACT_REGIONS)
+}
+
+/// Follows the option and the matched windows, frame by frame.
+#[derive(Default)]
+pub struct Redactor {
+    option: String,
+    // `None` if the option is invalid
+    config: Option<RedactConfig>,
+    // The matched windows and when they were listed
+    windows: Option<(Instant, Option<Vec<RedactRect>>)>,
+    #[cfg(target_os = "linux")]
+    x11: Option<Rc<scrap::x11::Server>>,
+}
+
+impl Redactor {
+    /// The regions of the frame of `width` x `height` at `origin` on the
+    /// screen and how to redact them, `None` if nothing is redacted.
+    pub fn regions(
+        &mut self,
+        origin: (i32, i32),
+        width: usize,
+        height: usize,
+    ) -> Option<(Vec<RedactRect>, RedactStyle)> {
+        self.regions_of(option(), origin, width, height)
+    }
+
+    fn regions_of(
+        &mut self,
+        option: String,
+        origin: (i32, i32),
+        width: usize,
+        height: usize,
+    ) -> Option<(Vec<RedactRect>, RedactStyle)> {
+        if option != self.option {
+            self.config = match RedactConfig::parse(&option) {
+                Ok(cfg) => {
+                    log::info!("redaction: {:?}", cfg);
+                    Some(cfg)
+                }
+                Err(e) => {
+                    log::error!("Invalid redaction, the whole screen is redacted: {}", e);
+                    None
+                }
+            };
+            self.option = option;
+            self.windows = None;
+        }
+        if self.option.is_empty() {
+            return None;
+        }
+        let whole = vec![RedactRect {
+            x: 0,
+            y: 0,
+            w: width as _,
+            h: height as _,
+        }];
+        let Some(cfg) = self.config.as_ref() else {
+            return Some((whole, RedactStyle::Black));
+        };
+        let mut rects: Vec<_> = cfg
+            .rects
+            .iter()
+            .map(|r| r.offset(-origin.0, -origin.1))
+            .collect();
+        if !cfg.windows.is_empty() {
+            #[cfg(target_os = "linux")]
+            let x11 = &mut self.x11;
+            let windows = cached_windows(&mut self.windows, Instant::now(), || {
+                #[cfg(target_os = "linux")]
+                return matched_windows(x11, &cfg.windows);
+                #[cfg(not(target_os = "linux"))]
+                None
+            });
+            match windows {
+                Some(windows) => {
+                    rects.extend(windows.iter().map(|r| r.offset(-origin.0, -origin.1)))
+                }
+                None => return Some((whole, cfg.style)),
+            }
+        }
+        Some((rects, cfg.style))
+    }
+}
+
+// The matched windows of `cache` if listed less than `WINDOWS_INTERVAL` ago,
+// else listed again with `list`
+fn cached_windows(
+    cache: &mut Option<(Instant, Option<Vec<RedactRect>>)>,
+    now: Instant,
+    list: impl FnOnce() -> Option<Vec<RedactRect>>,
+) -> Option<Vec<RedactRect>> {
+    if let Some((at, windows)) = cache {
+        if now.saturating_duration_since(*at) < WINDOWS_INTERVAL {
+            return windows.clone();
+        }
+    }
+    let windows = list();
+    *cache = Some((now, windows.clone()));
+    windows
+}
+
+// The padded places of the windows matching `patterns` on the screen, `None`
+// if the windows can't be listed
+#[cfg(target_os = "linux")]
+fn matched_windows(
+    x11: &mut Option<Rc<scrap::x11::Server>>,
+    patterns: &[String],
+) -> Option<Vec<RedactRect>> {
+    if !is_x11() {
+        return None;
+    }
+    if x11.is_none() {
+        *x11 = scrap::x11::Server::default().ok();
+    }
+    let windows = scrap::x11::windows(x11.as_ref()?);
+    Some(
+        windows
+            .into_iter()
+            .filter(|w| patterns.iter().any(|p| w.matches(p)))
+            .map(|w| RedactRect {
+                x: w.rect.x as i32 - WINDOW_PADDING,
+                y: w.rect.y as i32 - WINDOW_PADDING,
+                w: w.rect.w as i32 + WINDOW_PADDING * 2,
+                h: w.rect.h as i32 + WINDOW_PADDING * 2,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions() {
+        let mut redactor = Redactor::default();
+        let mut regions =
+            |option: &str| redactor.regions_of(option.to_owned(), (1920, 0), 1280, 720);
+        assert_eq!(regions(""), None);
+        // In the coordinates of the frame
+        assert_eq!(
+            regions("rect=2000,100,50,60;style=pixelate"),
+            Some((
+                vec![RedactRect {
+                    x: 80,
+                    y: 100,
+                    w: 50,
+                    h: 60
+                }],
+                RedactStyle::Pixelate
+            ))
+        );
+        let whole = vec![RedactRect {
+            x: 0,
+            y: 0,
+            w: 1280,
+            h: 720,
+        }];
+        assert_eq!(
+            regions("rect=2000,100"),
+            Some((whole.clone(), RedactStyle::Black))
+        );
+        // No windows to list here
+        #[cfg(not(target_os = "linux"))]
+        assert_eq!(regions("window=bank"), Some((whole, RedactStyle::Black)));
+    }
+
+    #[test]
+    fn test_cached_windows() {
+        let mut cache = None;
+        let mut listed = 0;
+        let start = Instant::now();
+        let bank = vec![RedactRect {
+            x: 10,
+            y: 20,
+            w: 30,
+            h: 40,
+        }];
+        let mut list = |cache: &mut _, at| {
+            cached_windows(cache, at, || {
+                listed += 1;
+                Some(bank.clone())
+            })
+        };
+        assert_eq!(list(&mut cache, start), Some(bank.clone()));
+        assert_eq!(
+            list(&mut cache, start + WINDOWS_INTERVAL / 2),
+            Some(bank.clone())
+        );
+        assert_eq!(
+            list(&mut cache, start + WINDOWS_INTERVAL),
+            Some(bank.clone())
+        );
+        assert_eq!(listed, 2);
+        // Failing to list is cached too, the whole frame stays redacted
+        let mut cache = Some((start, None));
+        assert_eq!(cached_windows(&mut cache, start, || unreachable!()), None);
+    }
+}
@@ -47,7 +47,10 @@ use scrap::Capturer;
 use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg},
-    record::{Recorder, RecorderContext},
+    record::{RecordState, Recorder, RecorderContext},
+    record_event::{RecordEvent, RecordEventType},
+    redact::{redact_rgba, redact_yuv},
+    synthetic::{SyntheticCapturer, SyntheticConfig},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
 };
@@ -71,6 +74,11 @@ lazy_static::lazy_static! {
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     static ref SCREENSHOTS: Mutex<HashMap<usize, Screenshot>> = Default::default();
+    // Recorders of incoming sessions, which the audio service writes to
+    static ref RECORDERS: Mutex<Vec<std::sync::Weak<Mutex<Option<Recorder>>>>> = Default::default();
+    static ref RECORD_AUDIO_CHANNELS: Mutex<u32> = Mutex::new(2);
+    // Logins of open connections, for recordings started after them
+    static ref RECORD_LOGINS: Mutex<Vec<RecordEvent>> = Default::default();
 }
 
 struct Screenshot {
@@ -138,6 +146,8 @@ impl VideoFrameController {
 pub enum VideoSource {
     Monitor,
     Camera,
+    Synthetic,
+    Window,
 }
 
 impl VideoSource {
@@ -145,6 +155,8 @@ impl VideoSource {
         match self {
             VideoSource::Monitor => "monitor",
             VideoSource::Camera => "camera",
+            VideoSource::Synthetic => "synthetic",
+            VideoSource::Window => "window",
         }
     }
 
@@ -157,6 +169,164 @@ impl VideoSource {
     }
 }
 
+/// Synthetic frames replace the displays if `OPTION_SYNTHETIC_CAPTURE` is set,
+/// to run without a display, e.g. in tests and demos.
+pub fn is_synthetic_capture() -> bool {
+    !Config::get_option(config::keys::OPTION_SYNTHETIC_CAPTURE).is_empty()
+}
+
+/// A single window is shared instead of the displays if `OPTION_SHARE_WINDOW`
+/// is set. X11 only, elsewhere nothing is shared rather than the displays.
+pub fn is_window_sharing() -> bool {
+    !Config::get_option(config::keys::OPTION_SHARE_WINDOW).is_empty()
+}
+
+/// The source and index of the video service new connections subscribe to.
+pub fn primary_video_service() -> (VideoSource, usize) {
+    if is_synthetic_capture() {
+        (VideoSource::Synthetic, 0)
+    } else if is_window_sharing() {
+        (VideoSource::Window, 0)
+    } else {
+        (VideoSource::Monitor, *display_service::PRIMARY_DISPLAY_IDX)
+    }
+}
+
+fn synthetic_capturer() -> ResultType<SyntheticCapturer> {
+    let cfg = SyntheticConfig::parse(&Config::get_option(config::keys::OPTION_SYNTHETIC_CAPTURE))?;
+    SyntheticCapturer::new(cfg)
+}
+
+// The topmost window matching `OPTION_SHARE_WINDOW`
+#[cfg(target_os = "linux")]
+fn shared_window() -> ResultType<scrap::x11::Window> {
+    if !is_x11() {
+        bail!("Window sharing is only supported on X11");
+    }
+    let pattern = Config::get_option(config::keys::OPTION_SHARE_WINDOW);
+    scrap::WindowCapturer::windows()?
+        .into_iter()
+        .rev()
+        .find(|w| w.matches(&pattern))
+        .ok_or_else(|| anyhow!("No window matches {:?}", pattern))
+}
+
+/// The only display of the synthetic capture or the window sharing.
+pub fn single_display_info(source: VideoSource) -> ResultType<DisplayInfo> {
+    let (width, height, name) = match source {
+        VideoSource::Synthetic => {
+            let capturer = synthetic_capturer()?;
+            (capturer.width(), capturer.height(), "synthetic".to_owned())
+        }
+        #[cfg(target_os = "linux")]
+        VideoSource::Window => {
+            let window = shared_window()?;
+            (window.rect.w as _, window.rect.h as _, window.title)
+        }
+        #[cfg(not(target_os = "linux"))]
+        VideoSource::Window => bail!("Window sharing is only supported on X11"),
+        _ => bail!("{:?} is not a single display source", source),
+    };
+    Ok(DisplayInfo {
+        width: width as _,
+        height: height as _,
+        name,
+        online: true,
+        ..Default::default()
+    })
+}
+
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    // Updated by the capturer of the shared window as it moves
+    static ref SHARED_WINDOW_STATE: Mutex<Option<Arc<Mutex<scrap::x11::WindowState>>>> = Default::default();
+}
+
+// The state of the shared window, none while it isn't captured
+#[cfg(target_os = "linux")]
+fn shared_window_state() -> Option<scrap::x11::WindowState> {
+    let lock = SHARED_WINDOW_STATE.lock().unwrap();
+    let state = lock.as_ref()?;
+    // Stale once the capturer is gone
+    if Arc::strong_count(state) < 2 {
+        return None;
+    }
+    let state = state.lock().unwrap().clone();
+    Some(state)
+}
+
+// (x, y, width, height) of the shared window on the screen, none while it
+// isn't captured
+#[cfg(target_os = "linux")]
+fn shared_window_rect() -> Option<(i32, i32, i32, i32)> {
+    let r = shared_window_state()?.rect;
+    Some((r.x as _, r.y as _, r.w as _, r.h as _))
+}
+
+// The point on the screen of the mouse event at (x, y) on the window at `rect`.
+// Clicks and moves stay inside the window, wheel and trackpad events carry
+// deltas.
+#[cfg(target_os = "linux")]
+fn window_point_to_screen(mask: i32, x: i32, y: i32, rect: (i32, i32, i32, i32)) -> (i32, i32) {
+    use crate::input::*;
+    match mask & 0x7 {
+        MOUSE_TYPE_MOVE | MOUSE_TYPE_DOWN | MOUSE_TYPE_UP => (
+            rect.0 + x.clamp(0, (rect.2 - 1).max(0)),
+            rect.1 + y.clamp(0, (rect.3 - 1).max(0)),
+        ),
+        _ => (x, y),
+    }
+}
+
+/// Moves the mouse event on the shared window to the screen, false if it must
+/// be dropped, e.g. as the window isn't captured or the point is on a window
+/// above it.
+#[cfg(target_os = "linux")]
+pub fn window_to_screen(me: &mut MouseEvent) -> bool {
+    use crate::input::*;
+    let Some(state) = shared_window_state() else {
+        return false;
+    };
+    let r = state.rect;
+    let rect = (r.x as _, r.y as _, r.w as _, r.h as _);
+    let (x, y) = window_point_to_screen(me.mask, me.x, me.y, rect);
+    // Wheel and trackpad events go to the window under the cursor
+    let pointed = matches!(
+        me.mask & 0x7,
+        MOUSE_TYPE_MOVE | MOUSE_TYPE_DOWN | MOUSE_TYPE_UP
+    );
+    if pointed && state.is_covered(x, y) {
+        return false;
+    }
+    (me.x, me.y) = (x, y);
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn window_to_screen(_: &mut MouseEvent) -> bool {
+    false
+}
+
+/// If the keys can go to the shared window, i.e. it has the input focus.
+#[cfg(target_os = "linux")]
+pub fn shared_window_focused() -> bool {
+    shared_window_state().is_some_and(|s| s.focused)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn shared_window_focused() -> bool {
+    false
+}
+
+/// Moves the cursor position on the screen to the shared window.
+#[cfg(target_os = "linux")]
+pub fn screen_to_window(pos: &mut CursorPosition) {
+    if let Some((x, y, _, _)) = shared_window_rect() {
+        pos.x -= x;
+        pos.y -= y;
+    }
+}
+
 #[derive(Clone)]
 pub struct VideoService {
     sp: GenericService,
@@ -452,6 +622,68 @@ fn get_capturer_camera(current: usize) -> ResultType<CapturerInfo> {
         capturer,
     });
 }
+
+fn get_capturer_synthetic(current: usize) -> ResultType<CapturerInfo> {
+    if current != 0 {
+        bail!(
+            "Failed to get synthetic display {}, displays len: 1",
+            current
+        );
+    }
+    let capturer = synthetic_capturer()?;
+    let (width, height) = (capturer.width(), capturer.height());
+    log::debug!("synthetic capture, width={}, height={}", width, height);
+    Ok(CapturerInfo {
+        origin: (0, 0),
+        width,
+        height,
+        ndisplay: 1,
+        current,
+        privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        _capturer_privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        capturer: Box::new(capturer),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn get_capturer_window(current: usize) -> ResultType<CapturerInfo> {
+    if current != 0 {
+        bail!("Failed to get window display {}, displays len: 1", current);
+    }
+    let window = shared_window()?;
+    let capturer = scrap::WindowCapturer::new(window.id)?;
+    let (width, height) = (capturer.width(), capturer.height());
+    log::debug!(
+        "window capture, id={:#x}, class={}, width={}, height={}",
+        window.id,
+        window.class,
+        width,
+        height
+    );
+    *SHARED_WINDOW_STATE.lock().unwrap() = Some(capturer.state());
+    Ok(CapturerInfo {
+        origin: (0, 0),
+        width,
+        height,
+        ndisplay: 1,
+        current,
+        privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        _capturer_privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        capturer: Box::new(capturer),
+    })
+}
+
+// The place of the captured frame on the screen, where the redacted regions are
+fn redaction_origin(source: VideoSource, origin: (i32, i32)) -> (i32, i32) {
+    match source {
+        #[cfg(target_os = "linux")]
+        VideoSource::Window => shared_window_rect()
+            .map(|(x, y, _, _)| (x, y))
+            .unwrap_or_default(),
+        _ => origin,
+    }
+}
+
 fn get_capturer(
     source: VideoSource,
     current: usize,
@@ -460,6 +692,11 @@ fn get_capturer(
     match source {
         VideoSource::Monitor => get_capturer_monitor(current, portable_service_running),
         VideoSource::Camera => get_capturer_camera(current),
+        VideoSource::Synthetic => get_capturer_synthetic(current),
+        #[cfg(target_os = "linux")]
+        VideoSource::Window => get_capturer_window(current),
+        #[cfg(not(target_os = "linux"))]
+        VideoSource::Window => bail!("Window sharing is only supported on X11"),
     }
 }
 
@@ -584,7 +821,10 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut first_frame = true;
     let capture_width = c.width;
     let capture_height = c.height;
+    let capture_origin = c.origin;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    let mut redactor = video_redact::Redactor::default();
+    let redaction_option = video_redact::option();
 
     while sp.ok() {
         #[cfg(windows)]
@@ -622,6 +862,11 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        // A static screen is not captured again, a new capturer redacts it at once
+        if video_redact::option() != redaction_option {
+            log::info!("switch due to redaction changed");
+            bail!("SWITCH");
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
@@ -655,12 +900,25 @@ fn run(vs: VideoService) -> ResultType<()> {
             Ok(frame) => {
                 repeat_encode_counter = 0;
                 if frame.valid() {
+                    // Nothing of the regions is sent, recorded or in a screenshot
+                    let redaction = if vs.source.is_camera() {
+                        None
+                    } else {
+                        let origin = redaction_origin(vs.source, capture_origin);
+                        redactor.regions(origin, capture_width, capture_height)
+                    };
                     let screenshot = SCREENSHOTS.lock().unwrap().remove(&display_idx);
                     if let Some(mut screenshot) = screenshot {
                         let restore_vram = screenshot.restore_vram;
                         let (msg, w, h, data) = match &frame {
                             scrap::Frame::PixelBuffer(f) => match get_rgba_from_pixelbuf(f) {
-                                Ok(rgba) => ("".to_owned(), f.width(), f.height(), rgba),
+                                Ok(mut rgba) => {
+                                    if let Some((rects, style)) = redaction.as_ref() {
+                                        let (w, h) = (f.width(), f.height());
+                                        redact_rgba(&mut rgba, w, h, w * 4, rects, *style);
+                                    }
+                                    ("".to_owned(), f.width(), f.height(), rgba)
+                                }
                                 Err(e) => {
                                     let serr = e.to_string();
                                     log::error!(
@@ -698,7 +956,21 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
 
-                    let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
+                    let mut frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
+                    if let Some((rects, style)) = redaction.as_ref() {
+                        match frame {
+                            EncodeInput::YUV(_) => {
+                                redact_yuv(&mut yuv, &encoder.yuvfmt(), rects, *style)?;
+                                frame = EncodeInput::YUV(&yuv);
+                            }
+                            EncodeInput::Texture(_) => {
+                                log::info!("switch due to redaction, textures can't be redacted");
+                                #[cfg(all(windows, feature = "vram"))]
+                                VRamEncoder::set_not_use(sp.name(), true);
+                                bail!("SWITCH");
+                            }
+                        }
+                    }
                     let send_conn_ids = handle_one_frame(
                         display_idx,
                         &sp,
@@ -781,6 +1053,12 @@ fn run(vs: VideoService) -> ResultType<()> {
                 if vs.source.is_monitor() {
                     try_broadcast_display_changed(&sp, display_idx, &c, true)?;
                 }
+                // The window is resized or closed, the service restarts with the new size
+                if vs.source == VideoSource::Window {
+                    if let Some(msg_out) = make_display_changed_msg(display_idx, None, vs.source) {
+                        sp.send(msg_out);
+                    }
+                }
 
                 #[cfg(windows)]
                 if !c.is_gdi() {
@@ -893,7 +1171,11 @@ fn get_encoder_config(
     _source: VideoSource,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "vram"))]
-    if _portable_service || c.is_gdi() || _source == VideoSource::Camera {
+    if _portable_service
+        || c.is_gdi()
+        || !_source.is_monitor()
+        || !video_redact::option().is_empty()
+    {
         log::info!("gdi:{}, portable:{}", c.is_gdi(), _portable_service);
         VRamEncoder::set_not_use(_name, true);
     }
@@ -961,41 +1243,103 @@ fn get_encoder_config(
     }
 }
 
+/// The directory of the incoming recordings, with the retention sweeper
+/// started on it, and the channel of their upload if it is enabled.
+pub(super) fn start_incoming_record() -> (String, Option<std::sync::mpsc::Sender<RecordState>>) {
+    use crate::hbbs_http::record_upload;
+
+    #[cfg(windows)]
+    let root = crate::platform::is_root();
+    #[cfg(not(windows))]
+    let root = false;
+    let dir = crate::ui_interface::video_save_directory(root);
+    scrap::record_retention::start_sweeper(dir.clone());
+    let tx = if record_upload::is_enable() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        record_upload::run(rx);
+        Some(tx)
+    } else {
+        None
+    };
+    (dir, tx)
+}
+
 fn get_recorder(
     record_incoming: bool,
     display_idx: usize,
     camera: bool,
 ) -> Arc<Mutex<Option<Recorder>>> {
-    #[cfg(windows)]
-    let root = crate::platform::is_root();
-    #[cfg(not(windows))]
-    let root = false;
     let recorder = if record_incoming {
-        use crate::hbbs_http::record_upload;
-
-        let tx = if record_upload::is_enable() {
-            let (tx, rx) = std::sync::mpsc::channel();
-            record_upload::run(rx);
-            Some(tx)
-        } else {
-            None
-        };
+        let (dir, tx) = start_incoming_record();
         Recorder::new(RecorderContext {
             server: true,
             id: Config::get_id(),
-            dir: crate::ui_interface::video_save_directory(root),
+            dir,
             display_idx,
             camera,
             tx,
+            encryption_key: Config::get_option(config::keys::OPTION_RECORD_ENCRYPTION_KEY),
+        })
+        .map_err(|e| log::error!("Failed to create recorder: {e}"))
+        .map_or(Default::default(), |mut r| {
+            r.set_audio_channels(*RECORD_AUDIO_CHANNELS.lock().unwrap());
+            for e in RECORD_LOGINS.lock().unwrap().iter() {
+                r.write_event(e.clone());
+            }
+            Arc::new(Mutex::new(Some(r)))
         })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
     } else {
         Default::default()
     };
+    if recorder.lock().unwrap().is_some() {
+        let mut recorders = RECORDERS.lock().unwrap();
+        recorders.retain(|r| r.strong_count() > 0);
+        recorders.push(Arc::downgrade(&recorder));
+    }
 
     recorder
 }
 
+/// Set the channels of the audio encoded by the audio service.
+pub fn record_audio_format(channels: u32) {
+    *RECORD_AUDIO_CHANNELS.lock().unwrap() = channels;
+    for r in RECORDERS.lock().unwrap().iter().filter_map(|r| r.upgrade()) {
+        if let Some(r) = r.lock().unwrap().as_mut() {
+            r.set_audio_channels(channels);
+        }
+    }
+}
+
+/// Write a session event to the recordings of incoming sessions.
+pub fn record_event(event: RecordEvent) {
+    match event.event {
+        RecordEventType::Login => RECORD_LOGINS.lock().unwrap().push(event.clone()),
+        RecordEventType::Close => RECORD_LOGINS
+            .lock()
+            .unwrap()
+            .retain(|e| e.conn_id != event.conn_id),
+        _ => {}
+    }
+    for r in RECORDERS.lock().unwrap().iter().filter_map(|r| r.upgrade()) {
+        if let Some(r) = r.lock().unwrap().as_mut() {
+            r.write_event(event.clone());
+        }
+    }
+}
+
+/// Write an encoded audio frame to the recordings of incoming sessions.
+pub fn record_audio(data: &[u8]) {
+    let recorders = RECORDERS.lock().unwrap();
+    if recorders.is_empty() {
+        return;
+    }
+    for r in recorders.iter().filter_map(|r| r.upgrade()) {
+        if let Some(r) = r.lock().unwrap().as_mut() {
+            r.write_audio(data);
+        }
+    }
+}
+
 #[cfg(target_os = "android")]
 fn check_change_scale(hardware: bool) -> ResultType<()> {
     use hbb_common::config::keys::OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE as SCALE_SOFT;
@@ -1086,11 +1430,13 @@ fn handle_one_frame(
             vf.display = display as _;
             let mut msg = Message::new();
             msg.set_video_frame(vf);
-            recorder
-                .lock()
-                .unwrap()
-                .as_mut()
-                .map(|r| r.write_message(&msg, width, height));
+            let disk_notice = recorder.lock().unwrap().as_mut().and_then(|r| {
+                r.write_message(&msg, width, height);
+                r.take_disk_notice()
+            });
+            if let Some(low) = disk_notice {
+                super::connection::on_record_disk_notice(low);
+            }
             send_conn_ids = sp.send_video_frame(msg);
         }
         Err(e) => {
@@ -1198,6 +1544,7 @@ pub fn make_display_changed_msg(
             VideoSource::Camera => camera::Cameras::get_sync_cameras()
                 .get(display_idx)?
                 .clone(),
+            VideoSource::Synthetic | VideoSource::Window => single_display_info(source).ok()?,
         },
     };
     let mut misc = Misc::new();
@@ -1209,7 +1556,7 @@ pub fn make_display_changed_msg(
         height: display.height,
         cursor_embedded: match source {
             VideoSource::Monitor => display_service::capture_cursor_embedded(),
-            VideoSource::Camera => false,
+            VideoSource::Camera | VideoSource::Synthetic | VideoSource::Window => false,
         },
         #[cfg(not(target_os = "android"))]
         resolutions: Some(SupportedResolutions {
@@ -1225,6 +1572,7 @@ pub fn make_display_changed_msg(
                     .ok()
                     .into_iter()
                     .collect(),
+                VideoSource::Synthetic | VideoSource::Window => vec![],
             },
             ..SupportedResolutions::default()
         })
@@ -1347,3 +1695,81 @@ fn handle_screenshot(screenshot: Screenshot, msg: String, w: usize, h: usize, da
         log::error!("Failed to send screenshot, {}", e);
     }
 }
+
+#[cfg(test)]
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    // Frames captured in `secs` at the pace of the QoS, the synthetic content
+    // changing 30 times a second
+    fn capture(qos: &VideoQoS, secs: u64) -> usize {
+        let mut capturer = SyntheticCapturer::new(SyntheticConfig {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        })
+        .unwrap();
+        let (mut elapsed, mut frames) = (Duration::ZERO, 0);
+        while elapsed < Duration::from_secs(secs) {
+            if capturer.frame_at(elapsed).is_ok() {
+                frames += 1;
+            }
+            elapsed += qos.spf();
+        }
+        frames
+    }
+
+    fn assert_captured(qos: &VideoQoS) {
+        let expected = qos.fps() as usize * 10;
+        let frames = capture(qos, 10);
+        assert!(frames.abs_diff(expected) <= 1, "{} != {}", frames, expected);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_window_point_to_screen() {
+        use crate::input::*;
+        let rect = (100, 50, 640, 480);
+        assert_eq!(
+            window_point_to_screen(MOUSE_TYPE_MOVE, 10, 20, rect),
+            (110, 70)
+        );
+        let down = MOUSE_TYPE_DOWN | (MOUSE_BUTTON_LEFT << 3);
+        assert_eq!(window_point_to_screen(down, -5, 1000, rect), (100, 529));
+        assert_eq!(
+            window_point_to_screen(MOUSE_TYPE_UP, 640, 0, rect),
+            (739, 50)
+        );
+        assert_eq!(
+            window_point_to_screen(MOUSE_TYPE_WHEEL, 0, -120, rect),
+            (0, -120)
+        );
+    }
+
+    #[test]
+    fn test_qos_reaction() {
+        let mut qos = VideoQoS::default();
+        qos.on_connection_open(1);
+        for _ in 0..5 {
+            qos.user_network_delay(1, 20);
+        }
+        let good_fps = qos.fps();
+        assert!(good_fps >= 10, "{}", good_fps);
+        assert_captured(&qos);
+
+        for _ in 0..5 {
+            qos.user_network_delay(1, 600);
+        }
+        let bad_fps = qos.fps();
+        assert!(bad_fps < good_fps / 2, "{} {}", bad_fps, good_fps);
+        assert_captured(&qos);
+
+        // Recovers with the network
+        for _ in 0..20 {
+            qos.user_network_delay(1, 20);
+        }
+        assert!(qos.fps() > bad_fps);
+        assert_captured(&qos);
+    }
+}
@@ -514,6 +514,7 @@ impl sciter::EventHandler for SciterSession {
         fn confirm_delete_files(i32, i32);
         fn set_no_confirm(i32);
         fn cancel_job(i32);
+        fn schedule_job(i32, bool, i32);
         fn send_files(i32, i32, String, String, i32, bool, bool);
         fn add_job(i32, i32, String, String, i32, bool, bool);
         fn resume_job(i32, bool);
@@ -2,6 +2,8 @@
 use crate::ipc::Connection;
 #[cfg(not(any(target_os = "ios")))]
 use crate::ipc::{self, Data};
+#[cfg(not(any(target_os = "ios")))]
+use crate::file_sandbox::{self, FileSandbox};
 #[cfg(target_os = "windows")]
 use crate::{clipboard::ClipboardSide, ipc::ClipboardNonFile};
 #[cfg(target_os = "windows")]
@@ -440,9 +442,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     self.cm.new_message(self.conn_id, text);
                                 }
                                 Data::FS(mut fs) => {
-                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
+                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed, delta_copy_blocks, delta_block_size, content_hash } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
-                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
+                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed, delta_copy_blocks, delta_block_size, content_hash};
                                             handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
                                         }
                                     } else {
@@ -754,6 +756,14 @@ async fn handle_fs(
 
     use hbb_common::fs::serialize_transfer_job;
 
+    let sandbox = FileSandbox::load();
+    if let Err(err) = sandbox.check_fs(&fs) {
+        log::warn!("File operation denied: {}", err);
+        if let Some((id, file_num)) = file_sandbox::fs_job(&fs) {
+            send_raw(fs::new_error(id, err, file_num), tx);
+        }
+        return;
+    }
     match fs {
         ipc::FS::ReadEmptyDirs {
             dir,
@@ -765,7 +775,7 @@ async fn handle_fs(
             dir,
             include_hidden,
         } => {
-            read_dir(&dir, include_hidden, tx).await;
+            read_dir(&dir, include_hidden, &sandbox, tx).await;
         }
         ipc::FS::RemoveDir {
             path,
@@ -824,8 +834,13 @@ async fn handle_fs(
         ipc::FS::WriteDone { id, file_num } => {
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.modify_time();
-                send_raw(fs::new_done(id, file_num), tx);
-                tx_log.map(|tx| tx.send(serialize_transfer_job(&job, true, false, "")));
+                if let Some(err) = job.job_error() {
+                    send_raw(fs::new_error(id, &err, file_num), tx);
+                    tx_log.map(|tx| tx.send(serialize_transfer_job(&job, false, false, &err)));
+                } else {
+                    send_raw(fs::new_done(id, file_num), tx);
+                    tx_log.map(|tx| tx.send(serialize_transfer_job(&job, true, false, "")));
+                }
             }
         }
         ipc::FS::WriteError { id, file_num, err } => {
@@ -839,6 +854,9 @@ async fn handle_fs(
             file_num,
             data,
             compressed,
+            delta_copy_blocks,
+            delta_block_size,
+            content_hash,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -847,11 +865,20 @@ async fn handle_fs(
                         file_num,
                         data,
                         compressed,
+                        delta_copy_blocks,
+                        delta_block_size,
+                        content_hash: content_hash.into(),
                         ..Default::default()
                     })
                     .await
                 {
                     send_raw(fs::new_error(id, err, file_num), &tx);
+                } else if sandbox.is_restricted() && job.finished_size() > job.total_size() {
+                    // Sizes were checked as announced by the peer
+                    if let Some(job) = fs::remove_job(id, write_jobs) {
+                        job.remove_download_file();
+                    }
+                    send_raw(fs::new_error(id, "File larger than announced", file_num), &tx);
                 }
             }
         }
@@ -862,6 +889,8 @@ async fn handle_fs(
             last_modified,
             is_upload,
             is_resume,
+            supports_content_hash,
+            content_hash,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 let mut req = FileTransferSendConfirmRequest {
@@ -870,19 +899,24 @@ async fn handle_fs(
                     union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
                     ..Default::default()
                 };
-                let digest = FileTransferDigest {
+                let mut digest = FileTransferDigest {
                     id,
                     file_num,
                     last_modified,
                     file_size,
                     ..Default::default()
                 };
+                if supports_content_hash {
+                    digest.hash_algorithm = FileHashAlgorithm::Sha256.into();
+                    digest.content_hash = content_hash.clone().into();
+                }
                 if let Some(file) = job.files().get(file_num as usize) {
                     if let fs::DataSource::FilePath(p) = &job.data_source {
                         let path = get_string(&fs::TransferJob::join(p, &file.name));
-                        match is_write_need_confirmation(is_resume, &path, &digest) {
+                        match is_write_need_confirmation(is_resume, &path, &digest).await {
                             Ok(digest_result) => {
                                 job.set_digest(file_size, last_modified);
+                                job.set_content_hash(content_hash);
                                 match digest_result {
                                     DigestCheckResult::IsSame => {
                                         req.set_skip(true);
@@ -902,6 +936,11 @@ async fn handle_fs(
                                         let msg_out = new_send_confirm(req);
                                         send_raw(msg_out, &tx);
                                     }
+                                    DigestCheckResult::NeedContentHash => {
+                                        req.union = None;
+                                        req.need_content_hash = true;
+                                        send_raw(new_send_confirm(req), &tx);
+                                    }
                                 }
                             }
                             Err(err) => {
@@ -947,7 +986,12 @@ async fn read_empty_dirs(dir: &str, include_hidden: bool, tx: &UnboundedSender<D
 }
 
 #[cfg(not(any(target_os = "ios")))]
-async fn read_dir(dir: &str, include_hidden: bool, tx: &UnboundedSender<Data>) {
+async fn read_dir(
+    dir: &str,
+    include_hidden: bool,
+    sandbox: &FileSandbox,
+    tx: &UnboundedSender<Data>,
+) {
     let path = {
         if dir.is_empty() {
             Config::get_home()
@@ -955,7 +999,18 @@ async fn read_dir(dir: &str, include_hidden: bool, tx: &UnboundedSender<Data>) {
             fs::get_path(dir)
         }
     };
-    if let Ok(Ok(fd)) = spawn_blocking(move || fs::read_dir(&path, include_hidden)).await {
+    // Parents of the sandbox roots only show the way to them
+    let visible = match sandbox.check_read_dir(&path) {
+        Ok(visible) => visible,
+        Err(err) => {
+            log::warn!("Read dir {:?} denied: {}", path, err);
+            return;
+        }
+    };
+    if let Ok(Ok(mut fd)) = spawn_blocking(move || fs::read_dir(&path, include_hidden)).await {
+        if let Some(names) = visible {
+            fd.entries.retain(|e| names.contains(&e.name));
+        }
         let mut msg_out = Message::new();
         let mut file_response = FileResponse::new();
         file_response.set_dir(fd);
@@ -942,6 +942,25 @@ pub fn video_save_directory(root: bool) -> String {
     Default::default()
 }
 
+/// Recordings of the recording directory as json, oldest first.
+pub fn list_recordings(root: bool) -> String {
+    match scrap::record_retention::list_recordings(&video_save_directory(root)) {
+        Ok(v) => serde_json::to_string(&v).unwrap_or("[]".to_owned()),
+        Err(e) => {
+            log::error!("Failed to list recordings: {}", e);
+            "[]".to_owned()
+        }
+    }
+}
+
+/// Remove a recording with its sidecars, returns the error.
+pub fn delete_recording(root: bool, path: String) -> String {
+    match scrap::record_retention::delete_recording(&video_save_directory(root), &path) {
+        Ok(()) => "".to_owned(),
+        Err(e) => e.to_string(),
+    }
+}
+
 #[inline]
 pub fn get_api_server() -> String {
     crate::get_api_server(
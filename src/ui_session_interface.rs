@@ -46,6 +46,8 @@ use crate::keyboard;
 use crate::{client::Data, client::Interface};
 
 const CHANGE_RESOLUTION_VALID_TIMEOUT_SECS: u64 = 15;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const PORT_FORWARD_USAGE: &str = "Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port<br> rustdesk --port-forward remote-id listen-port socks5<br> rustdesk --port-forward remote-id -R remote-listen-port local-host local-port";
 
 #[derive(Clone, Default)]
 pub struct Session<T: InvokeUiSession> {
@@ -1894,7 +1896,10 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
             loop {
                 match receiver.recv().await {
                     Some(Data::AddPortForward((port, remote_host, remote_port))) => {
-                        if port <= 0 || remote_port <= 0 {
+                        if port <= 0
+                            || (remote_port <= 0
+                                && !crate::port_forward::is_dynamic(&remote_host, remote_port))
+                        {
                             continue;
                         }
                         let (sender, receiver) = mpsc::unbounded_channel::<Data>();
@@ -1931,16 +1936,48 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
                     _ => {}
                 }
             }
+        } else if handler.args[0] == "-R" {
+            let remote_port = handler
+                .args
+                .get(1)
+                .and_then(|p| p.parse::<i32>().ok())
+                .unwrap_or(0);
+            let local_host = handler.args.get(2).cloned().unwrap_or_default();
+            let local_port = handler
+                .args
+                .get(3)
+                .and_then(|p| p.parse::<i32>().ok())
+                .unwrap_or(0);
+            if handler.args.len() != 4 || remote_port <= 0 || local_port <= 0 {
+                handler.on_error(PORT_FORWARD_USAGE);
+                return;
+            }
+            start_one_reverse_forward(
+                handler,
+                remote_port,
+                local_host,
+                local_port,
+                receiver,
+                &key,
+                &token,
+            )
+            .await;
         } else {
             let port = handler.args[0].parse::<i32>().unwrap_or(0);
-            if handler.args.len() != 3
-                || handler.args[2].parse::<i32>().unwrap_or(0) <= 0
+            let dynamic =
+                handler.args.len() == 2 && handler.args[1] == crate::port_forward::DYNAMIC;
+            if !dynamic
+                && (handler.args.len() != 3 || handler.args[2].parse::<i32>().unwrap_or(0) <= 0)
                 || port <= 0
             {
-                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port");
+                handler.on_error(PORT_FORWARD_USAGE);
             }
-            let remote_host = handler.args[1].clone();
-            let remote_port = handler.args[2].parse::<i32>().unwrap_or(0);
+            let remote_host = handler.args.get(1).cloned().unwrap_or_default();
+            let remote_port = handler
+                .args
+                .get(2)
+                .and_then(|p| p.parse::<i32>().ok())
+                .unwrap_or(0);
             start_one_port_forward(
                 handler,
                 port,
@@ -1980,6 +2017,7 @@ async fn start_one_port_forward<T: InvokeUiSession>(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        None,
     )
     .await
     {
@@ -1988,6 +2026,38 @@ async fn start_one_port_forward<T: InvokeUiSession>(
     log::info!("port forward (:{}) exit", port);
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+async fn start_one_reverse_forward<T: InvokeUiSession>(
+    handler: Session<T>,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+    receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) {
+    if let Err(err) = crate::port_forward::listen_reverse(
+        handler.get_id(),
+        handler.password.clone(),
+        remote_port,
+        handler.clone(),
+        receiver,
+        key,
+        token,
+        handler.lc.clone(),
+        local_host,
+        local_port,
+    )
+    .await
+    {
+        handler.on_error(&format!(
+            "Failed to forward remote port {}: {}",
+            remote_port, err
+        ));
+    }
+    log::info!("reverse port forward (:{}) exit", remote_port);
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn send_note(url: String, id: String, sid: u64, note: String) {
     let body = serde_json::json!({ "id": id, "session_id": sid, "note": note });